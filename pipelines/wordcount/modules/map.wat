@@ -0,0 +1,59 @@
+;; the source of `map.wasm`, e.g. `wat2wasm map.wat`
+;; emits a `<word> 1` line for every word of the input, lowercased, where a word is a run of ascii
+;; letters. the input is placed at 1024 and the output right after it, which takes at most twice
+;; the length of the input (a letter and a separator become `x 1\n`)
+(module
+  (memory (export "memory") 1)
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $pages i32)
+    (local.set $pages
+      (i32.shr_u
+        (i32.add
+          (i32.add (i32.const 1024) (i32.mul (local.get $len) (i32.const 3)))
+          (i32.const 65535))
+        (i32.const 16)))
+    (if (i32.gt_u (local.get $pages) (memory.size))
+      (then (drop (memory.grow (i32.sub (local.get $pages) (memory.size))))))
+    (i32.const 1024))
+
+  (func (export "run") (param $ptr i32) (param $len i32) (result i64)
+    (local $at i32)
+    (local $end i32)
+    (local $out i32)
+    (local $char i32)
+    (local $word i32)
+    (local.set $at (local.get $ptr))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    (local.set $out (local.get $end))
+    (block $done
+      (loop $next
+        ;; a separator past the end, so the last word is terminated
+        (if (i32.lt_u (local.get $at) (local.get $end))
+          (then (local.set $char (i32.load8_u (local.get $at))))
+          (else (local.set $char (i32.const 32))))
+        (if (i32.and
+              (i32.ge_u (local.get $char) (i32.const 65))
+              (i32.le_u (local.get $char) (i32.const 90)))
+          (then (local.set $char (i32.add (local.get $char) (i32.const 32)))))
+        (if (i32.and
+              (i32.ge_u (local.get $char) (i32.const 97))
+              (i32.le_u (local.get $char) (i32.const 122)))
+          (then
+            (i32.store8 (local.get $out) (local.get $char))
+            (local.set $out (i32.add (local.get $out) (i32.const 1)))
+            (local.set $word (i32.const 1)))
+          (else
+            (if (local.get $word)
+              (then
+                (i32.store8 (local.get $out) (i32.const 32))
+                (i32.store8 offset=1 (local.get $out) (i32.const 49))
+                (i32.store8 offset=2 (local.get $out) (i32.const 10))
+                (local.set $out (i32.add (local.get $out) (i32.const 3)))
+                (local.set $word (i32.const 0))))))
+        (br_if $done (i32.ge_u (local.get $at) (local.get $end)))
+        (local.set $at (i32.add (local.get $at) (i32.const 1)))
+        (br $next)))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $end)) (i64.const 32))
+      (i64.extend_i32_u (i32.sub (local.get $out) (local.get $end))))))
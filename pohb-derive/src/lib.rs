@@ -0,0 +1,135 @@
+// the derives for the clock types composed of a causality part and a proof part, and for the
+// contexts wrapping the context that verifies them, re-exported by `pohb`
+// * `#[derive(Clock)]` orders and compares a clock by its causality part, i.e. the field marked
+//   `#[causality]` (or the only field), and implements `Causality` by it, e.g.
+//   `struct SignedClock { #[causality] clock: OrdinaryClock, signature: Vec<u8> }`
+// * `#[derive(ClockClientContext)]` verifies through the field marked `#[client_context]` (or the
+//   only field), e.g. a proving context holding the client context of its clocks
+// the generated code refers to `::pohb`, which `pohb` itself aliases to `crate`
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Index, Member, Type};
+
+// the member marked with the attribute, or the only one
+fn delegate(input: &DeriveInput, attribute: &str) -> syn::Result<(Member, Type)> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(input, "expect a struct"));
+    };
+    let members = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| (Member::Named(field.ident.clone().unwrap()), field))
+            .collect(),
+        Fields::Unnamed(fields) => fields
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(index, field)| (Member::Unnamed(Index::from(index)), field))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let mut marked = members.iter().filter(|(_, field)| {
+        field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident(attribute))
+    });
+    let delegate = match (marked.next(), marked.next(), &members[..]) {
+        (Some(delegate), None, _) => delegate,
+        (None, _, [only]) => only,
+        (Some(_), Some((member, _)), _) => {
+            return Err(syn::Error::new_spanned(
+                member,
+                format!("more than one field is marked #[{attribute}]"),
+            ))
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                format!("expect a field marked #[{attribute}]"),
+            ))
+        }
+    };
+    Ok((delegate.0.clone(), delegate.1.ty.clone()))
+}
+
+#[proc_macro_derive(Clock, attributes(causality))]
+pub fn derive_clock(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    clock(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn clock(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let (member, ty) = delegate(input, "causality")?;
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(#ty: ::core::cmp::PartialOrd + ::pohb::attribution::Causality));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::core::cmp::PartialOrd for #name #ty_generics #where_clause {
+            fn partial_cmp(&self, other: &Self) -> ::core::option::Option<::core::cmp::Ordering> {
+                self.#member.partial_cmp(&other.#member)
+            }
+        }
+
+        impl #impl_generics ::core::cmp::PartialEq for #name #ty_generics #where_clause {
+            fn eq(&self, other: &Self) -> bool {
+                self.#member == other.#member
+            }
+        }
+
+        impl #impl_generics ::pohb::attribution::Causality for #name #ty_generics #where_clause {
+            fn causality(&self) -> &::pohb::OrdinaryClock {
+                ::pohb::attribution::Causality::causality(&self.#member)
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(ClockClientContext, attributes(client_context))]
+pub fn derive_clock_client_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    clock_client_context(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn clock_client_context(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let (member, ty) = delegate(input, "client_context")?;
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(#ty: ::pohb::ClockClientContext));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pohb::ClockClientContext for #name #ty_generics #where_clause {
+            type Clock = <#ty as ::pohb::ClockClientContext>::Clock;
+            type Output = <#ty as ::pohb::ClockClientContext>::Output;
+
+            fn verify(
+                &self,
+                clock: &Self::Clock,
+                output: &Self::Output,
+                binding: &::pohb::Binding,
+            ) -> ::std::result::Result<(), ::pohb::Error> {
+                ::pohb::ClockClientContext::verify(&self.#member, clock, output, binding)
+            }
+
+            fn verify_batch(
+                &self,
+                batch: &[::pohb::VerifyRequest<'_, Self::Clock, Self::Output>],
+            ) -> ::std::result::Result<(), ::pohb::Error> {
+                ::pohb::ClockClientContext::verify_batch(&self.#member, batch)
+            }
+        }
+    })
+}
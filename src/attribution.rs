@@ -0,0 +1,92 @@
+// who contributed to a task result, and by how much
+// the producer of each stage is told by the causality part of its clock: it is the only node
+// whose entry advances over the clocks of the upstream stages. every stage is credited to its
+// producer by the weight of the stage, flat by default, and the credit of a node is its share of
+// the total weight. so a node executing the expensive stages of a workflow is not credited the
+// same as one executing a cheap preprocessing stage
+// the measured weights are the execution times that the workers record into the messages, which
+// are claims of the workers just like the recorded program versions, so a workflow should only
+// measure when its workers are trusted to report honestly
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{NodeId, OrdinaryClock, TaskResult, Workflow};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageWeights {
+    // the cost of each stage as declared by the workflow author. a stage without an entry weighs 1
+    Cost(BTreeMap<String, f64>),
+    // the execution time of each stage in milliseconds, as recorded by its worker
+    Measured,
+}
+
+// the clocks that can tell their producers
+pub trait Causality {
+    fn causality(&self) -> &OrdinaryClock;
+}
+
+impl Causality for OrdinaryClock {
+    fn causality(&self) -> &OrdinaryClock {
+        self
+    }
+}
+
+pub fn producer(clock: &OrdinaryClock, previous: &OrdinaryClock) -> anyhow::Result<NodeId> {
+    let mut advanced = clock
+        .iter()
+        .filter(|(id, seq)| **seq > previous.get(id).copied().unwrap_or_default())
+        .map(|(id, _)| *id);
+    match (advanced.next(), advanced.next()) {
+        (Some(id), None) => Ok(id),
+        _ => anyhow::bail!("no single producer advances the clock"),
+    }
+}
+
+fn weight(task: &Workflow, stage: &str, elapsed: &HashMap<String, u64>) -> anyhow::Result<f64> {
+    Ok(match &task.weights {
+        None => 1.,
+        Some(StageWeights::Cost(costs)) => costs.get(stage).copied().unwrap_or(1.),
+        Some(StageWeights::Measured) => *elapsed.get(stage).ok_or(anyhow::format_err!(
+            "missing execution time of stage {stage}"
+        ))? as _,
+    })
+}
+
+// the shares sum up to 1, unless every stage weighs nothing, in which case there are no shares
+pub fn shares<C: Causality, O>(
+    task: &Workflow,
+    result: &TaskResult<C, O>,
+) -> anyhow::Result<BTreeMap<NodeId, f64>> {
+    // only the stages on the branches taken are executed
+    let task = &*task.taken(&result.branches);
+    let mut credits = BTreeMap::new();
+    for stage in &task.stages {
+        // an expired task only credits the stages completed before its deadline
+        if result.expired.is_some() && !result.clocks.contains_key(stage) {
+            continue;
+        }
+        let clock = result
+            .clocks
+            .get(stage)
+            .ok_or(anyhow::format_err!("missing clock of stage {stage}"))?
+            .causality();
+        let producer = task
+            .upstream_clock(stage, &result.clocks)
+            .map_err(Into::into)
+            .and_then(|previous| producer(clock, &previous))
+            .map_err(|err| anyhow::format_err!("stage {stage}: {err}"))?;
+        let weight = weight(task, stage, &result.elapsed)?;
+        anyhow::ensure!(weight >= 0., "negative weight of stage {stage}");
+        *credits.entry(producer).or_insert(0.) += weight;
+    }
+    let total = credits.values().sum::<f64>();
+    if total == 0. {
+        return Ok(BTreeMap::new());
+    }
+    Ok(credits
+        .into_iter()
+        .map(|(id, credit)| (id, credit / total))
+        .collect())
+}
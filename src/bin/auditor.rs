@@ -0,0 +1,132 @@
+use std::{env, fs::canonicalize, path::Path, process::Stdio};
+
+use pohb::{
+    crypto,
+    hub::Reexecutor as _,
+    transport::{HttpTransport, HubTransport as _},
+    worker::ScriptReexecutor,
+    AuditOutcome, AuditRequest, AuditVerdict, NodeId, StageRecord,
+};
+use reqwest::Client;
+use tokio::{io::AsyncWriteExt as _, process::Command};
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+// usage: auditor
+// audits the stages the hub samples (see `POHB_AUDIT_RATE` of `network`), independently of the
+// workers that executed them. a stage with a scorer, an executable at `scorers/<stage>` (or under
+// `POHB_AUDIT_SCORERS`), is scored: the scorer reads the recorded output on stdin and prints a
+// score between 0 and 1, which passes from `POHB_AUDIT_THRESHOLD` (0.5 by default). any other stage
+// is re-executed with the scripts in `scripts`, and passes if the output is the recorded one, which
+// only suits the deterministic stages
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let crypto = crypto::from_env()?;
+    let reexecutor = ScriptReexecutor::new(canonicalize(".")?.join("scripts"), crypto);
+    let scorers = env::var("POHB_AUDIT_SCORERS").unwrap_or("scorers".into());
+    let threshold = match env::var("POHB_AUDIT_THRESHOLD") {
+        Ok(threshold) => threshold.parse()?,
+        Err(_) => 0.5,
+    };
+    let id = rand::random::<NodeId>();
+    info!("audit as {id:08x}");
+    let transport = HttpTransport::new(Client::new(), "http://localhost:3000");
+    transport.handshake().await?;
+    let mut requests = transport.subscribe_audits().await?;
+    while let Some(request) = requests.next().await {
+        let request = request?;
+        let record = match transport.stage_record(request.id, &request.stage).await {
+            Ok(record) => record,
+            Err(err) => {
+                warn!(
+                    "failed to fetch stage {} of task {:08x}: {err}",
+                    request.stage, request.id
+                );
+                continue;
+            }
+        };
+        let scorer = Path::new(&scorers).join(&request.stage);
+        let audited = if scorer.exists() {
+            score(&scorer, &record).await.map(|score| {
+                let verdict = if score >= threshold {
+                    AuditVerdict::Passed
+                } else {
+                    AuditVerdict::Failed
+                };
+                (verdict, Some(score))
+            })
+        } else {
+            reexecute(&reexecutor, &request, record)
+                .await
+                .map(|verdict| (verdict, None))
+        };
+        let (verdict, score) = match audited {
+            Ok(audited) => audited,
+            Err(err) => {
+                warn!(
+                    "failed to audit stage {} of task {:08x}: {err}",
+                    request.stage, request.id
+                );
+                continue;
+            }
+        };
+        info!(
+            "stage {} of task {:08x} {}",
+            request.stage,
+            request.id,
+            match verdict {
+                AuditVerdict::Passed => "passed",
+                AuditVerdict::Failed => "failed",
+            }
+        );
+        let outcome = AuditOutcome {
+            id: request.id,
+            stage: request.stage,
+            auditor: id,
+            verdict,
+            score,
+        };
+        if let Err(err) = transport.submit_audit(&outcome).await {
+            warn!("failed to submit audit: {err}")
+        }
+    }
+    Ok(())
+}
+
+async fn reexecute(
+    reexecutor: &ScriptReexecutor,
+    request: &AuditRequest,
+    record: StageRecord,
+) -> anyhow::Result<AuditVerdict> {
+    let output = reexecutor
+        .reexecute(&request.stage, &request.program, record.input)
+        .await?;
+    Ok(if output == record.output {
+        AuditVerdict::Passed
+    } else {
+        AuditVerdict::Failed
+    })
+}
+
+async fn score(scorer: &Path, record: &StageRecord) -> anyhow::Result<f64> {
+    let mut child = Command::new(scorer)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&record.output)
+        .await?;
+    let output = child.wait_with_output().await?;
+    anyhow::ensure!(
+        output.status.success(),
+        "scorer exits with {}",
+        output.status
+    );
+    let score = String::from_utf8(output.stdout)?.trim().parse::<f64>()?;
+    anyhow::ensure!((0. ..=1.).contains(&score), "score {score} out of range");
+    Ok(score)
+}
@@ -0,0 +1,85 @@
+use std::env::{self, args};
+
+use bytes::Bytes;
+use pohb::{
+    crypto,
+    hub::Reexecutor as _,
+    transport::{HttpTransport, HubTransport as _},
+    wasm::WasmReexecutor,
+    Challenge, OrdinaryClock, TaskResult, Workflow,
+};
+use reqwest::Client;
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+// usage: checker <task.json>
+// re-executes the deterministic stages of a sample of the results on the chain with the modules in
+// `modules` (or `POHB_WASM_MODULES`), and challenges the results whose recorded outputs differ.
+// `POHB_CHECK_PERCENT` of the results are sampled, 10 by default
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let task = args()
+        .nth(1)
+        .ok_or(anyhow::format_err!("missing task description"))?;
+    let task = Workflow::from_path(task)?;
+    let modules = env::var("POHB_WASM_MODULES").unwrap_or("modules".into());
+    let percent = match env::var("POHB_CHECK_PERCENT") {
+        Ok(percent) => percent.parse()?,
+        Err(_) => 10,
+    };
+    let crypto = crypto::from_env()?;
+    let reexecutor = WasmReexecutor::new(modules, crypto.clone());
+    let transport = HttpTransport::new(Client::new(), "http://localhost:3000");
+    transport.handshake().await?;
+    let mut chain = transport
+        .subscribe_chain::<TaskResult<OrdinaryClock, Bytes>>()
+        .await?;
+    while let Some(result) = chain.next().await {
+        let result = result?;
+        // the same sampling as the canaries, so a task id is either always or never sampled. an
+        // expired task has no result to check
+        if result.chunk.is_some() || result.expired.is_some() || result.id % 100 >= percent {
+            continue;
+        }
+        for stage in &task.deterministic {
+            let record = match transport.stage_record(result.id, stage).await {
+                Ok(record) => record,
+                Err(err) => {
+                    warn!(
+                        "failed to fetch stage {stage} of task {:08x}: {err}",
+                        result.id
+                    );
+                    continue;
+                }
+            };
+            let output = match reexecutor
+                .reexecute(stage, &record.program, record.input)
+                .await
+            {
+                Ok(output) => output,
+                Err(err) => {
+                    warn!(
+                        "failed to re-execute stage {stage} of task {:08x}: {err}",
+                        result.id
+                    );
+                    continue;
+                }
+            };
+            if crypto.digest(&output) == crypto.digest(&record.output) {
+                info!("stage {stage} of task {:08x} checked", result.id);
+                continue;
+            }
+            warn!("stage {stage} of task {:08x} has a wrong output", result.id);
+            let challenge = Challenge {
+                id: result.id,
+                stage: stage.clone(),
+                output,
+            };
+            if let Err(err) = transport.submit_challenge(&challenge).await {
+                warn!("failed to challenge: {err}")
+            }
+        }
+    }
+    Ok(())
+}
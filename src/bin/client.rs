@@ -1,60 +1,184 @@
-use std::fmt::Write;
+use std::{
+    fmt::Write,
+    future::Future,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bytes::Bytes;
-use pohb::{OrdinaryClock, StageSource, TaskResult, TaskStage};
+use pohb::{
+    blob::{self, DEFAULT_MAX_BLOB_SIZE},
+    config::{self, ClientConfig},
+    payload::{Json, JSON},
+    transport::{HttpTransport, HubTransport as _, Overloaded},
+    Chunk, OrdinaryClock, TaskResult, TaskStage,
+};
 use reqwest::Client;
-use reqwest_eventsource::{Event, EventSource};
+use serde_json::Value;
+use tokio::{select, time::sleep};
 use tokio_stream::StreamExt as _;
-use tracing::info;
+use tracing::{info, warn};
 
+// usage: client [<chunk count>] [--<key> <value>...]
+// with a chunk count the task is a streaming one, whose input is the sequence of chunks. the other
+// settings are the ones of `ClientConfig`, which may also come from a configuration file or the
+// environment, see `config`
+// with `accept_cached` an ordinary task accepts the cached result of an earlier task of the same
+// input
+// the task is submitted as `submitter` with the `task_labels` (comma separated `<key>=<value>`),
+// if set, which chain subscribers may filter by
+// `task_priority` (`low`, `normal` or `high`) decides how early the task is shed by an overloaded
+// hub, in which case it is published again once the hub asks to retry
+// with `task_deadline` the task must finish within that many seconds, otherwise the hub expires it
+// and the client fails with the stage it got to
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    let input = b"hello"; //
+    let config = config::load::<ClientConfig>("client", &["chunks"])?;
+    let input = config.input.as_bytes();
     let task_id = rand::random();
+    let deadline = config
+        .task_deadline
+        .map(|secs| anyhow::Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + secs))
+        .transpose()?;
 
-    let mut event_source = EventSource::get("http://localhost:3000/chain");
-    let Some(event) = event_source.next().await else {
-        anyhow::bail!("empty event source")
-    };
-    let Event::Open = event? else {
-        anyhow::bail!("unimplemented")
-    };
+    let transport = HttpTransport::new(Client::new(), &config.hub);
+    transport.handshake().await?;
+    let mut chain = transport
+        .subscribe_chain::<TaskResult<OrdinaryClock, Bytes>>()
+        .await?;
+    let mut progress = transport.subscribe_progress().await?;
 
     info!("publish task {task_id:08x}");
-    let task_stage = TaskStage::<OrdinaryClock, _> {
-        id: task_id,
-        source: StageSource::Start,
-        input: Bytes::from(input.to_vec()),
-        clocks: Default::default(),
+    let task_stage = |chunk: Option<Chunk>, input| {
+        anyhow::Ok(TaskStage::<OrdinaryClock, _> {
+            chunk,
+            accept_cached: config.accept_cached && chunk.is_none(),
+            submitter: config.submitter.clone(),
+            priority: config.task_priority,
+            labels: config.task_labels.clone(),
+            deadline,
+            ..TaskStage::start(task_id, &input)?
+        })
     };
-    Client::new()
-        .post("http://localhost:3000/gossip/publish")
-        .json(&task_stage)
-        .send()
-        .await?
-        .error_for_status()?;
+    match config.chunks {
+        None => {
+            let message = task_stage(None, Bytes::copy_from_slice(input))?;
+            if !message.accept_cached {
+                retrying(|| transport.publish_gossip(&message)).await?;
+            } else if let Some(message) =
+                retrying(|| transport.submit_cached::<TaskResult<OrdinaryClock, Bytes>>(&message))
+                    .await?
+            {
+                info!("served the cached result of task {:08x}", message.id);
+                return show(&transport, message).await;
+            }
+        }
+        Some(count) => {
+            for seq in 0..count {
+                let chunk = Chunk {
+                    seq,
+                    last: seq + 1 == count,
+                };
+                let input = Bytes::from([input, &seq.to_be_bytes()].concat());
+                let message = task_stage(Some(chunk), input)?;
+                retrying(|| transport.publish_gossip(&message)).await?;
+            }
+        }
+    }
 
-    while let Some(event) = event_source.next().await {
-        let Event::Message(message) = event? else {
-            anyhow::bail!("unimplemented")
+    loop {
+        let message = select! {
+            Some(event) = progress.next() => {
+                match event {
+                    Ok(event) if event.id == task_id => {
+                        info!("progress of stage {}: {}", event.stage, event.message)
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("failed to receive progress: {err}"),
+                }
+                continue;
+            }
+            message = chain.next() => message,
         };
-        let message = serde_json::from_str::<TaskResult<OrdinaryClock, Bytes>>(&message.data)?;
+        let Some(message) = message else {
+            anyhow::bail!("event source exhausted before task finished")
+        };
+        let message = message?;
         if message.id != task_id {
             continue;
         }
-        info!("task done");
-        info!("clocks");
-        for (stage, clock) in &message.clocks {
-            info!("  {stage}: {clock:?}")
+        if let Some(expired) = &message.expired {
+            anyhow::bail!(
+                "task expired after stage {}",
+                expired.stage.as_deref().unwrap_or("none")
+            )
+        }
+        let done = message.chunk.is_none_or(|chunk| chunk.last);
+        show(&transport, message).await?;
+        if done {
+            return Ok(());
         }
-        info!("output");
-        let mut output_line = String::from("  ");
-        for b in &message.output {
-            write!(&mut output_line, "{b:02x} ")?
+    }
+}
+
+async fn show(
+    transport: &HttpTransport,
+    mut message: TaskResult<OrdinaryClock, Bytes>,
+) -> anyhow::Result<()> {
+    if let Some(blob) = message.blob.take() {
+        message.output = blob::reassemble(transport, &blob, DEFAULT_MAX_BLOB_SIZE).await?
+    }
+    message.decompress(DEFAULT_MAX_BLOB_SIZE)?;
+    match message.chunk {
+        None => info!("task done"),
+        Some(chunk) => info!("checkpoint at chunk {}", chunk.seq),
+    }
+    info!("clocks");
+    for (stage, clock) in &message.clocks {
+        info!("  {stage}: {clock:?}")
+    }
+    // the causality of the result as the hub sees it, stage by stage
+    match transport.verify_result(&message).await {
+        Ok(report) => {
+            for finding in &report.findings {
+                match &finding.stage {
+                    Some(stage) => warn!("  {stage} fails verification: {}", finding.message),
+                    None => warn!("  result fails verification: {}", finding.message),
+                }
+            }
         }
-        info!("{output_line}");
+        Err(err) => warn!("failed to verify the result: {err}"),
+    }
+    for stage in message.logs.keys() {
+        info!("  {stage} has log at /tasks/{}/logs/{stage}", message.id)
+    }
+    info!("output");
+    if message.content_type.as_deref() == Some(JSON) {
+        let Json(output) = message.decode_output::<Json<Value>>()?;
+        info!("  {output}");
         return Ok(());
     }
-    anyhow::bail!("event source exhausted before task finished")
+    let mut output_line = String::from("  ");
+    for b in &message.output {
+        write!(&mut output_line, "{b:02x} ")?
+    }
+    info!("{output_line}");
+    Ok(())
+}
+
+async fn retrying<T, F: Future<Output = anyhow::Result<T>>>(
+    publish: impl Fn() -> F,
+) -> anyhow::Result<T> {
+    loop {
+        match publish().await {
+            Err(err) => match err.downcast_ref::<Overloaded>() {
+                Some(overloaded) => {
+                    warn!("{overloaded}");
+                    sleep(overloaded.retry_after).await
+                }
+                None => return Err(err),
+            },
+            result => return result,
+        }
+    }
 }
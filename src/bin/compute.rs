@@ -1,109 +1,98 @@
-use std::{env::args, fs::canonicalize, process::Stdio};
+use std::{fs::canonicalize, sync::Arc, time::Duration};
 
 use bytes::Bytes;
 use pohb::{
-    ClockContext, OrdinaryClock, OrdinaryContext, StageSource, TaskResult, TaskStage, Workflow,
+    config::{self, WorkerConfig},
+    crypto::CryptoSuite,
+    hex,
+    secrets::HubSecrets,
+    signer::{LocalSigner, Signer as _},
+    transport::{HttpTransport, HubTransport as _},
+    worker::{ScriptExecutor, StageExecutor, Worker},
+    NodeId, OrdinaryContext, Workflow,
 };
 use reqwest::Client;
-use reqwest_eventsource::{Event, EventSource};
-use tokio::{fs, io::AsyncWriteExt as _, process::Command};
-use tokio_stream::StreamExt as _;
-use tracing::{info, warn};
+use tokio::fs;
+use tracing::info;
 
+// usage: compute [<task.json>] <stage> [--<key> <value>...]
+// without the task description the current workflow is fetched from the hub. either way the tasks
+// are executed under the workflow versions they reference, which are fetched from the hub as they
+// show up, so the worker follows the workflow reloads of the hub without a restart. the other
+// settings are the ones of `WorkerConfig`, which may also come from a configuration file or the
+// environment, see `config`
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    let task = args()
-        .nth(1)
-        .ok_or(anyhow::format_err!("missing task description"))?;
-    let task = serde_json::from_str::<Workflow>(&fs::read_to_string(task).await?)?;
-    let stage = args()
-        .nth(2)
+    let mut config = config::load::<WorkerConfig>("worker", &["workflow", "stage"])?;
+    // a single positional argument is the stage
+    if config.stage.is_none() {
+        config.stage = config
+            .workflow
+            .take()
+            .map(|stage| stage.to_string_lossy().into_owned())
+    }
+    let stage = config
+        .stage
+        .clone()
         .ok_or(anyhow::format_err!("missing stage name"))?;
-    let source = task
-        .stages
-        .iter()
-        .take_while(|other_stage| **other_stage != stage)
-        .last()
-        .cloned()
-        .map(StageSource::Name)
-        .unwrap_or(StageSource::Start);
+    let transport = HttpTransport::new(Client::new(), &config.hub);
+    let task = match &config.workflow {
+        Some(task) => Workflow::from_path(task)?,
+        None => transport.workflow(None).await?,
+    };
 
-    let id = rand::random();
+    let crypto = config.common.crypto()?;
+    let id = config.node_id.unwrap_or_else(rand::random);
     info!("start with id {id:08x}");
-    let context = OrdinaryContext::<Bytes, _>::new(id);
-    let mut event_source = EventSource::get("http://localhost:3000/gossip");
-    while let Some(event) = event_source.next().await {
-        let message = match event? {
-            Event::Open => {
-                info!("gossip initialized");
-                continue;
-            }
-            Event::Message(message) => {
-                serde_json::from_str::<TaskStage<OrdinaryClock, Bytes>>(&message.data)?
-            }
-        };
-        if message.source != source {
-            continue;
-        }
-        if let Err(err) = message.verify(&task, &context) {
-            warn!("failed to verify gossip message: {err}");
-            continue;
-        }
-
-        info!("start execute for task {:08x}", message.id);
-        let mut child = Command::new(canonicalize(".")?.join("scripts").join(&stage))
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-        child
-            .stdin
-            .take()
-            .unwrap()
-            .write_all(&message.input)
-            .await?;
-        let output = child.wait_with_output().await?;
-        anyhow::ensure!(output.status.success());
-        let output = Bytes::from(output.stdout);
-
-        let mut clocks = message.clocks;
-        clocks.insert(
-            stage.clone(),
-            context.prove(
-                &match &source {
-                    StageSource::Start => Vec::new(),
-                    StageSource::Name(name) => vec![(&clocks[name], &message.input)],
-                },
-                &output,
-            )?,
-        );
-        if Some(&stage) == task.stages.last() {
-            let task_result = TaskResult {
-                id: message.id,
-                output,
-                clocks,
-            };
-            Client::new()
-                .post("http://localhost:3000/chain/propose")
-                .json(&task_result)
-                .send()
-                .await?
-                .error_for_status()?;
-        } else {
-            let task_stage = TaskStage {
-                id: message.id,
-                source: StageSource::Name(stage.clone()),
-                input: output,
-                clocks,
-            };
-            Client::new()
-                .post("http://localhost:3000/gossip/publish")
-                .json(&task_stage)
-                .send()
-                .await?
-                .error_for_status()?;
-        }
+    // with the `wasm` feature the stage may execute a module in `wasm_modules` instead
+    #[cfg(feature = "wasm")]
+    if let Some(modules) = &config.wasm_modules {
+        let executor = pohb::wasm::WasmExecutor::new(modules, &stage, crypto.clone());
+        return run(task, stage, executor, id, crypto, transport, config).await;
+    }
+    let scripts = canonicalize(".")?.join(&config.scripts);
+    let mut executor = ScriptExecutor::new(scripts, &stage, crypto.clone());
+    if let Some(path) = &config.worker_key {
+        let signer = LocalSigner::new(crypto.clone(), fs::read(path).await?)?;
+        // to be listed among the readers of the secrets of the stage
+        info!("read secrets with public key {}", hex(signer.public_key()));
+        let secrets = HubSecrets::new(transport.clone(), &stage, Arc::new(signer));
+        executor = executor.secrets(Arc::new(secrets))
     }
+    run(task, stage, executor, id, crypto, transport, config).await
+}
 
-    Ok(())
+async fn run(
+    task: Workflow,
+    stage: String,
+    executor: impl StageExecutor,
+    id: NodeId,
+    crypto: Arc<dyn CryptoSuite>,
+    transport: HttpTransport,
+    config: WorkerConfig,
+) -> anyhow::Result<()> {
+    // the worker awaits the proving (`ClockContext::prove_async`), so a context waiting on a remote
+    // signer, a TEE or a SNARK prover fits in here without stalling this single-threaded runtime
+    let context = OrdinaryContext::<Bytes, Bytes>::new(id);
+    let mut worker = Worker::new(task, stage, executor, context, transport)?;
+    if let Some(dir) = config.dead_letter_dir {
+        worker = worker.dead_letters(dir)
+    }
+    worker
+        .max_inline_size(config.common.max_inline_size)
+        .max_blob_size(config.common.max_blob_size)
+        .clock_limits(config.common.clock_limits())
+        .membership(config.common.membership())
+        .compression(config.compression)
+        .clock_deltas(config.clock_deltas)
+        .scheduled(id, config.worker_labels)
+        .shard(config.shard)
+        .publish_ack(config.publish_ack)
+        .registry(crypto)
+        .batch_proofs(config.proof_batch)
+        .retries(config.stage_retries)
+        .join_timeout(Duration::from_secs(config.join_timeout))
+        .run()
+        .await
 }
@@ -0,0 +1,56 @@
+use std::env::args;
+
+use pohb::{
+    blob::FsBlobStore,
+    consistency::{self, Violation},
+    history::HistoryEvent,
+    hub,
+};
+
+// usage: consistency <blob dir> [<task id>...]
+// replays the gossip log a hub keeps in the blob store directory (`POHB_BLOB_DIR` of `network`),
+// every task or only the given ones, and checks the invariants of `pohb::consistency` across all
+// the messages. every violation is printed along with the offending events, and the exit status
+// tells whether there is any. the hub may keep running, the log is only read
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let dir = args()
+        .nth(1)
+        .ok_or(anyhow::format_err!("missing blob directory"))?;
+    let only = args()
+        .skip(2)
+        .map(|id| id.parse())
+        .collect::<Result<Vec<u32>, _>>()?;
+    let blobs = FsBlobStore::new(dir);
+    let mut checked = 0;
+    let mut replayed = 0;
+    let mut violations = 0;
+    for (id, events) in hub::histories(&blobs)? {
+        if !only.is_empty() && !only.contains(&id) {
+            continue;
+        }
+        checked += 1;
+        replayed += events.len();
+        for violation in consistency::check(id, &events) {
+            violations += 1;
+            report(&violation, &events)?
+        }
+    }
+    println!("checked {checked} tasks, {replayed} events, {violations} violations");
+    anyhow::ensure!(violations == 0, "gossip log is inconsistent");
+    Ok(())
+}
+
+fn report(violation: &Violation, events: &[HistoryEvent]) -> anyhow::Result<()> {
+    println!(
+        "task {} {}: {}",
+        violation.task,
+        serde_json::to_string(&violation.kind)?.trim_matches('"'),
+        violation.message
+    );
+    for &index in &violation.events {
+        println!("  #{index} {}", serde_json::to_string(&events[index])?)
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::env::args;
+
+use pohb::{
+    crypto, notary,
+    transport::{HttpTransport, HubTransport as _},
+    NodeId,
+};
+use reqwest::Client;
+
+// usage: contribution <task id> <stage> <node id>
+// prints the proof that the node contributed the stage to the accepted result of the task as json,
+// for a worker to claim credit with a third party, which checks it with
+// `pohb::notary::verify_contribution` against the trusted key of the hub. the ids are decimal, the
+// node id as the `node_id` of the worker
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let id = args()
+        .nth(1)
+        .ok_or(anyhow::format_err!("missing task id"))?
+        .parse()?;
+    let stage = args().nth(2).ok_or(anyhow::format_err!("missing stage"))?;
+    let node = args()
+        .nth(3)
+        .ok_or(anyhow::format_err!("missing node id"))?
+        .parse::<NodeId>()?;
+    let crypto = crypto::from_env()?;
+    let transport = HttpTransport::new(Client::new(), "http://localhost:3000");
+    transport.handshake().await?;
+    let notarization = transport.notarization(id).await?;
+    let proof = notary::contribution(notarization, &*crypto, node, &stage)?;
+    println!("{}", serde_json::to_string_pretty(&proof)?);
+    Ok(())
+}
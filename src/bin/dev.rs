@@ -0,0 +1,58 @@
+use std::{env::args, fs::canonicalize};
+
+use bytes::Bytes;
+use pohb::{crypto, simulation::Simulation, worker::ScriptExecutor, Workflow};
+use tokio::fs;
+use tracing::{info, warn};
+
+// usage: dev <task.json> [<input file>]
+// runs the whole workflow within this process, with an in-memory hub and a worker for each stage
+// executing the scripts in `./scripts` (or the modules in `POHB_WASM_MODULES` with the `wasm`
+// feature) as `compute` does, and prints the task result as json. the input defaults to the one of
+// `client`
+// nothing is deployed or kept, so a stage author can iterate on the programs without a hub and
+// workers. a failing stage is logged, and the task then never finishes
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let task = args()
+        .nth(1)
+        .ok_or(anyhow::format_err!("missing task description"))?;
+    let task = Workflow::from_path(task)?;
+    let input = match args().nth(2) {
+        Some(path) => Bytes::from(fs::read(path).await?),
+        None => Bytes::from_static(b"hello"),
+    };
+    for problem in task.validate() {
+        warn!("{problem}")
+    }
+
+    let crypto = crypto::from_env()?;
+    let simulation = Simulation::new(task.clone(), crypto.clone()).await?;
+    for stage in &task.stages {
+        #[cfg(feature = "wasm")]
+        if let Ok(modules) = std::env::var("POHB_WASM_MODULES") {
+            let executor = pohb::wasm::WasmExecutor::new(modules, stage, crypto.clone());
+            simulation.spawn(stage, executor).await?;
+            continue;
+        }
+        let executor =
+            ScriptExecutor::new(canonicalize(".")?.join("scripts"), stage, crypto.clone());
+        simulation.spawn(stage, executor).await?
+    }
+
+    let task_id = rand::random();
+    info!("publish task {task_id:08x}");
+    let result = simulation.run(task_id, input).await?;
+    for stage in result.logs.keys() {
+        if let Some(log) = simulation
+            .blobs()
+            .get(&format!("tasks/{task_id}/logs/{stage}"))?
+        {
+            info!("log of stage {stage}\n{}", String::from_utf8_lossy(&log))
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    // the workers are dropped along with the runtime
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use std::env::args;
+
+use pohb::history::{self, HistoryEvent};
+use reqwest::Client;
+
+// usage: history <task id>
+// prints the causal history of a task as a timeline, for the post-mortem analysis of wrong or slow
+// results. the task id is the decimal one of the results and the endpoints
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let id = args()
+        .nth(1)
+        .ok_or(anyhow::format_err!("missing task id"))?
+        .parse::<u32>()?;
+    let events = Client::new()
+        .get(format!("http://localhost:3000/tasks/{id}/history"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<HistoryEvent>>()
+        .await?;
+    print!("{}", history::render(&events));
+    Ok(())
+}
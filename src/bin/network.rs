@@ -1,79 +1,117 @@
-use std::{convert::identity, env::args, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
-use axum::{
-    extract::State,
-    response::{sse::Event, IntoResponse, Response, Sse},
-    routing::{get, post},
-    Json, Router,
+use pohb::{
+    blob::FsBlobStore,
+    config::{self, HubConfig},
+    hex,
+    hub::{Hub, Store},
+    secrets::{DirSecrets, SecretStore},
+    signer::{LocalSigner, Signer as _},
+    transport::{HttpTransport, HubTransport as _},
+    worker::ScriptReexecutor,
+    Workflow,
 };
-use bytes::Bytes;
-use pohb::{OrdinaryClientContext, OrdinaryClock, TaskResult, TaskStage, Workflow};
-use reqwest::StatusCode;
-use tokio::{fs, net::TcpListener, sync::watch::Sender};
-use tokio_stream::{wrappers::WatchStream, StreamExt as _};
+use reqwest::Client;
+use tokio::{fs, net::TcpListener};
+use tracing::info;
 
+// usage: network <task.json> [<hub id> <listen address>] [--<key> <value>...]
+// with only the task description the hub runs standalone on port 3000, as before. with a hub id
+// it runs as a member of a raft group, and with `--mirror <url>` as a read-only mirror of the hub
+// at the url, which may go without the task description. the other settings are the ones of
+// `HubConfig`, which may also come from a configuration file or the environment, see `config`
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
-    let task = args()
-        .nth(1)
-        .ok_or(anyhow::format_err!("missing task description"))?;
-    let task = serde_json::from_str(&fs::read_to_string(task).await?)?;
-    let app = Router::new()
-        .route("/gossip", get(gossip_subscribe))
-        .route("/gossip/publish", post(gossip_publish))
-        .route("/chain", get(chain_subscribe))
-        .route("/chain/propose", post(chain_propose))
-        .with_state(Shared::new(task));
-    let listener = TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await?;
-    Ok(())
-}
-
-type C = OrdinaryClock;
-type GossipMessage = TaskStage<C, Bytes>;
-type ChainMessage = TaskResult<C, Bytes>;
-
-#[derive(Clone)]
-struct Shared {
-    gossip: Sender<Option<GossipMessage>>,
-    chain: Sender<Option<ChainMessage>>,
-    task: Arc<Workflow>,
-    context: Arc<OrdinaryClientContext<Bytes>>,
-}
-
-impl Shared {
-    fn new(task: Workflow) -> Self {
-        Self {
-            gossip: Sender::new(None),
-            chain: Sender::new(None),
-            task: Arc::new(task),
-            context: Arc::new(OrdinaryClientContext::new()),
+    tracing_subscriber::fmt::init();
+    let config = config::load::<HubConfig>("hub", &["workflow", "hub_id", "listen"])?;
+    let task = match (&config.workflow, &config.mirror) {
+        (Some(path), _) => Workflow::from_path(path)?,
+        (None, Some(primary)) => {
+            HttpTransport::new(Client::new(), primary)
+                .workflow(None)
+                .await?
         }
+        (None, None) => anyhow::bail!("missing task description"),
+    };
+    let store = match (config.hub_id, config.mirror) {
+        (Some(_), Some(_)) => anyhow::bail!("a member of a raft group cannot be a mirror"),
+        (Some(id), None) => Store::Raft(id),
+        (None, Some(primary)) => Store::Mirror(primary),
+        (None, None) => Store::Local,
+    };
+    let crypto = config.common.crypto()?;
+    let mut builder = Hub::builder();
+    if let Some(dir) = config.blob_dir {
+        builder = builder.blobs(Arc::new(FsBlobStore::new(dir)))
     }
-}
-
-async fn gossip_subscribe(shared: State<Shared>) -> impl IntoResponse {
-    let stream = WatchStream::new(shared.gossip.subscribe())
-        .filter_map(identity)
-        .map(|message| Event::default().json_data(message));
-    Sse::new(stream)
-}
-
-async fn gossip_publish(shared: State<Shared>, Json(message): Json<GossipMessage>) {
-    let _ = shared.gossip.send(Some(message));
-}
-
-async fn chain_subscribe(shared: State<Shared>) -> impl IntoResponse {
-    let stream = WatchStream::new(shared.chain.subscribe())
-        .filter_map(identity)
-        .map(|message| Event::default().json_data(message));
-    Sse::new(stream)
-}
-
-async fn chain_propose(shared: State<Shared>, Json(message): Json<ChainMessage>) -> Response {
-    if let Err(err) = message.verify(&shared.task, &*shared.context) {
-        return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+    let secrets = config
+        .secrets_dir
+        .map(|dir| Arc::new(DirSecrets::new(dir)) as Arc<dyn SecretStore>);
+    if let Some(secrets) = &secrets {
+        builder = builder.secrets(secrets.clone())
+    }
+    if let Some(dir) = config.reexecute_scripts {
+        let mut reexecutor = ScriptReexecutor::new(dir, crypto.clone());
+        if let Some(secrets) = secrets {
+            reexecutor = reexecutor.secrets(secrets)
+        }
+        builder = builder.reexecutor(Arc::new(reexecutor))
+    }
+    #[cfg(feature = "wasm")]
+    if let Some(dir) = config.reexecute_wasm {
+        builder = builder.reexecutor(Arc::new(pohb::wasm::WasmReexecutor::new(
+            dir,
+            crypto.clone(),
+        )))
+    }
+    for path in config.other_workflows {
+        builder = builder.other_workflow(Workflow::from_path(path)?)
+    }
+    if let Some(retention) = config.retention {
+        builder = builder.retention(Duration::from_secs(retention))
+    }
+    if let Some(window) = config.prune_after {
+        builder = builder.prune_after(Duration::from_secs(window))
+    }
+    if let Some(interval) = config.gc_interval {
+        builder = builder.gc_interval(Duration::from_secs(interval))
+    }
+    if let Some(length) = config.epoch_length {
+        builder = builder.epoch_length(Duration::from_secs(length))
+    }
+    if let Some(rate) = config.max_publish_rate {
+        builder = builder.max_publish_rate(rate)
+    }
+    if let Some(rate) = config.max_partition_rate {
+        builder = builder.max_partition_rate(rate)
+    }
+    // see the `auditor` binary
+    if let Some(rate) = config.audit_rate {
+        builder = builder.audit_rate(rate)
+    }
+    if let Some(threads) = config.verifier_threads {
+        builder = builder.verifier_threads(threads)
+    }
+    let secret_key = match config.hub_key {
+        Some(path) => fs::read(path).await?,
+        None => crypto.generate_key(),
+    };
+    let signer = LocalSigner::new(crypto.clone(), secret_key)?;
+    info!("notarizing with public key {}", hex(signer.public_key()));
+    if let Some(path) = config.workflow {
+        builder = builder.workflow_path(path)
     }
-    let _ = shared.chain.send(Some(message));
-    StatusCode::OK.into_response()
+    let hub = builder
+        .workflow(task)
+        .signer(Arc::new(signer))
+        .store(store)
+        .crypto(crypto)
+        .max_inline_size(config.common.max_inline_size)
+        .max_blob_size(config.common.max_blob_size)
+        .clock_limits(config.common.clock_limits())
+        .membership(config.common.membership())
+        .gossip_verification(config.gossip_verification)
+        .build()
+        .await?;
+    hub.serve(TcpListener::bind(config.listen).await?).await
 }
@@ -0,0 +1,72 @@
+use std::{
+    env::args,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use pohb::{crypto, simulation::Pipeline};
+use tokio::time::timeout;
+
+const LIMIT: Duration = Duration::from_secs(60);
+
+// usage: pipelines [<dir>]
+// runs the example pipelines in the directory, `pipelines` by default, end to end within this
+// process (see `pohb::simulation::Pipeline`), and compares every output to the expected one. a
+// pipeline of modules is skipped without the `wasm` feature. every failed pipeline is printed, and
+// the exit status tells whether there is any
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let dir = args()
+        .nth(1)
+        .map_or(PathBuf::from("pipelines"), PathBuf::from);
+    let mut pipelines = fs::read_dir(&dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    pipelines.retain(|path| Pipeline::description(path).is_some());
+    pipelines.sort();
+    anyhow::ensure!(!pipelines.is_empty(), "no pipeline in {}", dir.display());
+    let (mut failed, mut skipped) = (0, 0);
+    for path in &pipelines {
+        let name = path.file_name().unwrap().to_string_lossy();
+        match timeout(LIMIT, run(path)).await {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => {
+                skipped += 1;
+                println!("{name}: skipped, its modules need the wasm feature")
+            }
+            Ok(Err(err)) => {
+                failed += 1;
+                println!("{name}: {err}")
+            }
+            Err(_) => {
+                failed += 1;
+                println!("{name}: not finished within {LIMIT:?}, a stage may have failed")
+            }
+        }
+    }
+    println!(
+        "ran {} pipelines, {failed} failed, {skipped} skipped",
+        pipelines.len()
+    );
+    if failed > 0 {
+        std::process::exit(1)
+    }
+    Ok(())
+}
+
+// false if skipped
+async fn run(path: &Path) -> anyhow::Result<bool> {
+    let pipeline = Pipeline::load(path)?;
+    let Some(result) = pipeline.run(crypto::from_env()?).await? else {
+        return Ok(false);
+    };
+    anyhow::ensure!(
+        result.output == pipeline.expect,
+        "output {:?} instead of {:?}",
+        String::from_utf8_lossy(&result.output),
+        String::from_utf8_lossy(&pipeline.expect)
+    );
+    Ok(true)
+}
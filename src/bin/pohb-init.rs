@@ -0,0 +1,431 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write as _,
+    net::IpAddr,
+    os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use pohb::{
+    config::{self, CommonConfig},
+    hex, NodeId, Workflow,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::info;
+
+// usage: pohb-init [<dir>] [--<key> <value>...]
+// lays out a new deployment in the directory, wired together the way the binaries expect it
+// * `pohb.json`, the configuration file of the hub, the workers and the client (see `config`)
+// * `workflow.json`, a sample workflow running the `stages` in a row, and `scripts/<stage>`, a
+//   sample program of every stage to replace with the actual one
+// * `keys/hub.key`, the key the hub notarizes with, and `keys/<worker>.key` of every worker, along
+//   with the hex encoded public keys as `.pub`
+// * `secrets/<stage>/readers`, allowing the workers of every stage to read the secrets of the
+//   stage once they are put next to it (see `secrets::DirSecrets`)
+// * `tls/`, a certificate authority and a certificate of the hub issued by it, made with the
+//   `openssl` command, and `proxy/nginx.conf` terminating tls in front of the hub, which serves
+//   plain http itself
+// * `systemd/` units of the hub and of every worker, and a `docker-compose.yml` of the same
+// every worker serves one stage with a fixed node id, so it keeps its contributions across
+// restarts. the paths within the configuration are relative to the directory, which the units and
+// the containers run in. the settings are the ones of `InitConfig`, which may also come from the
+// `init` section of a configuration file or the environment as for the other binaries, and the
+// crypto suite is written to the configuration for the whole deployment to agree on
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct InitConfig {
+    // must not exist yet, or be empty
+    dir: PathBuf,
+    stages: Vec<String>,
+    // the workers of every stage
+    replicas: usize,
+    // the host the workers and the client reach the hub at outside of the containers
+    hub_host: String,
+    // the name the clients reach the proxy at, which the certificate of the hub is issued for
+    public_host: String,
+    // of the containers, holding the binaries
+    image: String,
+    // where the binaries are installed, for the systemd units
+    bin_dir: PathBuf,
+    // without tls the hub is exposed as is, and no `openssl` is needed
+    tls: bool,
+    #[serde(flatten)]
+    common: CommonConfig,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            dir: "deployment".into(),
+            stages: vec!["ingest".into(), "transform".into(), "digest".into()],
+            replicas: 1,
+            hub_host: "localhost".into(),
+            public_host: "localhost".into(),
+            image: "pohb:latest".into(),
+            bin_dir: "/usr/local/bin".into(),
+            tls: true,
+            common: Default::default(),
+        }
+    }
+}
+
+const PORT: u16 = 3000;
+
+const TLS_PORT: u16 = 443;
+
+// where the directory is mounted within the containers
+const CONTAINER_DIR: &str = "/pohb";
+
+#[derive(Debug)]
+struct Worker {
+    name: String,
+    stage: String,
+    node_id: NodeId,
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let config = config::load::<InitConfig>("init", &["dir"])?;
+    anyhow::ensure!(!config.stages.is_empty(), "no stages");
+    anyhow::ensure!(config.replicas > 0, "no workers of the stages");
+    for stage in &config.stages {
+        anyhow::ensure!(
+            !stage.is_empty()
+                && stage
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')),
+            "stage name {stage} is not usable as a file and a service name"
+        )
+    }
+    // the keys of an existing deployment are never overwritten
+    if config.dir.exists() {
+        anyhow::ensure!(
+            fs::read_dir(&config.dir)?.next().is_none(),
+            "{} is not empty",
+            config.dir.display()
+        )
+    }
+    let dir = &config.dir;
+    for sub in ["keys", "scripts", "secrets", "systemd"] {
+        fs::create_dir_all(dir.join(sub))?
+    }
+    let dir = &dir.canonicalize()?;
+
+    let workflow = json!({ "stages": config.stages });
+    // the sample is a workflow the hub takes as is
+    serde_json::from_value::<Workflow>(workflow.clone())?;
+    write(
+        dir,
+        "workflow.json",
+        serde_json::to_string_pretty(&workflow)?,
+    )?;
+    for stage in &config.stages {
+        let path = Path::new("scripts").join(stage);
+        write(
+            dir,
+            &path,
+            format!(
+                "#!/bin/sh\n# the program of stage {stage}, which reads the input from stdin and \
+                writes the output to stdout\nsha256sum | cut -d ' ' -f 1\n"
+            ),
+        )?;
+        fs::set_permissions(dir.join(path), fs::Permissions::from_mode(0o755))?
+    }
+
+    let crypto = config.common.crypto()?;
+    let hub_key = crypto.generate_key();
+    write_key(dir, "hub", &hub_key, &crypto.public_key(&hub_key)?)?;
+    let mut workers = Vec::new();
+    for stage in &config.stages {
+        let mut readers = String::new();
+        for replica in 1..=config.replicas {
+            let worker = Worker {
+                name: format!("{stage}-{replica}"),
+                stage: stage.clone(),
+                node_id: rand::random(),
+            };
+            let key = crypto.generate_key();
+            let public_key = crypto.public_key(&key)?;
+            write_key(dir, &worker.name, &key, &public_key)?;
+            readers += &format!("{}\n", hex(&public_key));
+            workers.push(worker)
+        }
+        fs::create_dir_all(dir.join("secrets").join(stage))?;
+        write(
+            dir,
+            Path::new("secrets").join(stage).join("readers"),
+            readers,
+        )?
+    }
+
+    let hub_url = format!("http://{}:{PORT}", config.hub_host);
+    let mut common = Map::new();
+    if let Some(suite) = &config.common.crypto_suite {
+        common.insert("crypto_suite".into(), suite.clone().into());
+    }
+    let mut settings = json!({
+        "hub": {
+            "workflow": "workflow.json",
+            "listen": format!("0.0.0.0:{PORT}"),
+            "blob_dir": "blobs",
+            "hub_key": "keys/hub.key",
+            "secrets_dir": "secrets",
+        },
+        "worker": { "hub": hub_url, "scripts": "scripts" },
+        "client": { "hub": hub_url },
+    });
+    for section in ["hub", "worker"] {
+        if let Some(Value::Object(section)) = settings.get_mut(section) {
+            section.extend(common.clone())
+        }
+    }
+    write(dir, "pohb.json", serde_json::to_string_pretty(&settings)?)?;
+
+    if config.tls {
+        issue_certificates(dir, &config.public_host)?;
+        fs::create_dir_all(dir.join("proxy"))?;
+        write(dir, "proxy/nginx.conf", nginx_conf(&config.public_host))?
+    }
+    write(dir, "systemd/pohb-hub.service", hub_unit(dir, &config))?;
+    for worker in &workers {
+        write(
+            dir,
+            format!("systemd/pohb-worker-{}.service", worker.name),
+            worker_unit(dir, &config, worker),
+        )?
+    }
+    write(dir, "docker-compose.yml", compose(&config, &workers))?;
+
+    info!(
+        "deployment of {} workers laid out in {}",
+        workers.len(),
+        dir.display()
+    );
+    for worker in &workers {
+        info!(
+            "worker {} serves stage {} with id {:08x}",
+            worker.name, worker.stage, worker.node_id
+        )
+    }
+    Ok(())
+}
+
+fn write(dir: &Path, path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    Ok(fs::write(dir.join(path), content)?)
+}
+
+// readable by the owner only
+fn write_key(dir: &Path, name: &str, key: &[u8], public_key: &[u8]) -> anyhow::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(dir.join("keys").join(format!("{name}.key")))?
+        .write_all(key)?;
+    write(
+        dir,
+        Path::new("keys").join(format!("{name}.pub")),
+        format!("{}\n", hex(public_key)),
+    )
+}
+
+fn openssl(dir: &Path, args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new("openssl")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|err| anyhow::format_err!("failed to run openssl ({err}), try --tls false"))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "openssl {} failed: {}",
+        args[0],
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+// a certificate authority for the clients to trust, and the certificate of the hub for the proxy
+fn issue_certificates(dir: &Path, public_host: &str) -> anyhow::Result<()> {
+    let tls = dir.join("tls");
+    fs::create_dir_all(&tls)?;
+    let curve = ["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:prime256v1"];
+    openssl(
+        &tls,
+        &[
+            &["req", "-x509", "-nodes", "-days", "3650"][..],
+            &curve,
+            &["-keyout", "ca.key", "-out", "ca.crt"],
+            &["-subj", "/CN=pohb deployment ca"],
+        ]
+        .concat(),
+    )?;
+    let subject = format!("/CN={public_host}");
+    openssl(
+        &tls,
+        &[
+            &["req", "-nodes"][..],
+            &curve,
+            &["-keyout", "hub.key", "-out", "hub.csr", "-subj", &subject],
+        ]
+        .concat(),
+    )?;
+    // the public host, and the names the hub is reached at locally and within the containers
+    let mut names = vec![match public_host.parse::<IpAddr>() {
+        Ok(_) => format!("IP:{public_host}"),
+        Err(_) => format!("DNS:{public_host}"),
+    }];
+    for name in ["DNS:hub", "DNS:localhost", "IP:127.0.0.1"] {
+        if !names.iter().any(|other| other == name) {
+            names.push(name.into())
+        }
+    }
+    write(
+        &tls,
+        "hub.ext",
+        format!("subjectAltName={}\n", names.join(",")),
+    )?;
+    openssl(
+        &tls,
+        &[
+            "x509",
+            "-req",
+            "-in",
+            "hub.csr",
+            "-CA",
+            "ca.crt",
+            "-CAkey",
+            "ca.key",
+            "-set_serial",
+            "1",
+            "-days",
+            "825",
+            "-extfile",
+            "hub.ext",
+            "-out",
+            "hub.crt",
+        ],
+    )?;
+    for leftover in ["hub.csr", "hub.ext"] {
+        fs::remove_file(tls.join(leftover))?
+    }
+    for key in ["ca.key", "hub.key"] {
+        fs::set_permissions(tls.join(key), fs::Permissions::from_mode(0o600))?
+    }
+    Ok(())
+}
+
+// the subscriptions are long lived server-sent events and websockets, which must not be buffered
+// or cut off by the proxy
+fn nginx_conf(public_host: &str) -> String {
+    format!(
+        r#"events {{}}
+
+http {{
+    server {{
+        listen {TLS_PORT} ssl;
+        server_name {public_host};
+        ssl_certificate /etc/nginx/tls/hub.crt;
+        ssl_certificate_key /etc/nginx/tls/hub.key;
+        client_max_body_size 0;
+
+        location / {{
+            proxy_pass http://hub:{PORT};
+            proxy_http_version 1.1;
+            proxy_set_header Host $host;
+            proxy_set_header Upgrade $http_upgrade;
+            proxy_set_header Connection $http_connection;
+            proxy_buffering off;
+            proxy_read_timeout 1d;
+        }}
+    }}
+}}
+"#
+    )
+}
+
+fn hub_unit(dir: &Path, config: &InitConfig) -> String {
+    unit(
+        dir,
+        "pohb hub",
+        None,
+        &format!("{}/network", config.bin_dir.display()),
+    )
+}
+
+fn worker_unit(dir: &Path, config: &InitConfig, worker: &Worker) -> String {
+    unit(
+        dir,
+        &format!("pohb worker {}", worker.name),
+        // the hub may be on another host, which the unit cannot wait for
+        (config.hub_host == "localhost").then_some("pohb-hub.service"),
+        &format!(
+            "{}/compute {} --node-id {} --worker-key keys/{}.key",
+            config.bin_dir.display(),
+            worker.stage,
+            worker.node_id,
+            worker.name
+        ),
+    )
+}
+
+fn unit(dir: &Path, description: &str, after: Option<&str>, command: &str) -> String {
+    let after = after
+        .map(|after| format!("After={after}\nWants={after}\n"))
+        .unwrap_or_default();
+    format!(
+        "[Unit]\nDescription={description}\n{after}\n[Service]\nWorkingDirectory={}\n\
+        Environment=POHB_CONFIG=pohb.json\nExecStart={command}\nRestart=on-failure\n\n\
+        [Install]\nWantedBy=multi-user.target\n",
+        dir.display()
+    )
+}
+
+// json strings are yaml strings as well
+fn quoted(value: &str) -> String {
+    Value::from(value).to_string()
+}
+
+fn compose(config: &InitConfig, workers: &[Worker]) -> String {
+    let image = quoted(&config.image);
+    let environment = format!(
+        "    working_dir: {CONTAINER_DIR}\n    volumes:\n      - ./:{CONTAINER_DIR}\n    \
+        environment:\n      POHB_CONFIG: pohb.json\n"
+    );
+    // behind the proxy the hub is only exposed to the host itself, e.g. for the client
+    let published = match config.tls {
+        true => format!("127.0.0.1:{PORT}:{PORT}"),
+        false => format!("{PORT}:{PORT}"),
+    };
+    let mut compose = format!(
+        "services:\n  hub:\n    image: {image}\n    command: [\"network\"]\n{environment}    \
+        ports:\n      - \"{published}\"\n    restart: on-failure\n"
+    );
+    for worker in workers {
+        let command = [
+            "compute".into(),
+            worker.stage.clone(),
+            "--node-id".into(),
+            worker.node_id.to_string(),
+            "--worker-key".into(),
+            format!("keys/{}.key", worker.name),
+        ]
+        .map(|arg| quoted(&arg))
+        .join(", ");
+        compose += &format!(
+            "  worker-{}:\n    image: {image}\n    command: [{command}]\n{environment}      \
+            POHB_HUB: http://hub:{PORT}\n    depends_on: [hub]\n    restart: on-failure\n",
+            worker.name
+        )
+    }
+    if config.tls {
+        compose += &format!(
+            "  proxy:\n    image: nginx:stable\n    volumes:\n      - \
+            ./proxy/nginx.conf:/etc/nginx/nginx.conf:ro\n      - ./tls:/etc/nginx/tls:ro\n    \
+            ports:\n      - \"{TLS_PORT}:{TLS_PORT}\"\n    depends_on: [hub]\n    restart: \
+            on-failure\n"
+        )
+    }
+    compose
+}
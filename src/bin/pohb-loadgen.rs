@@ -0,0 +1,247 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use pohb::{
+    blob::{self, DEFAULT_MAX_BLOB_SIZE, DEFAULT_MAX_INLINE_SIZE},
+    config,
+    transport::{HttpTransport, HubTransport as _, Overloaded},
+    OrdinaryClientContext, OrdinaryClock, Priority, TaskId, TaskResult, TaskStage, Workflow,
+    WorkflowDigest,
+};
+use rand::{seq::SliceRandom as _, RngCore as _};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    select,
+    sync::mpsc,
+    time::{interval, sleep_until, MissedTickBehavior},
+};
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+// usage: pohb-loadgen [--<key> <value>...]
+// submits synthetic tasks to the hub at a steady rate for a while, and prints a report of the end
+// to end latencies of their results and how fast the results verify, for validating a deployment
+// under load. the settings are the ones of `LoadgenConfig`, which may also come from the `loadgen`
+// section of a configuration file or the environment as for the other binaries, see `config`
+// the tasks are submitted whether or not the earlier ones are done, so a rate beyond what the
+// deployment sustains shows up as growing latencies, and as shed tasks on a hub with a publish
+// rate limit, which are counted rather than retried
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct LoadgenConfig {
+    // the base url
+    hub: String,
+    // new tasks per second
+    rate: f64,
+    // of the submission, in seconds
+    duration: u64,
+    // how long the outstanding results are waited for after the submission, in seconds
+    drain: u64,
+    // the input sizes in bytes, each task takes one of them at random
+    payload_sizes: Vec<usize>,
+    task_priority: Priority,
+    // the larger inputs are offloaded into the blob store
+    max_inline_size: usize,
+}
+
+impl Default for LoadgenConfig {
+    fn default() -> Self {
+        Self {
+            hub: "http://localhost:3000".into(),
+            rate: 10.,
+            duration: 60,
+            drain: 30,
+            payload_sizes: vec![64],
+            task_priority: Default::default(),
+            max_inline_size: DEFAULT_MAX_INLINE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    submitted: u64,
+    // refused by an overloaded hub
+    shed: u64,
+    // failed to submit otherwise
+    failed: u64,
+    completed: u64,
+    expired: u64,
+    // without a result by the end of the drain
+    outstanding: u64,
+    // results that fail the verification
+    invalid: u64,
+    // results per second over the whole run
+    throughput: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<Latencies>,
+    // results verified per second spent verifying
+    verification_throughput: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Latencies {
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Latencies {
+    // `None` if there is none
+    fn new(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let percentile = |p: f64| {
+            let rank = ((p * latencies.len() as f64).ceil() as usize).max(1);
+            latencies[rank - 1].as_secs_f64() * 1000.
+        };
+        Some(Self {
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: percentile(1.),
+        })
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let config = config::load::<LoadgenConfig>("loadgen", &[])?;
+    anyhow::ensure!(config.rate > 0., "rate must be positive");
+    anyhow::ensure!(!config.payload_sizes.is_empty(), "no payload size");
+
+    let transport = HttpTransport::new(Client::new(), &config.hub);
+    transport.handshake().await?;
+    let mut chain = transport
+        .subscribe_chain::<TaskResult<OrdinaryClock, Bytes>>()
+        .await?;
+    // the submissions run in the background so a slow one does not hold up the rate, and report
+    // back whether they are accepted
+    let (outcomes, mut submissions) = mpsc::unbounded_channel();
+
+    info!(
+        "submit {} tasks per second for {}s",
+        config.rate, config.duration
+    );
+    let start = Instant::now();
+    let submit_end = start + Duration::from_secs(config.duration);
+    let end = submit_end + Duration::from_secs(config.drain);
+    let mut ticks = interval(Duration::from_secs_f64(1. / config.rate));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let mut pending = HashMap::<TaskId, Instant>::new();
+    let mut workflows = HashMap::new();
+    let mut latencies = Vec::new();
+    let mut verifying = Duration::ZERO;
+    let mut report = Report::default();
+    loop {
+        if Instant::now() >= submit_end && pending.is_empty() {
+            break;
+        }
+        select! {
+            _ = ticks.tick(), if Instant::now() < submit_end => {
+                let id = rand::random();
+                let size = *config.payload_sizes.choose(&mut rand::thread_rng()).unwrap();
+                pending.insert(id, Instant::now());
+                report.submitted += 1;
+                let transport = transport.clone();
+                let outcomes = outcomes.clone();
+                let (priority, max_inline_size) = (config.task_priority, config.max_inline_size);
+                tokio::spawn(async move {
+                    let result = submit(&transport, id, size, priority, max_inline_size).await;
+                    let _ = outcomes.send((id, result));
+                });
+            }
+            Some((id, result)) = submissions.recv() => {
+                let Err(err) = result else { continue };
+                pending.remove(&id);
+                if err.downcast_ref::<Overloaded>().is_some() {
+                    report.shed += 1
+                } else {
+                    warn!("failed to submit task {id:08x}: {err}");
+                    report.failed += 1
+                }
+            }
+            message = chain.next() => {
+                let Some(message) = message else {
+                    anyhow::bail!("event source exhausted")
+                };
+                let message = message?;
+                let Some(submitted) = pending.remove(&message.id) else {
+                    continue;
+                };
+                if message.expired.is_some() {
+                    report.expired += 1;
+                    continue;
+                }
+                latencies.push(submitted.elapsed());
+                report.completed += 1;
+                match verify(&transport, &mut workflows, message).await {
+                    Ok(elapsed) => verifying += elapsed,
+                    Err(err) => {
+                        warn!("failed to verify result: {err}");
+                        report.invalid += 1
+                    }
+                }
+            }
+            _ = sleep_until(end.into()) => break,
+        }
+    }
+
+    report.outstanding = pending.len() as _;
+    report.throughput = report.completed as f64 / start.elapsed().as_secs_f64();
+    let verified = report.completed - report.invalid;
+    if !verifying.is_zero() {
+        report.verification_throughput = verified as f64 / verifying.as_secs_f64()
+    }
+    report.latency_ms = Latencies::new(latencies);
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+async fn submit(
+    transport: &HttpTransport,
+    id: TaskId,
+    size: usize,
+    priority: Priority,
+    max_inline_size: usize,
+) -> anyhow::Result<()> {
+    let mut input = vec![0; size];
+    rand::thread_rng().fill_bytes(&mut input);
+    let input = Bytes::from(input);
+    let mut message = TaskStage::<OrdinaryClock, _> {
+        priority,
+        ..TaskStage::start(id, &input)?
+    };
+    if input.len() > max_inline_size {
+        message.blob = Some(blob::offload(transport, &input).await?);
+        message.input = Bytes::new()
+    }
+    transport.publish_gossip(&message).await.map(drop)
+}
+
+// the time spent verifying the result, without reassembling its output and fetching its workflow
+async fn verify(
+    transport: &HttpTransport,
+    workflows: &mut HashMap<Option<WorkflowDigest>, Workflow>,
+    mut message: TaskResult<OrdinaryClock, Bytes>,
+) -> anyhow::Result<Duration> {
+    if let Some(blob) = message.blob.take() {
+        message.output = blob::reassemble(transport, &blob, DEFAULT_MAX_BLOB_SIZE).await?
+    }
+    message.decompress(DEFAULT_MAX_BLOB_SIZE)?;
+    let task = match workflows.entry(message.workflow) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(transport.workflow(message.workflow.as_ref()).await?),
+    };
+    let verifying = Instant::now();
+    message.verify(task, &OrdinaryClientContext::new())?;
+    Ok(verifying.elapsed())
+}
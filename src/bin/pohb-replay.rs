@@ -0,0 +1,337 @@
+use std::{collections::HashMap, fs::canonicalize, path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use pohb::{
+    blob::CHUNK_SIZE,
+    compression::compress,
+    config::{self, CommonConfig},
+    crypto::{CryptoSuite, Digest},
+    hex,
+    history::{EventKind, HistoryEvent},
+    hub::Reexecutor,
+    notary,
+    outputs::{self, NamedOutputs},
+    secrets::DirSecrets,
+    transport::{HttpTransport, HubTransport as _},
+    unhex,
+    worker::ScriptReexecutor,
+    ClockOrdering, CompareClock as _, NodeId, OrdinaryClientContext, OrdinaryClock, TaskId,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+// usage: pohb-replay <task id> [--<key> <value>...]
+// re-executes the whole pipeline of an accepted task locally, from the input it started with, and
+// checks the output of every deterministic stage against the one committed to the history of the
+// task along with the clock the accepted result carries. it prints a provenance report as json,
+// and exits with 1 unless every deterministic stage passes, for gating the high-stakes results.
+// the settings are the ones of `ReplayConfig`, which may also come from the `replay` section of a
+// configuration file or the environment as for the other binaries, see `config`
+// the result itself is verified against the notarization of the hub (see `notary`), so the clocks,
+// the program versions and the final output are the ones the hub signs. the outputs of the other
+// stages are checked against the digests in the history of the task as the hub serves it, since
+// ordinary clocks do not commit to their outputs. under a workflow that audits, the result records
+// the digests of the outputs of all the stages itself, and the recorded outputs are audited
+// against them as well (see `TaskResult::audit`)
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ReplayConfig {
+    // the base url, or the comma separated base urls of the members of a raft group
+    hub: String,
+    // the decimal id of the task
+    id: Option<TaskId>,
+    // the hex encoded public key of the hub that the notarization must be signed with. the key in
+    // the notarization is taken as is without it, which only proves the result as kept by whoever
+    // serves it
+    hub_public_key: Option<String>,
+    // the stage programs, laid out as for the workers
+    scripts: PathBuf,
+    // or the stage modules, with the `wasm` feature
+    wasm_modules: Option<PathBuf>,
+    // the secrets of the stages, laid out as `secrets::DirSecrets`
+    secrets_dir: Option<PathBuf>,
+    #[serde(flatten)]
+    common: CommonConfig,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            hub: "http://localhost:3000".into(),
+            id: None,
+            hub_public_key: None,
+            scripts: "scripts".into(),
+            wasm_modules: None,
+            secrets_dir: None,
+            common: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    id: TaskId,
+    workflow: String,
+    hub_public_key: String,
+    // whether the key is the configured one, rather than taken from the notarization
+    trusted_key: bool,
+    // the head of the ledger of the hub the result is notarized under
+    ledger_size: u64,
+    ledger_root: String,
+    reverted: bool,
+    // whether the input the task started with is the committed one
+    input: Outcome,
+    stages: Vec<StageReport>,
+    // whether the recorded outputs pass `TaskResult::audit`, if the workflow audits
+    audit: Outcome,
+    passed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Passed,
+    Failed,
+    // not checked, e.g. a stage that is not deterministic
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct StageReport {
+    stage: String,
+    program: Option<String>,
+    producer: Option<NodeId>,
+    // the hex encoded digests of the committed output, as published, and of the replayed one
+    committed: Option<String>,
+    replayed: Option<String>,
+    outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+// the digest of a payload in the form the event commits to it, i.e. of the inline bytes or of the
+// chunk digests of the offloaded ones (see `history::HistoryEvent`), after the compression it is
+// published with
+fn published(
+    crypto: &dyn CryptoSuite,
+    event: &HistoryEvent,
+    payload: &[u8],
+) -> anyhow::Result<Digest> {
+    let payload = match event.compression {
+        Some(compression) => compress(payload, compression)?,
+        None => Bytes::copy_from_slice(payload),
+    };
+    let inline = crypto.digest(&payload);
+    let chunks = payload
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| crypto.digest(chunk))
+        .collect::<Vec<_>>();
+    let offloaded = crypto.digest(&chunks.concat());
+    Ok(match event.payload == Some(offloaded) {
+        true => offloaded,
+        false => inline,
+    })
+}
+
+// the event that publishes the output of the stage, or the start input for `None`, with the clock
+// of the accepted result. the latest one, should the same execution be published again, e.g. on a
+// migration
+fn committed<'a>(
+    events: &'a [HistoryEvent],
+    stage: Option<&str>,
+    clocks: &HashMap<String, OrdinaryClock>,
+) -> Option<&'a HistoryEvent> {
+    events.iter().rev().find(|event| {
+        matches!(event.kind, EventKind::Gossip | EventKind::Result)
+            && event.chunk.is_none()
+            && event.stage.as_deref() == stage
+            && stage.is_none_or(|stage| {
+                event
+                    .clocks
+                    .get(stage)
+                    .zip(clocks.get(stage))
+                    .is_some_and(|(clock, accepted)| {
+                        clock.compare(accepted) == ClockOrdering::Equal
+                    })
+            })
+    })
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let config = config::load::<ReplayConfig>("replay", &["id"])?;
+    let id = config.id.ok_or(anyhow::format_err!("missing task id"))?;
+    let crypto = config.common.crypto()?;
+    let reexecutor = reexecutor(&config, crypto.clone())?;
+    let transport = HttpTransport::new(Client::new(), &config.hub);
+    transport.handshake().await?;
+
+    let notarization = transport.notarization(id).await?;
+    let hub_public_key = match &config.hub_public_key {
+        Some(key) => unhex(key)?,
+        None => {
+            warn!("no hub public key, take the one of the notarization");
+            notarization.public_key.clone()
+        }
+    };
+    let notarized = notary::verify(&notarization, &*crypto, &hub_public_key)?;
+    let (task, result) = (&notarized.workflow, &notarized.result);
+    let events = transport.history(id).await?;
+    info!("replay task {id:08x} of {} stages", task.stages.len());
+
+    // the input is the one the hub keeps for the first stage, which must be the committed one
+    let first = task
+        .stages
+        .first()
+        .ok_or(anyhow::format_err!("workflow without stages"))?;
+    let start = transport.stage_record(id, first).await?.input;
+    let input = match committed(&events, None, &result.clocks) {
+        Some(event) if event.payload == Some(published(&*crypto, event, &start)?) => {
+            Outcome::Passed
+        }
+        _ => Outcome::Failed,
+    };
+
+    // only the stages on the branches taken are executed
+    let taken = task.taken(&result.branches);
+    // the outputs known to be the committed ones, by stage, which the downstream stages execute
+    // upon
+    let mut outputs = HashMap::<&str, Bytes>::new();
+    // the outputs the hub records, by stage, whatever the replay turns out
+    let mut recorded = HashMap::new();
+    let mut stages = Vec::new();
+    for stage in &taken.stages {
+        let last = Some(stage) == taken.stages.last();
+        let event = committed(&events, Some(stage), &result.clocks);
+        // the final output is the notarized one
+        let committed = match last {
+            true => Some(crypto.digest(&result.output)),
+            false => event.and_then(|event| event.payload),
+        };
+        let digest = |output: &[u8]| match (last, event) {
+            (false, Some(event)) => published(&*crypto, event, output),
+            _ => Ok(crypto.digest(output)),
+        };
+        let mut report = StageReport {
+            stage: stage.clone(),
+            program: result.programs.get(stage).map(|program| hex(program)),
+            producer: event.and_then(|event| event.producer),
+            committed: committed.map(|digest| hex(&digest)),
+            replayed: None,
+            outcome: Outcome::Skipped,
+            reason: None,
+        };
+        // the recorded output feeds the downstream stages as long as it is the committed one,
+        // whatever the replay of this stage turns out
+        if let Ok(record) = transport.stage_record(id, stage).await {
+            if committed == Some(digest(&record.output)?) {
+                outputs.insert(stage, record.output.clone());
+            }
+            recorded.insert(stage.clone(), record.output);
+        }
+        let upstream = taken.upstream(stage).unwrap_or_default();
+        let input = match &upstream[..] {
+            [] => Some(start.clone()),
+            [upstream] => outputs.get(upstream).cloned(),
+            upstream => upstream
+                .iter()
+                .map(|upstream| Some((upstream.to_string(), outputs.get(upstream)?.clone())))
+                .collect::<Option<NamedOutputs>>()
+                .map(|inputs| outputs::encode(&inputs)),
+        };
+        let program = result.programs.get(stage);
+        match (task.deterministic.contains(stage), input, program) {
+            (false, ..) => report.reason = Some("stage is not deterministic".into()),
+            (true, None, _) => {
+                report.reason = Some("committed input is not available".into());
+                report.outcome = Outcome::Failed
+            }
+            (true, _, None) => {
+                report.reason = Some("program version is not recorded".into());
+                report.outcome = Outcome::Failed
+            }
+            (true, Some(input), Some(program)) => {
+                match reexecutor.reexecute(stage, program, input).await {
+                    Ok(output) => {
+                        let replayed = digest(&output)?;
+                        report.replayed = Some(hex(&replayed));
+                        if committed == Some(replayed) {
+                            report.outcome = Outcome::Passed;
+                            outputs.insert(stage, output);
+                        } else {
+                            report.reason = Some("output differs from the committed one".into());
+                            report.outcome = Outcome::Failed
+                        }
+                    }
+                    Err(err) => {
+                        report.reason = Some(format!("failed to re-execute: {err}"));
+                        report.outcome = Outcome::Failed
+                    }
+                }
+            }
+        }
+        match report.outcome {
+            Outcome::Passed => info!("stage {stage} passed"),
+            Outcome::Failed => warn!("stage {stage} failed: {}", report.reason.as_ref().unwrap()),
+            Outcome::Skipped => info!("stage {stage} skipped: {}", report.reason.as_ref().unwrap()),
+        }
+        stages.push(report)
+    }
+
+    let audit = match task.audit {
+        false => Outcome::Skipped,
+        true => {
+            let context = OrdinaryClientContext::new();
+            match result.audit(task, &context, &config.common.membership(), &recorded) {
+                Ok(()) => Outcome::Passed,
+                Err(err) => {
+                    warn!("audit failed: {err}");
+                    Outcome::Failed
+                }
+            }
+        }
+    };
+    let passed = input == Outcome::Passed
+        && notarized.reverted.is_none()
+        && stages.iter().all(|stage| stage.outcome != Outcome::Failed)
+        && audit != Outcome::Failed;
+    let report = Report {
+        id,
+        workflow: hex(&task.digest(&*crypto)),
+        hub_public_key: hex(&hub_public_key),
+        trusted_key: config.hub_public_key.is_some(),
+        ledger_size: notarized.head.size,
+        ledger_root: hex(&notarized.head.root),
+        reverted: notarized.reverted.is_some(),
+        input,
+        stages,
+        audit,
+        passed,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !passed {
+        std::process::exit(1)
+    }
+    Ok(())
+}
+
+fn reexecutor(
+    config: &ReplayConfig,
+    crypto: Arc<dyn CryptoSuite>,
+) -> anyhow::Result<Box<dyn Reexecutor>> {
+    #[cfg(feature = "wasm")]
+    if let Some(modules) = &config.wasm_modules {
+        return Ok(Box::new(pohb::wasm::WasmReexecutor::new(modules, crypto)));
+    }
+    anyhow::ensure!(
+        config.wasm_modules.is_none(),
+        "stage modules need the wasm feature"
+    );
+    let mut reexecutor = ScriptReexecutor::new(canonicalize(".")?.join(&config.scripts), crypto);
+    if let Some(dir) = &config.secrets_dir {
+        reexecutor = reexecutor.secrets(Arc::new(DirSecrets::new(dir)))
+    }
+    Ok(Box::new(reexecutor))
+}
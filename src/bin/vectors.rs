@@ -0,0 +1,27 @@
+use std::{env::args, path::PathBuf};
+
+use pohb::vectors;
+
+// usage: vectors [<dir>]
+// checks the golden vectors of the wire format in the directory, `vectors` by default, see
+// `pohb::vectors`. every failed vector is printed, and the exit status tells whether there is any,
+// so a change of the encoding can be gated on it
+fn main() -> anyhow::Result<()> {
+    let dir = args()
+        .nth(1)
+        .map_or(PathBuf::from("vectors"), PathBuf::from);
+    let vectors = vectors::load(&dir)?;
+    anyhow::ensure!(!vectors.is_empty(), "no vector in {}", dir.display());
+    let mut failed = 0;
+    for (name, vector) in &vectors {
+        if let Err(err) = vectors::check(vector) {
+            failed += 1;
+            println!("{name} ({}): {err}", vector.description)
+        }
+    }
+    println!("checked {} vectors, {failed} failed", vectors.len());
+    if failed > 0 {
+        std::process::exit(1)
+    }
+    Ok(())
+}
@@ -0,0 +1,238 @@
+// where the hub keeps payloads that are referenced by, but not carried in, the messages, e.g. the
+// logs of stage executions and the outputs that are too large to be inlined
+// blobs are keyed by slash separated paths such as `tasks/<task id>/logs/<stage>`. the store is
+// local to a hub instance and not replicated, so the members of a raft group should share a
+// directory (e.g. a network file system) if the blobs must survive the loss of a member
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "network")]
+use crate::transport::HubTransport;
+use crate::{crypto::Digest, hex};
+
+pub trait BlobStore: Send + Sync {
+    fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()>;
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    // the names directly under a key prefix, e.g. the task ids under `chain`, in no particular
+    // order. an unknown prefix has nothing under it
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+
+    // the blob of the key along with everything under the key as a prefix, e.g. all the blobs of
+    // a task under `tasks/<task id>`. deleting an unknown key does nothing
+    fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore(Mutex<HashMap<String, Bytes>>);
+
+impl BlobStore for MemoryBlobStore {
+    fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()> {
+        self.0.lock().unwrap().insert(key.into(), data);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        Ok(self.0.lock().unwrap().get(key).cloned())
+    }
+
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = format!("{prefix}/");
+        let names = self
+            .0
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(|name| name.split('/').next().unwrap().to_string())
+            .collect::<BTreeSet<_>>();
+        Ok(names.into_iter().collect())
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let prefix = format!("{key}/");
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|other, _| other != key && !other.starts_with(&prefix));
+        Ok(())
+    }
+}
+
+// one file per blob under the root directory. the blobs are expected to be small enough that
+// blocking on the file system is not a concern
+#[derive(Debug)]
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let key = Path::new(key);
+        anyhow::ensure!(
+            key.components()
+                .all(|component| matches!(component, Component::Normal(_))),
+            "invalid blob key {}",
+            key.display()
+        );
+        Ok(self.root.join(key))
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data.into())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.path(prefix)?) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            names.extend(entry?.file_name().into_string().ok())
+        }
+        Ok(names)
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        let deleted = match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+            Ok(_) => fs::remove_file(path),
+            Err(err) => Err(err),
+        };
+        match deleted {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// every message fans out to every subscriber, so a payload larger than this is offloaded into
+// the blob store by its producer and referenced from the message instead, and the hub rejects
+// messages inlining anything larger. the producers and the hub must agree on the limit, which is
+// configured with `max_inline_size` for the binaries (see `config`)
+pub const DEFAULT_MAX_INLINE_SIZE: usize = 64 << 10;
+
+// the offloaded payloads are uploaded in chunks, so no single request gets too large
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+// the size a blob reference may declare, so a message cannot make its receivers reassemble (or
+// decompress, see `compression`) without bound. configured with `max_blob_size` for the binaries
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 256 << 20;
+
+// an offloaded payload, as the digests of its chunks in order. the chunks are content addressed
+// at `blobs/<hex digest>`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    pub size: u64,
+    pub chunks: Vec<Digest>,
+}
+
+impl BlobRef {
+    // the declared size must fit the chunks, and the limit, before anything is fetched
+    pub fn check(&self, max_size: u64) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.size <= self.chunks.len() as u64 * CHUNK_SIZE as u64,
+            "blob of {} bytes does not fit its {} chunks",
+            self.size,
+            self.chunks.len()
+        );
+        anyhow::ensure!(
+            self.size <= max_size,
+            "blob of {} bytes exceeds the limit of {max_size} bytes",
+            self.size
+        );
+        Ok(())
+    }
+}
+
+pub fn blob_key(digest: &Digest) -> String {
+    format!("blobs/{}", hex(digest))
+}
+
+#[cfg(feature = "network")]
+pub async fn offload(transport: &impl HubTransport, payload: &Bytes) -> anyhow::Result<BlobRef> {
+    let mut chunks = Vec::new();
+    for offset in (0..payload.len()).step_by(CHUNK_SIZE) {
+        let chunk = payload.slice(offset..payload.len().min(offset + CHUNK_SIZE));
+        chunks.push(transport.upload_blob(chunk).await?)
+    }
+    Ok(BlobRef {
+        size: payload.len() as _,
+        chunks,
+    })
+}
+
+// the buffer grows with the chunks as fetched rather than with the declared size
+#[cfg(feature = "network")]
+pub async fn reassemble(
+    transport: &impl HubTransport,
+    blob: &BlobRef,
+    max_size: u64,
+) -> anyhow::Result<Bytes> {
+    blob.check(max_size)?;
+    let mut payload = BytesMut::new();
+    for digest in &blob.chunks {
+        extend(&mut payload, &transport.download_blob(digest).await?, blob)?
+    }
+    anyhow::ensure!(payload.len() as u64 == blob.size, "blob size mismatch");
+    Ok(payload.freeze())
+}
+
+// the hub side reassembly, from its own store
+pub fn reassemble_local(
+    store: &dyn BlobStore,
+    blob: &BlobRef,
+    max_size: u64,
+) -> anyhow::Result<Bytes> {
+    blob.check(max_size)?;
+    let mut payload = BytesMut::new();
+    for digest in &blob.chunks {
+        let chunk = store
+            .get(&blob_key(digest))?
+            .ok_or(anyhow::format_err!("missing blob {}", hex(digest)))?;
+        extend(&mut payload, &chunk, blob)?
+    }
+    anyhow::ensure!(payload.len() as u64 == blob.size, "blob size mismatch");
+    Ok(payload.freeze())
+}
+
+// the chunks are fetched by digest, so a chunk repeated over and over stops at the declared size
+fn extend(payload: &mut BytesMut, chunk: &[u8], blob: &BlobRef) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (payload.len() + chunk.len()) as u64 <= blob.size,
+        "blob size mismatch"
+    );
+    payload.extend_from_slice(chunk);
+    Ok(())
+}
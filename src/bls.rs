@@ -0,0 +1,479 @@
+// BLS aggregate signed clocks, for stages whose executions are witnessed by several attestors, e.g.
+// independent hosts re-executing the stage or watching the worker. every attestor signs the same
+// message as a `signed` clock is signed over, i.e. the causality part, the stage it is produced for
+// and the digest of the output, and the signatures are aggregated into one, so the proof part is
+// one signature (96 bytes) plus a bitmap of who signed over the registered attestor set, however
+// many of them signed
+// the scheme is BLS12-381 with the public keys in G1, as in Ethereum, and the aggregate is verified
+// against the aggregate of the signers' public keys. that is only sound when every registered key
+// comes with its proof of possession, which rules out the keys made up to cancel out the others, so
+// the attestor set is registered along with the proofs
+// the attestors are `Signer`s holding BLS keys, e.g. a `LocalSigner` of a `BlsSuite`
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{marker::PhantomData, sync::Arc};
+
+use blst::{
+    min_pk::{AggregateSignature, PublicKey, SecretKey, Signature},
+    BLST_ERROR,
+};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    crypto::{CryptoSuite, Digest, HashAlgorithm},
+    signer::Signer,
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+// the ciphersuites of the proof of possession scheme (see the BLS signature draft), the one to sign
+// the messages with and the one to prove the possession of the keys with
+const SIGNATURE_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+const POSSESSION_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+fn check(err: BLST_ERROR) -> anyhow::Result<()> {
+    anyhow::ensure!(err == BLST_ERROR::BLST_SUCCESS, "BLS error {err:?}");
+    Ok(())
+}
+
+fn public_key(bytes: &[u8]) -> anyhow::Result<PublicKey> {
+    PublicKey::key_validate(bytes).map_err(|err| anyhow::format_err!("invalid public key {err:?}"))
+}
+
+fn signature(bytes: &[u8]) -> anyhow::Result<Signature> {
+    Signature::sig_validate(bytes, true)
+        .map_err(|err| anyhow::format_err!("invalid signature {err:?}"))
+}
+
+fn secret_key(bytes: &[u8]) -> anyhow::Result<SecretKey> {
+    SecretKey::from_bytes(bytes).map_err(|err| anyhow::format_err!("invalid secret key {err:?}"))
+}
+
+// the BLS signatures as a `CryptoSuite`, so the attestor keys are generated and held as any other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlsSuite {
+    pub hash: HashAlgorithm,
+}
+
+impl BlsSuite {
+    // the proof of possession to register the public key of the secret key with
+    pub fn prove_possession(&self, secret_key: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let secret_key = self::secret_key(secret_key)?;
+        let public_key = secret_key.sk_to_pk().compress();
+        Ok(secret_key
+            .sign(&public_key, POSSESSION_DST, &[])
+            .compress()
+            .to_vec())
+    }
+
+    pub fn verify_possession(&self, public_key: &[u8], proof: &[u8]) -> anyhow::Result<()> {
+        let key = self::public_key(public_key)?;
+        check(signature(proof)?.verify(true, public_key, POSSESSION_DST, &[], &key, false))
+    }
+}
+
+impl CryptoSuite for BlsSuite {
+    fn name(&self) -> String {
+        format!("{}-bls", self.hash.name())
+    }
+
+    fn digest(&self, data: &[u8]) -> Digest {
+        self.hash.digest(data)
+    }
+
+    fn generate_key(&self) -> Vec<u8> {
+        let mut material = [0; 32];
+        rand::thread_rng().fill_bytes(&mut material);
+        SecretKey::key_gen(&material, &[])
+            .expect("enough key material")
+            .to_bytes()
+            .to_vec()
+    }
+
+    fn public_key(&self, secret_key: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(self::secret_key(secret_key)?.sk_to_pk().compress().to_vec())
+    }
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(self::secret_key(secret_key)?
+            .sign(message, SIGNATURE_DST, &[])
+            .compress()
+            .to_vec())
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        let key = self::public_key(public_key)?;
+        check(self::signature(signature)?.verify(true, message, SIGNATURE_DST, &[], &key, false))
+    }
+}
+
+// `signers` has a bit for every registered attestor, in the order of registration, the least
+// significant bit of the first byte for the first one
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct BlsClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    pub signers: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+fn signed_message(
+    hash: HashAlgorithm,
+    clock: &OrdinaryClock,
+    binding: &Binding,
+    output: &[u8],
+) -> Vec<u8> {
+    let mut message = clock.encode();
+    message.extend(binding.encode());
+    message.extend(hash.digest(output));
+    message
+}
+
+#[derive(Debug)]
+pub struct BlsClientContext<O> {
+    hash: HashAlgorithm,
+    attestors: Vec<PublicKey>,
+    threshold: usize,
+    _output: PhantomData<O>,
+}
+
+impl<O> BlsClientContext<O> {
+    // the attestors are registered as their public keys along with the proofs of possession, and a
+    // clock is accepted when at least `threshold` of them signed it
+    pub fn new(
+        hash: HashAlgorithm,
+        attestors: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+        threshold: usize,
+    ) -> anyhow::Result<Self> {
+        let suite = BlsSuite { hash };
+        let attestors = attestors
+            .into_iter()
+            .map(|(public_key, proof)| {
+                suite.verify_possession(&public_key, &proof)?;
+                self::public_key(&public_key)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // or a single attestor signing at both positions would count twice towards the threshold
+        for (i, attestor) in attestors.iter().enumerate() {
+            anyhow::ensure!(
+                !attestors[..i].contains(attestor),
+                "attestor {i} is registered more than once"
+            )
+        }
+        anyhow::ensure!(
+            (1..=attestors.len()).contains(&threshold),
+            "threshold {threshold} out of 1..={}",
+            attestors.len()
+        );
+        Ok(Self {
+            hash,
+            attestors,
+            threshold,
+            _output: PhantomData,
+        })
+    }
+
+    // the position of the attestor in the registered set
+    fn index(&self, public_key: &[u8]) -> anyhow::Result<usize> {
+        self.attestors
+            .iter()
+            .position(|attestor| attestor.compress() == public_key)
+            .ok_or(anyhow::format_err!("unregistered attestor"))
+    }
+
+    fn signers(&self, bitmap: &[u8]) -> anyhow::Result<Vec<&PublicKey>> {
+        anyhow::ensure!(
+            bitmap.len() == self.attestors.len().div_ceil(8),
+            "signer bitmap has {} bytes for {} attestors",
+            bitmap.len(),
+            self.attestors.len()
+        );
+        anyhow::ensure!(
+            (self.attestors.len()..bitmap.len() * 8).all(|index| !bit(bitmap, index)),
+            "signer bitmap has bits beyond the attestors"
+        );
+        let signers = (0..self.attestors.len())
+            .filter(|&index| bit(bitmap, index))
+            .map(|index| &self.attestors[index])
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            signers.len() >= self.threshold,
+            "clock is signed by {} attestors, expect at least {}",
+            signers.len(),
+            self.threshold
+        );
+        Ok(signers)
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &BlsClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        let signers = self.signers(&clock.signers)?;
+        // only the individual signatures are rejected for being the identity, see `aggregate`
+        let signature = Signature::sig_validate(&clock.signature, false)
+            .map_err(|err| anyhow::format_err!("invalid signature {err:?}"))?;
+        let message = signed_message(self.hash, &clock.clock, binding, output);
+        check(signature.fast_aggregate_verify(false, &message, SIGNATURE_DST, &signers))
+    }
+}
+
+fn bit(bitmap: &[u8], index: usize) -> bool {
+    bitmap[index / 8] & (1 << (index % 8)) != 0
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for BlsClientContext<O> {
+    type Clock = BlsClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+// the producer gathers the attestations of every attestor it can reach, which is usually a part of
+// the registered set, e.g. a worker attesting its own executions and a few watchers. an attestor
+// failing to sign, or signing something else, is left out, as long as the others meet the threshold
+#[derive(Debug, ClockClientContext)]
+pub struct BlsContext<I, O> {
+    id: NodeId,
+    // with the positions in the registered set
+    attestors: Vec<(usize, Arc<dyn Signer>)>,
+    #[client_context]
+    client: BlsClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> BlsContext<I, O> {
+    pub fn new(
+        id: NodeId,
+        attestors: Vec<Arc<dyn Signer>>,
+        client: BlsClientContext<O>,
+    ) -> anyhow::Result<Self> {
+        let attestors = attestors
+            .into_iter()
+            .map(|attestor| Ok((client.index(attestor.public_key())?, attestor)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // or its signature would be aggregated twice under a single bit, which never verifies
+        for (i, (index, _)) in attestors.iter().enumerate() {
+            anyhow::ensure!(
+                !attestors[..i].iter().any(|(other, _)| other == index),
+                "attestor {index} is given more than once"
+            )
+        }
+        Ok(Self {
+            id,
+            attestors,
+            client,
+            _input: PhantomData,
+        })
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> BlsContext<I, O> {
+    // the clock and the message the attestors sign
+    fn unsigned(
+        &self,
+        predecessors: &[Predecessor<'_, BlsClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<(OrdinaryClock, Vec<u8>), Error> {
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let message = signed_message(self.client.hash, &clock, binding, output.as_ref());
+        Ok((clock, message))
+    }
+}
+
+// the attestations in the order of `BlsContext::attestors`
+fn aggregate(
+    registered: &[PublicKey],
+    threshold: usize,
+    indexes: impl IntoIterator<Item = usize>,
+    clock: OrdinaryClock,
+    message: &[u8],
+    attestations: impl IntoIterator<Item = anyhow::Result<Vec<u8>>>,
+) -> anyhow::Result<BlsClock> {
+    let mut signers = vec![0; registered.len().div_ceil(8)];
+    let mut signatures = Vec::new();
+    for (index, attestation) in indexes.into_iter().zip(attestations) {
+        let attestation = attestation.and_then(|attestation| {
+            let signature = signature(&attestation)?;
+            let public_key = &registered[index];
+            check(signature.verify(false, message, SIGNATURE_DST, &[], public_key, false))?;
+            Ok(signature)
+        });
+        match attestation {
+            Ok(signature) => {
+                signers[index / 8] |= 1 << (index % 8);
+                signatures.push(signature)
+            }
+            Err(err) => warn!("leave out attestor {index}: {err}"),
+        }
+    }
+    anyhow::ensure!(
+        signatures.len() >= threshold,
+        "{} attestations, expect at least {threshold}",
+        signatures.len()
+    );
+    let signature = AggregateSignature::aggregate(&signatures.iter().collect::<Vec<_>>(), false)
+        .map_err(|err| anyhow::format_err!("failed to aggregate {err:?}"))?
+        .to_signature();
+    Ok(BlsClock {
+        clock,
+        signers,
+        signature: signature.compress().to_vec(),
+    })
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for BlsContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let (clock, message) = self.unsigned(predecessors, output, binding)?;
+        aggregate(
+            &self.client.attestors,
+            self.client.threshold,
+            self.attestors.iter().map(|(index, _)| *index),
+            clock,
+            &message,
+            self.attestors
+                .iter()
+                .map(|(_, attestor)| attestor.sign(&message)),
+        )
+        .map_err(Error::Proving)
+    }
+
+    // the attestors sign on blocking threads all at once, so the proving takes as long as the
+    // slowest of them rather than all of them
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unsigned = self.unsigned(predecessors, output, binding);
+        let attestors = self.attestors.clone();
+        let (registered, threshold) = (self.client.attestors.clone(), self.client.threshold);
+        async move {
+            let (clock, message) = unsigned?;
+            let message = Arc::<[u8]>::from(message);
+            let signing = attestors
+                .iter()
+                .map(|(_, attestor)| {
+                    let (attestor, message) = (attestor.clone(), message.clone());
+                    tokio::task::spawn_blocking(move || attestor.sign(&message))
+                })
+                .collect::<Vec<_>>();
+            let mut attestations = Vec::new();
+            for signing in signing {
+                attestations.push(signing.await.map_err(|err| Error::Proving(err.into()))?)
+            }
+            aggregate(
+                &registered,
+                threshold,
+                attestors.iter().map(|(index, _)| *index),
+                clock,
+                &message,
+                attestations,
+            )
+            .map_err(Error::Proving)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::LocalSigner;
+
+    // the public key along with its proof of possession
+    type Registration = (Vec<u8>, Vec<u8>);
+
+    // along with their registrations
+    fn attestors(n: usize) -> (Vec<Arc<dyn Signer>>, Vec<Registration>) {
+        let suite = BlsSuite::default();
+        (0..n)
+            .map(|_| {
+                let secret_key = suite.generate_key();
+                let registration = (
+                    suite.public_key(&secret_key).unwrap(),
+                    suite.prove_possession(&secret_key).unwrap(),
+                );
+                let signer = LocalSigner::new(Arc::new(suite), secret_key).unwrap();
+                (Arc::new(signer) as _, registration)
+            })
+            .unzip()
+    }
+
+    fn client(registrations: &[Registration], threshold: usize) -> BlsClientContext<Vec<u8>> {
+        BlsClientContext::new(
+            HashAlgorithm::default(),
+            registrations.iter().cloned(),
+            threshold,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies_threshold_of_attestors() {
+        let (signers, registered) = attestors(3);
+        let prove = |signers: &[Arc<dyn Signer>], output: &Vec<u8>, binding: &Binding| {
+            BlsContext::<Vec<u8>, _>::new(1, signers.to_vec(), client(&registered, 2))
+                .unwrap()
+                .prove(&[], output, binding)
+        };
+        let (output, binding) = (b"output".to_vec(), Binding::new(1, "stage"));
+        let clock = prove(&signers[..2], &output, &binding).unwrap();
+        client(&registered, 2)
+            .verify(&clock, &output, &binding)
+            .unwrap();
+        assert!(client(&registered, 3)
+            .verify(&clock, &output, &binding)
+            .is_err());
+        assert!(client(&registered, 2)
+            .verify(&clock, &b"other".to_vec(), &binding)
+            .is_err());
+        assert!(client(&registered, 2)
+            .verify(&clock, &output, &Binding::new(1, "other"))
+            .is_err());
+        // fewer attestors than the threshold cannot prove
+        assert!(prove(&signers[..1], &output, &binding).is_err());
+        // nor can a signer claim the bit of another attestor
+        let mut forged = clock.clone();
+        forged.signers = vec![0b101];
+        assert!(client(&registered, 2)
+            .verify(&forged, &output, &binding)
+            .is_err());
+    }
+
+    #[test]
+    fn refuses_duplicate_attestors() {
+        let (signers, registered) = attestors(2);
+        let twice = [registered[0].clone(), registered[0].clone()];
+        assert!(BlsClientContext::<Vec<u8>>::new(HashAlgorithm::default(), twice, 1).is_err());
+        let twice = vec![signers[0].clone(), signers[0].clone()];
+        assert!(BlsContext::<Vec<u8>, _>::new(1, twice, client(&registered, 1)).is_err());
+    }
+}
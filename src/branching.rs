@@ -0,0 +1,246 @@
+// conditional branches of a workflow: a branching stage decides which one of two of its downstream
+// stages executes upon its output, by a predicate over the output, e.g. a classifying stage
+// sending the images of cats one way and everything else the other way
+// the predicate holds when a value of the output equals the expected one, where the value is the
+// one at a json pointer into the output decoded as json, or the whole output, decoded as json or
+// else taken as its text without the surrounding whitespace. the branch not taken is skipped
+// along with every stage executing upon skipped stages only, and a stage joining several branches
+// executes upon the taken ones (see `Workflow::taken`)
+// the decision is recorded in the message as the branch of the stage: the worker of the branching
+// stage makes it on its raw output, the hub checks it against the payload, and the verification
+// walks the clocks of the taken branches only, refusing the clocks of the stages skipped. as the
+// routes, the decision is carried along by every message downstream of the branching stage
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "network")]
+use crate::StageSource;
+use crate::{Error, Workflow};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    // the json pointer of the value, e.g. `/label`. the whole output if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub equals: Value,
+    // the stage executing when the value equals, and the one executing otherwise
+    pub then: String,
+    #[serde(rename = "else")]
+    pub otherwise: String,
+}
+
+impl Branch {
+    pub fn targets(&self) -> [&str; 2] {
+        [&self.then, &self.otherwise]
+    }
+
+    pub fn validate(&self, stage: &str, task: &Workflow) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(key) = &self.key {
+            if !key.is_empty() && !key.starts_with('/') {
+                problems.push(format!(
+                    "branch key {key:?} of stage {stage} is not a json pointer"
+                ))
+            }
+        }
+        if self.then == self.otherwise {
+            problems.push(format!("both branches of stage {stage} take {}", self.then))
+        }
+        for target in self.targets() {
+            // the target executes upon the branching stage alone, so skipping it skips nothing
+            // else that is taken
+            if task.upstream(target) != Some(vec![stage]) {
+                problems.push(format!(
+                    "branch {target} of stage {stage} does not execute upon stage {stage} alone"
+                ))
+            }
+            if task.stages.last().is_some_and(|last| last == target) {
+                problems.push(format!(
+                    "branch {target} of stage {stage} is the last stage, which always executes"
+                ))
+            }
+        }
+        problems
+    }
+
+    // the stage taken upon the output, which must be reassembled and decompressed
+    pub fn decide(&self, output: &[u8]) -> anyhow::Result<&str> {
+        Ok(if value(self.key.as_deref(), output)? == self.equals {
+            &self.then
+        } else {
+            &self.otherwise
+        })
+    }
+}
+
+// the value of the output a predicate compares, see above
+pub(crate) fn value(key: Option<&str>, output: &[u8]) -> anyhow::Result<Value> {
+    Ok(match key {
+        None => serde_json::from_slice::<Value>(output)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(output).trim().to_string())),
+        Some(pointer) => serde_json::from_slice::<Value>(output)
+            .map_err(|err| anyhow::format_err!("output is not json: {err}"))?
+            .pointer(pointer)
+            .ok_or(anyhow::format_err!("output has no value at {pointer}"))?
+            .clone(),
+    })
+}
+
+// every executed branching stage must have decided one of its branches, and no stage skipped by
+// the decisions may be executed
+pub(crate) fn verify_branches<C>(
+    clocks: &HashMap<String, C>,
+    branches: &HashMap<String, String>,
+    task: &Workflow,
+) -> Result<(), Error> {
+    for (stage, taken) in branches {
+        let branch = task
+            .branching
+            .get(stage)
+            .ok_or(Error::WorkflowMismatch(format!(
+                "branch of stage {stage}, which does not branch"
+            )))?;
+        if !branch.targets().contains(&taken.as_str()) {
+            return Err(Error::WorkflowMismatch(format!(
+                "stage {stage} takes {taken}, which is not one of its branches"
+            )));
+        }
+        if !clocks.contains_key(stage) {
+            return Err(Error::WorkflowMismatch(format!(
+                "branch of stage {stage}, which is not executed"
+            )));
+        }
+    }
+    for stage in task.branching.keys() {
+        if clocks.contains_key(stage) && !branches.contains_key(stage) {
+            return Err(Error::WorkflowMismatch(format!(
+                "missing branch of stage {stage}"
+            )));
+        }
+    }
+    if let Some(stage) = task
+        .skipped(branches)
+        .into_iter()
+        .find(|stage| clocks.contains_key(*stage))
+    {
+        return Err(Error::WorkflowMismatch(format!(
+            "stage {stage} is executed on a branch not taken"
+        )));
+    }
+    Ok(())
+}
+
+// the branch of the stage the message is from must be the decision on its payload
+#[cfg(feature = "network")]
+pub(crate) fn check_branch(
+    source: &StageSource,
+    branches: &HashMap<String, String>,
+    payload: &[u8],
+    task: &Workflow,
+) -> Result<(), Error> {
+    let StageSource::Name(stage) = source else {
+        return Ok(());
+    };
+    let Some(branch) = task.branching.get(stage) else {
+        return Ok(());
+    };
+    let decided = branch
+        .decide(payload)
+        .map_err(|err| Error::WorkflowMismatch(err.to_string()))?;
+    match branches.get(stage) {
+        Some(taken) if taken == decided => Ok(()),
+        Some(taken) => Err(Error::WorkflowMismatch(format!(
+            "stage {stage} takes {taken} instead of {decided}"
+        ))),
+        None => Err(Error::WorkflowMismatch(format!(
+            "missing branch of stage {stage}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task() -> Workflow {
+        serde_json::from_str(
+            r#"{
+                "stages": ["classify", "cats", "dogs", "report"],
+                "depends": {"cats": ["classify"], "dogs": ["classify"], "report": ["cats", "dogs"]},
+                "branching": {
+                    "classify": {"key": "/label", "equals": "cat", "then": "cats", "else": "dogs"}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn clocks(stages: &[&str]) -> HashMap<String, ()> {
+        stages.iter().map(|stage| (stage.to_string(), ())).collect()
+    }
+
+    fn branches(taken: &str) -> HashMap<String, String> {
+        HashMap::from([("classify".into(), taken.into())])
+    }
+
+    #[test]
+    fn decides_on_the_output() {
+        let task = task();
+        let branch = &task.branching["classify"];
+        assert_eq!(branch.decide(br#"{"label": "cat"}"#).unwrap(), "cats");
+        assert_eq!(branch.decide(br#"{"label": "dog"}"#).unwrap(), "dogs");
+        assert!(branch.decide(br#"{"kind": "cat"}"#).is_err());
+        assert!(branch.decide(b"cat").is_err());
+        let whole = Branch {
+            key: None,
+            ..branch.clone()
+        };
+        assert_eq!(whole.decide(b" cat\n").unwrap(), "cats");
+        assert_eq!(whole.decide(br#""cat""#).unwrap(), "cats");
+        assert_eq!(whole.decide(b"dog").unwrap(), "dogs")
+    }
+
+    #[test]
+    fn verifies_the_taken_branch() {
+        let task = task();
+        let taken = clocks(&["classify", "cats", "report"]);
+        verify_branches(&taken, &branches("cats"), &task).unwrap();
+        let taken = clocks(&["classify", "dogs", "report"]);
+        verify_branches(&taken, &branches("dogs"), &task).unwrap();
+        // not executed that far yet
+        verify_branches(&clocks(&["classify"]), &branches("cats"), &task).unwrap();
+        assert_eq!(
+            task.skipped(&branches("cats")),
+            ["dogs"].into_iter().collect()
+        )
+    }
+
+    #[test]
+    fn refuses_the_untaken_branch() {
+        let task = task();
+        let executed = clocks(&["classify", "dogs", "report"]);
+        assert!(verify_branches(&executed, &branches("cats"), &task).is_err());
+        let both = clocks(&["classify", "cats", "dogs", "report"]);
+        assert!(verify_branches(&both, &branches("cats"), &task).is_err());
+        assert!(verify_branches(&executed, &HashMap::new(), &task).is_err());
+        assert!(verify_branches(&executed, &branches("report"), &task).is_err());
+        let unexecuted = HashMap::from([("cats".into(), "report".into())]);
+        assert!(verify_branches(&clocks(&["classify"]), &unexecuted, &task).is_err());
+        assert!(verify_branches(&clocks(&[]), &branches("cats"), &task).is_err())
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn checks_the_branch_of_the_payload() {
+        let task = task();
+        let source = StageSource::Name("classify".into());
+        let payload = br#"{"label": "cat"}"#;
+        check_branch(&source, &branches("cats"), payload, &task).unwrap();
+        assert!(check_branch(&source, &branches("dogs"), payload, &task).is_err());
+        assert!(check_branch(&source, &HashMap::new(), payload, &task).is_err());
+        let source = StageSource::Name("cats".into());
+        check_branch(&source, &branches("cats"), payload, &task).unwrap()
+    }
+}
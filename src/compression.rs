@@ -0,0 +1,69 @@
+// compression of the stage payloads in transit, for the pipelines whose payloads are large and
+// compress well, e.g. text
+// a compressed payload is marked by the `compression` of its message, and is compressed before it
+// is offloaded, so the inline size limit and the blob store apply to the compressed payload. but
+// beyond the transit everything is about the decompressed payload: the clocks prove it, the hub
+// verifies, records and indexes it, and the receivers decompress it (after reassembling) before
+// anything else
+// a peer unaware of the field would take a compressed payload as is, so a worker only compresses
+// when the operator enables it, which should be done once every peer of the deployment handles
+// compressed payloads, and only if the hub advertises the compression in the handshake
+// zstd is only built with the `compression` feature. without it the field is still understood, but
+// nothing is advertised, compressed or decompressed
+// a few bytes of zstd may decompress to gigabytes, so the decompressed payload is bounded as an
+// offloaded one is (see `blob::DEFAULT_MAX_BLOB_SIZE`)
+#[cfg(feature = "compression")]
+use std::io::Read as _;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Zstd,
+}
+
+#[cfg(feature = "compression")]
+pub const SUPPORTED: &[Compression] = &[Compression::Zstd];
+#[cfg(not(feature = "compression"))]
+pub const SUPPORTED: &[Compression] = &[];
+
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub fn compress(payload: &[u8], compression: Compression) -> anyhow::Result<Bytes> {
+    match compression {
+        #[cfg(feature = "compression")]
+        Compression::Zstd => Ok(zstd::bulk::compress(payload, ZSTD_LEVEL)?.into()),
+        #[cfg(not(feature = "compression"))]
+        Compression::Zstd => anyhow::bail!("zstd needs the compression feature"),
+    }
+}
+
+// of at most `max_size` bytes decompressed
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub fn decompress(
+    payload: Bytes,
+    compression: Option<Compression>,
+    max_size: u64,
+) -> anyhow::Result<Bytes> {
+    match compression {
+        None => Ok(payload),
+        #[cfg(feature = "compression")]
+        Some(Compression::Zstd) => {
+            let mut decompressed = Vec::new();
+            zstd::stream::read::Decoder::new(&*payload)?
+                .take(max_size.saturating_add(1))
+                .read_to_end(&mut decompressed)?;
+            anyhow::ensure!(
+                decompressed.len() as u64 <= max_size,
+                "payload decompresses beyond the limit of {max_size} bytes"
+            );
+            Ok(decompressed.into())
+        }
+        #[cfg(not(feature = "compression"))]
+        Some(Compression::Zstd) => anyhow::bail!("zstd needs the compression feature"),
+    }
+}
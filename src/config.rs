@@ -0,0 +1,349 @@
+// the settings of the `network`, `compute` and `client` binaries, each layered over the one before
+// * the defaults
+// * the section of the binary (`hub`, `worker` or `client`) in the json file at `--config` or
+//   `POHB_CONFIG`, e.g. `{"hub": {"retention": 86400}, "worker": {"hub": "http://hub:3000"}}`,
+//   so one file can configure a whole deployment
+// * the environment variables, `POHB_<KEY>` for every key e.g. `POHB_RETENTION`
+// * the command line, `--<key> <value>` (with dashes or underscores, and a flag without a value is
+//   true), along with the positional arguments of each binary
+// the environment and the command line only carry strings, so a value is taken as json if that
+// fits the key (e.g. a number), then as a plain string, then as a comma separated list (of strings
+// or of json values e.g. numbers) or `<key>=<value>` pairs, e.g. `POHB_TASK_LABELS=team=nlp,draft`.
+// a key that is not of the binary is ignored, so every binary of a deployment can share the same
+// environment
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    blob::{DEFAULT_MAX_BLOB_SIZE, DEFAULT_MAX_INLINE_SIZE},
+    compression::Compression,
+    crypto::{self, CryptoSuite},
+    hub::{GossipVerification, HubId, PublishAck},
+    worker, ClockLimits, Membership, NodeId, OversizePolicy, Priority,
+};
+
+const ENV_PREFIX: &str = "POHB_";
+
+// the settings shared by the hub and the workers, which must agree on them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommonConfig {
+    // e.g. `blake3-secp256k1`, or a post-quantum suite with the `pq` feature, see `crypto`
+    pub crypto_suite: Option<String>,
+    pub max_inline_size: usize,
+    pub max_blob_size: u64,
+    pub max_clock_entries: usize,
+    pub max_clocks: usize,
+    pub clock_oversize: OversizePolicy,
+    // the node ids of the retired workers, e.g. `POHB_RETIRED_NODES=7,12`, see `Membership`
+    pub retired_nodes: BTreeSet<NodeId>,
+}
+
+impl Default for CommonConfig {
+    fn default() -> Self {
+        let limits = ClockLimits::default();
+        Self {
+            crypto_suite: None,
+            max_inline_size: DEFAULT_MAX_INLINE_SIZE,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            max_clock_entries: limits.max_entries,
+            max_clocks: limits.max_clocks,
+            clock_oversize: limits.oversize,
+            retired_nodes: Default::default(),
+        }
+    }
+}
+
+impl CommonConfig {
+    pub fn crypto(&self) -> anyhow::Result<Arc<dyn CryptoSuite>> {
+        match &self.crypto_suite {
+            None => Ok(Arc::new(crypto::StandardSuite::default())),
+            Some(name) => crypto::suite(name),
+        }
+    }
+
+    pub fn clock_limits(&self) -> ClockLimits {
+        ClockLimits {
+            max_entries: self.max_clock_entries,
+            max_clocks: self.max_clocks,
+            oversize: self.clock_oversize,
+        }
+    }
+
+    pub fn membership(&self) -> Membership {
+        Membership {
+            retired: self.retired_nodes.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HubConfig {
+    // the task description, which is watched for changes
+    pub workflow: Option<PathBuf>,
+    // runs as a member of a raft group, standalone otherwise
+    pub hub_id: Option<HubId>,
+    // the base url of the hub to follow as its read-only mirror instead, whose current workflow is
+    // taken without a task description, see `hub::mirror`
+    pub mirror: Option<String>,
+    pub listen: String,
+    // kept in memory otherwise
+    pub blob_dir: Option<PathBuf>,
+    // the scripts to re-execute for deciding challenges, laid out as for the workers
+    pub reexecute_scripts: Option<PathBuf>,
+    // or the modules, with the `wasm` feature
+    pub reexecute_wasm: Option<PathBuf>,
+    // the workflows served alongside, which are not watched
+    pub other_workflows: Vec<PathBuf>,
+    // in seconds, the tasks are kept forever without a retention
+    pub retention: Option<u64>,
+    // in seconds, the bodies of the results are kept until their tasks expire without a window
+    pub prune_after: Option<u64>,
+    // in seconds, the garbage is only collected on `POST /admin/gc` without an interval
+    pub gc_interval: Option<u64>,
+    // in seconds, the attribution is not rolled up into epochs without a length
+    pub epoch_length: Option<u64>,
+    // the gossip messages per second beyond which new tasks are shed by their priorities
+    pub max_publish_rate: Option<u32>,
+    // the same of every workflow on its own
+    pub max_partition_rate: Option<u32>,
+    // the fraction of the accepted stages sampled for the auditors
+    pub audit_rate: Option<f64>,
+    // the secret key file the notarizations are signed with, or a key generated on start, which
+    // the auditors can only trust for the lifetime of the process
+    pub hub_key: Option<PathBuf>,
+    // the secrets served to the workers of the stages, laid out as `secrets::DirSecrets`
+    pub secrets_dir: Option<PathBuf>,
+    // where the gossip messages are verified, see `hub::GossipVerification`
+    pub gossip_verification: GossipVerification,
+    // the size of the pool the verification is offloaded to
+    pub verifier_threads: Option<usize>,
+    #[serde(flatten)]
+    pub common: CommonConfig,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            workflow: None,
+            hub_id: None,
+            mirror: None,
+            listen: "0.0.0.0:3000".into(),
+            blob_dir: None,
+            reexecute_scripts: None,
+            reexecute_wasm: None,
+            other_workflows: Default::default(),
+            retention: None,
+            prune_after: None,
+            gc_interval: None,
+            epoch_length: None,
+            max_publish_rate: None,
+            max_partition_rate: None,
+            audit_rate: None,
+            hub_key: None,
+            secrets_dir: None,
+            gossip_verification: Default::default(),
+            verifier_threads: None,
+            common: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WorkerConfig {
+    // the base url, or the comma separated base urls of the members of a raft group to fail over
+    // between, see `transport::HttpTransport`
+    pub hub: String,
+    // fetched from the hub if not set
+    pub workflow: Option<PathBuf>,
+    pub stage: Option<String>,
+    // where the stage scripts are, as `<stage>` and `<stage>.canary`
+    pub scripts: PathBuf,
+    // where the stage modules are instead, with the `wasm` feature
+    pub wasm_modules: Option<PathBuf>,
+    // matched against the affinity of the workflow
+    pub worker_labels: BTreeSet<String>,
+    // the most clocks to prove at once
+    pub proof_batch: usize,
+    pub compression: Option<Compression>,
+    // publish the clocks as deltas against the message executed upon, see `delta`
+    pub clock_deltas: bool,
+    // the shard of the stage to serve, if the workflow routes it
+    pub shard: Option<u32>,
+    // the node id to execute as, random if not set. a worker claiming its contributions keeps it
+    // across restarts, see `notary::ContributionProof`
+    pub node_id: Option<NodeId>,
+    // `persisted` to wait for the hub to keep every published output, or `none` to only wait for
+    // its checks
+    pub publish_ack: PublishAck,
+    // of a retryable failure of the stage program, see `StageOutcome`
+    pub stage_retries: u32,
+    // how many seconds the messages of a joining stage are held for while some are missing, for a
+    // task without a deadline
+    pub join_timeout: u64,
+    // where the messages of the tasks with an invalid input are written, only reported otherwise
+    pub dead_letter_dir: Option<PathBuf>,
+    // the secret key file the worker asks the hub for the secrets of its stage with, which are
+    // not fetched without it
+    pub worker_key: Option<PathBuf>,
+    #[serde(flatten)]
+    pub common: CommonConfig,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            hub: "http://localhost:3000".into(),
+            workflow: None,
+            stage: None,
+            scripts: "scripts".into(),
+            wasm_modules: None,
+            worker_labels: Default::default(),
+            proof_batch: 1,
+            compression: None,
+            clock_deltas: false,
+            shard: None,
+            node_id: None,
+            publish_ack: Default::default(),
+            stage_retries: worker::DEFAULT_RETRIES,
+            join_timeout: worker::DEFAULT_JOIN_TIMEOUT.as_secs(),
+            dead_letter_dir: None,
+            worker_key: None,
+            common: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    // the base url, or the comma separated base urls of the members of a raft group to fail over
+    // between, see `transport::HttpTransport`
+    pub hub: String,
+    pub input: String,
+    // the task is a streaming one of this many chunks, an ordinary one otherwise
+    pub chunks: Option<u64>,
+    pub accept_cached: bool,
+    pub submitter: Option<String>,
+    pub task_labels: BTreeMap<String, String>,
+    pub task_priority: Priority,
+    // in seconds from the submission
+    pub task_deadline: Option<u64>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            hub: "http://localhost:3000".into(),
+            input: "hello".into(),
+            chunks: None,
+            accept_cached: false,
+            submitter: None,
+            task_labels: Default::default(),
+            task_priority: Default::default(),
+            task_deadline: None,
+        }
+    }
+}
+
+// the settings of the `section`, from the file, the environment and the command line of the
+// process. the positional arguments are taken as the `positional` keys in order
+pub fn load<T: DeserializeOwned>(section: &str, positional: &[&str]) -> anyhow::Result<T> {
+    let (file, flags) = parse_args(env::args().skip(1), positional)?;
+    let mut settings = Map::new();
+    if let Some(path) = file.or_else(|| env::var("POHB_CONFIG").ok()) {
+        let mut file = serde_json::from_str::<Map<String, Value>>(&std::fs::read_to_string(path)?)?;
+        match file.remove(section) {
+            Some(Value::Object(section)) => settings.extend(section),
+            Some(_) => anyhow::bail!("section {section} of the configuration is not an object"),
+            None => {}
+        }
+    }
+    let vars = env::vars().filter_map(|(name, value)| {
+        let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+        (key != "config").then_some((key, value))
+    });
+    for (key, value) in vars.chain(flags) {
+        let value = coerce::<T>(&key, &value)?;
+        settings.insert(key, value);
+    }
+    serde_json::from_value(Value::Object(settings))
+        .map_err(|err| anyhow::format_err!("invalid configuration of {section}: {err}"))
+}
+
+// the configuration file, and the other flags along with the positional arguments by their keys
+#[allow(clippy::type_complexity)]
+fn parse_args(
+    args: impl IntoIterator<Item = String>,
+    positional: &[&str],
+) -> anyhow::Result<(Option<String>, Vec<(String, String)>)> {
+    let mut file = None;
+    let mut flags = Vec::new();
+    let mut positional = positional.iter();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            let key = positional
+                .next()
+                .ok_or(anyhow::format_err!("unexpected argument {arg}"))?;
+            flags.push((key.to_string(), arg));
+            continue;
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key, value.to_string()),
+            None => match args.next_if(|next| !next.starts_with("--")) {
+                Some(value) => (flag, value),
+                None => (flag, "true".into()),
+            },
+        };
+        let key = key.replace('-', "_");
+        if key == "config" {
+            file = Some(value)
+        } else {
+            flags.push((key, value))
+        }
+    }
+    Ok((file, flags))
+}
+
+// the first reading of the string that the key of `T` takes, any reading if `T` has no such key
+fn coerce<T: DeserializeOwned>(key: &str, value: &str) -> anyhow::Result<Value> {
+    let items = || value.split(',').filter(|item| !item.is_empty());
+    let candidates = [
+        serde_json::from_str(value).ok(),
+        Some(Value::String(value.into())),
+        Some(items().map(|item| Value::String(item.into())).collect()),
+        Some(
+            items()
+                .map(|item| serde_json::from_str(item).unwrap_or(Value::String(item.into())))
+                .collect(),
+        ),
+        Some(Value::Object(
+            items()
+                .map(|item| {
+                    let (key, value) = item.split_once('=').unwrap_or((item, ""));
+                    (key.into(), Value::String(value.into()))
+                })
+                .collect(),
+        )),
+        // a flag set to anything but false, e.g. `POHB_ACCEPT_CACHED=1`
+        Some(Value::Bool(!matches!(value, "false" | "0"))),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .find(|candidate| {
+            let probe = Map::from_iter([(key.to_string(), candidate.clone())]);
+            serde_json::from_value::<T>(Value::Object(probe)).is_ok()
+        })
+        .ok_or(anyhow::format_err!("invalid value of {key}: {value}"))
+}
@@ -0,0 +1,117 @@
+// checking the kept gossip log as a whole against the invariants of an honest deployment, which
+// verifying every message on its own does not catch
+// * no clock regression: a node advances its clock of a stage of a task with every execution, so
+//   a clock that is before or equal to an earlier one of the same node and stage means the node
+//   lost or reused its state. the clocks are only compared per stage, since a node executing the
+//   consecutive stages of a streaming task may produce equal clocks for different stages
+// * no equivocation: two outputs of the same stage and chunk of a task under comparable clocks
+//   must be the same. concurrent ones are independent executions, e.g. by two unscheduled
+//   workers, and differently compressed ones cannot be told apart by their digests
+// * no orphan: every stage follows an event of the previous stage or the start of the task, every
+//   challenge follows a result, and every expiry follows its last completed stage, as told by
+//   `history::predecessors`
+// the log is the history the hub keeps of every task (see `history`), so the analysis runs offline
+// against the blob store of a hub, see the `consistency` binary
+use serde::Serialize;
+
+use crate::{
+    history::{predecessors, EventKind, HistoryEvent},
+    ClockOrdering, CompareClock as _, TaskId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    ClockRegression,
+    Equivocation,
+    Orphan,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub task: TaskId,
+    pub kind: ViolationKind,
+    pub message: String,
+    // the indexes of the offending events in the history
+    pub events: Vec<usize>,
+}
+
+fn chunk_seq(event: &HistoryEvent) -> Option<u64> {
+    event.chunk.map(|chunk| chunk.seq)
+}
+
+// the events of one task, in the order they are applied
+pub fn check(task: TaskId, events: &[HistoryEvent]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut violation = |kind, message, events| {
+        violations.push(Violation {
+            task,
+            kind,
+            message,
+            events,
+        })
+    };
+    for (index, event) in events.iter().enumerate() {
+        let (Some(stage), Some(clock)) = (&event.stage, event.clock()) else {
+            continue;
+        };
+        for (earlier_index, earlier) in events[..index].iter().enumerate() {
+            if earlier.stage.as_ref() != Some(stage) {
+                continue;
+            }
+            let Some(earlier_clock) = earlier.clock() else {
+                continue;
+            };
+            let ordering = clock.compare(earlier_clock);
+            if let (Some(producer), true) = (
+                event.producer,
+                matches!(ordering, ClockOrdering::Before | ClockOrdering::Equal),
+            ) {
+                if earlier.producer == Some(producer) {
+                    violation(
+                        ViolationKind::ClockRegression,
+                        format!(
+                            "clock of node {producer:08x} at stage {stage} is {ordering} its \
+                            earlier clock"
+                        ),
+                        vec![earlier_index, index],
+                    )
+                }
+            }
+            if ordering != ClockOrdering::Concurrent
+                && chunk_seq(earlier) == chunk_seq(event)
+                && earlier.compression == event.compression
+                && earlier.payload.is_some()
+                && event.payload.is_some()
+                && earlier.payload != event.payload
+            {
+                violation(
+                    ViolationKind::Equivocation,
+                    format!(
+                        "different outputs of stage {stage} under a clock {ordering} the earlier \
+                        clock"
+                    ),
+                    vec![earlier_index, index],
+                )
+            }
+        }
+    }
+    for (index, event) in events.iter().enumerate() {
+        let what = match (event.kind, &event.stage) {
+            (EventKind::Gossip, None) => continue,
+            (EventKind::Gossip | EventKind::Result, Some(stage)) => format!("stage {stage}"),
+            (EventKind::Result, None) => "result".to_string(),
+            (EventKind::Challenge, _) => "challenge".to_string(),
+            (EventKind::Expiry, _) => "expiry".to_string(),
+            (EventKind::Migration, _) => "migration".to_string(),
+        };
+        if predecessors(events, index).is_empty() {
+            violation(
+                ViolationKind::Orphan,
+                format!("{what} follows no event of the task"),
+                vec![index],
+            )
+        }
+    }
+    violations
+}
@@ -0,0 +1,182 @@
+// the digest and signature algorithms, abstracted so a deployment can match the primitives of the
+// chain it integrates with
+// everything that is hashed or signed (workflow and program versions, proof parts of clocks,
+// addresses of stored payloads, blocks) should go through a `CryptoSuite` instead of naming an
+// algorithm directly. all parties of a deployment must agree on the suite, which is selected with
+// the `POHB_CRYPTO_SUITE` environment variable e.g. `blake3-secp256k1` (default `sha256-ed25519`)
+// keys and signatures are passed around as opaque bytes, so the trait stays object safe and a
+// deployment can plug in its own suite
+use std::{env, fmt::Debug, str::FromStr, sync::Arc};
+
+use ed25519_dalek::{Signer as _, Verifier as _};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+// all supported digest algorithms produce 32 bytes, which keeps digests fixed size on the wire
+// regardless of the selected suite
+pub type Digest = [u8; 32];
+
+pub trait CryptoSuite: Debug + Send + Sync {
+    fn name(&self) -> String;
+
+    fn digest(&self, data: &[u8]) -> Digest;
+
+    fn generate_key(&self) -> Vec<u8>;
+
+    fn public_key(&self, secret_key: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Digest {
+        match self {
+            Self::Sha256 => Sha256::digest(data).into(),
+            Self::Blake3 => blake3::hash(data).into(),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => anyhow::bail!("unknown hash algorithm {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StandardSuite {
+    pub hash: HashAlgorithm,
+    pub signature: SignatureAlgorithm,
+}
+
+// the suite selected for this deployment by `POHB_CRYPTO_SUITE`, for the binaries outside `config`
+pub fn from_env() -> anyhow::Result<Arc<dyn CryptoSuite>> {
+    match env::var("POHB_CRYPTO_SUITE") {
+        Ok(name) => suite(&name),
+        Err(env::VarError::NotPresent) => Ok(Arc::new(StandardSuite::default())),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// e.g. `blake3-ed25519`, while post-quantum suites e.g. `sha256-mldsa65` are only available with
+// the `pq` feature
+pub fn suite(name: &str) -> anyhow::Result<Arc<dyn CryptoSuite>> {
+    #[cfg(feature = "pq")]
+    if let Ok(suite) = name.parse::<crate::pq::PqSuite>() {
+        return Ok(Arc::new(suite));
+    }
+    Ok(Arc::new(name.parse::<StandardSuite>()?))
+}
+
+impl FromStr for StandardSuite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, signature) = s
+            .split_once('-')
+            .ok_or(anyhow::format_err!("expect <hash>-<signature>, got {s}"))?;
+        let hash = hash.parse()?;
+        let signature = match signature {
+            "ed25519" => SignatureAlgorithm::Ed25519,
+            "secp256k1" => SignatureAlgorithm::Secp256k1,
+            _ => anyhow::bail!("unknown signature algorithm {signature}"),
+        };
+        Ok(Self { hash, signature })
+    }
+}
+
+impl CryptoSuite for StandardSuite {
+    fn name(&self) -> String {
+        let hash = self.hash.name();
+        let signature = match self.signature {
+            SignatureAlgorithm::Ed25519 => "ed25519",
+            SignatureAlgorithm::Secp256k1 => "secp256k1",
+        };
+        format!("{hash}-{signature}")
+    }
+
+    fn digest(&self, data: &[u8]) -> Digest {
+        self.hash.digest(data)
+    }
+
+    fn generate_key(&self) -> Vec<u8> {
+        match self.signature {
+            SignatureAlgorithm::Ed25519 => ed25519_dalek::SigningKey::generate(&mut OsRng)
+                .to_bytes()
+                .to_vec(),
+            SignatureAlgorithm::Secp256k1 => k256::ecdsa::SigningKey::random(&mut OsRng)
+                .to_bytes()
+                .to_vec(),
+        }
+    }
+
+    fn public_key(&self, secret_key: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(match self.signature {
+            SignatureAlgorithm::Ed25519 => ed25519_dalek::SigningKey::try_from(secret_key)?
+                .verifying_key()
+                .to_bytes()
+                .to_vec(),
+            SignatureAlgorithm::Secp256k1 => k256::ecdsa::SigningKey::from_slice(secret_key)?
+                .verifying_key()
+                .to_sec1_bytes()
+                .to_vec(),
+        })
+    }
+
+    // secp256k1 signs the prehash of the message with sha256 as the ecdsa convention, which is
+    // independent of the selected digest algorithm
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(match self.signature {
+            SignatureAlgorithm::Ed25519 => ed25519_dalek::SigningKey::try_from(secret_key)?
+                .sign(message)
+                .to_vec(),
+            SignatureAlgorithm::Secp256k1 => {
+                let signature: k256::ecdsa::Signature =
+                    k256::ecdsa::SigningKey::from_slice(secret_key)?.sign(message);
+                signature.to_vec()
+            }
+        })
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        match self.signature {
+            SignatureAlgorithm::Ed25519 => ed25519_dalek::VerifyingKey::try_from(public_key)?
+                .verify(message, &ed25519_dalek::Signature::from_slice(signature)?)?,
+            SignatureAlgorithm::Secp256k1 => {
+                k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)?
+                    .verify(message, &k256::ecdsa::Signature::from_slice(signature)?)?
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+// delta-encoded clocks on the gossip path: the clocks of a stage message are the clocks of the
+// message it executed upon along with its own, so for long pipelines most of every message is a
+// copy of the message before. a worker may publish only the clocks that are new or changed against
+// that message, its base, along with a `ClockDelta` naming the base, and the hub reconstructs the
+// full clocks from the base it keeps (see `TaskStage::apply_delta`) before anything else looks at
+// them, so the subscribers and the history only ever see full messages
+// the delta carries a digest of the clocks of the base, so a base the hub has superseded since,
+// e.g. by a racing worker or a later chunk, is refused rather than reconstructed into clocks the
+// worker never proved upon. the worker then publishes the full clocks instead. the digest is of
+// the causality parts (see `attribution::Causality`), which the clocks without any, e.g. the
+// scalar ones, leave to the verification, and fixed rather than of the crypto suite, so every node
+// agrees on it
+// the delta is an optional field that a peer must not ignore, so a worker only publishes deltas
+// once enabled by the operator and advertised by the hub in the `Handshake`, and never for the
+// joins, whose messages have several bases
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::{attribution::Causality, crypto::Digest, StageSource, TaskStage};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockDelta {
+    // the source of the message executed upon, whose clocks are the base
+    pub base: StageSource,
+    // of the clocks of the base, see `digest`
+    pub digest: Digest,
+    // the clocks of the base that are not carried on
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub dropped: BTreeSet<String>,
+}
+
+// of the clocks by stage, in the order of the stages
+pub fn digest<C: Causality>(clocks: &HashMap<String, C>) -> Digest {
+    let mut hasher = Sha256::new();
+    for (stage, clock) in clocks.iter().collect::<BTreeMap<_, _>>() {
+        hasher.update((stage.len() as u32).to_be_bytes());
+        hasher.update(stage);
+        hasher.update(clock.causality().digest())
+    }
+    hasher.finalize().into()
+}
+
+impl ClockDelta {
+    // the delta of `clocks` against the clocks of the base, along with the clocks it carries
+    pub fn new<C: Causality + PartialEq + Clone>(
+        base: StageSource,
+        base_clocks: &HashMap<String, C>,
+        clocks: &HashMap<String, C>,
+    ) -> (Self, HashMap<String, C>) {
+        let changed = clocks
+            .iter()
+            .filter(|(stage, clock)| base_clocks.get(*stage) != Some(*clock))
+            .map(|(stage, clock)| (stage.clone(), clock.clone()))
+            .collect();
+        let delta = Self {
+            base,
+            digest: digest(base_clocks),
+            dropped: base_clocks
+                .keys()
+                .filter(|stage| !clocks.contains_key(*stage))
+                .cloned()
+                .collect(),
+        };
+        (delta, changed)
+    }
+
+    pub fn validate<C: Causality>(&self, base_clocks: &HashMap<String, C>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.digest == digest(base_clocks),
+            "clocks of the base {:?} are not the ones the delta is against",
+            self.base
+        );
+        if let Some(stage) = self
+            .dropped
+            .iter()
+            .find(|stage| !base_clocks.contains_key(*stage))
+        {
+            anyhow::bail!("dropped clock of stage {stage} is not in the base")
+        }
+        Ok(())
+    }
+
+    // the full clocks, of the base patched with `changed`
+    pub fn apply<C: Causality + Clone>(
+        &self,
+        base_clocks: &HashMap<String, C>,
+        changed: HashMap<String, C>,
+    ) -> anyhow::Result<HashMap<String, C>> {
+        self.validate(base_clocks)?;
+        // a clock only ever advances from one message to the next, so a changed clock lowering
+        // any entry of the one of the base is not a clock proven upon it
+        if let Some(stage) = changed.iter().find_map(|(stage, clock)| {
+            let base = base_clocks.get(stage)?;
+            (!clock.causality().dominates(base.causality())).then_some(stage)
+        }) {
+            anyhow::bail!("changed clock of stage {stage} lowers the one of the base")
+        }
+        let mut clocks = base_clocks
+            .iter()
+            .filter(|(stage, _)| !self.dropped.contains(*stage))
+            .map(|(stage, clock)| (stage.clone(), clock.clone()))
+            .collect::<HashMap<_, _>>();
+        clocks.extend(changed);
+        Ok(clocks)
+    }
+}
+
+impl<C: Causality + PartialEq + Clone, I> TaskStage<C, I> {
+    // publish only the clocks that are new or changed against the message of `base`
+    pub fn delta_against(&mut self, base: StageSource, base_clocks: &HashMap<String, C>) {
+        let (delta, changed) = ClockDelta::new(base, base_clocks, &self.clocks);
+        self.clocks = changed;
+        self.clock_delta = Some(delta)
+    }
+
+    // reconstruct the full clocks of a message published as a delta, against the clocks of the
+    // base it names. nothing to do for a message with full clocks
+    pub fn apply_delta(&mut self, base_clocks: &HashMap<String, C>) -> anyhow::Result<()> {
+        let Some(delta) = &self.clock_delta else {
+            return Ok(());
+        };
+        self.clocks = delta.apply(base_clocks, self.clocks.clone())?;
+        self.clock_delta = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NodeId, OrdinaryClock};
+
+    fn clock(entries: &[(NodeId, u32)]) -> OrdinaryClock {
+        OrdinaryClock(entries.iter().copied().collect())
+    }
+
+    fn clocks(stages: &[(&str, OrdinaryClock)]) -> HashMap<String, OrdinaryClock> {
+        stages
+            .iter()
+            .map(|(stage, clock)| (stage.to_string(), clock.clone()))
+            .collect()
+    }
+
+    fn base() -> HashMap<String, OrdinaryClock> {
+        clocks(&[
+            ("start", clock(&[(0, 1)])),
+            ("fetch", clock(&[(0, 1), (1, 1)])),
+            ("parse", clock(&[(0, 1), (1, 1), (2, 1)])),
+        ])
+    }
+
+    #[test]
+    fn applies_to_the_clocks_diffed() {
+        let base = base();
+        let mut full = base.clone();
+        full.remove("fetch");
+        full.insert("parse".into(), clock(&[(0, 1), (1, 1), (2, 2)]));
+        full.insert("train".into(), clock(&[(0, 1), (1, 1), (2, 2), (3, 1)]));
+        let source = StageSource::Name("parse".into());
+        let (delta, changed) = ClockDelta::new(source, &base, &full);
+        assert_eq!(changed.len(), 2);
+        assert!(!changed.contains_key("start"));
+        assert_eq!(delta.dropped, BTreeSet::from(["fetch".into()]));
+        assert_eq!(delta.apply(&base, changed).unwrap(), full);
+
+        let (delta, changed) = ClockDelta::new(StageSource::Start, &base, &base);
+        assert!(changed.is_empty());
+        assert_eq!(delta.apply(&base, changed).unwrap(), base)
+    }
+
+    #[test]
+    fn refuses_another_base() {
+        let base = base();
+        let mut full = base.clone();
+        full.insert("train".into(), clock(&[(0, 1), (1, 1), (2, 1), (3, 1)]));
+        let (delta, changed) = ClockDelta::new(StageSource::Name("parse".into()), &base, &full);
+        let mut superseded = base.clone();
+        superseded.insert("parse".into(), clock(&[(0, 1), (1, 1), (2, 2)]));
+        assert!(delta.validate(&superseded).is_err());
+        assert!(delta.apply(&superseded, changed.clone()).is_err());
+        let mut fewer = base.clone();
+        fewer.remove("fetch");
+        assert!(delta.apply(&fewer, changed).is_err());
+
+        let mut dropping = delta.clone();
+        dropping.dropped.insert("train".into());
+        assert!(dropping.validate(&base).is_err())
+    }
+
+    #[test]
+    fn refuses_lowering_an_entry() {
+        let base = base();
+        let mut full = base.clone();
+        full.insert("parse".into(), clock(&[(0, 1), (1, 1)]));
+        let (delta, changed) = ClockDelta::new(StageSource::Name("parse".into()), &base, &full);
+        assert!(delta.validate(&base).is_ok());
+        assert!(delta.apply(&base, changed).is_err());
+
+        // concurrent to the clock of the base rather than lower as a whole
+        full.insert("parse".into(), clock(&[(0, 1), (1, 1), (3, 1)]));
+        let (delta, changed) = ClockDelta::new(StageSource::Name("parse".into()), &base, &full);
+        assert!(delta.apply(&base, changed).is_err())
+    }
+}
@@ -0,0 +1,434 @@
+// the causal history of a task, for the post-mortem analysis of wrong or slow results
+// the hub keeps every event of a task as it is applied, i.e. every gossip message of its stages,
+// its results and the challenges against them, without the payloads, and serves them in order at
+// `GET /tasks/<task id>/history`. `render` lays them out as a timeline, linking each event to the
+// events it causally happens after by their clocks
+// the events are numbered in the order they are kept, so the state of every task as of a sequence
+// number (see `TaskSnapshot`) stays the same however late it is read
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attribution,
+    blob::BlobRef,
+    compression::Compression,
+    crypto::{CryptoSuite, Digest},
+    hex, Challenge, Chunk, NodeId, OrdinaryClock, StageSource, TaskId, TaskResult, TaskStage,
+    WorkflowDigest,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Gossip,
+    Result,
+    Challenge,
+    // the terminal record of a task that missed its deadline (see `TaskExpired`)
+    Expiry,
+    // an in-flight task moved to another workflow version, along with its pending message
+    Migration,
+}
+
+// the workflow versions an in-flight task is migrated between
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Migrated {
+    pub from: WorkflowDigest,
+    pub to: WorkflowDigest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEvent {
+    // microseconds since the unix epoch, by the clock of the hub instance applying the event
+    pub at: u64,
+    // the position in the log of the events of all the tasks, the same for every member of a raft
+    // group. `None` for the events kept before they were numbered, which precede all the others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    pub kind: EventKind,
+    // the stage that produced a gossip message (`None` for the start stage) or a result, or is
+    // challenged, or the last stage completed by an expired or a migrated task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub clocks: HashMap<String, OrdinaryClock>,
+    // the node advancing the clock of the stage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer: Option<NodeId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<NodeId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<Digest>,
+    // the execution time of the stage in milliseconds, as recorded by its worker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<u64>,
+    // of the payload, before decompression
+    pub size: u64,
+    // the digest of the payload as published, i.e. of the inline bytes, or of the chunk digests
+    // of an offloaded one, which tells the outputs apart without keeping them (see `consistency`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Digest>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrated: Option<Migrated>,
+}
+
+fn payload_digest(crypto: &dyn CryptoSuite, inline: &[u8], blob: &Option<BlobRef>) -> Digest {
+    match blob {
+        None => crypto.digest(inline),
+        Some(blob) => crypto.digest(&blob.chunks.concat()),
+    }
+}
+
+impl HistoryEvent {
+    pub fn gossip(
+        at: u64,
+        crypto: &dyn CryptoSuite,
+        message: &TaskStage<OrdinaryClock, impl AsRef<[u8]>>,
+    ) -> Self {
+        let stage = match &message.source {
+            StageSource::Start => None,
+            StageSource::Name(stage) => Some(stage.clone()),
+        };
+        let producer = stage
+            .as_ref()
+            .and_then(|stage| producer(&message.clocks, stage));
+        Self {
+            at,
+            seq: None,
+            kind: EventKind::Gossip,
+            program: stage
+                .as_ref()
+                .and_then(|stage| message.programs.get(stage).copied()),
+            elapsed: stage
+                .as_ref()
+                .and_then(|stage| message.elapsed.get(stage).copied()),
+            stage,
+            chunk: message.chunk,
+            clocks: message.clocks.clone(),
+            producer,
+            assignee: message.assignee,
+            size: message
+                .blob
+                .as_ref()
+                .map_or(message.input.as_ref().len() as _, |blob| blob.size),
+            payload: Some(payload_digest(
+                crypto,
+                message.input.as_ref(),
+                &message.blob,
+            )),
+            compression: message.compression,
+            migrated: None,
+        }
+    }
+
+    // the pending message of the task as re-stamped with the version migrated to
+    pub fn migration(
+        at: u64,
+        crypto: &dyn CryptoSuite,
+        from: WorkflowDigest,
+        message: &TaskStage<OrdinaryClock, impl AsRef<[u8]>>,
+    ) -> Self {
+        Self {
+            kind: EventKind::Migration,
+            producer: None,
+            program: None,
+            elapsed: None,
+            migrated: message.workflow.map(|to| Migrated { from, to }),
+            ..Self::gossip(at, crypto, message)
+        }
+    }
+
+    pub fn result(
+        at: u64,
+        crypto: &dyn CryptoSuite,
+        result: &TaskResult<OrdinaryClock, impl AsRef<[u8]>>,
+    ) -> Self {
+        if let Some(expired) = &result.expired {
+            return Self {
+                at,
+                seq: None,
+                kind: EventKind::Expiry,
+                stage: expired.stage.clone(),
+                chunk: None,
+                clocks: result.clocks.clone(),
+                producer: None,
+                assignee: None,
+                program: None,
+                elapsed: None,
+                size: 0,
+                payload: None,
+                compression: None,
+                migrated: None,
+            };
+        }
+        let stage = latest(&result.clocks).map(|(stage, _)| stage.clone());
+        Self {
+            at,
+            seq: None,
+            kind: EventKind::Result,
+            producer: stage
+                .as_ref()
+                .and_then(|stage| producer(&result.clocks, stage)),
+            program: stage
+                .as_ref()
+                .and_then(|stage| result.programs.get(stage).copied()),
+            elapsed: stage
+                .as_ref()
+                .and_then(|stage| result.elapsed.get(stage).copied()),
+            stage,
+            chunk: result.chunk,
+            clocks: result.clocks.clone(),
+            assignee: None,
+            size: result
+                .blob
+                .as_ref()
+                .map_or(result.output.as_ref().len() as _, |blob| blob.size),
+            payload: Some(payload_digest(crypto, result.output.as_ref(), &result.blob)),
+            compression: result.compression,
+            migrated: None,
+        }
+    }
+
+    pub fn challenge(at: u64, challenge: &Challenge) -> Self {
+        Self {
+            at,
+            seq: None,
+            kind: EventKind::Challenge,
+            stage: Some(challenge.stage.clone()),
+            chunk: None,
+            clocks: Default::default(),
+            producer: None,
+            assignee: None,
+            program: None,
+            elapsed: None,
+            size: challenge.output.len() as _,
+            payload: None,
+            compression: None,
+            migrated: None,
+        }
+    }
+
+    // the clock of the stage the event is ordered by, `None` for the start stages, the challenges,
+    // the expiries and the migrations, which carry no clock of their own
+    pub fn clock(&self) -> Option<&OrdinaryClock> {
+        match self.kind {
+            EventKind::Gossip | EventKind::Result => self.clocks.get(self.stage.as_ref()?),
+            EventKind::Challenge | EventKind::Expiry | EventKind::Migration => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    // a final result is accepted, i.e. the result of an ordinary task or the last chunk of a
+    // streaming one
+    Completed,
+    // a result is reverted by a successful challenge
+    Reverted,
+    // the deadline passed before a final result
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskState {
+    pub id: TaskId,
+    pub status: TaskStatus,
+    // the stages executed so far, with the node of their latest execution
+    pub stages: BTreeMap<String, Option<NodeId>>,
+    pub results: u64,
+    pub challenges: u64,
+    // of the latest event of the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+// a page of the states of the tasks as of a sequence number, i.e. after the events numbered
+// before it. the next page is read at the same sequence number after the last task of the page,
+// so no task is observed half updated across the pages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskSnapshot {
+    pub seq: u64,
+    pub tasks: Vec<TaskState>,
+    // the task to read the next page after, `None` on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<TaskId>,
+}
+
+// of the events of a task numbered before `seq`, `None` if there is none
+pub fn state(id: TaskId, events: &[HistoryEvent], seq: u64) -> Option<TaskState> {
+    let mut state = TaskState {
+        id,
+        status: TaskStatus::Running,
+        stages: Default::default(),
+        results: 0,
+        challenges: 0,
+        seq: None,
+    };
+    let mut any = false;
+    for event in events
+        .iter()
+        .filter(|event| event.seq.is_none_or(|event| event < seq))
+    {
+        any = true;
+        state.seq = state.seq.max(event.seq);
+        // the last stage only produces results
+        if let (EventKind::Gossip | EventKind::Result, Some(stage)) = (event.kind, &event.stage) {
+            state.stages.insert(stage.clone(), event.producer);
+        }
+        match event.kind {
+            EventKind::Gossip | EventKind::Migration => {}
+            EventKind::Result => {
+                state.results += 1;
+                if state.status == TaskStatus::Running && event.chunk.is_none_or(|chunk| chunk.last)
+                {
+                    state.status = TaskStatus::Completed
+                }
+            }
+            EventKind::Challenge => {
+                state.challenges += 1;
+                state.status = TaskStatus::Reverted
+            }
+            EventKind::Expiry => {
+                if state.status == TaskStatus::Running {
+                    state.status = TaskStatus::Expired
+                }
+            }
+        }
+    }
+    any.then_some(state)
+}
+
+// the stage of the latest clock, e.g. the last executed one
+fn latest<'a>(
+    clocks: impl IntoIterator<Item = (&'a String, &'a OrdinaryClock)>,
+) -> Option<(&'a String, &'a OrdinaryClock)> {
+    clocks
+        .into_iter()
+        .reduce(|latest, other| if other.1 > latest.1 { other } else { latest })
+}
+
+// the node advancing the clock of the stage over the clocks of the stages before it
+pub fn producer(clocks: &HashMap<String, OrdinaryClock>, stage: &str) -> Option<NodeId> {
+    let clock = clocks.get(stage)?;
+    // the stages before have smaller clocks, whose merge is the clock the stage executes upon
+    let previous = OrdinaryClock::merge(clocks.values().filter(|other| *other < clock));
+    attribution::producer(clock, &previous).ok()
+}
+
+// the indexes of the latest events that the event of the index causally happens after. a stage
+// follows the earlier stages by their clocks, and the first stage follows the start of the same
+// chunk. a result follows the stages its clocks cover, or the start if the workflow has a single
+// stage, a challenge follows the result, and an expiry or a migration follows the last completed
+// stage or the start
+pub fn predecessors(events: &[HistoryEvent], index: usize) -> Vec<usize> {
+    let event = &events[index];
+    let before = |other: &HistoryEvent| match (event.kind, event.clock(), other.clock()) {
+        (EventKind::Gossip, Some(clock), Some(other)) => other < clock,
+        (EventKind::Gossip | EventKind::Result, Some(_), None) => {
+            other.kind == EventKind::Gossip
+                && event.clocks.len() == 1
+                && other.chunk.map(|chunk| chunk.seq) == event.chunk.map(|chunk| chunk.seq)
+        }
+        (EventKind::Result, Some(clock), Some(other_clock)) => {
+            other.kind == EventKind::Gossip && other_clock <= clock
+        }
+        (EventKind::Challenge, _, _) => other.kind == EventKind::Result,
+        (EventKind::Expiry | EventKind::Migration, _, _) => {
+            other.kind == EventKind::Gossip && other.stage == event.stage
+        }
+        _ => false,
+    };
+    let candidates = (0..events.len())
+        .filter(|&other| other != index && before(&events[other]))
+        .collect::<Vec<_>>();
+    // only the latest ones, which do not happen before any other candidate
+    candidates
+        .iter()
+        .copied()
+        .filter(|&other| {
+            !candidates.iter().any(
+                |&later| match (events[other].clock(), events[later].clock()) {
+                    (Some(clock), Some(later)) => clock < later,
+                    _ => false,
+                },
+            )
+        })
+        .collect()
+}
+
+// one line per event, with the time since the first event, and the time since the latest event it
+// happens after, which covers the queuing and the transfers along with the execution itself
+pub fn render(events: &[HistoryEvent]) -> String {
+    let mut timeline = String::new();
+    let Some(first) = events.first() else {
+        return timeline;
+    };
+    for (index, event) in events.iter().enumerate() {
+        let what = match (event.kind, &event.stage) {
+            (EventKind::Gossip, None) => "start".to_string(),
+            (EventKind::Gossip, Some(stage)) => format!("stage {stage}"),
+            (EventKind::Result, stage) => {
+                format!("stage {} result accepted", stage.as_deref().unwrap_or("?"))
+            }
+            (EventKind::Challenge, stage) => {
+                format!("stage {} challenged", stage.as_deref().unwrap_or("?"))
+            }
+            (EventKind::Expiry, None) => "expired before any stage".to_string(),
+            (EventKind::Expiry, Some(stage)) => format!("expired after stage {stage}"),
+            (EventKind::Migration, stage) => {
+                let after = stage
+                    .as_ref()
+                    .map_or("start".to_string(), |stage| format!("stage {stage}"));
+                match &event.migrated {
+                    Some(migrated) => format!(
+                        "migrated after {after} from workflow {} to {}",
+                        &hex(&migrated.from)[..8],
+                        &hex(&migrated.to)[..8]
+                    ),
+                    None => format!("migrated after {after}"),
+                }
+            }
+        };
+        let _ = write!(
+            timeline,
+            "#{index:<3} +{:>9.3}s  {what}",
+            (event.at.saturating_sub(first.at)) as f64 / 1e6
+        );
+        if let Some(chunk) = event.chunk {
+            let _ = write!(timeline, " chunk {}", chunk.seq);
+        }
+        if let Some(producer) = event.producer {
+            let _ = write!(timeline, " by {producer:08x}");
+        }
+        if let Some(assignee) = event.assignee {
+            let _ = write!(timeline, " next stage assigned to {assignee:08x}");
+        }
+        if let Some(program) = &event.program {
+            let _ = write!(timeline, " program {}", &hex(program)[..8]);
+        }
+        if let Some(elapsed) = event.elapsed {
+            let _ = write!(timeline, " ran {elapsed}ms");
+        }
+        if event.kind == EventKind::Gossip {
+            let _ = write!(timeline, " {} bytes", event.size);
+        }
+        for predecessor in predecessors(events, index) {
+            let _ = write!(
+                timeline,
+                " after #{predecessor} (+{}ms)",
+                event.at.saturating_sub(events[predecessor].at) / 1000
+            );
+        }
+        timeline.push('\n')
+    }
+    timeline
+}
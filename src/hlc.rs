@@ -0,0 +1,241 @@
+// hybrid logical clocks: every entry of the vector clock carries the hybrid timestamp of the event
+// it counts, i.e. the wall time of its producer in milliseconds along with a logical counter that
+// orders the events within the same millisecond. a producer stamps its event no earlier than any
+// entry it merges from the predecessors, so the timestamps never run backwards along causality,
+// even between hosts whose wall clocks disagree
+// the comparison is still the one of the counts, so causality decides whenever it can.
+// `estimated_cmp` orders the concurrent clocks by their timestamps as well, an estimate of the
+// real-time order that is as good as the wall clocks of the producers are in sync. the timestamp
+// of the producer (see `HlcClock::time`) reads as the completion time of the stage
+// the verifier rejects clocks stamped ahead of its own wall clock by more than the drift it
+// tolerates, and clocks whose producer is stamped before an entry it merged. the timestamps are
+// claims of the producers, which the clocks do not prove, so they are meant to be combined with a
+// context that proves the computation, e.g. by running the stages on trusted hosts
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    marker::PhantomData,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Timestamp {
+    // milliseconds since the unix epoch
+    pub physical: u64,
+    pub logical: u32,
+}
+
+impl Timestamp {
+    // the earliest timestamp after every one of `merged` that is no earlier than the wall time
+    // `now`. fails on a merged one at the highest logical counter of a millisecond not passed yet,
+    // which nothing comes after
+    fn next<'a>(now: u64, merged: impl Iterator<Item = &'a Self>) -> anyhow::Result<Self> {
+        let latest = merged.max().copied().unwrap_or_default();
+        if now > latest.physical {
+            return Ok(Self {
+                physical: now,
+                logical: 0,
+            });
+        }
+        Ok(Self {
+            physical: latest.physical,
+            logical: latest.logical.checked_add(1).ok_or(anyhow::format_err!(
+                "timestamp {}.{} cannot be advanced",
+                latest.physical,
+                latest.logical
+            ))?,
+        })
+    }
+}
+
+fn wall_time() -> anyhow::Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as _)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct HlcClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    // of the events the entries count
+    pub times: BTreeMap<NodeId, Timestamp>,
+    // the producer of the clock
+    pub node: NodeId,
+}
+
+impl HlcClock {
+    // of the producer, i.e. when the clock is proven
+    pub fn time(&self) -> Timestamp {
+        self.times.get(&self.node).copied().unwrap_or_default()
+    }
+
+    // the causal order if any, and otherwise the order of the timestamps of the producers, then of
+    // the producers themselves, so every two clocks are ordered
+    pub fn estimated_cmp(&self, other: &Self) -> Ordering {
+        self.partial_cmp(other)
+            .unwrap_or_else(|| (self.time(), self.node).cmp(&(other.time(), other.node)))
+    }
+}
+
+#[derive(Debug)]
+pub struct HlcClientContext<O> {
+    // how far ahead of the wall time of the verifier a clock may be stamped
+    max_drift: Duration,
+    _output: PhantomData<O>,
+}
+
+impl<O> HlcClientContext<O> {
+    pub fn new(max_drift: Duration) -> Self {
+        Self {
+            max_drift,
+            _output: PhantomData,
+        }
+    }
+
+    // the output is not bound by the clock
+    fn verify_clock(&self, clock: &HlcClock) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            clock.clock.len() == clock.times.len()
+                && clock
+                    .times
+                    .keys()
+                    .all(|node| clock.clock.contains_key(node)),
+            "clock does not hold a timestamp for every count"
+        );
+        anyhow::ensure!(
+            clock.clock.get(&clock.node).is_some_and(|count| *count > 0),
+            "producer {:08x} is not counted",
+            clock.node
+        );
+        let time = clock.time();
+        anyhow::ensure!(
+            clock.times.values().all(|other| *other <= time),
+            "producer {:08x} is stamped before an event it merges",
+            clock.node
+        );
+        let bound = wall_time()? + self.max_drift.as_millis() as u64;
+        anyhow::ensure!(
+            time.physical <= bound,
+            "clock is stamped {}ms ahead of the tolerated drift",
+            time.physical - bound
+        );
+        Ok(())
+    }
+}
+
+impl<O> ClockClientContext for HlcClientContext<O> {
+    type Clock = HlcClock;
+    type Output = O;
+
+    fn verify(&self, clock: &Self::Clock, _: &Self::Output, _: &Binding) -> Result<(), Error> {
+        self.verify_clock(clock).map_err(Error::ProofInvalid)
+    }
+}
+
+#[derive(Debug, ClockClientContext)]
+pub struct HlcContext<I, O> {
+    id: NodeId,
+    #[client_context]
+    client: HlcClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> HlcContext<I, O> {
+    pub fn new(id: NodeId, client: HlcClientContext<O>) -> Self {
+        Self {
+            id,
+            client,
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<I, O> ClockContext for HlcContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        _: &Self::Output,
+        _: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        // the timestamp of every entry at its latest count
+        let mut times = BTreeMap::<NodeId, (u32, Timestamp)>::new();
+        for (clock, ..) in predecessors {
+            self.client
+                .verify_clock(clock)
+                .map_err(Error::ProofInvalid)?;
+            for (node, time) in &clock.times {
+                let entry = (clock.clock[node], *time);
+                let merged = times.entry(*node).or_insert(entry);
+                *merged = (*merged).max(entry)
+            }
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let time = Timestamp::next(
+            wall_time().map_err(Error::Proving)?,
+            times.values().map(|(_, time)| time),
+        )
+        .map_err(Error::Proving)?;
+        let mut times = times
+            .into_iter()
+            .map(|(node, (_, time))| (node, time))
+            .collect::<BTreeMap<_, _>>();
+        times.insert(self.id, time);
+        Ok(HlcClock {
+            clock,
+            times,
+            node: self.id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRIFT: Duration = Duration::from_secs(3600);
+
+    fn prove(context: &HlcContext<(), ()>, predecessors: &[&HlcClock]) -> Result<HlcClock, Error> {
+        let binding = Binding::new(1, "stage");
+        let predecessors = predecessors
+            .iter()
+            .map(|clock| (*clock, &(), &binding))
+            .collect::<Vec<_>>();
+        context.prove(&predecessors, &(), &binding)
+    }
+
+    #[test]
+    fn stamps_after_predecessors() {
+        let first = prove(&HlcContext::new(1, HlcClientContext::new(DRIFT)), &[]).unwrap();
+        let second = prove(&HlcContext::new(2, HlcClientContext::new(DRIFT)), &[&first]).unwrap();
+        assert!(second > first);
+        assert!(second.time() > first.time());
+        assert_eq!(second.times[&1], first.time());
+    }
+
+    // a predecessor stamped ahead, within the drift, at the highest logical counter
+    #[test]
+    fn refuses_logical_overflow() {
+        let ahead = Timestamp {
+            physical: wall_time().unwrap() + 60_000,
+            logical: u32::MAX,
+        };
+        let hostile = HlcClock {
+            clock: OrdinaryClock([(1, 1)].into()),
+            times: [(1, ahead)].into(),
+            node: 1,
+        };
+        let context = HlcContext::new(2, HlcClientContext::new(DRIFT));
+        assert!(matches!(
+            prove(&context, &[&hostile]),
+            Err(Error::Proving(_))
+        ));
+    }
+}
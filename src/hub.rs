@@ -0,0 +1,2495 @@
+// the coordinator of a deployment: the gossip network that stages publish to and subscribe from,
+// and the consensus infrastructure that task results are proposed to. the `network` binary is a
+// thin wrapper around it, and an application can embed it into its own process instead, either
+// serving it as is or merging its `router` into an existing axum app
+// with a workflow path the hub watches the file for changes and reloads it, which can also be
+// triggered with `POST /admin/reload`. in a raft group every member reloads its own copy of the
+// file, so the operator should update the file of all members
+mod audit;
+mod backfill;
+mod cache;
+mod challenge;
+mod deadline;
+mod filter;
+mod gc;
+mod handoff;
+mod hint;
+mod history;
+mod hook;
+mod lease;
+mod ledger;
+mod lineage;
+mod load;
+mod migration;
+mod mirror;
+mod partition;
+mod quarantine;
+mod raft;
+mod resume;
+mod rollup;
+mod scheduler;
+mod stats;
+mod validate;
+mod verifier;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::identity,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocketUpgrade},
+        OriginalUri, Path, Query, State,
+    },
+    http::{
+        header::{LOCATION, RETRY_AFTER},
+        HeaderMap,
+    },
+    middleware,
+    response::{sse::Event, IntoResponse, Response, Sse},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use bytes::Bytes;
+use openraft::BasicNode;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    fs,
+    net::TcpListener,
+    select,
+    sync::{broadcast, mpsc, watch::Sender},
+    time::interval,
+};
+use tokio_stream::{
+    wrappers::{BroadcastStream, ReceiverStream, WatchStream},
+    Stream, StreamExt as _,
+};
+use tracing::{info, warn};
+
+pub use challenge::Reexecutor;
+pub use filter::ChainFilter;
+pub use history::histories;
+pub use hook::ResultHook;
+pub use raft::HubId;
+pub use verifier::GossipVerification;
+
+use crate::{
+    attribution,
+    blob::{
+        blob_key, BlobRef, BlobStore, MemoryBlobStore, CHUNK_SIZE, DEFAULT_MAX_BLOB_SIZE,
+        DEFAULT_MAX_INLINE_SIZE,
+    },
+    branching,
+    crypto::{CryptoSuite, StandardSuite},
+    hex, looping,
+    notary::ResultHeader,
+    protocol,
+    routing::{self, Route},
+    schema::Violation,
+    secrets::{self, SecretRequest, SecretStore},
+    signer::Signer,
+    Allowlist, Audit, AuditOutcome, CanaryReport, Challenge, ClockLimits, Error, LeaseRequest,
+    Membership, OrdinaryClientContext, OrdinaryClock, OversizePolicy, ProgramPolicy, ProgressEvent,
+    StageSource, TaskId, TaskResult, TaskStage, VerificationReport, WorkerStatus, Workflow,
+    WorkflowDigest,
+};
+
+use self::{
+    backfill::{chain_key, Report},
+    deadline::Deadlines,
+    filter::Filter,
+    hint::Hints,
+    history::Sequence,
+    hook::Hooks,
+    ledger::Ledger,
+    lineage::LineageQuery,
+    migration::Migration,
+    partition::{Merged, Partitions, Scope},
+    quarantine::Quarantine,
+    resume::Numbered,
+    scheduler::Scheduler,
+    stats::{Stats, StatsQuery},
+    verifier::Verifier,
+};
+
+// where the accepted events are kept before they are observed by subscribers
+#[derive(Debug, Clone, Default)]
+pub enum Store {
+    // applied immediately by this instance alone
+    #[default]
+    Local,
+    // replicated through a raft group as the member of the id, which must be formed through the
+    // `/raft/*` administration endpoints after all members are started
+    Raft(HubId),
+    // followed from the hub at the base url (or the comma separated base urls of the members of
+    // a raft group) as its read-only mirror, see `mirror`
+    Mirror(String),
+}
+
+#[derive(Default)]
+pub struct HubBuilder {
+    workflow: Option<Workflow>,
+    other_workflows: Vec<Workflow>,
+    path: Option<PathBuf>,
+    store: Store,
+    crypto: Option<Arc<dyn CryptoSuite>>,
+    policy: Option<Arc<dyn ProgramPolicy + Send + Sync>>,
+    blobs: Option<Arc<dyn BlobStore>>,
+    max_inline_size: Option<usize>,
+    max_blob_size: Option<u64>,
+    reexecutor: Option<Arc<dyn Reexecutor>>,
+    secrets: Option<Arc<dyn SecretStore>>,
+    hooks: Hooks,
+    signer: Option<Arc<dyn Signer>>,
+    retention: Option<Duration>,
+    prune_after: Option<Duration>,
+    gc_interval: Option<Duration>,
+    epoch_length: Option<Duration>,
+    max_publish_rate: Option<u32>,
+    max_partition_rate: Option<u32>,
+    clock_limits: Option<ClockLimits>,
+    membership: Membership,
+    audit_rate: Option<f64>,
+    gossip_verification: GossipVerification,
+    verifier_threads: Option<usize>,
+}
+
+impl HubBuilder {
+    pub fn workflow(mut self, workflow: Workflow) -> Self {
+        self.workflow = Some(workflow);
+        self
+    }
+
+    // a workflow that is served alongside, e.g. for the tasks handed off to, whose tasks must be
+    // started with its digest. the stage names must not collide with the ones of other workflows
+    pub fn other_workflow(mut self, workflow: Workflow) -> Self {
+        self.other_workflows.push(workflow);
+        self
+    }
+
+    // the file the workflow is loaded from, to be watched and reloaded on changes
+    pub fn workflow_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn store(mut self, store: Store) -> Self {
+        self.store = store;
+        self
+    }
+
+    // default to `StandardSuite::default()`
+    pub fn crypto(mut self, crypto: Arc<dyn CryptoSuite>) -> Self {
+        self.crypto = Some(crypto);
+        self
+    }
+
+    // default to `Allowlist`
+    pub fn policy(mut self, policy: Arc<dyn ProgramPolicy + Send + Sync>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    // without one the results of optimistic workflows cannot be challenged
+    pub fn reexecutor(mut self, reexecutor: Arc<dyn Reexecutor>) -> Self {
+        self.reexecutor = Some(reexecutor);
+        self
+    }
+
+    // without one the workers of the stages get no secrets (see `secrets`)
+    pub fn secrets(mut self, secrets: Arc<dyn SecretStore>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    // a hook the workflows may name to process their results before they are delivered (see
+    // `hook`)
+    pub fn result_hook(mut self, name: impl Into<String>, hook: Arc<dyn ResultHook>) -> Self {
+        self.hooks.insert(name.into(), hook);
+        self
+    }
+
+    // the key the notarizations are signed with, without which the hub does not notarize
+    pub fn signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    // default to `MemoryBlobStore`
+    pub fn blobs(mut self, blobs: Arc<dyn BlobStore>) -> Self {
+        self.blobs = Some(blobs);
+        self
+    }
+
+    // default to `DEFAULT_MAX_INLINE_SIZE`
+    pub fn max_inline_size(mut self, size: usize) -> Self {
+        self.max_inline_size = Some(size);
+        self
+    }
+
+    // default to `DEFAULT_MAX_BLOB_SIZE`
+    pub fn max_blob_size(mut self, size: u64) -> Self {
+        self.max_blob_size = Some(size);
+        self
+    }
+
+    // how long the tasks are kept after their latest event, by default forever. the expired tasks
+    // are deleted by the garbage collection (see `gc`)
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    // how long the bodies of the results are kept after the latest event of their tasks, by default
+    // until the tasks expire. the pruned results leave their headers, whose inclusion in the ledger
+    // is still notarized, see `gc`
+    pub fn prune_after(mut self, window: Duration) -> Self {
+        self.prune_after = Some(window);
+        self
+    }
+
+    // the garbage collection runs periodically with one, otherwise only on `POST /admin/gc`
+    pub fn gc_interval(mut self, interval: Duration) -> Self {
+        self.gc_interval = Some(interval);
+        self
+    }
+
+    // the attribution of the finished tasks is rolled up into a summary per epoch of this length
+    // with one (see `rollup`), otherwise it is only available per task
+    pub fn epoch_length(mut self, length: Duration) -> Self {
+        self.epoch_length = Some(length);
+        self
+    }
+
+    // the gossip messages per second beyond which new tasks are shed (see `load`), by default
+    // nothing is shed
+    pub fn max_publish_rate(mut self, rate: u32) -> Self {
+        self.max_publish_rate = Some(rate);
+        self
+    }
+
+    // the gossip messages per second of a single workflow beyond which its new tasks are shed, so
+    // it cannot take the capacity of the others (see `partition`), by default nothing is shed
+    pub fn max_partition_rate(mut self, rate: u32) -> Self {
+        self.max_partition_rate = Some(rate);
+        self
+    }
+
+    // default to `ClockLimits::default()`
+    pub fn clock_limits(mut self, limits: ClockLimits) -> Self {
+        self.clock_limits = Some(limits);
+        self
+    }
+
+    // the retired nodes that the clocks are pruned against on publish, and verified tolerating so,
+    // see `Membership`. by default nothing is pruned
+    pub fn membership(mut self, membership: Membership) -> Self {
+        self.membership = membership;
+        self
+    }
+
+    // the fraction of the accepted stages sampled for audits (see `audit`), by default nothing is
+    // audited
+    pub fn audit_rate(mut self, rate: f64) -> Self {
+        self.audit_rate = Some(rate);
+        self
+    }
+
+    // where the gossip messages are verified (see `verifier`), by default nowhere but at the
+    // workers, trusting the verification of the results
+    pub fn gossip_verification(mut self, verification: GossipVerification) -> Self {
+        self.gossip_verification = verification;
+        self
+    }
+
+    // the size of the pool of verifier threads the verification is offloaded to, 2 by default
+    pub fn verifier_threads(mut self, threads: usize) -> Self {
+        self.verifier_threads = Some(threads);
+        self
+    }
+
+    // the raft group member starts participating in the group on build, and the workflow path
+    // starts being watched, so this must be called within a tokio runtime
+    pub async fn build(self) -> anyhow::Result<Hub> {
+        let workflow = self
+            .workflow
+            .ok_or(anyhow::format_err!("missing workflow"))?;
+        let blobs = self
+            .blobs
+            .unwrap_or_else(|| Arc::new(MemoryBlobStore::default()));
+        let crypto = self
+            .crypto
+            .unwrap_or_else(|| Arc::new(StandardSuite::default()));
+        let audit_rate = self.audit_rate.unwrap_or_default();
+        anyhow::ensure!((0. ..=1.).contains(&audit_rate), "audit rate out of range");
+        let mut task = Workflows::new(workflow, &*crypto);
+        let mut registered = vec![task.current];
+        for workflow in self.other_workflows {
+            registered.push(task.add(workflow, &*crypto));
+        }
+        for task in task.versions.values() {
+            let missing = hook::missing(&self.hooks, task);
+            anyhow::ensure!(missing.is_empty(), "unknown result hooks {missing:?}");
+        }
+        let task = Arc::new(RwLock::new(task));
+        let partitions = Partitions::new(registered, self.max_partition_rate);
+        let max_blob_size = self.max_blob_size.unwrap_or(DEFAULT_MAX_BLOB_SIZE);
+        let fanout = Fanout::new(
+            blobs.clone(),
+            crypto.clone(),
+            partitions,
+            task.clone(),
+            self.hooks,
+            max_blob_size,
+        );
+        let (raft, mirror) = match self.store {
+            Store::Local => (None, None),
+            Store::Raft(id) => (Some(raft::start(id, fanout.clone()).await?), None),
+            Store::Mirror(primary) => (None, Some(primary.into())),
+        };
+        let shared = Shared {
+            fanout,
+            path: self.path.map(Arc::new),
+            task,
+            context: Arc::new(OrdinaryClientContext::new()),
+            policy: self.policy.unwrap_or_else(|| Arc::new(Allowlist)),
+            crypto,
+            canaries: Default::default(),
+            streams: Default::default(),
+            scheduler: Default::default(),
+            hints: Default::default(),
+            leases: Default::default(),
+            load: Arc::new(Mutex::new(load::Load::new(self.max_publish_rate))),
+            audits: Arc::new(Mutex::new(audit::Audits::new(audit_rate))),
+            verifier: Arc::new(Verifier::new(
+                self.gossip_verification,
+                self.verifier_threads.unwrap_or(verifier::DEFAULT_THREADS),
+            )?),
+            backfill: Default::default(),
+            gc: Default::default(),
+            retention: self.retention,
+            prune_after: self.prune_after,
+            epoch_length: self.epoch_length,
+            blobs,
+            max_inline_size: self.max_inline_size.unwrap_or(DEFAULT_MAX_INLINE_SIZE),
+            max_blob_size,
+            clock_limits: self.clock_limits.unwrap_or_default(),
+            membership: Arc::new(self.membership),
+            reexecutor: self.reexecutor,
+            secrets: self.secrets,
+            signer: self.signer,
+            raft,
+            mirror,
+        };
+        if shared.path.is_some() {
+            tokio::spawn(watch_workflow(shared.clone()));
+        }
+        if let Some(interval) = self.gc_interval {
+            anyhow::ensure!(!interval.is_zero(), "zero garbage collection interval");
+            tokio::spawn(gc::periodically(shared.clone(), interval));
+        }
+        if let Some(length) = self.epoch_length {
+            anyhow::ensure!(length.as_secs() > 0, "epoch shorter than a second");
+            tokio::spawn(rollup::periodically(shared.clone(), length));
+        }
+        tokio::spawn(deadline::periodically(shared.clone()));
+        if shared.mirror.is_some() {
+            tokio::spawn(mirror::follow(shared.clone()));
+        }
+        Ok(Hub { shared })
+    }
+}
+
+#[derive(Clone)]
+pub struct Hub {
+    shared: Shared,
+}
+
+impl Hub {
+    pub fn builder() -> HubBuilder {
+        HubBuilder::default()
+    }
+
+    // all endpoints of the hub, with the state already provided, so it can be nested or merged
+    // into an application's router as is
+    pub fn router(&self) -> Router {
+        let mut routes = Router::new()
+            .route("/gossip", get(gossip_subscribe))
+            .route("/gossip/ws", get(gossip_subscribe_ws))
+            .route("/gossip/publish", post(gossip_publish))
+            .route("/chain", get(chain_subscribe))
+            .route("/chain/ws", get(chain_subscribe_ws))
+            .route("/chain/propose", post(chain_propose))
+            .route("/chain/verify", post(chain_verify))
+            .route("/challenges", get(challenge_subscribe))
+            .route("/challenges/submit", post(challenge_submit))
+            .route("/audits", get(audit_subscribe))
+            .route("/audits/submit", post(audit_submit))
+            .route("/audits/summary", get(audit_summary))
+            .route("/quarantine", get(quarantine_summary))
+            .route("/protocol", get(handshake))
+            .route("/admin/reload", post(admin_reload))
+            .route("/workflows/validate", post(workflow_validate))
+            .route("/workflows/:digest", get(workflow_definition))
+            .route("/admin/backfill", get(backfill_report).post(backfill_start))
+            .route("/admin/gc", get(gc_report).post(gc_start))
+            .route("/canary", get(canary_summary))
+            .route("/canary/report", post(canary_report))
+            .route("/load", get(load_report))
+            .route("/partitions", get(partition_report))
+            .route("/stats", get(stats_report))
+            .route("/progress", get(progress_subscribe))
+            .route("/progress/ws", get(progress_subscribe_ws))
+            .route("/progress/report", post(progress_report))
+            .route("/tasks", get(task_snapshot))
+            .route("/tasks/:id/logs/:stage", put(log_upload).get(log_download))
+            .route("/tasks/:id/lineage", get(lineage))
+            .route("/tasks/:id/attribution", get(attribution))
+            .route("/attribution/epochs/:epoch", get(epoch_summary))
+            .route("/tasks/:id/stages/:stage", get(stage_record))
+            .route("/tasks/:id/outputs/:name", get(named_output))
+            .route("/tasks/:id/notarize", get(notarize))
+            .route("/tasks/:id/header", get(header))
+            .route("/tasks/:id/header/notarize", get(notarize_header))
+            .route("/tasks/:id/history", get(task_history))
+            .route("/tasks/:id/migrate", post(migrate))
+            .route("/tasks/:id/audits", get(task_audits))
+            .route("/tasks/:id/quarantine", get(task_quarantine))
+            .route("/cache/:workflow/:input", get(cache_lookup))
+            .route("/blobs", post(blob_upload))
+            .route("/blobs/:digest", get(blob_download))
+            .route("/scheduler", get(scheduler_summary))
+            .route("/scheduler/report", post(scheduler_report))
+            .route("/leases", get(lease_summary).post(lease_acquire))
+            .route("/leases/:lease", delete(lease_release))
+            .route("/leases/:lease/renew", post(lease_renew))
+            .route("/secrets", post(secrets_fetch));
+        if self.shared.mirror.is_some() {
+            routes = routes.layer(middleware::from_fn_with_state(
+                self.shared.clone(),
+                mirror::refuse_writes,
+            ))
+        }
+        let mut router = routes.with_state(self.shared.clone());
+        if let Some(raft) = &self.shared.raft {
+            router = router.merge(raft::router().with_state(raft.clone()))
+        }
+        router
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> anyhow::Result<()> {
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+}
+
+type C = OrdinaryClock;
+type GossipMessage = TaskStage<C, Bytes>;
+type ChainMessage = TaskResult<C, Bytes>;
+
+// everything that changes what the subscribers observe goes through a `HubEvent`, so that a
+// replicated hub can agree on the events before anyone observes them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HubEvent {
+    Gossip(GossipMessage),
+    Chain(ChainMessage),
+    // a successful challenge, which reverts the challenged result
+    Challenge(Challenge),
+    Audit(Audit),
+    // an in-flight task moved to another workflow version, see `migration`
+    Migration(Migration),
+    // a message naming stages its workflow version does not have, see `quarantine`
+    Quarantine(Quarantine),
+}
+
+// the outcome of applying an event, which the publisher is acknowledged with (see `commit`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Applied {
+    // the number of the event in the history, `None` if it is kept already (e.g. a publish retried
+    // after the hub applied it) or is not a part of the history
+    pub seq: Option<u64>,
+    // why the event is not kept, if it is not. it is fanned out regardless
+    pub failure: Option<String>,
+}
+
+impl IntoResponse for Applied {
+    fn into_response(self) -> Response {
+        if let Some(failure) = self.failure {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("event is not kept: {failure}"),
+            )
+                .into_response();
+        }
+        match self.seq {
+            Some(seq) => {
+                (StatusCode::OK, [(protocol::SEQ_HEADER, seq.to_string())]).into_response()
+            }
+            None => StatusCode::OK.into_response(),
+        }
+    }
+}
+
+// how `POST /gossip/publish?ack=<mode>` is acknowledged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishAck {
+    // once the event is applied and kept, or committed by the raft group, with its sequence number
+    // in the `protocol::SEQ_HEADER` header
+    #[default]
+    Persisted,
+    // with 202 as soon as the message passes the checks, while the event is applied in the
+    // background, so a failure to keep it is only logged
+    None,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PublishQuery {
+    #[serde(default)]
+    ack: PublishAck,
+}
+
+#[derive(Clone)]
+struct Fanout {
+    // the gossip and the chain, by workflow
+    partitions: Arc<Partitions>,
+    // of the results, by the workflows they are of
+    task: Arc<RwLock<Workflows>>,
+    hooks: Arc<Hooks>,
+    challenges: Sender<Option<Challenge>>,
+    // progress events are not hub events, since they are neither agreed on nor kept. they are
+    // broadcast instead of watched, so a burst of them from one task does not hide the others
+    progress: broadcast::Sender<ProgressEvent>,
+    // where the events are kept for looking back later, e.g. by the backfill verification and the
+    // challenges
+    blobs: Arc<dyn BlobStore>,
+    // for indexing the results by their inputs
+    crypto: Arc<dyn CryptoSuite>,
+    max_blob_size: u64,
+    ledger: Arc<Ledger>,
+    sequence: Arc<Sequence>,
+    deadlines: Arc<Deadlines>,
+    // the aggregates of `/stats`, see `stats`
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Fanout {
+    fn new(
+        blobs: Arc<dyn BlobStore>,
+        crypto: Arc<dyn CryptoSuite>,
+        partitions: Partitions,
+        task: Arc<RwLock<Workflows>>,
+        hooks: Hooks,
+        max_blob_size: u64,
+    ) -> Self {
+        Self {
+            partitions: Arc::new(partitions),
+            task,
+            hooks: Arc::new(hooks),
+            challenges: Sender::new(None),
+            progress: broadcast::Sender::new(PROGRESS_CAPACITY),
+            blobs,
+            crypto,
+            max_blob_size,
+            ledger: Default::default(),
+            sequence: Default::default(),
+            deadlines: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    fn apply(&self, event: HubEvent) -> Applied {
+        let mut failure = None;
+        let mut failed = |what: &str, err: anyhow::Error| {
+            warn!("failed to keep {what}: {err}");
+            failure.get_or_insert(format!("{what}: {err}"));
+        };
+        let seq = self
+            .sequence
+            .keep(&*self.blobs, &*self.crypto, &event)
+            .unwrap_or_else(|err| {
+                failed("task history", err);
+                None
+            });
+        match event {
+            HubEvent::Gossip(message) => {
+                let kept = handoff::keep(&*self.blobs, &message)
+                    .and_then(|()| challenge::keep_gossip(&*self.blobs, &message));
+                if let Err(err) = kept {
+                    failed("gossip message", err)
+                }
+                self.deadlines.track(&message);
+                let task = self.task.read().unwrap().get(message.workflow);
+                self.stats.lock().unwrap().gossip(task.as_deref(), &message);
+                self.partitions.publish(seq, message);
+            }
+            HubEvent::Chain(message) => {
+                let kept = serde_json::to_vec(&message)
+                    .map_err(Into::into)
+                    .and_then(|data| {
+                        self.ledger
+                            .append(&*self.blobs, &*self.crypto, &message, &data)?;
+                        self.blobs.put(&chain_key(&message), data.into())
+                    })
+                    .and_then(|()| challenge::keep_accepted(&*self.blobs, &message))
+                    .and_then(|()| {
+                        cache::keep(&*self.blobs, &*self.crypto, &message, self.max_blob_size)
+                    });
+                if let Err(err) = kept {
+                    failed("chain result", err)
+                }
+                self.deadlines.finish(&message);
+                let task = self.task.read().unwrap().get(message.workflow);
+                self.stats.lock().unwrap().result(task.as_deref(), &message);
+                if let Some(message) = self.process(message) {
+                    self.partitions.accept(seq, message)
+                }
+            }
+            HubEvent::Challenge(challenge) => {
+                if let Err(err) = challenge::keep_reverted(&*self.blobs, &challenge) {
+                    failed("challenge", err)
+                }
+                self.stats.lock().unwrap().revert();
+                let _ = self.challenges.send(Some(challenge));
+            }
+            HubEvent::Audit(audit) => {
+                if let Err(err) = audit::keep(&*self.blobs, &audit) {
+                    failed("audit", err)
+                }
+            }
+            HubEvent::Quarantine(quarantine) => {
+                if let Err(err) = quarantine::keep(&*self.blobs, &quarantine) {
+                    failed("quarantine", err)
+                }
+            }
+            HubEvent::Migration(migration) => {
+                let kept = migration::keep(&*self.blobs, &migration)
+                    .and_then(|()| challenge::keep_gossip(&*self.blobs, &migration.message));
+                if let Err(err) = kept {
+                    failed("migration", err)
+                }
+                self.deadlines.track(&migration.message);
+                self.partitions.publish(seq, migration.message);
+            }
+        }
+        Applied { seq, failure }
+    }
+
+    // the copy of the result for the subscribers, `None` if it is withheld
+    fn process(&self, mut message: ChainMessage) -> Option<ChainMessage> {
+        if self.hooks.is_empty() {
+            return Some(message);
+        }
+        let processed = self
+            .task
+            .read()
+            .unwrap()
+            .get(message.workflow)
+            .ok_or(anyhow::format_err!("unknown workflow version"))
+            .and_then(|task| hook::process(&self.hooks, &task, &mut message));
+        match processed {
+            Ok(()) => Some(message),
+            Err(err) => {
+                warn!("withhold result of task {:08x}: {err}", message.id);
+                None
+            }
+        }
+    }
+}
+
+// every workflow version ever loaded is kept, since an in-flight task may still be running under
+// any of them. new tasks start under the current one
+struct Workflows {
+    current: WorkflowDigest,
+    versions: HashMap<WorkflowDigest, Arc<Workflow>>,
+}
+
+impl Workflows {
+    fn new(task: Workflow, crypto: &dyn CryptoSuite) -> Self {
+        let current = task.digest(crypto);
+        Self {
+            current,
+            versions: [(current, Arc::new(task))].into(),
+        }
+    }
+
+    fn add(&mut self, task: Workflow, crypto: &dyn CryptoSuite) -> WorkflowDigest {
+        let digest = task.digest(crypto);
+        self.versions.entry(digest).or_insert(Arc::new(task));
+        digest
+    }
+
+    fn swap(&mut self, task: Workflow, crypto: &dyn CryptoSuite) -> bool {
+        let digest = self.add(task, crypto);
+        std::mem::replace(&mut self.current, digest) != digest
+    }
+
+    fn get(&self, digest: Option<WorkflowDigest>) -> Option<Arc<Workflow>> {
+        self.versions.get(&digest.unwrap_or(self.current)).cloned()
+    }
+}
+
+// the canary reports are diagnostics of the workers that happen to report to this instance, so
+// they are neither replicated nor verified
+#[derive(Debug, Default, Serialize)]
+struct CanaryStats {
+    runs: u64,
+    mismatches: u64,
+    recent_mismatches: VecDeque<CanaryReport>,
+}
+
+const RECENT_CANARY_MISMATCHES: usize = 16;
+
+#[derive(Clone)]
+struct Shared {
+    fanout: Fanout,
+    path: Option<Arc<PathBuf>>,
+    task: Arc<RwLock<Workflows>>,
+    context: Arc<OrdinaryClientContext<Bytes>>,
+    policy: Arc<dyn ProgramPolicy + Send + Sync>,
+    crypto: Arc<dyn CryptoSuite>,
+    canaries: Arc<Mutex<BTreeMap<String, CanaryStats>>>,
+    blobs: Arc<dyn BlobStore>,
+    max_inline_size: usize,
+    max_blob_size: u64,
+    clock_limits: ClockLimits,
+    membership: Arc<Membership>,
+    reexecutor: Option<Arc<dyn Reexecutor>>,
+    secrets: Option<Arc<dyn SecretStore>>,
+    signer: Option<Arc<dyn Signer>>,
+    // the workflow versions of the ongoing streaming tasks
+    streams: Arc<Mutex<HashMap<TaskId, WorkflowDigest>>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    hints: Arc<Mutex<Hints>>,
+    leases: Arc<Mutex<lease::Leases>>,
+    load: Arc<Mutex<load::Load>>,
+    audits: Arc<Mutex<audit::Audits>>,
+    verifier: Arc<Verifier>,
+    backfill: Arc<Mutex<Report>>,
+    gc: Arc<Mutex<gc::Report>>,
+    retention: Option<Duration>,
+    prune_after: Option<Duration>,
+    epoch_length: Option<Duration>,
+    raft: Option<raft::Raft>,
+    // the primary this instance mirrors, see `mirror`
+    mirror: Option<Arc<str>>,
+}
+
+impl Shared {
+    fn check_inline_size(&self, size: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            size <= self.max_inline_size,
+            "inline payload of {size} bytes exceeds the limit of {} bytes, offload it into the \
+            blob store instead",
+            self.max_inline_size
+        );
+        Ok(())
+    }
+
+    // an offloaded payload is only reassembled up to the limit, see `BlobRef::check`
+    fn check_blob_size(&self, blob: &Option<BlobRef>) -> anyhow::Result<()> {
+        match blob {
+            Some(blob) => blob.check(self.max_blob_size),
+            None => Ok(()),
+        }
+    }
+
+    // the clocks are ordinary, so their zero entries can be compacted as well, and the entries of
+    // the retired nodes are pruned before they count against the limits
+    fn check_clocks(
+        &self,
+        clocks: &mut HashMap<String, C>,
+        iterations: &mut HashMap<String, Vec<C>>,
+        task: &Workflow,
+    ) -> anyhow::Result<()> {
+        let all = clocks.values_mut().chain(iterations.values_mut().flatten());
+        if !self.membership.is_empty() {
+            all.for_each(|clock| clock.prune(&self.membership))
+        } else if self.clock_limits.oversize == OversizePolicy::Compact {
+            all.for_each(OrdinaryClock::compact)
+        }
+        self.clock_limits.enforce(clocks, iterations, task)
+    }
+
+    // the full clocks of a message published as a delta, against the kept message of its base
+    fn apply_delta(&self, message: &mut GossipMessage) -> anyhow::Result<()> {
+        let Some(delta) = &message.clock_delta else {
+            return Ok(());
+        };
+        let base = challenge::kept_gossip(&*self.blobs, message.id, &delta.base)?;
+        message.apply_delta(&base.clocks)
+    }
+
+    // the input of a start stage against what the first stage of its workflow accepts, see
+    // `schema`. every chunk of a streaming task is checked on its own
+    fn check_input(&self, message: &GossipMessage, task: &Workflow) -> anyhow::Result<()> {
+        let Some(spec) = &task.input else {
+            return Ok(());
+        };
+        if message.source != StageSource::Start {
+            return Ok(());
+        }
+        if message.compression.is_none() {
+            let size = match &message.blob {
+                Some(blob) => blob.size,
+                None => message.input.len() as _,
+            };
+            spec.check_size(size)?
+        }
+        let input = if spec.inspects_input() {
+            challenge::payload(
+                &*self.blobs,
+                &message.input,
+                &message.blob,
+                message.compression,
+                self.max_blob_size,
+            )?
+        } else {
+            Bytes::new()
+        };
+        spec.check(message.content_type.as_deref(), &input)?;
+        Ok(())
+    }
+
+    // the worker the next stage of the message is assigned to, or else the hints for the workers
+    // racing on it, see `hint`
+    fn dispatch(&self, message: &mut GossipMessage, task: &Workflow) {
+        message.assignee = self.scheduler.lock().unwrap().assign(task, message);
+        let hints = self.hints.lock().unwrap().stamp(message);
+        message.hints = match message.assignee {
+            Some(_) => Default::default(),
+            None => hints,
+        }
+    }
+
+    // the route of every next stage must be the decision on the payload, which the hub makes
+    // itself for a new task, see `routing`
+    fn check_route(&self, message: &mut GossipMessage, task: &Workflow) -> anyhow::Result<()> {
+        let task = &*task.taken(&message.branches);
+        let next = task.downstream(&message.source);
+        for (next, routing) in next
+            .iter()
+            .filter_map(|next| Some((*next, task.routing.get(*next)?)))
+        {
+            let input = challenge::payload(
+                &*self.blobs,
+                &message.input,
+                &message.blob,
+                message.compression,
+                self.max_blob_size,
+            )?;
+            let shard = routing
+                .shard(&input)
+                .map_err(|err| Error::WorkflowMismatch(err.to_string()))?;
+            if message.source == StageSource::Start {
+                message.routes.entry(next.into()).or_insert(Route {
+                    shard,
+                    executed: None,
+                });
+            }
+            if let Some(route) = message
+                .routes
+                .get(next)
+                .filter(|route| route.shard != shard)
+            {
+                return Err(Error::WorkflowMismatch(format!(
+                    "stage {next} is routed to shard {} instead of {shard}",
+                    route.shard
+                ))
+                .into());
+            }
+        }
+        routing::verify_routes(&message.clocks, &message.routes, &next, task)?;
+        Ok(())
+    }
+
+    // the branch of the stage the message is from must be the decision on the payload, see
+    // `branching`
+    fn check_branch(&self, message: &GossipMessage, task: &Workflow) -> anyhow::Result<()> {
+        let StageSource::Name(stage) = &message.source else {
+            return Ok(());
+        };
+        if !task.branching.contains_key(stage) {
+            return Ok(());
+        }
+        let payload = challenge::payload(
+            &*self.blobs,
+            &message.input,
+            &message.blob,
+            message.compression,
+            self.max_blob_size,
+        )?;
+        branching::check_branch(&message.source, &message.branches, &payload, task)?;
+        Ok(())
+    }
+
+    // a looping stage the message is from must only stop before its bound on a payload its
+    // condition holds for, see `looping`
+    fn check_iterations(&self, message: &GossipMessage, task: &Workflow) -> anyhow::Result<()> {
+        let StageSource::Name(stage) = &message.source else {
+            return Ok(());
+        };
+        if !task.looping.contains_key(stage) {
+            return Ok(());
+        }
+        let payload = challenge::payload(
+            &*self.blobs,
+            &message.input,
+            &message.blob,
+            message.compression,
+            self.max_blob_size,
+        )?;
+        looping::check_iterations(&message.source, &message.iterations, &payload, task)?;
+        Ok(())
+    }
+
+    // the offloaded or compressed input is verified as reassembled and decompressed, as the output
+    // of a result
+    fn verify_stage(&self, message: &GossipMessage, task: &Workflow) -> anyhow::Result<()> {
+        if message.blob.is_none() && message.compression.is_none() {
+            message.verify_pruned(task, &*self.context, &self.membership)?
+        } else {
+            let input = challenge::payload(
+                &*self.blobs,
+                &message.input,
+                &message.blob,
+                message.compression,
+                self.max_blob_size,
+            )?;
+            TaskStage {
+                input,
+                blob: None,
+                compression: None,
+                ..message.clone()
+            }
+            .verify_pruned(task, &*self.context, &self.membership)?
+        }
+        Ok(())
+    }
+
+    // where the deployment places it, see `verifier`
+    async fn verify_gossip(
+        &self,
+        message: &GossipMessage,
+        task: &Arc<Workflow>,
+    ) -> anyhow::Result<()> {
+        match &*self.verifier {
+            Verifier::Skip => Ok(()),
+            Verifier::Inline => self.verify_stage(message, task),
+            Verifier::Offload(pool) => {
+                let (shared, message, task) = (self.clone(), message.clone(), task.clone());
+                pool.verify(move || shared.verify_stage(&message, &task))
+                    .await
+            }
+        }
+    }
+
+    // the offloaded or compressed output is verified as reassembled and decompressed
+    fn verify_result(&self, message: &ChainMessage, task: &Workflow) -> anyhow::Result<()> {
+        self.verify_results(std::slice::from_ref(message), task)
+            .pop()
+            .expect("a verification per result")
+    }
+
+    // in the same order, with the proofs of the outputs verified in one batch, see
+    // `TaskResult::verify_batch`
+    fn verify_results(
+        &self,
+        messages: &[ChainMessage],
+        task: &Workflow,
+    ) -> Vec<anyhow::Result<()>> {
+        let reassembled = messages
+            .iter()
+            .map(|message| self.reassembled(message))
+            .collect::<Vec<_>>();
+        let verifiable = reassembled
+            .iter()
+            .filter_map(|message| Some(&**message.as_ref().ok()?))
+            .collect::<Vec<_>>();
+        let mut verified =
+            TaskResult::verify_batch(&verifiable, task, &*self.context, &self.membership)
+                .into_iter();
+        reassembled
+            .into_iter()
+            .map(|message| {
+                let message = message?;
+                verified.next().expect("a verification per result")?;
+                if !task.outputs.is_empty() && message.expired.is_none() {
+                    message.named_outputs(task)?;
+                }
+                if message.expired.is_none() {
+                    check_final_iterations(&message, task)?;
+                }
+                message.verify_programs(task, &*self.policy)?;
+                Ok(())
+            })
+            .collect()
+    }
+
+    // everything `verify_results` finds in a result, rather than the first of it
+    fn report_result(
+        &self,
+        message: &ChainMessage,
+        task: &Workflow,
+    ) -> anyhow::Result<VerificationReport> {
+        let message = self.reassembled(message)?;
+        let mut report = message.report(task, &*self.context, &self.membership);
+        if !task.outputs.is_empty() && message.expired.is_none() {
+            if let Err(err) = message.named_outputs(task) {
+                report.push(None, Error::WorkflowMismatch(err.to_string()))
+            }
+        }
+        if message.expired.is_none() {
+            if let Err(err) = check_final_iterations(&message, task) {
+                report.push(None, err)
+            }
+        }
+        if let Err(err) = message.verify_programs(task, &*self.policy) {
+            report.push(None, err)
+        }
+        Ok(report)
+    }
+
+    fn reassembled<'a>(&self, message: &'a ChainMessage) -> anyhow::Result<Cow<'a, ChainMessage>> {
+        if message.blob.is_none() && message.compression.is_none() {
+            return Ok(Cow::Borrowed(message));
+        }
+        let output = challenge::payload(
+            &*self.blobs,
+            &message.output,
+            &message.blob,
+            message.compression,
+            self.max_blob_size,
+        )?;
+        Ok(Cow::Owned(TaskResult {
+            output,
+            blob: None,
+            compression: None,
+            ..message.clone()
+        }))
+    }
+
+    async fn reload(&self) -> anyhow::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or(anyhow::format_err!("workflow is not loaded from a file"))?;
+        let task = Workflow::parse(path, &fs::read_to_string(&**path).await?)?;
+        let missing = hook::missing(&self.fanout.hooks, &task);
+        anyhow::ensure!(missing.is_empty(), "unknown result hooks {missing:?}");
+        let digest = task.digest(&*self.crypto);
+        self.fanout.partitions.reload(digest);
+        if self.task.write().unwrap().swap(task, &*self.crypto) {
+            info!("workflow reloaded as version {}", hex(&digest))
+        }
+        Ok(())
+    }
+
+    // a standalone hub applies the event immediately. a replicated hub applies it after it is
+    // committed by the group, which happens on every member including this one. a member that is
+    // not the leader redirects the writer to the leader, and reqwest follows 307 redirects with
+    // the body preserved, so workers and clients are not aware of the redirection
+    // answers once the event is applied and kept, by this instance or by the raft group
+    async fn commit(&self, event: HubEvent, uri: &OriginalUri) -> Response {
+        let Some(raft) = &self.raft else {
+            return self.fanout.apply(event).into_response();
+        };
+        match raft.client_write(event).await {
+            Ok(response) => response.data.into_response(),
+            Err(err) => match err.forward_to_leader() {
+                Some(forward) => redirect(forward.leader_node.as_ref(), uri),
+                None => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+            },
+        }
+    }
+
+    // the publisher is answered with why once the message is kept, or redirected to the leader
+    async fn quarantine(&self, quarantine: Quarantine, uri: &OriginalUri) -> Response {
+        let reason = quarantine.reason();
+        warn!(
+            "quarantine message of task {:08x}: {reason}",
+            quarantine.message.id
+        );
+        let response = self.commit(HubEvent::Quarantine(quarantine), uri).await;
+        if !response.status().is_success() {
+            return response;
+        }
+        (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response()
+    }
+
+    // a standalone hub always leads, and a mirror never does
+    fn leads(&self) -> bool {
+        self.mirror.is_none()
+            && self.raft.as_ref().is_none_or(|raft| {
+                let metrics = raft.metrics().borrow().clone();
+                metrics.current_leader == Some(metrics.id)
+            })
+    }
+
+    // the writes that are not replicated, but kept by the leader only
+    fn forward_to_leader(&self, uri: &OriginalUri) -> Option<Response> {
+        let metrics = self.raft.as_ref()?.metrics().borrow().clone();
+        if metrics.current_leader == Some(metrics.id) {
+            return None;
+        }
+        let leader = metrics
+            .current_leader
+            .and_then(|id| metrics.membership_config.membership().get_node(&id));
+        Some(redirect(leader, uri))
+    }
+}
+
+// the last stage looping stops on the output of the result as any other on its payload, see
+// `Shared::check_iterations`
+fn check_final_iterations(message: &ChainMessage, task: &Workflow) -> Result<(), Error> {
+    let Some(last_stage) = task.stages.last() else {
+        return Ok(());
+    };
+    let source = StageSource::Name(last_stage.clone());
+    looping::check_iterations(&source, &message.iterations, &message.output, task)
+}
+
+// a message failing the verification is refused with the status of the failure, so the publisher
+// can tell a malformed message (400) from one out of order (409), with an invalid proof (403), or
+// not fitting the workflow (422). any other failure takes the `fallback`
+fn refused(err: anyhow::Error, fallback: StatusCode) -> Response {
+    let status = match err.downcast_ref::<Error>() {
+        Some(Error::MissingClock(_)) => StatusCode::BAD_REQUEST,
+        Some(Error::OrderViolation(_)) => StatusCode::CONFLICT,
+        Some(Error::ProofInvalid(_)) => StatusCode::FORBIDDEN,
+        Some(Error::WorkflowMismatch(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+        Some(Error::Transport(_)) => StatusCode::BAD_GATEWAY,
+        // the hub does not prove clocks itself
+        Some(Error::Proving(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        None => match err.downcast_ref::<Violation>() {
+            Some(Violation::TooLarge(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            Some(Violation::ContentType(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Some(Violation::Schema(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            None => fallback,
+        },
+    };
+    (status, err.to_string()).into_response()
+}
+
+fn redirect(leader: Option<&BasicNode>, uri: &OriginalUri) -> Response {
+    match leader {
+        Some(leader) => (
+            StatusCode::TEMPORARY_REDIRECT,
+            [(LOCATION, format!("http://{}{}", leader.addr, uri.0))],
+        )
+            .into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "no leader elected").into_response(),
+    }
+}
+
+// polling the modification time is good enough for a file that is edited by hand once in a while
+async fn watch_workflow(shared: Shared) {
+    let Some(path) = shared.path.clone() else {
+        return;
+    };
+    let mut modified = None;
+    let mut interval = interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let Ok(metadata) = fs::metadata(&*path).await else {
+            continue;
+        };
+        let Ok(new_modified) = metadata.modified() else {
+            continue;
+        };
+        if modified
+            .replace(new_modified)
+            .is_some_and(|modified| modified != new_modified)
+        {
+            if let Err(err) = shared.reload().await {
+                warn!("failed to reload workflow: {err}")
+            }
+        }
+    }
+}
+
+async fn admin_reload(shared: State<Shared>) -> Response {
+    match shared.reload().await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+// the versions this instance knows, by digest or `current`, for the workers to run the same
+// definitions as the hub verifies against
+async fn workflow_definition(shared: State<Shared>, Path(digest): Path<String>) -> Response {
+    let digest = match &*digest {
+        "current" => None,
+        digest => match parse_digest(digest) {
+            Some(digest) => Some(digest),
+            None => return (StatusCode::BAD_REQUEST, "malformed digest").into_response(),
+        },
+    };
+    match shared.task.read().unwrap().get(digest) {
+        Some(task) => Json(&*task).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// the workers are known to the leader only
+async fn workflow_validate(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Json(request): Json<validate::Request>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    Json(validate::validate(&shared, request)).into_response()
+}
+
+async fn load_report(shared: State<Shared>) -> Response {
+    Json(shared.load.lock().unwrap().report()).into_response()
+}
+
+async fn partition_report(shared: State<Shared>) -> Response {
+    shared
+        .fanout
+        .partitions
+        .report(|reports| Json(reports).into_response())
+}
+
+async fn stats_report(shared: State<Shared>, Query(query): Query<StatsQuery>) -> Response {
+    let report = shared
+        .fanout
+        .stats
+        .lock()
+        .unwrap()
+        .report(&query, |workflow| shared.task.read().unwrap().get(workflow));
+    Json(report).into_response()
+}
+
+async fn backfill_start(shared: State<Shared>) -> Response {
+    if backfill::start(&shared) {
+        StatusCode::ACCEPTED.into_response()
+    } else {
+        (StatusCode::CONFLICT, "backfill verification is running").into_response()
+    }
+}
+
+async fn backfill_report(shared: State<Shared>) -> Response {
+    Json(&*shared.backfill.lock().unwrap()).into_response()
+}
+
+async fn gc_start(shared: State<Shared>) -> Response {
+    if gc::start(&shared) {
+        StatusCode::ACCEPTED.into_response()
+    } else {
+        (StatusCode::CONFLICT, "garbage collection is running").into_response()
+    }
+}
+
+async fn gc_report(shared: State<Shared>) -> Response {
+    Json(&*shared.gc.lock().unwrap()).into_response()
+}
+
+async fn handshake() -> Json<protocol::Handshake> {
+    Json(Default::default())
+}
+
+fn requested_version(headers: &HeaderMap) -> anyhow::Result<u32> {
+    let value = headers
+        .get(protocol::HEADER)
+        .map(|value| value.to_str())
+        .transpose()?;
+    protocol::parse_header(value)
+}
+
+fn parse_message<M: DeserializeOwned>(
+    headers: &HeaderMap,
+    mut message: Value,
+) -> anyhow::Result<M> {
+    requested_version(headers)?;
+    protocol::upgrade(&mut message)?;
+    Ok(serde_json::from_value(message)?)
+}
+
+fn encode(message: impl Serialize, version: u32) -> anyhow::Result<Value> {
+    let mut message = serde_json::to_value(message)?;
+    protocol::downgrade(&mut message, version)?;
+    Ok(message)
+}
+
+fn watch<M: Clone + Send + Sync + 'static>(
+    sender: &Sender<Option<M>>,
+) -> impl Stream<Item = M> + Send + 'static {
+    WatchStream::new(sender.subscribe()).filter_map(identity)
+}
+
+// a lagging subscriber misses the overwritten events
+fn broadcast<M: Clone + Send + 'static>(
+    sender: &broadcast::Sender<M>,
+) -> impl Stream<Item = M> + Send + 'static {
+    BroadcastStream::new(sender.subscribe()).filter_map(Result::ok)
+}
+
+fn subscribe<M: Serialize + Send + 'static>(
+    stream: impl Stream<Item = M> + Send + 'static,
+    headers: &HeaderMap,
+) -> Response {
+    subscribe_numbered(stream.map(Numbered::unnumbered), headers, None)
+}
+
+// the events carry the numbers of the messages as their ids, so the subscriber can resume after
+// the last one it has received, and a message after missed ones follows a gap event, see `resume`.
+// the events start with a resume event carrying `token` if any
+fn subscribe_numbered<M: Serialize + Send + 'static>(
+    stream: impl Stream<Item = Numbered<M>> + Send + 'static,
+    headers: &HeaderMap,
+    token: Option<String>,
+) -> Response {
+    let version = match requested_version(headers) {
+        Ok(version) => version,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        if let Some(token) = token {
+            let resume = Event::default()
+                .event(resume::RESUME_EVENT)
+                .id(token)
+                .data("");
+            if sender.send(Ok(resume)).await.is_err() {
+                return;
+            }
+        }
+        let mut stream = pin!(stream);
+        loop {
+            let numbered = select! {
+                () = sender.closed() => break,
+                numbered = stream.next() => numbered,
+            };
+            let Some(numbered) = numbered else {
+                break;
+            };
+            if numbered.missed {
+                let gap = Event::default().event(resume::GAP_EVENT).data("");
+                if sender.send(Ok(gap)).await.is_err() {
+                    break;
+                }
+            }
+            let event = encode(numbered.message, version)
+                .map_err(|err| axum::Error::new(err.to_string()))
+                .and_then(|message| Event::default().json_data(message))
+                .map(|event| match numbered.seq {
+                    Some(seq) => event.id(seq.to_string()),
+                    None => event,
+                });
+            if sender.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    Sse::new(ReceiverStream::new(receiver)).into_response()
+}
+
+// the same subscription over a websocket, one text message per event. the socket is only written
+// by the hub, and writes keep going through the plain http endpoints
+fn subscribe_ws<M: Serialize + Send + 'static>(
+    stream: impl Stream<Item = M> + Send + 'static,
+    headers: &HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let version = match requested_version(headers) {
+        Ok(version) => version,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    upgrade.on_upgrade(move |mut socket| async move {
+        let mut stream = pin!(stream);
+        while let Some(message) = stream.next().await {
+            let message = match encode(message, version) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("failed to encode message: {err}");
+                    break;
+                }
+            };
+            if socket
+                .send(Message::Text(message.to_string()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
+
+const PROGRESS_CAPACITY: usize = 1024;
+
+async fn progress_subscribe(shared: State<Shared>, headers: HeaderMap) -> Response {
+    subscribe(broadcast(&shared.fanout.progress), &headers)
+}
+
+async fn progress_subscribe_ws(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    subscribe_ws(broadcast(&shared.fanout.progress), &headers, upgrade)
+}
+
+async fn progress_report(shared: State<Shared>, Json(event): Json<ProgressEvent>) {
+    let _ = shared.fanout.progress.send(event);
+}
+
+async fn blob_upload(shared: State<Shared>, blob: Bytes) -> Response {
+    if blob.len() > CHUNK_SIZE {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "blob exceeds the chunk size").into_response();
+    }
+    let digest = shared.crypto.digest(&blob);
+    match shared.blobs.put(&blob_key(&digest), blob) {
+        Ok(()) => Json(digest).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn blob_download(shared: State<Shared>, Path(digest): Path<String>) -> Response {
+    if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return (StatusCode::BAD_REQUEST, "malformed digest").into_response();
+    }
+    match shared
+        .blobs
+        .get(&format!("blobs/{}", digest.to_ascii_lowercase()))
+    {
+        Ok(Some(blob)) => blob.into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+fn log_key(id: TaskId, stage: &str) -> String {
+    format!("tasks/{id}/logs/{stage}")
+}
+
+// the log of a streaming task is the one of the latest chunk
+async fn log_upload(
+    shared: State<Shared>,
+    Path((id, stage)): Path<(TaskId, String)>,
+    log: Bytes,
+) -> Response {
+    let digest = shared.crypto.digest(&log);
+    match shared.blobs.put(&log_key(id, &stage), log) {
+        Ok(()) => Json(digest).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn log_download(
+    shared: State<Shared>,
+    Path((id, stage)): Path<(TaskId, String)>,
+) -> Response {
+    match shared.blobs.get(&log_key(id, &stage)) {
+        Ok(Some(log)) => log.into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn scheduler_report(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Json(status): Json<WorkerStatus>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    shared.scheduler.lock().unwrap().report(status);
+    StatusCode::OK.into_response()
+}
+
+async fn scheduler_summary(shared: State<Shared>) -> Response {
+    Json(shared.scheduler.lock().unwrap().workers()).into_response()
+}
+
+// a stage without a limit is leased without bound, the workers just do not ask for it
+async fn lease_acquire(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Json(request): Json<LeaseRequest>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    let Some(task) = shared.task.read().unwrap().get(request.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    if !task.stages.contains(&request.stage) {
+        return (StatusCode::NOT_FOUND, "unknown stage").into_response();
+    }
+    let limit = task
+        .concurrency
+        .get(&request.stage)
+        .copied()
+        .unwrap_or(u32::MAX);
+    match shared.leases.lock().unwrap().acquire(&request.stage, limit) {
+        Some(lease) => Json(lease).into_response(),
+        None => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "stage is at its concurrency limit",
+        )
+            .into_response(),
+    }
+}
+
+async fn lease_renew(shared: State<Shared>, uri: OriginalUri, Path(lease): Path<u64>) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    if shared.leases.lock().unwrap().renew(lease) {
+        StatusCode::OK.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+async fn lease_release(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Path(lease): Path<u64>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    shared.leases.lock().unwrap().release(lease);
+    StatusCode::OK.into_response()
+}
+
+// of the stages limited by the current workflow version
+async fn lease_summary(shared: State<Shared>) -> Response {
+    let task = shared
+        .task
+        .read()
+        .unwrap()
+        .get(None)
+        .expect("current workflow is known");
+    Json(shared.leases.lock().unwrap().summary(&task.concurrency)).into_response()
+}
+
+// not forwarded to the leader, since every member serves the secrets of its own store
+async fn secrets_fetch(shared: State<Shared>, Json(request): Json<SecretRequest>) -> Response {
+    let Some(store) = &shared.secrets else {
+        return (StatusCode::NOT_FOUND, "no secrets").into_response();
+    };
+    match secrets::seal(&**store, &*shared.crypto, &request) {
+        Ok(sealed) => Json(sealed).into_response(),
+        Err(err) => {
+            warn!("refuse secrets of stage {}: {err}", request.stage);
+            (StatusCode::FORBIDDEN, err.to_string()).into_response()
+        }
+    }
+}
+
+async fn canary_report(shared: State<Shared>, Json(report): Json<CanaryReport>) {
+    let mut canaries = shared.canaries.lock().unwrap();
+    let stats = canaries.entry(report.stage.clone()).or_default();
+    stats.runs += 1;
+    if report.diff_offset.is_some() {
+        stats.mismatches += 1;
+        if stats.recent_mismatches.len() == RECENT_CANARY_MISMATCHES {
+            stats.recent_mismatches.pop_front();
+        }
+        stats.recent_mismatches.push_back(report)
+    }
+}
+
+async fn canary_summary(shared: State<Shared>) -> Response {
+    Json(&*shared.canaries.lock().unwrap()).into_response()
+}
+
+async fn gossip_subscribe(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Query(scope): Query<Scope>,
+) -> Response {
+    let resumed = scope.version().and_then(|version| {
+        let partitions = &shared.fanout.partitions;
+        let live = partitions.gossip(version);
+        let Some(from) = resume::resume_from(&headers)? else {
+            return Ok(live);
+        };
+        let replayed = resume::gossip(&*shared.blobs, &*shared.crypto, from)?
+            .into_iter()
+            .filter(|replayed| partitions.covers(version, replayed.message.workflow))
+            .collect();
+        Ok(Box::pin(resume::resumed(from, replayed, live)) as Merged<_>)
+    });
+    match resumed {
+        Ok(messages) => subscribe_numbered(messages, &headers, None),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn gossip_subscribe_ws(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Query(scope): Query<Scope>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    match scope.version() {
+        Ok(version) => subscribe_ws(
+            shared
+                .fanout
+                .partitions
+                .gossip(version)
+                .map(|numbered| numbered.message),
+            &headers,
+            upgrade,
+        ),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn gossip_publish(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    Query(query): Query<PublishQuery>,
+    Json(message): Json<Value>,
+) -> Response {
+    // a follower of a raft group cannot redirect the publisher once it has answered
+    if query.ack == PublishAck::None {
+        if let Some(redirect) = shared.forward_to_leader(&uri) {
+            return redirect;
+        }
+    }
+    let mut message = match parse_message::<GossipMessage>(&headers, message) {
+        Ok(message) => message,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    if let Err(err) = shared
+        .check_inline_size(message.input.len())
+        .and_then(|()| shared.check_blob_size(&message.blob))
+    {
+        return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+    }
+    // ahead of anything looking at the clocks. a base superseded since is a conflict, upon which
+    // the publisher sends the full clocks instead
+    if let Err(err) = shared.apply_delta(&mut message) {
+        return (StatusCode::CONFLICT, err.to_string()).into_response();
+    }
+    // a new task is the start of an ordinary task or of the first chunk of a streaming one
+    let new =
+        message.source == StageSource::Start && message.chunk.is_none_or(|chunk| chunk.seq == 0);
+    // the messages without a version are of the workflow of the hub, whichever version they end
+    // up with
+    let version = message
+        .workflow
+        .unwrap_or_else(|| shared.task.read().unwrap().current);
+    let admitted = shared
+        .fanout
+        .partitions
+        .admit(Some(version), new.then_some(message.priority))
+        .and_then(|()| {
+            shared
+                .load
+                .lock()
+                .unwrap()
+                .admit(new.then_some(message.priority))
+        });
+    if let Err(retry_after) = admitted {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(
+                RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().max(1.).to_string(),
+            )],
+            "hub is overloaded",
+        )
+            .into_response();
+    }
+    let checked = 'checks: {
+        let task = shared.task.read().unwrap();
+        if message.workflow.is_none() && message.source == StageSource::Start {
+            // the chunks of a streaming task keep running under the version of the first chunk
+            let mut streams = shared.streams.lock().unwrap();
+            let workflow = match message.chunk {
+                Some(chunk) if chunk.seq != 0 => {
+                    streams.get(&message.id).copied().unwrap_or(task.current)
+                }
+                _ => task.current,
+            };
+            match message.chunk {
+                Some(chunk) if chunk.last => {
+                    streams.remove(&message.id);
+                }
+                Some(_) => {
+                    streams.insert(message.id, workflow);
+                }
+                None => {}
+            }
+            message.workflow = Some(workflow)
+        }
+        let version = message.workflow.unwrap_or(task.current);
+        match migration::superseded(&*shared.blobs, message.id, version) {
+            Ok(false) => {}
+            Ok(true) => return (StatusCode::CONFLICT, "task is migrated").into_response(),
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        }
+        if let Err(err) = check_handoff(&message, &task) {
+            return (StatusCode::BAD_REQUEST, err.to_string()).into_response();
+        }
+        let Some(task) = task.get(message.workflow) else {
+            return (StatusCode::BAD_REQUEST, "unknown workflow version").into_response();
+        };
+        // ahead of the other checks, which would refuse it for a symptom of the mismatch
+        match quarantine::check(&message, &task, version) {
+            Ok(None) => {}
+            Ok(Some(quarantine)) => break 'checks Err(quarantine),
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        }
+        if let Err(err) = shared.check_clocks(&mut message.clocks, &mut message.iterations, &task) {
+            return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+        }
+        if let Err(err) = shared.check_input(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_route(&mut message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_branch(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_iterations(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if new {
+            match deadline::stamp(message.deadline, &task) {
+                Ok(deadline) => message.deadline = deadline,
+                Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            }
+        } else {
+            match deadline::expired(&*shared.blobs, message.id) {
+                Ok(false) => {}
+                Ok(true) => return (StatusCode::GONE, "task has expired").into_response(),
+                Err(err) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+                }
+            }
+            // the one tracked for the task rather than whatever the publisher says, see
+            // `Worker::join`
+            message.deadline = shared.fanout.deadlines.get(message.id)
+        }
+        if let Err(err) = message.verify_programs(&task, &*shared.policy) {
+            return refused(err.into(), StatusCode::FORBIDDEN);
+        }
+        if message.accept_cached {
+            match cached_result(&shared, &message, &headers) {
+                Ok(Some(result)) => return Json(result).into_response(),
+                Ok(None) => {}
+                Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            }
+        }
+        Ok(task)
+    };
+    let task = match checked {
+        Ok(task) => task,
+        Err(quarantine) => return shared.quarantine(quarantine, &uri).await,
+    };
+    match shared.verify_gossip(&message, &task).await {
+        Ok(()) => {}
+        Err(err) if err.is::<verifier::Saturated>() => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, "1")],
+                err.to_string(),
+            )
+                .into_response()
+        }
+        Err(err) => return refused(err, StatusCode::BAD_REQUEST),
+    }
+    shared.dispatch(&mut message, &task);
+    if query.ack == PublishAck::None {
+        let id = message.id;
+        tokio::spawn(async move {
+            let response = shared.commit(HubEvent::Gossip(message), &uri).await;
+            if !response.status().is_success() {
+                warn!(
+                    "failed to apply message of task {id:08x}: {}",
+                    response.status()
+                )
+            }
+        });
+        return StatusCode::ACCEPTED.into_response();
+    }
+    shared.commit(HubEvent::Gossip(message), &uri).await
+}
+
+// the kept result for an ordinary start stage, encoded in the version of the publisher
+fn cached_result(
+    shared: &Shared,
+    message: &GossipMessage,
+    headers: &HeaderMap,
+) -> anyhow::Result<Option<Value>> {
+    anyhow::ensure!(
+        message.source == StageSource::Start && message.chunk.is_none(),
+        "only the start stage of an ordinary task can accept a cached result"
+    );
+    let workflow = message.workflow.expect("workflow version is filled in");
+    let input = cache::input_digest(
+        &*shared.blobs,
+        &*shared.crypto,
+        message,
+        shared.max_blob_size,
+    )?;
+    let Some(result) = cache::lookup(&*shared.blobs, &workflow, &input)? else {
+        return Ok(None);
+    };
+    info!(
+        "task {:08x} is served the cached result of task {:08x}",
+        message.id, result.id
+    );
+    Ok(Some(encode(result, requested_version(headers)?)?))
+}
+
+async fn chain_subscribe(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Query(filter): Query<ChainFilter>,
+) -> Response {
+    let resumed = Filter::new(filter).and_then(|filter| {
+        let live = shared.fanout.partitions.chain(filter.workflow());
+        let Some(from) = resume::resume_from(&headers)? else {
+            // read after subscribing, so a result applied in between is delivered live or
+            // replayed upon resuming
+            let token = resume::token(history::size(&*shared.blobs)?);
+            return Ok((filter::filtered(&shared, filter, live), Some(token)));
+        };
+        // the filter checks the workflow of the replayed results
+        let replayed = resume::chain(&*shared.blobs, &*shared.crypto, from)?;
+        let results = Box::pin(resume::resumed(from, replayed, live));
+        Ok((filter::filtered(&shared, filter, results), None))
+    });
+    match resumed {
+        Ok((results, token)) => subscribe_numbered(results, &headers, token),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn chain_subscribe_ws(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Query(filter): Query<ChainFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    match Filter::new(filter) {
+        Ok(filter) => {
+            let live = shared.fanout.partitions.chain(filter.workflow());
+            let results = filter::filtered(&shared, filter, live);
+            subscribe_ws(results.map(|numbered| numbered.message), &headers, upgrade)
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn chain_propose(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    let mut message = match parse_message::<ChainMessage>(&headers, message) {
+        Ok(message) => message,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    // the terminal records of the expired tasks are only committed by the hub itself
+    if message.expired.is_some() {
+        return (StatusCode::FORBIDDEN, "only the hub expires tasks").into_response();
+    }
+    match deadline::expired(&*shared.blobs, message.id) {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::GONE, "task has expired").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    if let Err(err) = shared
+        .check_inline_size(message.output.len())
+        .and_then(|()| shared.check_blob_size(&message.blob))
+    {
+        return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+    }
+    let version = message
+        .workflow
+        .unwrap_or_else(|| shared.task.read().unwrap().current);
+    match migration::superseded(&*shared.blobs, message.id, version) {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::CONFLICT, "task is migrated").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    let Some(task) = shared.task.read().unwrap().get(message.workflow) else {
+        return (StatusCode::BAD_REQUEST, "unknown workflow version").into_response();
+    };
+    if let Err(err) = shared.check_clocks(&mut message.clocks, &mut message.iterations, &task) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+    }
+    // committed as referenced, if offloaded
+    if let Err(err) = shared.verify_result(&message, &task) {
+        return refused(err, StatusCode::FORBIDDEN);
+    }
+    if message.chunk.is_none_or(|chunk| chunk.last) {
+        shared.scheduler.lock().unwrap().finish(message.id);
+        shared.hints.lock().unwrap().finish(message.id)
+    }
+    let response = shared.commit(HubEvent::Chain(message.clone()), &uri).await;
+    if response.status() == StatusCode::OK && message.chunk.is_none() {
+        shared.audits.lock().unwrap().sample(&task, &message);
+        hand_off(&shared, &message, &uri).await
+    }
+    response
+}
+
+// a dry run of the verification of `chain_propose`, answering every finding rather than refusing
+// on the first, without committing anything
+async fn chain_verify(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    let mut message = match parse_message::<ChainMessage>(&headers, message) {
+        Ok(message) => message,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    let Some(task) = shared.task.read().unwrap().get(message.workflow) else {
+        return (StatusCode::BAD_REQUEST, "unknown workflow version").into_response();
+    };
+    if let Err(err) = shared.check_clocks(&mut message.clocks, &mut message.iterations, &task) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+    }
+    match shared.report_result(&message, &task) {
+        Ok(report) => Json(report).into_response(),
+        // the output cannot be reassembled to verify
+        Err(err) => refused(err, StatusCode::FORBIDDEN),
+    }
+}
+
+async fn challenge_subscribe(shared: State<Shared>, headers: HeaderMap) -> Response {
+    subscribe(watch(&shared.fanout.challenges), &headers)
+}
+
+// re-executing is expensive, so it is done by the leader only, which commits the challenge
+async fn challenge_submit(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Json(challenge): Json<Challenge>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    let Some(reexecutor) = &shared.reexecutor else {
+        return (StatusCode::NOT_IMPLEMENTED, "no re-executor").into_response();
+    };
+    let result = match backfill::kept_result(&*shared.blobs, challenge.id) {
+        Ok(Some(result)) => result,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    if result.expired.is_some() {
+        return (StatusCode::CONFLICT, "task has expired without a result").into_response();
+    }
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    if let Err(err) = challenge::check_window(&*shared.blobs, &task, challenge.id) {
+        return (StatusCode::CONFLICT, err.to_string()).into_response();
+    }
+    let verified = challenge::verify(
+        &*shared.blobs,
+        &**reexecutor,
+        &task,
+        &result,
+        &challenge,
+        shared.max_blob_size,
+    )
+    .await;
+    if let Err(err) = verified {
+        return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response();
+    }
+    warn!(
+        "result of task {:08x} is reverted by challenging stage {}",
+        challenge.id, challenge.stage
+    );
+    shared.commit(HubEvent::Challenge(challenge), &uri).await
+}
+
+async fn audit_subscribe(shared: State<Shared>, headers: HeaderMap) -> Response {
+    let requests = broadcast(shared.audits.lock().unwrap().requests());
+    subscribe(requests, &headers)
+}
+
+// the outcome of a requested audit, which the leader records
+async fn audit_submit(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Json(outcome): Json<AuditOutcome>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    if outcome
+        .score
+        .is_some_and(|score| !(0. ..=1.).contains(&score))
+    {
+        return (StatusCode::BAD_REQUEST, "score out of range").into_response();
+    }
+    let Some(request) = shared
+        .audits
+        .lock()
+        .unwrap()
+        .take(outcome.id, &outcome.stage)
+    else {
+        return (StatusCode::NOT_FOUND, "audit is not requested").into_response();
+    };
+    let audit = Audit {
+        request,
+        auditor: outcome.auditor,
+        verdict: outcome.verdict,
+        score: outcome.score,
+    };
+    shared.commit(HubEvent::Audit(audit), &uri).await
+}
+
+async fn audit_summary(shared: State<Shared>) -> Response {
+    match audit::summary(&*shared.blobs) {
+        Ok(summary) => Json(summary).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn task_audits(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    match audit::audits(&*shared.blobs, id) {
+        Ok(audits) => Json(audits).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn quarantine_summary(shared: State<Shared>) -> Response {
+    match quarantine::all(&*shared.blobs) {
+        Ok(quarantined) => Json(quarantined).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn task_quarantine(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    match quarantine::quarantined(&*shared.blobs, id) {
+        Ok(quarantined) => Json(quarantined).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+fn check_handoff(message: &GossipMessage, task: &Workflows) -> anyhow::Result<()> {
+    let mut handoff = message.handoff.as_ref();
+    if handoff.is_some() {
+        anyhow::ensure!(
+            message.source == StageSource::Start && message.chunk.is_none(),
+            "only the start stage of an ordinary task can declare a handoff"
+        )
+    }
+    while let Some(next) = handoff {
+        anyhow::ensure!(next.id != message.id, "task is handed off to itself");
+        anyhow::ensure!(
+            task.get(Some(next.workflow)).is_some(),
+            "unknown workflow version to hand off to"
+        );
+        handoff = next.then.as_deref()
+    }
+    Ok(())
+}
+
+// the upstream result is already accepted at this point, so a failed handoff is only logged
+async fn hand_off(shared: &Shared, result: &ChainMessage, uri: &OriginalUri) {
+    let mut message = match handoff::start(&*shared.blobs, result) {
+        Ok(Some(message)) => message,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("failed to hand off task {:08x}: {err}", result.id);
+            return;
+        }
+    };
+    if let Some(task) = shared.task.read().unwrap().get(message.workflow) {
+        // the output of the upstream task may not be what the downstream one accepts
+        if let Err(err) = shared.check_input(&message, &task) {
+            warn!("failed to hand off task {:08x}: {err}", result.id);
+            return;
+        }
+        if let Err(err) = shared.check_route(&mut message, &task) {
+            warn!("failed to hand off task {:08x}: {err}", result.id);
+            return;
+        }
+        message.deadline = deadline::stamp(None, &task).unwrap_or_default();
+        shared.dispatch(&mut message, &task)
+    }
+    info!("hand off task {:08x} to task {:08x}", result.id, message.id);
+    let response = shared.commit(HubEvent::Gossip(message), uri).await;
+    if response.status() != StatusCode::OK {
+        warn!(
+            "failed to hand off task {:08x}: {}",
+            result.id,
+            response.status()
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MigrateQuery {
+    // the hex encoded version to migrate to, the current one by default
+    to: Option<String>,
+}
+
+// the pending message of the task is verified in full under the version migrated to, however the
+// gossip is verified otherwise (see `verifier`), so the task is never let through unverified
+async fn migrate(
+    shared: State<Shared>,
+    uri: OriginalUri,
+    Path(id): Path<TaskId>,
+    Query(query): Query<MigrateQuery>,
+) -> Response {
+    if let Some(response) = shared.forward_to_leader(&uri) {
+        return response;
+    }
+    let to = match query.to.as_deref().map(parse_digest) {
+        None => None,
+        Some(Some(to)) => Some(to),
+        Some(None) => return (StatusCode::BAD_REQUEST, "malformed digest").into_response(),
+    };
+    match backfill::kept_result(&*shared.blobs, id) {
+        Ok(None) => {}
+        Ok(Some(_)) => return (StatusCode::CONFLICT, "task is finished").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    match deadline::expired(&*shared.blobs, id) {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::GONE, "task has expired").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    let migration = {
+        let workflows = shared.task.read().unwrap();
+        let to = to.unwrap_or(workflows.current);
+        let Some(task) = workflows.get(Some(to)) else {
+            return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+        };
+        let mut pending = migration::pending(&task, &*shared.blobs, id);
+        let mut message = match pending.len() {
+            0 => return StatusCode::NOT_FOUND.into_response(),
+            1 => pending.remove(0),
+            _ => {
+                return (StatusCode::CONFLICT, "task has several branches in flight")
+                    .into_response()
+            }
+        };
+        if message.chunk.is_some() {
+            return (
+                StatusCode::CONFLICT,
+                "only an ordinary task can be migrated",
+            )
+                .into_response();
+        }
+        let from = message.workflow.unwrap_or(workflows.current);
+        if from == to {
+            return (
+                StatusCode::CONFLICT,
+                "task is running under the workflow version",
+            )
+                .into_response();
+        }
+        let Some(previous) = workflows.get(Some(from)) else {
+            return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+        };
+        if let Err(err) = migration::compatible(&previous, &task, &message.source) {
+            return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response();
+        }
+        message.workflow = Some(to);
+        if let Err(err) = shared.check_clocks(&mut message.clocks, &mut message.iterations, &task) {
+            return (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response();
+        }
+        if let Err(err) = shared.check_input(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_route(&mut message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_branch(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = shared.check_iterations(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        if let Err(err) = message.verify_programs(&task, &*shared.policy) {
+            return refused(err.into(), StatusCode::FORBIDDEN);
+        }
+        if let Err(err) = shared.verify_stage(&message, &task) {
+            return refused(err, StatusCode::BAD_REQUEST);
+        }
+        shared.dispatch(&mut message, &task);
+        info!(
+            "migrate task {id:08x} from workflow version {} to {}",
+            &hex(&from)[..8],
+            &hex(&to)[..8]
+        );
+        Migration { from, message }
+    };
+    shared.commit(HubEvent::Migration(migration), &uri).await
+}
+
+async fn attribution(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    match challenge::reverted(&*shared.blobs, id) {
+        Ok(None) => {}
+        Ok(Some(_)) => return (StatusCode::GONE, "result is reverted").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    let result = match backfill::kept_result(&*shared.blobs, id) {
+        Ok(Some(result)) => result,
+        // as computed when the result was pruned
+        Ok(None) => {
+            return match ledger::pruned(&*shared.blobs, id) {
+                Ok(Some(header)) => Json(header.attribution).into_response(),
+                Ok(None) => StatusCode::NOT_FOUND.into_response(),
+                Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+            }
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    match attribution::shares(&task, &result) {
+        Ok(shares) => Json(shares).into_response(),
+        Err(err) => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response(),
+    }
+}
+
+async fn epoch_summary(shared: State<Shared>, Path(epoch): Path<u64>) -> Response {
+    let Some(length) = shared.epoch_length else {
+        return (StatusCode::NOT_FOUND, "epochs are not rolled up").into_response();
+    };
+    match rollup::summary(&*shared.blobs, length, epoch) {
+        Ok(Some(summary)) => Json(summary).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "epoch is not rolled up yet").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn stage_record(
+    shared: State<Shared>,
+    Path((id, stage)): Path<(TaskId, String)>,
+) -> Response {
+    let result = match backfill::kept_result(&*shared.blobs, id) {
+        Ok(Some(result)) => result,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    match challenge::stage_record(&*shared.blobs, &task, &result, &stage, shared.max_blob_size) {
+        Ok(record) => Json(record).into_response(),
+        Err(err) => (StatusCode::NOT_FOUND, err.to_string()).into_response(),
+    }
+}
+
+fn parse_digest(digest: &str) -> Option<crate::crypto::Digest> {
+    if digest.len() != 64 || !digest.is_ascii() {
+        return None;
+    }
+    let mut parsed = [0; 32];
+    for (byte, hex) in parsed.iter_mut().zip(digest.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?
+    }
+    Some(parsed)
+}
+
+async fn cache_lookup(
+    shared: State<Shared>,
+    headers: HeaderMap,
+    Path((workflow, input)): Path<(String, String)>,
+) -> Response {
+    let (Some(workflow), Some(input)) = (parse_digest(&workflow), parse_digest(&input)) else {
+        return (StatusCode::BAD_REQUEST, "malformed digest").into_response();
+    };
+    let version = match requested_version(&headers) {
+        Ok(version) => version,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+    match cache::lookup(&*shared.blobs, &workflow, &input)
+        .and_then(|result| result.map(|result| encode(result, version)).transpose())
+    {
+        Ok(Some(result)) => Json(result).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn named_output(shared: State<Shared>, Path((id, name)): Path<(TaskId, String)>) -> Response {
+    let mut result = match backfill::kept_result(&*shared.blobs, id) {
+        Ok(Some(result)) => result,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    match challenge::reverted(&*shared.blobs, id) {
+        Ok(None) => {}
+        Ok(Some(_)) => return (StatusCode::GONE, "result is reverted").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    if result.expired.is_some() {
+        return (StatusCode::GONE, "task has expired").into_response();
+    }
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    match challenge::payload(
+        &*shared.blobs,
+        &result.output,
+        &result.blob,
+        result.compression,
+        shared.max_blob_size,
+    ) {
+        Ok(output) => result.output = output,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+    let mut outputs = match result.named_outputs(&task) {
+        Ok(outputs) => outputs,
+        Err(err) => return (StatusCode::NOT_FOUND, err.to_string()).into_response(),
+    };
+    match outputs.remove(&name) {
+        Some(output) => output.into_response(),
+        None => (StatusCode::NOT_FOUND, "unknown output").into_response(),
+    }
+}
+
+async fn notarize(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    let Some(signer) = &shared.signer else {
+        return (StatusCode::NOT_IMPLEMENTED, "no signing key").into_response();
+    };
+    let result = match backfill::kept_result(&*shared.blobs, id) {
+        Ok(Some(result)) => result,
+        Ok(None) => {
+            return match ledger::pruned(&*shared.blobs, id) {
+                Ok(Some(_)) => (
+                    StatusCode::GONE,
+                    "result is pruned, its header is notarized",
+                )
+                    .into_response(),
+                Ok(None) => StatusCode::NOT_FOUND.into_response(),
+                Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+            }
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return (StatusCode::NOT_FOUND, "unknown workflow version").into_response();
+    };
+    match ledger::notarize(
+        &*shared.blobs,
+        &*shared.crypto,
+        &**signer,
+        &task,
+        id,
+        shared.max_blob_size,
+    ) {
+        Ok(Some(notarization)) => Json(notarization).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+// the retained header of the result of an ordinary task if it is pruned, or the header it would
+// leave otherwise
+fn result_header(shared: &Shared, id: TaskId) -> anyhow::Result<Option<ResultHeader>> {
+    let blobs = &*shared.blobs;
+    let Some(kept) = blobs.get(&format!("{}/{id}", backfill::CHAIN_PREFIX))? else {
+        return ledger::pruned(blobs, id);
+    };
+    let workflow = serde_json::from_slice::<ChainMessage>(&kept)?.workflow;
+    let task = shared.task.read().unwrap().get(workflow);
+    let name = id.to_string();
+    ledger::header(blobs, &*shared.crypto, task.as_deref(), &name, &kept).map(Some)
+}
+
+async fn header(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    match result_header(&shared, id) {
+        Ok(Some(header)) => Json(header).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn notarize_header(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    let Some(signer) = &shared.signer else {
+        return (StatusCode::NOT_IMPLEMENTED, "no signing key").into_response();
+    };
+    let header = match result_header(&shared, id) {
+        Ok(Some(header)) => header,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    match ledger::notarize_header(&*shared.blobs, &*shared.crypto, &**signer, header) {
+        Ok(notarization) => Json(notarization).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn task_history(shared: State<Shared>, Path(id): Path<TaskId>) -> Response {
+    match history::history(&*shared.blobs, id) {
+        Ok(events) if events.is_empty() => StatusCode::NOT_FOUND.into_response(),
+        Ok(events) => Json(events).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+const SNAPSHOT_PAGE_SIZE: usize = 100;
+
+// `GET /tasks?seq=<sequence number>&after=<task id>&limit=<page size>`, where a first page omits
+// the sequence number and the task, and the next pages pass the ones of the previous page
+#[derive(Debug, Deserialize)]
+struct SnapshotQuery {
+    seq: Option<u64>,
+    after: Option<TaskId>,
+    limit: Option<usize>,
+}
+
+async fn task_snapshot(shared: State<Shared>, Query(query): Query<SnapshotQuery>) -> Response {
+    let limit = query
+        .limit
+        .unwrap_or(SNAPSHOT_PAGE_SIZE)
+        .clamp(1, SNAPSHOT_PAGE_SIZE * 10);
+    match history::snapshot(&*shared.blobs, query.seq, query.after, limit) {
+        Ok(Some(snapshot)) => Json(snapshot).into_response(),
+        Ok(None) => (StatusCode::BAD_REQUEST, "snapshot is ahead of the log").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn lineage(
+    shared: State<Shared>,
+    Path(id): Path<TaskId>,
+    Query(query): Query<LineageQuery>,
+) -> Response {
+    let prov = match query.format.as_deref() {
+        None => false,
+        Some("prov") => true,
+        Some(format) => {
+            return (StatusCode::BAD_REQUEST, format!("unknown format {format}")).into_response()
+        }
+    };
+    match lineage::graph(&*shared.blobs, &*shared.crypto, id) {
+        Ok(Some(graph)) if prov => Json(graph.prov()).into_response(),
+        Ok(Some(graph)) => Json(graph).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
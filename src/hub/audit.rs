@@ -0,0 +1,150 @@
+// sampling the accepted stages for quality audits, so the reward policies can penalize the
+// contributors of low-quality outputs, which verifying the clocks does not catch
+// with an audit rate, every stage of an accepted result of an ordinary task is sampled at that
+// rate, and the sampled stages are fanned out to the auditors subscribing to `/audits`. an auditor
+// is an independent worker that fetches the stage record (see `challenge::stage_record`),
+// re-executes or scores the stage, and submits the outcome to `/audits/submit`. the outcome is
+// committed like the other events and kept at `tasks/<task id>/audits/<stage>`, where the garbage
+// collection expires it along with the task. `GET /audits/summary` aggregates the kept outcomes
+// by the producers of the audited stages
+// the sampling and the requests awaiting an outcome are local to the hub instance that accepts
+// the writes, i.e. the leader of a raft group, so the auditors subscribe to it. a request without
+// an outcome is dropped after a while
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    attribution, blob::BlobStore, Audit, AuditRequest, AuditVerdict, NodeId, TaskId, Workflow,
+};
+
+use super::ChainMessage;
+
+const REQUEST_TTL: Duration = Duration::from_secs(3600);
+
+const REQUEST_CAPACITY: usize = 1024;
+
+fn audits_prefix(id: TaskId) -> String {
+    format!("tasks/{id}/audits")
+}
+
+#[derive(Debug)]
+pub struct Audits {
+    rate: f64,
+    requests: broadcast::Sender<AuditRequest>,
+    pending: HashMap<(TaskId, String), (AuditRequest, Instant)>,
+}
+
+impl Audits {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            requests: broadcast::Sender::new(REQUEST_CAPACITY),
+            pending: Default::default(),
+        }
+    }
+
+    pub fn requests(&self) -> &broadcast::Sender<AuditRequest> {
+        &self.requests
+    }
+
+    // a stage is only sampled while some auditor subscribes, and if it records its program
+    // version to re-execute
+    pub fn sample(&mut self, task: &Workflow, result: &ChainMessage) {
+        if self.rate == 0. || result.chunk.is_some() || result.expired.is_some() {
+            return;
+        }
+        self.pending
+            .retain(|_, (_, requested)| requested.elapsed() < REQUEST_TTL);
+        let task = &*task.taken(&result.branches);
+        for stage in &task.stages {
+            let producer = result.clocks.get(stage).and_then(|clock| {
+                let previous = task.upstream_clock(stage, &result.clocks).ok()?;
+                attribution::producer(clock, &previous).ok()
+            });
+            let Some(program) = result.programs.get(stage) else {
+                continue;
+            };
+            if rand::random::<f64>() >= self.rate {
+                continue;
+            }
+            let request = AuditRequest {
+                id: result.id,
+                stage: stage.clone(),
+                program: *program,
+                producer,
+            };
+            if self.requests.send(request.clone()).is_ok() {
+                self.pending
+                    .insert((result.id, stage.clone()), (request, Instant::now()));
+            }
+        }
+    }
+
+    // `None` if the stage is not awaiting an outcome, e.g. it is already audited
+    pub fn take(&mut self, id: TaskId, stage: &str) -> Option<AuditRequest> {
+        let (request, requested) = self.pending.remove(&(id, stage.into()))?;
+        (requested.elapsed() < REQUEST_TTL).then_some(request)
+    }
+}
+
+pub fn keep(blobs: &dyn BlobStore, audit: &Audit) -> anyhow::Result<()> {
+    blobs.put(
+        &format!(
+            "{}/{}",
+            audits_prefix(audit.request.id),
+            audit.request.stage
+        ),
+        serde_json::to_vec(audit)?.into(),
+    )
+}
+
+pub fn audits(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Vec<Audit>> {
+    let prefix = audits_prefix(id);
+    let mut audits = Vec::new();
+    for stage in blobs.list(&prefix)? {
+        if let Some(audit) = blobs.get(&format!("{prefix}/{stage}"))? {
+            audits.push(serde_json::from_slice(&audit)?)
+        }
+    }
+    Ok(audits)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ProducerAudits {
+    passed: u64,
+    failed: u64,
+    // of the scored outcomes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean_score: Option<f64>,
+    #[serde(skip)]
+    scored: u64,
+}
+
+// of the kept outcomes of every task, by the producers of the audited stages
+pub fn summary(blobs: &dyn BlobStore) -> anyhow::Result<BTreeMap<NodeId, ProducerAudits>> {
+    let mut summary = BTreeMap::<_, ProducerAudits>::new();
+    for id in blobs.list("tasks")? {
+        let Ok(id) = id.parse() else { continue };
+        for audit in audits(blobs, id)? {
+            let Some(producer) = audit.request.producer else {
+                continue;
+            };
+            let audits = summary.entry(producer).or_default();
+            match audit.verdict {
+                AuditVerdict::Passed => audits.passed += 1,
+                AuditVerdict::Failed => audits.failed += 1,
+            }
+            if let Some(score) = audit.score {
+                let mean = audits.mean_score.unwrap_or_default();
+                audits.scored += 1;
+                audits.mean_score = Some(mean + (score - mean) / audits.scored as f64)
+            }
+        }
+    }
+    Ok(summary)
+}
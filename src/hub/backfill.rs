@@ -0,0 +1,116 @@
+// re-verifying the task results accepted in the past, after the verification policy is upgraded
+// e.g. a program version is removed from the allowlist, or a plugged policy gets stricter
+// every result on the chain is kept in the blob store at `chain/<task id>` (`chain/<task id>-<seq>`
+// for the checkpoints of streaming tasks) as accepted. the job checks them against the current
+// workflow and policy, and only reports the ones that fail, without touching what is kept or
+// what the subscribers have observed
+// the job is backlog work, so it pauses while the hub sheds load (see `load`)
+use std::{collections::BTreeMap, thread::sleep, time::Duration};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{blob::BlobStore, Chunk, TaskId};
+
+use super::{ChainMessage, Shared};
+
+pub const CHAIN_PREFIX: &str = "chain";
+
+const SHEDDING_PAUSE: Duration = Duration::from_millis(500);
+
+// the results verified at once, so a context verifying in batches amortizes over them (see
+// `TaskResult::verify_batch`), while the job still pauses in between for the load
+const BATCH_SIZE: usize = 64;
+
+pub fn chain_key(message: &ChainMessage) -> String {
+    result_key(message.id, message.chunk)
+}
+
+pub fn result_key(id: TaskId, chunk: Option<Chunk>) -> String {
+    match chunk {
+        None => format!("{CHAIN_PREFIX}/{id}"),
+        Some(chunk) => format!("{CHAIN_PREFIX}/{id}-{}", chunk.seq),
+    }
+}
+
+// the kept result of an ordinary task
+pub fn kept_result(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Option<ChainMessage>> {
+    match blobs.get(&result_key(id, None))? {
+        Some(result) => Ok(Some(serde_json::from_slice(&result)?)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    running: bool,
+    checked: u64,
+    // the reasons of the failed results, by their name under `chain`
+    flagged: BTreeMap<String, String>,
+}
+
+// returns false without starting anything if the job is already running
+pub fn start(shared: &Shared) -> bool {
+    {
+        let mut report = shared.backfill.lock().unwrap();
+        if report.running {
+            return false;
+        }
+        *report = Report {
+            running: true,
+            ..Default::default()
+        }
+    }
+    let shared = shared.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = run(&shared) {
+            warn!("backfill verification aborted: {err}")
+        }
+        shared.backfill.lock().unwrap().running = false
+    });
+    true
+}
+
+fn run(shared: &Shared) -> anyhow::Result<()> {
+    let task = shared
+        .task
+        .read()
+        .unwrap()
+        .get(None)
+        .expect("current workflow exists");
+    for names in shared.blobs.list(CHAIN_PREFIX)?.chunks(BATCH_SIZE) {
+        while shared.load.lock().unwrap().shedding() {
+            sleep(SHEDDING_PAUSE)
+        }
+        let mut messages = Vec::new();
+        let mut failed = Vec::new();
+        for name in names {
+            let Some(data) = shared.blobs.get(&format!("{CHAIN_PREFIX}/{name}"))? else {
+                continue;
+            };
+            match serde_json::from_slice::<ChainMessage>(&data) {
+                Ok(message) => messages.push((name, message)),
+                Err(err) => failed.push((name, err.into())),
+            }
+        }
+        let (names, messages): (Vec<_>, Vec<_>) = messages.into_iter().unzip();
+        let verified = shared.verify_results(&messages, &task);
+        let mut report = shared.backfill.lock().unwrap();
+        report.checked += (names.len() + failed.len()) as u64;
+        for (name, err) in names
+            .into_iter()
+            .zip(verified)
+            .filter_map(|(name, verified)| verified.err().map(|err| (name, err)))
+            .chain(failed)
+        {
+            report.flagged.insert(name.clone(), err.to_string());
+        }
+    }
+    let report = shared.backfill.lock().unwrap();
+    info!(
+        "backfill verification checked {} results, flagged {}",
+        report.checked,
+        report.flagged.len()
+    );
+    Ok(())
+}
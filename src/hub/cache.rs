@@ -0,0 +1,71 @@
+// the accepted results by their inputs, so identical requests are not run through the whole
+// pipeline again
+// when the result of an ordinary task is accepted, its task id is kept at
+// `cache/<workflow version>/<input digest>`, where the input is the one of its kept start stage. a
+// client publishing a start stage with `accept_cached` is responded with the kept result of the
+// same input under the same workflow version instead, if there is one that is not reverted, and
+// the task is not run. the result is served as it is on the chain, so the client verifies it like
+// any other result, and it carries the id of the task that actually produced it
+// a workflow update changes the version, so the new tasks never hit the results of the old ones
+use crate::{
+    blob::BlobStore,
+    crypto::{CryptoSuite, Digest},
+    hex, StageSource, WorkflowDigest,
+};
+
+use super::{
+    backfill::kept_result,
+    challenge::{kept_gossip, payload, reverted},
+    ChainMessage, GossipMessage,
+};
+
+fn cache_key(workflow: &WorkflowDigest, input: &Digest) -> String {
+    format!("cache/{}/{}", hex(workflow), hex(input))
+}
+
+// of the reassembled and decompressed input, so an input hits the same results however it is
+// transferred
+pub fn input_digest(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    message: &GossipMessage,
+    max_size: u64,
+) -> anyhow::Result<Digest> {
+    Ok(crypto.digest(&payload(
+        blobs,
+        &message.input,
+        &message.blob,
+        message.compression,
+        max_size,
+    )?))
+}
+
+pub fn keep(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    result: &ChainMessage,
+    max_size: u64,
+) -> anyhow::Result<()> {
+    // an expired task has no result to serve
+    let (None, Some(workflow), None) = (result.chunk, &result.workflow, &result.expired) else {
+        return Ok(());
+    };
+    let start = kept_gossip(blobs, result.id, &StageSource::Start)?;
+    let input = input_digest(blobs, crypto, &start, max_size)?;
+    blobs.put(&cache_key(workflow, &input), result.id.to_string().into())
+}
+
+pub fn lookup(
+    blobs: &dyn BlobStore,
+    workflow: &WorkflowDigest,
+    input: &Digest,
+) -> anyhow::Result<Option<ChainMessage>> {
+    let Some(id) = blobs.get(&cache_key(workflow, input))? else {
+        return Ok(None);
+    };
+    let id = std::str::from_utf8(&id)?.parse()?;
+    if reverted(blobs, id)?.is_some() {
+        return Ok(None);
+    }
+    kept_result(blobs, id)
+}
@@ -0,0 +1,209 @@
+// the dispute of the results of optimistic workflows
+// to be able to re-execute any stage of a task, the hub keeps the latest gossip message of each
+// stage at `tasks/<task id>/gossip/<stage>` (and the start stage at `tasks/<task id>/start`), and
+// the time a result is accepted at `tasks/<task id>/accepted`. within the challenge window of the
+// workflow anyone may challenge a stage of the result with the output it should have. the hub
+// re-executes the stage with a `Reexecutor` under the recorded program version, and if the output
+// matches the challenge but not the record, the challenge is committed to the chain, which marks
+// the result as reverted at `tasks/<task id>/reverted`. the result itself is kept as accepted
+use std::{
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    blob::{reassemble_local, BlobRef, BlobStore},
+    compression::{decompress, Compression},
+    outputs::{self, NamedOutputs},
+    Challenge, ProgramDigest, StageRecord, StageSource, TaskId, Workflow,
+};
+
+use super::{ChainMessage, GossipMessage};
+
+// how a hub re-executes a stage to decide a challenge, e.g. `worker::ScriptReexecutor`
+pub trait Reexecutor: Send + Sync {
+    // fails if the program version is not available
+    fn reexecute<'a>(
+        &'a self,
+        stage: &'a str,
+        program: &'a ProgramDigest,
+        input: Bytes,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Bytes>> + Send + 'a>>;
+}
+
+pub fn gossip_key(id: TaskId, source: &StageSource) -> String {
+    match source {
+        StageSource::Start => format!("tasks/{id}/start"),
+        StageSource::Name(stage) => format!("tasks/{id}/gossip/{stage}"),
+    }
+}
+
+fn accepted_key(id: TaskId) -> String {
+    format!("tasks/{id}/accepted")
+}
+
+fn reverted_key(id: TaskId) -> String {
+    format!("tasks/{id}/reverted")
+}
+
+pub fn keep_gossip(blobs: &dyn BlobStore, message: &GossipMessage) -> anyhow::Result<()> {
+    blobs.put(
+        &gossip_key(message.id, &message.source),
+        serde_json::to_vec(message)?.into(),
+    )
+}
+
+// by the local time of each hub instance, which is fine for windows of minutes or longer
+pub fn keep_accepted(blobs: &dyn BlobStore, result: &ChainMessage) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    blobs.put(&accepted_key(result.id), now.to_string().into())
+}
+
+pub fn keep_reverted(blobs: &dyn BlobStore, challenge: &Challenge) -> anyhow::Result<()> {
+    blobs.put(
+        &reverted_key(challenge.id),
+        serde_json::to_vec(challenge)?.into(),
+    )
+}
+
+pub fn reverted(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Option<Challenge>> {
+    match blobs.get(&reverted_key(id))? {
+        Some(challenge) => Ok(Some(serde_json::from_slice(&challenge)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn check_window(blobs: &dyn BlobStore, task: &Workflow, id: TaskId) -> anyhow::Result<()> {
+    let window = task
+        .challenge_window
+        .ok_or(anyhow::format_err!("workflow is not optimistic"))?;
+    let accepted = blobs
+        .get(&accepted_key(id))?
+        .ok_or(anyhow::format_err!("unknown acceptance time"))?;
+    let accepted = UNIX_EPOCH + Duration::from_secs(std::str::from_utf8(&accepted)?.parse()?);
+    anyhow::ensure!(
+        accepted + Duration::from_secs(window) > SystemTime::now(),
+        "challenge window is closed"
+    );
+    anyhow::ensure!(reverted(blobs, id)?.is_none(), "result is already reverted");
+    Ok(())
+}
+
+pub fn kept_gossip(
+    blobs: &dyn BlobStore,
+    id: TaskId,
+    source: &StageSource,
+) -> anyhow::Result<GossipMessage> {
+    let message = blobs
+        .get(&gossip_key(id, source))?
+        .ok_or(anyhow::format_err!("missing kept message of task {id}"))?;
+    Ok(serde_json::from_slice(&message)?)
+}
+
+// reassembled and decompressed, of at most `max_size` bytes when offloaded
+pub fn payload(
+    blobs: &dyn BlobStore,
+    inline: &Bytes,
+    blob: &Option<BlobRef>,
+    compression: Option<Compression>,
+    max_size: u64,
+) -> anyhow::Result<Bytes> {
+    let payload = match blob {
+        None => inline.clone(),
+        Some(blob) => reassemble_local(blobs, blob, max_size)?,
+    };
+    decompress(payload, compression, max_size)
+}
+
+pub fn stage_record(
+    blobs: &dyn BlobStore,
+    task: &Workflow,
+    result: &ChainMessage,
+    stage: &str,
+    max_size: u64,
+) -> anyhow::Result<StageRecord> {
+    let upstream = task
+        .upstream(stage)
+        .ok_or(anyhow::format_err!("unknown stage {stage}"))?;
+    let kept_input = |source| {
+        let message = kept_gossip(blobs, result.id, &source)?;
+        payload(
+            blobs,
+            &message.input,
+            &message.blob,
+            message.compression,
+            max_size,
+        )
+    };
+    let input = match &upstream[..] {
+        [] => kept_input(StageSource::Start)?,
+        [upstream] => kept_input(StageSource::Name(upstream.to_string()))?,
+        // a joining stage executes upon the outputs of its upstream stages as named outputs
+        upstream => {
+            let mut inputs = NamedOutputs::new();
+            for upstream in upstream {
+                let input = kept_input(StageSource::Name(upstream.to_string()))?;
+                inputs.insert(upstream.to_string(), input);
+            }
+            outputs::encode(&inputs)
+        }
+    };
+    let (output, programs) = if Some(stage) == task.stages.last().map(String::as_str) {
+        (
+            payload(
+                blobs,
+                &result.output,
+                &result.blob,
+                result.compression,
+                max_size,
+            )?,
+            result.programs.clone(),
+        )
+    } else {
+        let message = kept_gossip(blobs, result.id, &StageSource::Name(stage.into()))?;
+        (
+            payload(
+                blobs,
+                &message.input,
+                &message.blob,
+                message.compression,
+                max_size,
+            )?,
+            message.programs,
+        )
+    };
+    Ok(StageRecord {
+        program: *programs
+            .get(stage)
+            .ok_or(anyhow::format_err!("missing program version"))?,
+        input,
+        output,
+    })
+}
+
+// `Ok(())` if the challenge holds
+pub async fn verify(
+    blobs: &dyn BlobStore,
+    reexecutor: &dyn Reexecutor,
+    task: &Workflow,
+    result: &ChainMessage,
+    challenge: &Challenge,
+    max_size: u64,
+) -> anyhow::Result<()> {
+    let record = stage_record(blobs, task, result, &challenge.stage, max_size)?;
+    anyhow::ensure!(
+        record.output != challenge.output,
+        "challenged output is the recorded one"
+    );
+    let reexecuted = reexecutor
+        .reexecute(&challenge.stage, &record.program, record.input)
+        .await?;
+    anyhow::ensure!(
+        reexecuted == challenge.output,
+        "re-execution does not give the challenged output"
+    );
+    Ok(())
+}
@@ -0,0 +1,200 @@
+// expiring the tasks that miss their deadlines, so a stuck task still ends on the chain with the
+// attribution of the work that did happen
+// a client may declare a deadline on the start stage of a task, and a workflow may declare how
+// long its tasks may take. the hub stamps the earlier of the two into the start stage as it is
+// published, so every member of a raft group tracks the same deadline as the stage is applied.
+// once the deadline passes without a final result, the leader commits a terminal record in place
+// of the result (see `TaskExpired`), carrying the clocks of the stages completed by then on every
+// branch
+// afterwards the later stages and results of the task are refused. a result racing with the expiry
+// may still be accepted before the record, in which case the task is not expired
+// the tracked deadlines are not kept, so a standalone hub forgets them when it restarts, while a
+// raft member rebuilds them when it replays its log
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::OriginalUri, http::Uri};
+use bytes::Bytes;
+use reqwest::StatusCode;
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::{blob::BlobStore, protocol, StageSource, TaskExpired, TaskId, Workflow};
+
+use super::{
+    backfill::kept_result,
+    challenge::{gossip_key, kept_gossip},
+    ChainMessage, GossipMessage, HubEvent, Shared,
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// the deadline of a new task, of the one `declared` by its client and the one of the workflow
+pub fn stamp(declared: Option<u64>, task: &Workflow) -> anyhow::Result<Option<u64>> {
+    let deadline = declared
+        .into_iter()
+        .chain(task.deadline.map(|deadline| now() + deadline))
+        .min();
+    anyhow::ensure!(
+        deadline.is_none_or(|deadline| deadline > now()),
+        "deadline has already passed"
+    );
+    Ok(deadline)
+}
+
+// whether the task is expired, i.e. its terminal record is kept
+pub fn expired(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<bool> {
+    Ok(kept_result(blobs, id)?.is_some_and(|result| result.expired.is_some()))
+}
+
+// the deadlines of the running tasks
+#[derive(Debug, Default)]
+pub struct Deadlines(Mutex<HashMap<TaskId, u64>>);
+
+impl Deadlines {
+    pub fn track(&self, message: &GossipMessage) {
+        let new = message.source == StageSource::Start
+            && message.chunk.is_none_or(|chunk| chunk.seq == 0);
+        if let (true, Some(deadline)) = (new, message.deadline) {
+            self.0.lock().unwrap().insert(message.id, deadline);
+        }
+    }
+
+    pub fn get(&self, id: TaskId) -> Option<u64> {
+        self.0.lock().unwrap().get(&id).copied()
+    }
+
+    // on a final result or the terminal record
+    pub fn finish(&self, result: &ChainMessage) {
+        if result.chunk.is_none_or(|chunk| chunk.last) {
+            self.forget(result.id)
+        }
+    }
+
+    fn forget(&self, id: TaskId) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    fn due(&self) -> Vec<(TaskId, u64)> {
+        let now = now();
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(id, deadline)| (*id, *deadline))
+            .collect()
+    }
+}
+
+// the terminal record of the task, `None` if it has a final result after all
+fn expiry(shared: &Shared, id: TaskId, deadline: u64) -> anyhow::Result<Option<ChainMessage>> {
+    let blobs = &*shared.blobs;
+    if kept_result(blobs, id)?.is_some() {
+        return Ok(None);
+    }
+    let start = kept_gossip(blobs, id, &StageSource::Start)?;
+    let task = shared
+        .task
+        .read()
+        .unwrap()
+        .get(start.workflow)
+        .ok_or(anyhow::format_err!("unknown workflow version"))?;
+    // the furthest stage of every branch that published its output, whose messages carry the
+    // clocks and the records of the stages before them
+    let mut completed = BTreeSet::new();
+    for stage in &task.stages {
+        if blobs
+            .get(&gossip_key(id, &StageSource::Name(stage.clone())))?
+            .is_some()
+        {
+            completed.insert(stage.as_str());
+        }
+    }
+    let frontier = task.frontier(&completed);
+    let stage = frontier.last().map(|stage| stage.to_string());
+    let mut record = ChainMessage {
+        version: protocol::VERSION,
+        id,
+        workflow: start.workflow,
+        chunk: None,
+        output: Bytes::new(),
+        blob: None,
+        content_type: None,
+        compression: None,
+        clocks: start.clocks,
+        programs: start.programs,
+        output_digests: start.output_digests,
+        logs: start.logs,
+        elapsed: start.elapsed,
+        routes: start.routes,
+        branches: start.branches,
+        iterations: start.iterations,
+        expired: Some(TaskExpired { deadline, stage }),
+    };
+    for stage in frontier {
+        let message = kept_gossip(blobs, id, &StageSource::Name(stage.into()))?;
+        record.clocks.extend(message.clocks);
+        record.programs.extend(message.programs);
+        record.output_digests.extend(message.output_digests);
+        record.logs.extend(message.logs);
+        record.elapsed.extend(message.elapsed);
+        record.routes.extend(message.routes);
+        record.branches.extend(message.branches);
+        record.iterations.extend(message.iterations);
+    }
+    Ok(Some(record))
+}
+
+async fn sweep(shared: &Shared) {
+    for (id, deadline) in shared.fanout.deadlines.due() {
+        let record = match expiry(shared, id, deadline) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                shared.fanout.deadlines.forget(id);
+                continue;
+            }
+            Err(err) => {
+                warn!("failed to expire task {id:08x}: {err}");
+                shared.fanout.deadlines.forget(id);
+                continue;
+            }
+        };
+        info!(
+            "task {id:08x} expired after stage {}",
+            record
+                .expired
+                .as_ref()
+                .and_then(|expired| expired.stage.as_deref())
+                .unwrap_or("none")
+        );
+        shared.scheduler.lock().unwrap().finish(id);
+        shared.hints.lock().unwrap().finish(id);
+        let uri = OriginalUri(Uri::from_static("/chain/propose"));
+        let response = shared.commit(HubEvent::Chain(record), &uri).await;
+        if response.status() != StatusCode::OK {
+            warn!("failed to expire task {id:08x}: {}", response.status())
+        }
+    }
+}
+
+// only the leader of a raft group expires the tasks, the other members apply its records
+pub async fn periodically(shared: Shared) {
+    let mut interval = interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if shared.leads() {
+            sweep(&shared).await
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// the server-side filters of the chain subscriptions, e.g.
+// `GET /chain?workflow=<hex>&submitter=alice&labels=team=nlp,!draft&final=true`, so a consumer
+// only receives the slice of the chain it cares about. the filters are evaluated on the results
+// before they are encoded for the subscriber
+// the submitter and the labels of a task are declared by its start stage, so they are looked up
+// from the kept start stage of the result's task (see `challenge`)
+// a final result will not change anymore: the last result of a streaming task rather than a
+// checkpoint, and for an optimistic workflow a result whose challenge window has passed without
+// a successful challenge, which is only delivered after the window
+use std::{collections::BTreeMap, pin::Pin, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::{select, sync::mpsc, time::sleep};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt as _};
+
+use crate::{StageSource, WorkflowDigest};
+
+use super::{
+    challenge::{kept_gossip, reverted},
+    parse_digest,
+    resume::{keeping, Numbered},
+    ChainMessage, Shared,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChainFilter {
+    // the hex encoded workflow version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+    // comma separated selectors that all must hold for the labels of the task, each of
+    // `<key>=<value>`, `<key>!=<value>`, `<key>` (present) or `!<key>` (absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<String>,
+    // only the final results, otherwise every result is delivered as soon as it is accepted,
+    // including the ones that are reverted later
+    #[serde(default, rename = "final")]
+    pub only_final: bool,
+}
+
+enum Selector {
+    Equal(String, String),
+    NotEqual(String, String),
+    Present(String),
+    Absent(String),
+}
+
+impl Selector {
+    fn parse(selector: &str) -> anyhow::Result<Self> {
+        let selector = if let Some((key, value)) = selector.split_once("!=") {
+            Self::NotEqual(key.into(), value.into())
+        } else if let Some((key, value)) = selector.split_once('=') {
+            Self::Equal(key.into(), value.into())
+        } else if let Some(key) = selector.strip_prefix('!') {
+            Self::Absent(key.into())
+        } else {
+            Self::Present(selector.into())
+        };
+        let (Self::Equal(key, _) | Self::NotEqual(key, _) | Self::Present(key) | Self::Absent(key)) =
+            &selector;
+        anyhow::ensure!(!key.is_empty(), "empty label key in selector");
+        Ok(selector)
+    }
+
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::Equal(key, value) => labels.get(key) == Some(value),
+            Self::NotEqual(key, value) => labels.get(key) != Some(value),
+            Self::Present(key) => labels.contains_key(key),
+            Self::Absent(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+pub struct Filter {
+    workflow: Option<WorkflowDigest>,
+    submitter: Option<String>,
+    selectors: Vec<Selector>,
+    only_final: bool,
+}
+
+impl Filter {
+    pub fn new(filter: ChainFilter) -> anyhow::Result<Self> {
+        let workflow = filter
+            .workflow
+            .map(|workflow| {
+                parse_digest(&workflow).ok_or(anyhow::format_err!("malformed workflow version"))
+            })
+            .transpose()?;
+        let selectors = filter
+            .labels
+            .iter()
+            .flat_map(|labels| labels.split(','))
+            .filter(|selector| !selector.is_empty())
+            .map(Selector::parse)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            workflow,
+            submitter: filter.submitter,
+            selectors,
+            only_final: filter.only_final,
+        })
+    }
+
+    pub fn workflow(&self) -> Option<WorkflowDigest> {
+        self.workflow
+    }
+
+    // a result whose start stage is not kept has neither a submitter nor labels
+    fn matches(&self, shared: &Shared, result: &ChainMessage) -> bool {
+        if self.workflow.is_some() && result.workflow != self.workflow {
+            return false;
+        }
+        if self.only_final && result.chunk.is_some_and(|chunk| !chunk.last) {
+            return false;
+        }
+        if self.submitter.is_none() && self.selectors.is_empty() {
+            return true;
+        }
+        let start = kept_gossip(&*shared.blobs, result.id, &StageSource::Start).ok();
+        let (submitter, labels) = match &start {
+            Some(start) => (start.submitter.as_ref(), start.labels.clone()),
+            None => (None, Default::default()),
+        };
+        self.submitter
+            .as_ref()
+            .is_none_or(|expected| submitter == Some(expected))
+            && self
+                .selectors
+                .iter()
+                .all(|selector| selector.matches(&labels))
+    }
+
+    // the challenge window to wait before delivering the result, if any
+    fn window(&self, shared: &Shared, result: &ChainMessage) -> Option<Duration> {
+        if !self.only_final {
+            return None;
+        }
+        let task = shared.task.read().unwrap().get(result.workflow)?;
+        task.challenge_window.map(Duration::from_secs)
+    }
+}
+
+const CAPACITY: usize = 64;
+
+// `results` are the ones of the partition of the filtered workflow, replayed or live
+pub fn filtered(
+    shared: &Shared,
+    filter: Filter,
+    mut results: impl Stream<Item = Numbered<ChainMessage>> + Send + Unpin + 'static,
+) -> Pin<Box<dyn Stream<Item = Numbered<ChainMessage>> + Send>> {
+    if !filter.only_final {
+        let shared = shared.clone();
+        return Box::pin(keeping(results, move |result| {
+            filter.matches(&shared, &result.message)
+        }));
+    }
+    // the results within their windows are held aside, while the later results keep being received
+    let (sender, receiver) = mpsc::channel(CAPACITY);
+    let shared = shared.clone();
+    tokio::spawn(async move {
+        let mut missed = false;
+        loop {
+            let result = select! {
+                () = sender.closed() => break,
+                result = results.next() => result,
+            };
+            let Some(mut result) = result else {
+                break;
+            };
+            missed |= result.missed;
+            if !filter.matches(&shared, &result.message) {
+                continue;
+            }
+            result.missed = std::mem::take(&mut missed);
+            let Some(window) = filter.window(&shared, &result.message) else {
+                let _ = sender.send(result).await;
+                continue;
+            };
+            let sender = sender.clone();
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                sleep(window).await;
+                if matches!(reverted(&*shared.blobs, result.message.id), Ok(None)) {
+                    let _ = sender.send(result).await;
+                }
+            });
+        }
+    });
+    Box::pin(ReceiverStream::new(receiver))
+}
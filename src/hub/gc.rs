@@ -0,0 +1,241 @@
+// collecting the garbage of the blob store, so a long-running hub does not accumulate payloads
+// without bound
+// with a pruning window, a task whose latest event (see `history`) is older than the window is
+// pruned: the bodies of its results under `chain` are replaced by their headers (see
+// `ledger::header`), and its kept gossip messages and cache entries are deleted, while its other
+// records stay
+// with a retention window, a task whose latest event is older than the window is expired: its
+// results are pruned likewise, and everything else kept of it is deleted, i.e. its records under
+// `tasks/<task id>`, its handoff and its cache entries. a task of unknown age is retained
+// then the offloaded chunks under `blobs` are marked by the results and the kept gossip messages of
+// the retained tasks, and the unmarked ones are swept. a chunk is uploaded before the message
+// referencing it is published, so it is only swept if it is unmarked in two consecutive runs, and
+// the runs must be further apart than an upload takes to be referenced
+// the ledger (see `ledger`) and the headers are not collected, so the notarizations exported
+// earlier stay valid, and the inclusion of every result can still be proven
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::{blob::BlobRef, hex, TaskId};
+
+use super::{
+    backfill::CHAIN_PREFIX, history::history, ledger, ChainMessage, GossipMessage, Shared,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    running: bool,
+    // of the latest run
+    expired: u64,
+    pruned: u64,
+    retained: u64,
+    swept: u64,
+    // the chunks unmarked by the latest run, which are swept if they are still unmarked next time
+    #[serde(skip)]
+    candidates: HashSet<String>,
+}
+
+// returns false without starting anything if a run is in progress
+pub fn start(shared: &Shared) -> bool {
+    {
+        let mut report = shared.gc.lock().unwrap();
+        if report.running {
+            return false;
+        }
+        report.running = true
+    }
+    let shared = shared.clone();
+    tokio::task::spawn_blocking(move || {
+        let collected = run(&shared);
+        let mut report = shared.gc.lock().unwrap();
+        report.running = false;
+        match collected {
+            Ok(()) => info!(
+                "garbage collection expired {} tasks, pruned {}, retained {}, swept {} chunks",
+                report.expired, report.pruned, report.retained, report.swept
+            ),
+            Err(err) => warn!("garbage collection aborted: {err}"),
+        }
+    });
+    true
+}
+
+pub async fn periodically(shared: Shared, period: Duration) {
+    let mut interval = interval(period);
+    // the first tick is immediate, while the first run only marks anyway
+    loop {
+        interval.tick().await;
+        start(&shared);
+    }
+}
+
+fn task_id(name: &str) -> Option<TaskId> {
+    name.split('-').next()?.parse().ok()
+}
+
+// the time of the latest event of the task, or of its latest result by the records before the
+// histories were kept
+fn last_active(shared: &Shared, id: TaskId) -> anyhow::Result<Option<SystemTime>> {
+    if let Some(event) = history(&*shared.blobs, id)?.last() {
+        return Ok(Some(UNIX_EPOCH + Duration::from_micros(event.at)));
+    }
+    let Some(accepted) = shared.blobs.get(&format!("tasks/{id}/accepted"))? else {
+        return Ok(None);
+    };
+    let accepted = std::str::from_utf8(&accepted)?.parse()?;
+    Ok(Some(UNIX_EPOCH + Duration::from_secs(accepted)))
+}
+
+// the number of the results replaced by their headers. a result that is not in the ledger, i.e.
+// one kept before the ledger was, is left as is
+fn prune(shared: &Shared, results: &[String]) -> anyhow::Result<u64> {
+    let blobs = &*shared.blobs;
+    let mut pruned = 0;
+    for name in results {
+        let key = format!("{CHAIN_PREFIX}/{name}");
+        let Some(kept) = blobs.get(&key)? else {
+            continue;
+        };
+        let workflow = serde_json::from_slice::<ChainMessage>(&kept)?.workflow;
+        let task = shared.task.read().unwrap().get(workflow);
+        let header = match ledger::header(blobs, &*shared.crypto, task.as_deref(), name, &kept) {
+            Ok(header) => header,
+            Err(err) => {
+                warn!("failed to prune result {name}: {err}");
+                continue;
+            }
+        };
+        ledger::keep_header(blobs, name, &header)?;
+        blobs.delete(&key)?;
+        pruned += 1
+    }
+    Ok(pruned)
+}
+
+fn expire(shared: &Shared, id: TaskId, results: &[String]) -> anyhow::Result<()> {
+    prune(shared, results)?;
+    for name in results {
+        shared.blobs.delete(&format!("{CHAIN_PREFIX}/{name}"))?
+    }
+    shared.blobs.delete(&format!("tasks/{id}"))?;
+    shared.blobs.delete(&format!("handoffs/{id}"))?;
+    shared.blobs.delete(&format!("lineage/{id}"))
+}
+
+fn mark(marked: &mut HashSet<String>, blob: &Option<BlobRef>) {
+    for digest in blob.iter().flat_map(|blob| &blob.chunks) {
+        marked.insert(hex(digest));
+    }
+}
+
+fn run(shared: &Shared) -> anyhow::Result<()> {
+    let blobs = &*shared.blobs;
+    let mut tasks = BTreeSet::new();
+    for name in blobs.list("tasks")? {
+        tasks.extend(task_id(&name))
+    }
+    let chain = blobs.list(CHAIN_PREFIX)?;
+    for name in &chain {
+        tasks.extend(task_id(name))
+    }
+
+    let now = SystemTime::now();
+    let mut expired = 0;
+    let mut pruned = 0;
+    // whose cache entries are deleted
+    let mut forgotten = HashSet::new();
+    let mut marked = HashSet::new();
+    let mut retained = 0;
+    for id in tasks {
+        let results = chain
+            .iter()
+            .filter(|name| task_id(name) == Some(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        let active = last_active(shared, id)?;
+        let older = |window: Option<Duration>| {
+            window.is_some_and(|window| active.is_some_and(|active| active + window < now))
+        };
+        if older(shared.retention) {
+            expire(shared, id, &results)?;
+            forgotten.insert(id);
+            expired += 1;
+            continue;
+        }
+        retained += 1;
+        if older(shared.prune_after) {
+            let count = prune(shared, &results)?;
+            if count > 0 {
+                blobs.delete(&format!("tasks/{id}/gossip"))?;
+                blobs.delete(&format!("tasks/{id}/start"))?;
+                forgotten.insert(id);
+                pruned += 1
+            }
+        }
+        for name in &results {
+            if let Some(result) = blobs.get(&format!("{CHAIN_PREFIX}/{name}"))? {
+                mark(
+                    &mut marked,
+                    &serde_json::from_slice::<ChainMessage>(&result)?.blob,
+                )
+            }
+        }
+        let gossip = blobs
+            .list(&format!("tasks/{id}/gossip"))?
+            .into_iter()
+            .map(|stage| format!("tasks/{id}/gossip/{stage}"))
+            .chain([format!("tasks/{id}/start")]);
+        for key in gossip {
+            if let Some(message) = blobs.get(&key)? {
+                mark(
+                    &mut marked,
+                    &serde_json::from_slice::<GossipMessage>(&message)?.blob,
+                )
+            }
+        }
+    }
+
+    // the cache entries of the expired and pruned tasks, at `cache/<workflow version>/<input digest>`
+    if !forgotten.is_empty() {
+        for workflow in blobs.list("cache")? {
+            for input in blobs.list(&format!("cache/{workflow}"))? {
+                let key = format!("cache/{workflow}/{input}");
+                let Some(id) = blobs.get(&key)? else { continue };
+                if std::str::from_utf8(&id)?
+                    .parse()
+                    .is_ok_and(|id| forgotten.contains(&id))
+                {
+                    blobs.delete(&key)?
+                }
+            }
+        }
+    }
+
+    let previous = std::mem::take(&mut shared.gc.lock().unwrap().candidates);
+    let mut candidates = HashSet::new();
+    let mut swept = 0;
+    for digest in blobs.list("blobs")? {
+        if marked.contains(&digest) {
+            continue;
+        }
+        if previous.contains(&digest) {
+            blobs.delete(&format!("blobs/{digest}"))?;
+            swept += 1
+        } else {
+            candidates.insert(digest);
+        }
+    }
+    let mut report = shared.gc.lock().unwrap();
+    report.expired = expired;
+    report.pruned = pruned;
+    report.retained = retained;
+    report.swept = swept;
+    report.candidates = candidates;
+    Ok(())
+}
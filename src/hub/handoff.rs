@@ -0,0 +1,106 @@
+// the handoffs between tasks of different workflows, and the lineage they form
+// a declared handoff is kept at `handoffs/<task id>` when the start stage is accepted, along with
+// the reverse link `lineage/<downstream task id>`, so both survive the leader changes of a raft
+// group like the chain results do. once the upstream result is accepted, the hub publishes the
+// start stage of the downstream task with the output of the upstream one, offloaded or not
+use serde::Serialize;
+
+use crate::{blob::BlobStore, protocol, Challenge, Handoff, StageSource, TaskId};
+
+use super::{
+    backfill::kept_result,
+    challenge::{kept_gossip, reverted},
+    ChainMessage, GossipMessage,
+};
+
+fn handoff_key(id: TaskId) -> String {
+    format!("handoffs/{id}")
+}
+
+fn lineage_key(id: TaskId) -> String {
+    format!("lineage/{id}")
+}
+
+pub fn keep(blobs: &dyn BlobStore, message: &GossipMessage) -> anyhow::Result<()> {
+    let Some(handoff) = &message.handoff else {
+        return Ok(());
+    };
+    blobs.put(
+        &handoff_key(message.id),
+        serde_json::to_vec(handoff)?.into(),
+    )?;
+    blobs.put(&lineage_key(handoff.id), message.id.to_string().into())
+}
+
+// the start stage of the downstream task, if the task of the result is handed off
+pub fn start(
+    blobs: &dyn BlobStore,
+    result: &ChainMessage,
+) -> anyhow::Result<Option<GossipMessage>> {
+    let Some(handoff) = blobs.get(&handoff_key(result.id))? else {
+        return Ok(None);
+    };
+    let handoff = serde_json::from_slice::<Handoff>(&handoff)?;
+    // the downstream task is submitted on behalf of the submitter of the upstream one
+    let upstream = kept_gossip(blobs, result.id, &StageSource::Start)?;
+    Ok(Some(GossipMessage {
+        version: protocol::VERSION,
+        id: handoff.id,
+        workflow: Some(handoff.workflow),
+        source: StageSource::Start,
+        chunk: None,
+        assignee: None,
+        handoff: handoff.then.map(|then| *then),
+        accept_cached: false,
+        submitter: upstream.submitter,
+        priority: upstream.priority,
+        labels: upstream.labels,
+        // by the deadline of its own workflow, if any
+        deadline: None,
+        input: result.output.clone(),
+        blob: result.blob.clone(),
+        content_type: result.content_type.clone(),
+        compression: result.compression,
+        clocks: Default::default(),
+        programs: Default::default(),
+        output_digests: Default::default(),
+        logs: Default::default(),
+        elapsed: Default::default(),
+        routes: Default::default(),
+        branches: Default::default(),
+        iterations: Default::default(),
+        hints: Default::default(),
+        clock_delta: None,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lineage {
+    pub id: TaskId,
+    // `None` if the task has not finished yet
+    result: Option<ChainMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverted: Option<Challenge>,
+}
+
+// from the first upstream task to the task of the id
+pub fn lineage(blobs: &dyn BlobStore, mut id: TaskId) -> anyhow::Result<Vec<Lineage>> {
+    let mut lineage = Vec::new();
+    loop {
+        lineage.push(Lineage {
+            id,
+            result: kept_result(blobs, id)?,
+            reverted: reverted(blobs, id)?,
+        });
+        let Some(upstream) = blobs.get(&lineage_key(id))? else {
+            break;
+        };
+        id = std::str::from_utf8(&upstream)?.parse()?;
+        // the task ids are chosen by the clients, who may reuse them into a cycle
+        if lineage.iter().any(|entry| entry.id == id) {
+            break;
+        }
+    }
+    lineage.reverse();
+    Ok(lineage)
+}
@@ -0,0 +1,56 @@
+// clock-aware hints for the workers racing on the same tasks, i.e. every worker of a stage that
+// executes the messages the scheduler does not assign. all but one of their executions lose in the
+// end, and so does everything executed upon them, so the hub stamps the highest clocks it knows of
+// the task into each message it dispatches, and a worker skips the message when another execution
+// is ahead of it (see `TaskStage::stale`)
+// the highest clock of a stage is the first one the hub sees, replaced only by a clock that happens
+// after it, e.g. of an execution upon a newer predecessor. the concurrent executions of a stage
+// are thus settled by the order the hub sees them in, which is the order it dispatches them in
+// like the scheduler, the hints are local to the hub instance that accepts the writes, i.e. the
+// leader of a raft group, and a new leader starts without them
+use std::collections::HashMap;
+
+use crate::{ClockOrdering, CompareClock as _, TaskId};
+
+use super::{GossipMessage, C};
+
+#[derive(Debug, Default)]
+pub struct Hints {
+    // by task and chunk
+    clocks: HashMap<(TaskId, Option<u64>), HashMap<String, C>>,
+}
+
+impl Hints {
+    // keeps the clocks of the message, and returns the highest clocks of its task that are not its
+    // own, which are empty unless the message builds upon a losing execution or its next stage is
+    // executed already
+    pub fn stamp(&mut self, message: &GossipMessage) -> HashMap<String, C> {
+        let highest = self
+            .clocks
+            .entry((message.id, message.chunk.map(|chunk| chunk.seq)))
+            .or_default();
+        for (stage, clock) in &message.clocks {
+            match highest.get(stage) {
+                Some(highest) if clock.compare(highest) != ClockOrdering::After => {}
+                _ => {
+                    highest.insert(stage.clone(), clock.clone());
+                }
+            }
+        }
+        highest
+            .iter()
+            .filter(|(stage, highest)| {
+                message
+                    .clocks
+                    .get(*stage)
+                    .is_none_or(|clock| clock.compare(highest) != ClockOrdering::Equal)
+            })
+            .map(|(stage, highest)| (stage.clone(), highest.clone()))
+            .collect()
+    }
+
+    // forget a task that will not be executed any further
+    pub fn finish(&mut self, id: TaskId) {
+        self.clocks.retain(|(other_id, _), _| *other_id != id)
+    }
+}
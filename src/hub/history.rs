@@ -0,0 +1,154 @@
+// keeping the events of every task for its history (see `history`)
+// each event is kept at `tasks/<task id>/history/<digest of the event>` along with the time it is
+// applied, so an event applied again e.g. when a raft member replays its log keeps its first time
+// instead of being duplicated
+// a newly kept event is numbered after all the events kept before it, and the count is kept at
+// `history/size`. the members of a raft group apply the same events in the same order, so they
+// number them the same. a snapshot (see `snapshot`) only reads the events numbered before its
+// sequence number, so it is repeatable, until the task is expired by the garbage collection
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    blob::BlobStore,
+    crypto::CryptoSuite,
+    hex,
+    history::{self as task_history, EventKind, HistoryEvent, TaskSnapshot},
+    TaskId,
+};
+
+use super::HubEvent;
+
+const SIZE_KEY: &str = "history/size";
+
+fn history_prefix(id: TaskId) -> String {
+    format!("tasks/{id}/history")
+}
+
+// the number of the events kept so far, i.e. the sequence number of the next one
+pub fn size(blobs: &dyn BlobStore) -> anyhow::Result<u64> {
+    match blobs.get(SIZE_KEY)? {
+        Some(size) => Ok(std::str::from_utf8(&size)?.parse()?),
+        None => Ok(0),
+    }
+}
+
+// the standalone hub applies the events concurrently, so the numbering is serialized
+#[derive(Debug, Default)]
+pub struct Sequence(Mutex<()>);
+
+impl Sequence {
+    // the number of the event, `None` if it is kept already or is not a part of the history
+    pub fn keep(
+        &self,
+        blobs: &dyn BlobStore,
+        crypto: &dyn CryptoSuite,
+        event: &HubEvent,
+    ) -> anyhow::Result<Option<u64>> {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as _;
+        let (id, event) = match event {
+            HubEvent::Gossip(message) => (message.id, HistoryEvent::gossip(at, crypto, message)),
+            HubEvent::Chain(result) => (result.id, HistoryEvent::result(at, crypto, result)),
+            HubEvent::Challenge(challenge) => {
+                (challenge.id, HistoryEvent::challenge(at, challenge))
+            }
+            HubEvent::Migration(migration) => (
+                migration.message.id,
+                HistoryEvent::migration(at, crypto, migration.from, &migration.message),
+            ),
+            // an audit judges an event rather than advancing the task
+            HubEvent::Audit(_) => return Ok(None),
+            // nor does a quarantined message, see `quarantine`
+            HubEvent::Quarantine(_) => return Ok(None),
+        };
+        // the time and the number are not a part of the identity of an event
+        let digest = crypto.digest(&serde_json::to_vec(&HistoryEvent {
+            at: 0,
+            ..event.clone()
+        })?);
+        let key = format!("{}/{}", history_prefix(id), hex(&digest));
+        let _guard = self.0.lock().unwrap();
+        if blobs.get(&key)?.is_some() {
+            return Ok(None);
+        }
+        // the event is kept before the count covers it, so a snapshot never misses an event
+        // numbered before its sequence number
+        let seq = size(blobs)?;
+        let event = HistoryEvent {
+            seq: Some(seq),
+            ..event
+        };
+        blobs.put(&key, serde_json::to_vec(&event)?.into())?;
+        blobs.put(SIZE_KEY, (seq + 1).to_string().into())?;
+        Ok(Some(seq))
+    }
+}
+
+// in the order they are applied, and the results after the stages of the same time
+pub fn history(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Vec<HistoryEvent>> {
+    let prefix = history_prefix(id);
+    let mut events = Vec::new();
+    for name in blobs.list(&prefix)? {
+        if let Some(event) = blobs.get(&format!("{prefix}/{name}"))? {
+            events.push(serde_json::from_slice::<HistoryEvent>(&event)?)
+        }
+    }
+    events.sort_by_key(|event| (event.seq, event.at, event.kind != EventKind::Gossip));
+    Ok(events)
+}
+
+fn task_ids(blobs: &dyn BlobStore) -> anyhow::Result<Vec<TaskId>> {
+    let mut ids = blobs
+        .list("tasks")?
+        .into_iter()
+        .filter_map(|name| name.parse().ok())
+        .collect::<Vec<_>>();
+    ids.sort();
+    Ok(ids)
+}
+
+// of every task with a kept history, e.g. for replaying the whole log offline
+pub fn histories(blobs: &dyn BlobStore) -> anyhow::Result<Vec<(TaskId, Vec<HistoryEvent>)>> {
+    let mut histories = Vec::new();
+    for id in task_ids(blobs)? {
+        let events = history(blobs, id)?;
+        if !events.is_empty() {
+            histories.push((id, events))
+        }
+    }
+    Ok(histories)
+}
+
+// a page of at most `limit` task states as of `seq`, by default the current size of the log, of
+// the tasks after `after` in the order of their ids. `None` if `seq` is ahead of the log
+pub fn snapshot(
+    blobs: &dyn BlobStore,
+    seq: Option<u64>,
+    after: Option<TaskId>,
+    limit: usize,
+) -> anyhow::Result<Option<TaskSnapshot>> {
+    let size = size(blobs)?;
+    let seq = seq.unwrap_or(size);
+    if seq > size {
+        return Ok(None);
+    }
+    let mut tasks = Vec::new();
+    for id in task_ids(blobs)? {
+        if after.is_some_and(|after| id <= after) {
+            continue;
+        }
+        tasks.extend(task_history::state(id, &history(blobs, id)?, seq));
+        if tasks.len() > limit {
+            break;
+        }
+    }
+    let next = if tasks.len() > limit {
+        tasks.truncate(limit);
+        tasks.last().map(|task| task.id)
+    } else {
+        None
+    };
+    Ok(Some(TaskSnapshot { seq, tasks, next }))
+}
@@ -0,0 +1,44 @@
+// the post-processing of the accepted results before they are delivered to the subscribers, e.g.
+// converting the output into another format, scrubbing personal data out of it, or enriching it
+// from the attribution. a workflow names the hooks it runs in `hooks`, in order, out of the ones
+// registered with the hub (see `HubBuilder::result_hook`), so the pipelines sharing a hub can be
+// processed differently
+// a hook only changes the copy of the result for the subscribers. the result is kept, appended to
+// the ledger, cached and notarized as it is accepted, so a processed result may not verify
+// against its workflow anymore, and whoever needs to verify it fetches its notarization instead
+// the hooks run as the result is applied, i.e. on every member of a raft group, so they should be
+// registered alike with all members. a result that a hook fails on is withheld from the
+// subscribers rather than delivered unprocessed, which for a scrubbing hook would leak what it is
+// meant to remove
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::Workflow;
+
+use super::ChainMessage;
+
+pub trait ResultHook: Send + Sync {
+    // `task` is the workflow version of the result
+    fn process(&self, result: &mut ChainMessage, task: &Workflow) -> anyhow::Result<()>;
+}
+
+pub type Hooks = BTreeMap<String, Arc<dyn ResultHook>>;
+
+// the hooks the workflow names that are not registered
+pub fn missing<'a>(hooks: &Hooks, task: &'a Workflow) -> Vec<&'a str> {
+    task.hooks
+        .iter()
+        .filter(|name| !hooks.contains_key(*name))
+        .map(String::as_str)
+        .collect()
+}
+
+pub fn process(hooks: &Hooks, task: &Workflow, result: &mut ChainMessage) -> anyhow::Result<()> {
+    for name in &task.hooks {
+        let hook = hooks
+            .get(name)
+            .ok_or(anyhow::format_err!("unknown result hook {name}"))?;
+        hook.process(result, task)
+            .map_err(|err| anyhow::format_err!("result hook {name}: {err}"))?
+    }
+    Ok(())
+}
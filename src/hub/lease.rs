@@ -0,0 +1,101 @@
+// bounding the concurrent executions of the stages that declare a `concurrency` in the workflow,
+// across all the workers of the stage instead of per worker
+// a worker acquires a lease before executing such a stage, renews it while executing and releases
+// it afterwards. a full stage is refused, and the worker retries after a while. a lease that is
+// not renewed in time expires, so the slot of a crashed or partitioned worker frees up eventually
+// like the scheduler, the leases are local to the hub instance that accepts the writes, i.e. the
+// leader of a raft group. after a leader change the running executions renew in vain and are not
+// counted, so the limit may be exceeded until they finish
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::Lease;
+
+// long enough for a few renewals to get lost
+pub const LEASE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Held {
+    stage: String,
+    expires: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct Leases {
+    held: HashMap<u64, Held>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageLeases {
+    held: u32,
+    limit: u32,
+}
+
+impl Leases {
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.held.retain(|_, held| held.expires > now)
+    }
+
+    fn count(&self, stage: &str) -> u32 {
+        self.held
+            .values()
+            .filter(|held| held.stage == stage)
+            .count() as _
+    }
+
+    // `None` if the stage already runs as many executions as the limit
+    pub fn acquire(&mut self, stage: &str, limit: u32) -> Option<Lease> {
+        self.expire();
+        if self.count(stage) >= limit {
+            return None;
+        }
+        // random rather than sequential, so a lease of a former leader is not mistaken for one of
+        // this instance
+        let lease = rand::random();
+        self.held.insert(
+            lease,
+            Held {
+                stage: stage.into(),
+                expires: Instant::now() + LEASE_TTL,
+            },
+        );
+        Some(Lease {
+            lease,
+            ttl: LEASE_TTL.as_millis() as _,
+        })
+    }
+
+    // false if the lease is unknown, e.g. expired
+    pub fn renew(&mut self, lease: u64) -> bool {
+        self.expire();
+        let Some(held) = self.held.get_mut(&lease) else {
+            return false;
+        };
+        held.expires = Instant::now() + LEASE_TTL;
+        true
+    }
+
+    pub fn release(&mut self, lease: u64) {
+        self.held.remove(&lease);
+    }
+
+    // the held leases of every stage with a limit
+    pub fn summary(&mut self, limits: &BTreeMap<String, u32>) -> BTreeMap<String, StageLeases> {
+        self.expire();
+        limits
+            .iter()
+            .map(|(stage, limit)| {
+                let leases = StageLeases {
+                    held: self.count(stage),
+                    limit: *limit,
+                };
+                (stage.clone(), leases)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,209 @@
+// the append-only ledger of the accepted results, which the notarizations prove the inclusion in
+// (see `notary`)
+// the digest of every result as it is kept under `chain` is appended as `ledger/<index>`, and the
+// index of the latest leaf of each name under `chain` is kept at `ledger/index/<name>`. the members
+// of a raft group apply the same results in the same order, so they build the same ledger. a result
+// applied again e.g. when a member replays its log is not appended again
+// the head is recomputed from all the leaves for every notarization, which is fine for the sizes
+// a hub currently reaches
+// a pruned result (see `gc`) leaves its header at `pruned/<name>`, whose digest is still the leaf,
+// so its inclusion is still proven
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    attribution,
+    blob::{reassemble_local, BlobStore},
+    crypto::{CryptoSuite, Digest},
+    notary::{self, Bundle, HeaderBundle, LedgerHead, Notarization, ResultHeader},
+    signer::Signer,
+    TaskId, Workflow,
+};
+
+use super::{
+    backfill::{chain_key, CHAIN_PREFIX},
+    challenge::reverted,
+    ChainMessage,
+};
+
+const SIZE_KEY: &str = "ledger/size";
+
+const PRUNED_PREFIX: &str = "pruned";
+
+fn leaf_key(index: u64) -> String {
+    format!("ledger/{index}")
+}
+
+fn index_key(name: &str) -> String {
+    format!("ledger/index/{name}")
+}
+
+fn size(blobs: &dyn BlobStore) -> anyhow::Result<u64> {
+    match blobs.get(SIZE_KEY)? {
+        Some(size) => Ok(std::str::from_utf8(&size)?.parse()?),
+        None => Ok(0),
+    }
+}
+
+fn index(blobs: &dyn BlobStore, name: &str) -> anyhow::Result<u64> {
+    let index = blobs
+        .get(&index_key(name))?
+        .ok_or(anyhow::format_err!("result is not in the ledger"))?;
+    Ok(std::str::from_utf8(&index)?.parse()?)
+}
+
+fn leaf(blobs: &dyn BlobStore, index: u64) -> anyhow::Result<Digest> {
+    let leaf = blobs
+        .get(&leaf_key(index))?
+        .ok_or(anyhow::format_err!("missing ledger leaf {index}"))?;
+    Ok((*leaf).try_into()?)
+}
+
+// the standalone hub applies the results concurrently, so the appends are serialized
+#[derive(Debug, Default)]
+pub struct Ledger(Mutex<()>);
+
+impl Ledger {
+    pub fn append(
+        &self,
+        blobs: &dyn BlobStore,
+        crypto: &dyn CryptoSuite,
+        result: &ChainMessage,
+        kept: &[u8],
+    ) -> anyhow::Result<()> {
+        let key = chain_key(result);
+        let name = key.split_once('/').unwrap().1;
+        let digest = crypto.digest(kept);
+        let _guard = self.0.lock().unwrap();
+        if let Some(index) = blobs.get(&index_key(name))? {
+            if leaf(blobs, std::str::from_utf8(&index)?.parse()?)? == digest {
+                return Ok(());
+            }
+        }
+        let index = size(blobs)?;
+        blobs.put(&leaf_key(index), digest.to_vec().into())?;
+        blobs.put(&index_key(name), index.to_string().into())?;
+        blobs.put(SIZE_KEY, (index + 1).to_string().into())
+    }
+}
+
+fn leaves(blobs: &dyn BlobStore) -> anyhow::Result<Vec<Digest>> {
+    (0..size(blobs)?).map(|index| leaf(blobs, index)).collect()
+}
+
+// the header of the result kept as `kept` under `chain/<name>`, of the workflow `task` if it is
+// still known
+pub fn header(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    task: Option<&Workflow>,
+    name: &str,
+    kept: &[u8],
+) -> anyhow::Result<ResultHeader> {
+    let result = serde_json::from_slice::<ChainMessage>(kept)?;
+    let reverted = reverted(blobs, result.id)?.is_some();
+    let attribution = match task {
+        Some(task) if result.chunk.is_none() && !reverted => {
+            attribution::shares(task, &result).unwrap_or_default()
+        }
+        _ => Default::default(),
+    };
+    Ok(ResultHeader {
+        id: result.id,
+        chunk: result.chunk,
+        workflow: result.workflow,
+        digest: crypto.digest(kept),
+        index: index(blobs, name)?,
+        attribution,
+        expired: result.expired.is_some(),
+        reverted,
+    })
+}
+
+pub fn keep_header(blobs: &dyn BlobStore, name: &str, header: &ResultHeader) -> anyhow::Result<()> {
+    blobs.put(
+        &format!("{PRUNED_PREFIX}/{name}"),
+        serde_json::to_vec(header)?.into(),
+    )
+}
+
+// the retained header of the pruned result of an ordinary task, `None` if it is not pruned
+pub fn pruned(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Option<ResultHeader>> {
+    match blobs.get(&format!("{PRUNED_PREFIX}/{id}"))? {
+        Some(header) => Ok(Some(serde_json::from_slice(&header)?)),
+        None => Ok(None),
+    }
+}
+
+fn sign(
+    crypto: &dyn CryptoSuite,
+    signer: &dyn Signer,
+    bundle: &impl serde::Serialize,
+) -> anyhow::Result<Notarization> {
+    let bundle = serde_json::to_string(bundle)?;
+    Ok(Notarization {
+        suite: crypto.name(),
+        public_key: signer.public_key().to_vec(),
+        signature: signer.sign(bundle.as_bytes())?,
+        bundle,
+    })
+}
+
+// of the header of a result, whether it is pruned or not
+pub fn notarize_header(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    signer: &dyn Signer,
+    header: ResultHeader,
+) -> anyhow::Result<Notarization> {
+    let leaves = leaves(blobs)?;
+    let bundle = HeaderBundle {
+        head: LedgerHead {
+            size: leaves.len() as _,
+            root: notary::root(crypto, &leaves),
+        },
+        path: notary::prove(crypto, &leaves, header.index as _),
+        header,
+        notarized_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    sign(crypto, signer, &bundle)
+}
+
+// of the kept result of an ordinary task under its workflow, `None` if there is no such result
+pub fn notarize(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    signer: &dyn Signer,
+    task: &Workflow,
+    id: TaskId,
+    max_size: u64,
+) -> anyhow::Result<Option<Notarization>> {
+    let name = id.to_string();
+    let Some(kept) = blobs.get(&format!("{CHAIN_PREFIX}/{name}"))? else {
+        return Ok(None);
+    };
+    let index = index(blobs, &name)?;
+    let result = serde_json::from_slice::<ChainMessage>(&kept)?;
+    let output = result
+        .blob
+        .as_ref()
+        .map(|blob| reassemble_local(blobs, blob, max_size))
+        .transpose()?;
+    let leaves = leaves(blobs)?;
+    let bundle = Bundle {
+        result: String::from_utf8(kept.into())?,
+        workflow: task.clone(),
+        output,
+        head: LedgerHead {
+            size: leaves.len() as _,
+            root: notary::root(crypto, &leaves),
+        },
+        index,
+        path: notary::prove(crypto, &leaves, index as _),
+        reverted: reverted(blobs, id)?,
+        notarized_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    Ok(Some(sign(crypto, signer, &bundle)?))
+}
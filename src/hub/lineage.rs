@@ -0,0 +1,330 @@
+// the provenance of a task as a graph, for provenance tooling, built from the kept events of the
+// tasks of its handoff chain (see `history` and `handoff`). `GET /tasks/<task id>/lineage`
+// returns the graph, and `?format=prov` the same graph as W3C PROV-JSON
+// every kept event is a step: the start of a task, the output of a stage, the accepted result, a
+// challenge or an expiry, along with the node producing it, the digest of its clock, the program
+// and the payload, i.e. the digest of the payload as published and, for an offloaded payload, the
+// chunks of the blob. the steps are linked to the ones they happen after by their clocks (see
+// `history::predecessors`), and the final result of an upstream task to the start of the
+// downstream one it is handed off to
+// the blob of a payload is only known while its message is kept, i.e. for the latest message of
+// every stage until the task is pruned (see `gc`)
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    blob::{BlobRef, BlobStore},
+    crypto::CryptoSuite,
+    hex,
+    history::{predecessors, EventKind, HistoryEvent},
+    Chunk, NodeId, StageSource, TaskId,
+};
+
+use super::{
+    backfill::result_key,
+    challenge::kept_gossip,
+    handoff::{self, Lineage},
+    history::history,
+    ChainMessage,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LineageQuery {
+    // `prov` for PROV-JSON
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    // the handoff chain, from the first upstream task to the task itself
+    tasks: Vec<Lineage>,
+    steps: Vec<Step>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Step {
+    // `<task id>/<index of the event>`
+    id: String,
+    task: TaskId,
+    kind: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<Chunk>,
+    // microseconds since the unix epoch, as the event is applied
+    at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    producer: Option<NodeId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assignee: Option<NodeId>,
+    // the hex encoded digests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    size: u64,
+    // the hex encoded digests of the chunks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blob: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    // the step happens after the other one, e.g. a stage after the previous stage
+    After,
+    // the result of the upstream task starts the downstream one
+    HandedOff,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    // the ids of the steps, from the earlier one
+    from: String,
+    to: String,
+    relation: Relation,
+}
+
+fn step_id(id: TaskId, index: usize) -> String {
+    format!("{id}/{index}")
+}
+
+// of the payload of the event, as long as it is kept
+fn kept_blob(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    id: TaskId,
+    event: &HistoryEvent,
+) -> Option<BlobRef> {
+    let blob = match event.kind {
+        EventKind::Gossip | EventKind::Migration => {
+            let source = match &event.stage {
+                None => StageSource::Start,
+                Some(stage) => StageSource::Name(stage.clone()),
+            };
+            kept_gossip(blobs, id, &source).ok()?.blob?
+        }
+        EventKind::Result => {
+            let result = blobs.get(&result_key(id, event.chunk)).ok()??;
+            serde_json::from_slice::<ChainMessage>(&result).ok()?.blob?
+        }
+        EventKind::Challenge | EventKind::Expiry => return None,
+    };
+    // the kept message may have been superseded since, e.g. by a later chunk
+    (Some(crypto.digest(&blob.chunks.concat())) == event.payload).then_some(blob)
+}
+
+// `None` if nothing is kept of the task
+pub fn graph(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    id: TaskId,
+) -> anyhow::Result<Option<Graph>> {
+    let tasks = handoff::lineage(blobs, id)?;
+    let mut steps = Vec::new();
+    let mut edges = Vec::new();
+    // the final result of the upstream task
+    let mut upstream = None::<String>;
+    for task in &tasks {
+        let events = history(blobs, task.id)?;
+        for (index, event) in events.iter().enumerate() {
+            let id = step_id(task.id, index);
+            for predecessor in predecessors(&events, index) {
+                edges.push(Edge {
+                    from: step_id(task.id, predecessor),
+                    to: id.clone(),
+                    relation: Relation::After,
+                })
+            }
+            if let (EventKind::Gossip, None, Some(upstream)) = (event.kind, &event.stage, &upstream)
+            {
+                edges.push(Edge {
+                    from: upstream.clone(),
+                    to: id.clone(),
+                    relation: Relation::HandedOff,
+                })
+            }
+            steps.push(Step {
+                id,
+                task: task.id,
+                kind: event.kind,
+                stage: event.stage.clone(),
+                chunk: event.chunk,
+                at: event.at,
+                producer: event.producer,
+                assignee: event.assignee,
+                clock: event
+                    .clock()
+                    .map(|clock| serde_json::to_vec(clock).map(|clock| hex(&crypto.digest(&clock))))
+                    .transpose()?,
+                program: event.program.as_ref().map(|program| hex(program)),
+                payload: event.payload.as_ref().map(|payload| hex(payload)),
+                size: event.size,
+                blob: kept_blob(blobs, crypto, task.id, event)
+                    .map(|blob| blob.chunks.iter().map(|chunk| hex(chunk)).collect()),
+            })
+        }
+        upstream = events
+            .iter()
+            .position(|event| {
+                event.kind == EventKind::Result && event.chunk.is_none_or(|chunk| chunk.last)
+            })
+            .map(|index| step_id(task.id, index));
+    }
+    if steps.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Graph {
+        tasks,
+        steps,
+        edges,
+    }))
+}
+
+// `YYYY-MM-DDThh:mm:ss.ffffffZ` of the microseconds since the unix epoch
+fn date_time(at: u64) -> String {
+    let (secs, micros) = (at / 1_000_000, at % 1_000_000);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // the civil date of the days since the unix epoch, by the proleptic gregorian calendar
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{micros:06}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+impl Step {
+    fn prov_type(&self) -> &'static str {
+        match (self.kind, &self.stage) {
+            (EventKind::Gossip, None) => "pohb:start",
+            (EventKind::Gossip, Some(_)) => "pohb:stage",
+            (EventKind::Result, _) => "pohb:result",
+            (EventKind::Challenge, _) => "pohb:challenge",
+            (EventKind::Expiry, _) => "pohb:expiry",
+            (EventKind::Migration, _) => "pohb:migration",
+        }
+    }
+}
+
+fn payload_entity(payload: &str) -> String {
+    format!("pohb:payload/{payload}")
+}
+
+impl Graph {
+    // every step is an activity generating its payload as an entity, and using the payloads of
+    // the steps it happens after. the producing nodes are agents, following the programs as plans
+    pub fn prov(&self) -> Value {
+        let mut entities = Map::new();
+        let mut activities = Map::new();
+        let mut agents = Map::new();
+        let mut relations = BTreeMap::<_, Map<_, _>>::new();
+        let mut relate = |relation: &str, attributes: Value| {
+            let relations = relations.entry(relation.to_owned()).or_default();
+            let id = format!("_:{relation}{}", relations.len());
+            relations.insert(id, attributes);
+        };
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| (&step.id, step))
+            .collect::<BTreeMap<_, _>>();
+        for step in &self.steps {
+            let activity = format!("pohb:step/{}", step.id);
+            let mut attributes = json!({
+                "prov:type": step.prov_type(),
+                "prov:endTime": date_time(step.at),
+                "pohb:task": step.task,
+            });
+            let mut attribute = |name: &str, value: Value| {
+                if !value.is_null() {
+                    attributes[format!("pohb:{name}")] = value
+                }
+            };
+            attribute("stage", json!(step.stage));
+            attribute("chunk", json!(step.chunk.map(|chunk| chunk.seq)));
+            attribute("clock", json!(step.clock));
+            activities.insert(activity.clone(), attributes);
+            if let Some(payload) = &step.payload {
+                let entity = payload_entity(payload);
+                let mut attributes = json!({ "pohb:size": step.size });
+                if let Some(blob) = &step.blob {
+                    attributes["pohb:blob"] = json!(blob)
+                }
+                entities.insert(entity.clone(), attributes);
+                relate(
+                    "wasGeneratedBy",
+                    json!({ "prov:entity": entity, "prov:activity": activity }),
+                );
+            }
+            if let Some(producer) = step.producer {
+                let agent = format!("pohb:node/{producer:08x}");
+                agents.insert(agent.clone(), json!({ "prov:type": "prov:SoftwareAgent" }));
+                let mut association = json!({ "prov:activity": activity, "prov:agent": agent });
+                if let Some(program) = &step.program {
+                    let plan = format!("pohb:program/{program}");
+                    entities.insert(plan.clone(), json!({ "prov:type": "prov:Plan" }));
+                    association["prov:plan"] = json!(plan)
+                }
+                relate("wasAssociatedWith", association)
+            }
+        }
+        for edge in &self.edges {
+            let (Some(from), Some(to)) = (steps.get(&edge.from), steps.get(&edge.to)) else {
+                continue;
+            };
+            let (informant, informed) = (
+                format!("pohb:step/{}", from.id),
+                format!("pohb:step/{}", to.id),
+            );
+            match (edge.relation, &from.payload, &to.payload) {
+                (Relation::HandedOff, Some(from), Some(to)) => relate(
+                    "wasDerivedFrom",
+                    json!({
+                        "prov:generatedEntity": payload_entity(to),
+                        "prov:usedEntity": payload_entity(from),
+                    }),
+                ),
+                // a challenge invalidates the result it follows
+                (Relation::After, Some(payload), _) if to.kind == EventKind::Challenge => relate(
+                    "wasInvalidatedBy",
+                    json!({ "prov:entity": payload_entity(payload), "prov:activity": informed }),
+                ),
+                (Relation::After, Some(payload), _) => relate(
+                    "used",
+                    json!({ "prov:activity": informed, "prov:entity": payload_entity(payload) }),
+                ),
+                _ => {}
+            }
+            relate(
+                "wasInformedBy",
+                json!({ "prov:informed": informed, "prov:informant": informant }),
+            )
+        }
+        let mut document = json!({
+            "prefix": { "pohb": "urn:pohb:" },
+            "entity": entities,
+            "activity": activities,
+            "agent": agents,
+        });
+        for (relation, instances) in relations {
+            document[relation] = Value::Object(instances)
+        }
+        document
+    }
+}
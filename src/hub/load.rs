@@ -0,0 +1,105 @@
+// shedding new tasks under overload. otherwise the gossip fans out faster than the subscribers
+// observe it, and the watch channel silently coalesces the messages that a stage then misses
+// the load is the number of gossip messages published in the current second. with a capacity set,
+// a new task is admitted only while the load stays below the share of the capacity its priority
+// may take: a half for `Low`, four fifths for `Normal` and all of it for `High`, but at least one.
+// the rest of the gossip, i.e. the later stages and the later chunks of admitted streams, carries
+// work that is already done, so it is never shed, but it counts towards the load. the decisions
+// only depend on the order of the publishes within the second, so the same burst is always shed
+// the same way
+// a shed publish is refused with 503 and a `Retry-After` of the rest of the second, and the
+// backfill verification pauses as long as anything is being shed
+// the load is local to the hub instance receiving the publishes
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::Priority;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct Load {
+    capacity: Option<u32>,
+    window: Instant,
+    published: u32,
+    // the latest window in which anything is shed
+    shed_window: Option<Instant>,
+    admitted: BTreeMap<Priority, u64>,
+    shed: BTreeMap<Priority, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoadReport<'a> {
+    capacity: Option<u32>,
+    // of the current second
+    load: u32,
+    shedding: bool,
+    // the new tasks since start
+    admitted: &'a BTreeMap<Priority, u64>,
+    shed: &'a BTreeMap<Priority, u64>,
+}
+
+impl Load {
+    pub fn new(capacity: Option<u32>) -> Self {
+        Self {
+            capacity,
+            window: Instant::now(),
+            published: 0,
+            shed_window: None,
+            admitted: Default::default(),
+            shed: Default::default(),
+        }
+    }
+
+    fn roll(&mut self) {
+        if self.window.elapsed() >= WINDOW {
+            self.window = Instant::now();
+            self.published = 0
+        }
+    }
+
+    // `priority` of a new task, `None` for the rest of the gossip. returns how long to wait before
+    // publishing again if the task is shed
+    pub fn admit(&mut self, priority: Option<Priority>) -> Result<(), Duration> {
+        self.roll();
+        if let (Some(capacity), Some(priority)) = (self.capacity, priority) {
+            let share = match priority {
+                Priority::Low => capacity / 2,
+                Priority::Normal => (capacity as u64 * 4 / 5) as _,
+                Priority::High => capacity,
+            }
+            .max(1);
+            if self.published >= share {
+                *self.shed.entry(priority).or_default() += 1;
+                self.shed_window = Some(self.window);
+                return Err(WINDOW.saturating_sub(self.window.elapsed()));
+            }
+        }
+        if let Some(priority) = priority {
+            *self.admitted.entry(priority).or_default() += 1
+        }
+        self.published += 1;
+        Ok(())
+    }
+
+    // anything is shed in the current or the previous second
+    pub fn shedding(&self) -> bool {
+        self.shed_window
+            .is_some_and(|window| window.elapsed() < WINDOW * 2)
+    }
+
+    pub fn report(&mut self) -> LoadReport<'_> {
+        self.roll();
+        LoadReport {
+            capacity: self.capacity,
+            load: self.published,
+            shedding: self.shedding(),
+            admitted: &self.admitted,
+            shed: &self.shed,
+        }
+    }
+}
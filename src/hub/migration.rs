@@ -0,0 +1,134 @@
+// moving an in-flight ordinary task from the workflow version it started under to another one, e.g.
+// to the current version after a reload fixed the allowlist of a later stage. the pending message
+// of the task, i.e. the one its next stages execute upon, is re-stamped with the other version,
+// verified in full under it and published again, so the next stages execute under the other
+// version from there on. a task with several pending messages, i.e. with parallel branches in
+// flight or waiting to be joined, is not migrated, as the other branches would keep executing
+// under the old version, and be refused, while the join waits for them under the new one
+// the versions must agree on everything the completed stages are verified by and the remaining
+// stages consume: the same stages upon the same stages, so the clocks so far stay valid, the same
+// routing of the remaining stages, i.e. of the ones the pending message does not execute upon, and
+// the same outputs of the last one. the input of a task still at its start is checked against the
+// other version, as on submission
+// the migration is kept at `tasks/<task id>/migration` as the version migrated to, and the later
+// messages of the task under any other version are refused, e.g. of a worker that was executing
+// the next stage under the old version
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blob::BlobStore, StageSource, TaskId, Workflow, WorkflowDigest};
+
+use super::{challenge::kept_gossip, GossipMessage};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Migration {
+    pub from: WorkflowDigest,
+    // re-stamped with the version migrated to
+    pub message: GossipMessage,
+}
+
+fn migration_key(id: TaskId) -> String {
+    format!("tasks/{id}/migration")
+}
+
+pub fn keep(blobs: &dyn BlobStore, migration: &Migration) -> anyhow::Result<()> {
+    blobs.put(
+        &migration_key(migration.message.id),
+        serde_json::to_vec(&migration.message.workflow)?.into(),
+    )
+}
+
+// the version the task is migrated to, `None` if it is not migrated
+pub fn migrated(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Option<WorkflowDigest>> {
+    match blobs.get(&migration_key(id))? {
+        Some(workflow) => Ok(serde_json::from_slice(&workflow)?),
+        None => Ok(None),
+    }
+}
+
+// whether the later messages of the task under the version are refused, since the task is
+// migrated to another one
+pub fn superseded(
+    blobs: &dyn BlobStore,
+    id: TaskId,
+    workflow: WorkflowDigest,
+) -> anyhow::Result<bool> {
+    Ok(migrated(blobs, id)?.is_some_and(|to| to != workflow))
+}
+
+// the kept messages of the task its next stages execute upon, i.e. of the start or of a completed
+// stage some downstream stage of which has not completed yet, under the branches taken so far.
+// a task with several of them has a branch in flight, which keeps executing under the old version
+pub fn pending(task: &Workflow, blobs: &dyn BlobStore, id: TaskId) -> Vec<GossipMessage> {
+    let kept = [StageSource::Start]
+        .into_iter()
+        .chain(task.stages.iter().cloned().map(StageSource::Name))
+        .filter_map(|source| Some((kept_gossip(blobs, id, &source).ok()?, source)))
+        .collect::<Vec<_>>();
+    let branches = kept
+        .iter()
+        .flat_map(|(message, _)| message.branches.clone())
+        .collect();
+    let task = task.taken(&branches);
+    let completed = kept
+        .iter()
+        .filter_map(|(_, source)| match source {
+            StageSource::Start => None,
+            StageSource::Name(stage) => Some(stage.clone()),
+        })
+        .collect::<BTreeSet<_>>();
+    kept.into_iter()
+        .filter(|(_, source)| {
+            task.downstream(source)
+                .into_iter()
+                .any(|stage| !completed.contains(stage))
+        })
+        .map(|(message, _)| message)
+        .collect()
+}
+
+pub fn compatible(from: &Workflow, to: &Workflow, source: &StageSource) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        from.stages == to.stages
+            && from
+                .stages
+                .iter()
+                .all(|stage| from.upstream(stage) == to.upstream(stage)),
+        "workflow versions have different stages"
+    );
+    anyhow::ensure!(
+        from.outputs == to.outputs,
+        "workflow versions have different outputs"
+    );
+    // the branches taken and the iterations executed so far are verified under the new version
+    // as well
+    anyhow::ensure!(
+        serde_json::to_value(&from.branching)? == serde_json::to_value(&to.branching)?,
+        "workflow versions have different branches"
+    );
+    anyhow::ensure!(
+        serde_json::to_value(&from.looping)? == serde_json::to_value(&to.looping)?,
+        "workflow versions have different loops"
+    );
+    // the stages the source does not execute upon, including the parallel ones listed before it
+    let completed = match source {
+        StageSource::Start => Vec::new(),
+        StageSource::Name(stage) => from
+            .ancestors(stage)
+            .ok_or(anyhow::format_err!("unknown stage {stage}"))?,
+    };
+    let remaining = from
+        .stages
+        .iter()
+        .filter(|stage| !completed.contains(&stage.as_str()));
+    for stage in remaining {
+        // the routing has no equality of its own
+        anyhow::ensure!(
+            serde_json::to_value(from.routing.get(stage))?
+                == serde_json::to_value(to.routing.get(stage))?,
+            "workflow versions route stage {stage} differently"
+        )
+    }
+    Ok(())
+}
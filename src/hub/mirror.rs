@@ -0,0 +1,157 @@
+// a read-only mirror of another hub, the primary, for scaling out the auditors, dashboards and
+// other readers without loading the primary. the mirror follows the gossip and the chain of the
+// primary and applies them as its own events, so whatever is derived from them is served as by
+// the primary: the subscriptions, the histories, the lineage, the attribution, the ledger (whose
+// notarizations are signed with the key of the mirror) etc. every write is refused
+// the messages are applied as delivered, i.e. the mirror trusts the primary rather than verifying
+// them again. the offloaded payloads are copied along and the workflow versions are fetched as
+// they show up. the events are numbered by the mirror itself, while the number of the last message
+// applied from each subscription is kept, so a restarted mirror resumes after it. a new mirror
+// starts with what the primary applies from then on
+// what is not a part of the gossip and the chain stays with the primary, e.g. the challenges, the
+// audits, the logs, the leases and the scheduling. the results are the ones the primary delivers,
+// i.e. after its result hooks
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    blob::{blob_key, BlobRef},
+    hex,
+    transport::{HttpTransport, HubTransport as _},
+    WorkflowDigest,
+};
+
+use super::{HubEvent, Shared};
+
+const GOSSIP_KEY: &str = "mirror/gossip";
+
+const CHAIN_KEY: &str = "mirror/chain";
+
+pub async fn follow(shared: Shared) {
+    let Some(primary) = shared.mirror.clone() else {
+        return;
+    };
+    let primary = HttpTransport::new(Client::new(), &*primary);
+    let gossip = subscription(&shared, &primary, "/gossip", GOSSIP_KEY, HubEvent::Gossip);
+    let chain = subscription(&shared, &primary, "/chain", CHAIN_KEY, HubEvent::Chain);
+    let (gossip, chain) = tokio::join!(gossip, chain);
+    for err in [gossip, chain].into_iter().filter_map(Result::err) {
+        warn!("stop mirroring: {err}")
+    }
+}
+
+// the subscription is resumed by the transport whenever it breaks, so it only ends when the
+// primary refuses it
+async fn subscription<M: DeserializeOwned + Send + 'static>(
+    shared: &Shared,
+    primary: &HttpTransport,
+    path: &str,
+    key: &str,
+    event: fn(M) -> HubEvent,
+) -> anyhow::Result<()> {
+    let after = match shared.blobs.get(key)? {
+        Some(seq) => Some(std::str::from_utf8(&seq)?.parse()?),
+        None => None,
+    };
+    let mut messages = primary.subscribe_numbered::<M>(path, after).await?;
+    match after {
+        Some(after) => info!("mirror {path} after {after}"),
+        None => info!("mirror {path}"),
+    }
+    while let Some(message) = messages.next().await {
+        let (seq, event) = match message {
+            Ok((seq, message)) => (seq, event(message)),
+            Err(err) => {
+                warn!("failed to receive from {path}: {err}");
+                continue;
+            }
+        };
+        if let Err(err) = copy(shared, primary, &event).await {
+            warn!("failed to copy what {path} refers to: {err}")
+        }
+        if let Some(failure) = shared.fanout.apply(event).failure {
+            warn!("failed to mirror {path}: {failure}")
+        }
+        if let Some(seq) = seq {
+            shared.blobs.put(key, seq.to_string().into())?
+        }
+    }
+    anyhow::bail!("subscription {path} is closed")
+}
+
+// the workflow version and the offloaded payload of the message, if the mirror lacks them
+async fn copy(shared: &Shared, primary: &HttpTransport, event: &HubEvent) -> anyhow::Result<()> {
+    let (workflow, blob) = match event {
+        HubEvent::Gossip(message) => (message.workflow, &message.blob),
+        HubEvent::Chain(message) => (message.workflow, &message.blob),
+        _ => return Ok(()),
+    };
+    if let Some(digest) = workflow {
+        copy_workflow(shared, primary, digest).await?
+    }
+    if let Some(blob) = blob {
+        copy_blob(shared, primary, blob).await?
+    }
+    Ok(())
+}
+
+async fn copy_workflow(
+    shared: &Shared,
+    primary: &HttpTransport,
+    digest: WorkflowDigest,
+) -> anyhow::Result<()> {
+    if shared.task.read().unwrap().versions.contains_key(&digest) {
+        return Ok(());
+    }
+    let task = primary.workflow(Some(&digest)).await?;
+    anyhow::ensure!(
+        task.digest(&*shared.crypto) == digest,
+        "workflow version {} is served with another digest",
+        hex(&digest)
+    );
+    shared.task.write().unwrap().add(task, &*shared.crypto);
+    info!("mirrored workflow version {}", hex(&digest));
+    Ok(())
+}
+
+async fn copy_blob(shared: &Shared, primary: &HttpTransport, blob: &BlobRef) -> anyhow::Result<()> {
+    for digest in &blob.chunks {
+        let key = blob_key(digest);
+        if shared.blobs.get(&key)?.is_some() {
+            continue;
+        }
+        let chunk = primary.download_blob(digest).await?;
+        anyhow::ensure!(
+            shared.crypto.digest(&chunk) == *digest,
+            "blob chunk {} is served with another digest",
+            hex(digest)
+        );
+        shared.blobs.put(&key, chunk)?
+    }
+    Ok(())
+}
+
+// the endpoints that are posted to but only read, which the mirror serves as well
+const QUERIES: &[&str] = &["/chain/verify", "/workflows/validate"];
+
+// everything but reading is up to the primary
+pub async fn refuse_writes(shared: State<Shared>, request: Request, next: Next) -> Response {
+    let query = *request.method() == Method::POST && QUERIES.contains(&request.uri().path());
+    if query || matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let primary = shared.mirror.as_deref().unwrap_or_default();
+    (
+        StatusCode::FORBIDDEN,
+        format!("read-only mirror of {primary}"),
+    )
+        .into_response()
+}
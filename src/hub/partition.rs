@@ -0,0 +1,226 @@
+// the isolation of the workflows sharing a hub. the gossip and the chain are partitioned by the
+// registered workflows, i.e. the workflow of the hub, whose reloaded versions all stay in its
+// partition, and each of the workflows served alongside. every partition has its own channels, so
+// a burst of one pipeline only coalesces the messages of its own subscribers (see `load`) rather
+// than overwriting the ones of the other pipelines, and with a capacity set its own publish rate
+// limit, applied before the one of the whole hub, so it cannot take the shared capacity from them
+// `GET /gossip?workflow=<hex>` and `GET /chain?workflow=<hex>` subscribe to the partition of the
+// version, and the subscriptions without one merge all partitions. `GET /partitions` reports the
+// messages and the load of each partition
+// a message of an unknown version, which a raft member that has not reloaded its file yet may
+// apply, goes to the partition of the workflow of the hub
+// the channels count their messages, so a subscription tells when the channel has coalesced some
+// of them, and marks the next message as `missed` (see `resume`)
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch::Sender;
+use tokio_stream::{Stream, StreamExt as _, StreamMap};
+
+use crate::{hex, Priority, WorkflowDigest};
+
+use super::{
+    load::{Load, LoadReport},
+    parse_digest,
+    resume::Numbered,
+    watch, ChainMessage, GossipMessage,
+};
+
+// a message along with the count of the messages sent on the channel up to it
+type Counted<M> = (u64, Numbered<M>);
+
+pub struct Partition {
+    // the version the workflow is registered with
+    workflow: WorkflowDigest,
+    gossip: Sender<Option<Counted<GossipMessage>>>,
+    chain: Sender<Option<Counted<ChainMessage>>>,
+    published: AtomicU64,
+    accepted: AtomicU64,
+    load: Mutex<Load>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartitionReport<'a> {
+    workflow: String,
+    versions: Vec<String>,
+    // the gossip messages and the results applied since start
+    published: u64,
+    accepted: u64,
+    load: LoadReport<'a>,
+}
+
+// of a subscription
+#[derive(Debug, Default, Deserialize)]
+pub struct Scope {
+    // the hex encoded workflow version
+    workflow: Option<String>,
+}
+
+impl Scope {
+    pub fn version(&self) -> anyhow::Result<Option<WorkflowDigest>> {
+        self.workflow
+            .as_deref()
+            .map(|workflow| {
+                parse_digest(workflow).ok_or(anyhow::format_err!("malformed workflow version"))
+            })
+            .transpose()
+    }
+}
+
+pub struct Partitions {
+    // the workflow of the hub first
+    partitions: Vec<Partition>,
+    versions: RwLock<HashMap<WorkflowDigest, usize>>,
+}
+
+pub type Merged<M> = Pin<Box<dyn Stream<Item = M> + Send>>;
+
+fn counted<M: Clone + Send + Sync + 'static>(
+    sender: &Sender<Option<Counted<M>>>,
+) -> impl Stream<Item = Numbered<M>> + Send + 'static {
+    let mut last = None::<u64>;
+    watch(sender).map(move |(count, mut message)| {
+        message.missed = last.is_some_and(|last| count != last + 1);
+        last = Some(count);
+        message
+    })
+}
+
+fn send<M>(sender: &Sender<Option<Counted<M>>>, message: Numbered<M>) {
+    sender.send_modify(|last| {
+        let count = last.as_ref().map_or(0, |(count, _)| count + 1);
+        *last = Some((count, message))
+    })
+}
+
+impl Partitions {
+    pub fn new(workflows: impl IntoIterator<Item = WorkflowDigest>, capacity: Option<u32>) -> Self {
+        let partitions = workflows
+            .into_iter()
+            .map(|workflow| Partition {
+                workflow,
+                gossip: Sender::new(None),
+                chain: Sender::new(None),
+                published: Default::default(),
+                accepted: Default::default(),
+                load: Mutex::new(Load::new(capacity)),
+            })
+            .collect::<Vec<_>>();
+        assert!(!partitions.is_empty(), "no workflow to partition");
+        let versions = partitions
+            .iter()
+            .enumerate()
+            .map(|(index, partition)| (partition.workflow, index))
+            .collect();
+        Self {
+            partitions,
+            versions: RwLock::new(versions),
+        }
+    }
+
+    // a reloaded version of the workflow of the hub
+    pub fn reload(&self, version: WorkflowDigest) {
+        self.versions.write().unwrap().entry(version).or_insert(0);
+    }
+
+    fn of(&self, version: Option<WorkflowDigest>) -> &Partition {
+        let index = version
+            .and_then(|version| self.versions.read().unwrap().get(&version).copied())
+            .unwrap_or_default();
+        &self.partitions[index]
+    }
+
+    // whether a message of the version `of` is delivered to the subscribers of the partition of
+    // `version`, see `subscribe`
+    pub fn covers(&self, version: Option<WorkflowDigest>, of: Option<WorkflowDigest>) -> bool {
+        version.is_none() || std::ptr::eq(self.of(version), self.of(of))
+    }
+
+    // `None` of the version to merge all partitions
+    fn subscribe<M: Clone + Send + Sync + 'static>(
+        &self,
+        version: Option<WorkflowDigest>,
+        channel: impl Fn(&Partition) -> &Sender<Option<Counted<M>>>,
+    ) -> Merged<Numbered<M>> {
+        if version.is_some() {
+            return Box::pin(counted(channel(self.of(version))));
+        }
+        let merged = self
+            .partitions
+            .iter()
+            .enumerate()
+            .map(|(index, partition)| (index, counted(channel(partition))))
+            .collect::<StreamMap<_, _>>();
+        Box::pin(merged.map(|(_, message)| message))
+    }
+
+    pub fn gossip(&self, version: Option<WorkflowDigest>) -> Merged<Numbered<GossipMessage>> {
+        self.subscribe(version, |partition| &partition.gossip)
+    }
+
+    pub fn chain(&self, version: Option<WorkflowDigest>) -> Merged<Numbered<ChainMessage>> {
+        self.subscribe(version, |partition| &partition.chain)
+    }
+
+    // `seq` is the number of the message in the log, see `resume`
+    pub fn publish(&self, seq: Option<u64>, message: GossipMessage) {
+        let partition = self.of(message.workflow);
+        partition.published.fetch_add(1, Relaxed);
+        send(&partition.gossip, Numbered::new(seq, message))
+    }
+
+    pub fn accept(&self, seq: Option<u64>, message: ChainMessage) {
+        let partition = self.of(message.workflow);
+        partition.accepted.fetch_add(1, Relaxed);
+        send(&partition.chain, Numbered::new(seq, message))
+    }
+
+    // as `Load::admit`, within the partition of the version
+    pub fn admit(
+        &self,
+        version: Option<WorkflowDigest>,
+        priority: Option<Priority>,
+    ) -> Result<(), Duration> {
+        self.of(version).load.lock().unwrap().admit(priority)
+    }
+
+    // the loads are locked throughout, so the reports are taken by `f`
+    pub fn report<T>(&self, f: impl FnOnce(Vec<PartitionReport<'_>>) -> T) -> T {
+        let mut loads = self
+            .partitions
+            .iter()
+            .map(|partition| partition.load.lock().unwrap())
+            .collect::<Vec<_>>();
+        let versions = self.versions.read().unwrap();
+        let reports = self
+            .partitions
+            .iter()
+            .zip(&mut loads)
+            .enumerate()
+            .map(|(index, (partition, load))| {
+                let mut of = versions
+                    .iter()
+                    .filter(|(_, of)| **of == index)
+                    .map(|(version, _)| hex(version))
+                    .collect::<Vec<_>>();
+                of.sort();
+                PartitionReport {
+                    workflow: hex(&partition.workflow),
+                    versions: of,
+                    published: partition.published.load(Relaxed),
+                    accepted: partition.accepted.load(Relaxed),
+                    load: load.report(),
+                }
+            })
+            .collect();
+        f(reports)
+    }
+}
@@ -0,0 +1,113 @@
+// quarantining the gossip messages that name stages their workflow version does not have, e.g. of
+// a worker running a task description out of step with the hub (version skew). such a message
+// would otherwise fail one of the later checks with an error about clocks or routes that hides
+// the actual mismatch
+// the message is committed like the other events and kept at `tasks/<task id>/quarantine/<time>`
+// along with the unknown stages and the stages of the version, where the garbage collection
+// expires it along with the task. the publisher is answered with the same description.
+// `GET /quarantine` lists the kept messages of every task, for an operator to tell which workers
+// are out of step. a quarantined message does not advance its task, so it is not a part of the
+// history of the task
+use std::{
+    collections::BTreeSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blob::BlobStore, hex, StageSource, TaskId, Workflow, WorkflowDigest};
+
+use super::GossipMessage;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quarantine {
+    // microseconds since the unix epoch, by the clock of the hub instance quarantining it
+    pub at: u64,
+    pub workflow: WorkflowDigest,
+    // the stages the message names that the version does not have, and the ones it has
+    pub unknown: Vec<String>,
+    pub stages: Vec<String>,
+    pub message: GossipMessage,
+}
+
+impl Quarantine {
+    pub fn reason(&self) -> String {
+        format!(
+            "stages {} of the message are not in workflow version {} (stages {}), the publisher \
+            probably runs another version",
+            self.unknown.join(", "),
+            hex(&self.workflow),
+            self.stages.join(", "),
+        )
+    }
+}
+
+fn quarantine_prefix(id: TaskId) -> String {
+    format!("tasks/{id}/quarantine")
+}
+
+// `None` if every stage the message names, as its source or by its clocks, program versions or
+// routes, is a stage of the version. only the messages of the stages are checked, since the hub
+// stamps the start ones with the version itself
+pub fn check(
+    message: &GossipMessage,
+    task: &Workflow,
+    workflow: WorkflowDigest,
+) -> anyhow::Result<Option<Quarantine>> {
+    let StageSource::Name(source) = &message.source else {
+        return Ok(None);
+    };
+    let unknown = [source]
+        .into_iter()
+        .chain(message.clocks.keys())
+        .chain(message.programs.keys())
+        .chain(message.routes.keys())
+        .filter(|stage| !task.stages.contains(stage))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    if unknown.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Quarantine {
+        at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as _,
+        workflow,
+        unknown: unknown.into_iter().collect(),
+        stages: task.stages.clone(),
+        message: message.clone(),
+    }))
+}
+
+pub fn keep(blobs: &dyn BlobStore, quarantine: &Quarantine) -> anyhow::Result<()> {
+    blobs.put(
+        &format!(
+            "{}/{}",
+            quarantine_prefix(quarantine.message.id),
+            quarantine.at
+        ),
+        serde_json::to_vec(quarantine)?.into(),
+    )
+}
+
+// of the task, in the order they are quarantined
+pub fn quarantined(blobs: &dyn BlobStore, id: TaskId) -> anyhow::Result<Vec<Quarantine>> {
+    let prefix = quarantine_prefix(id);
+    let mut quarantined = Vec::new();
+    for name in blobs.list(&prefix)? {
+        if let Some(quarantine) = blobs.get(&format!("{prefix}/{name}"))? {
+            quarantined.push(serde_json::from_slice::<Quarantine>(&quarantine)?)
+        }
+    }
+    quarantined.sort_by_key(|quarantine| quarantine.at);
+    Ok(quarantined)
+}
+
+// of every task, in the order they are quarantined
+pub fn all(blobs: &dyn BlobStore) -> anyhow::Result<Vec<Quarantine>> {
+    let mut quarantined = Vec::new();
+    for id in blobs.list("tasks")? {
+        let Ok(id) = id.parse() else { continue };
+        quarantined.extend(self::quarantined(blobs, id)?)
+    }
+    quarantined.sort_by_key(|quarantine| quarantine.at);
+    Ok(quarantined)
+}
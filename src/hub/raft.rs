@@ -0,0 +1,406 @@
+// replicate the hub across a group of `network` instances with raft
+// the replicated state machine is the hub's event log: every accepted gossip publish and chain
+// proposal becomes one log entry, and applying an entry fans it out to the local subscribers. as
+// the result every instance serves subscriptions (they all apply the same log in the same order),
+// while only the leader accepts writes and the others redirect writers to it
+// this is not meant to be a full p2p network. the group is a small static set of operator run
+// instances that removes the hub as the single point of failure, and nothing more
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    io::Cursor,
+    ops::RangeBounds,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Json, Router,
+};
+use openraft::{
+    error::{InstallSnapshotError, NetworkError, RPCError, RaftError, RemoteError},
+    network::RPCOption,
+    raft::{
+        AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest,
+        InstallSnapshotResponse, VoteRequest, VoteResponse,
+    },
+    storage::{Adaptor, LogState, RaftLogReader, RaftSnapshotBuilder, RaftStorage, Snapshot},
+    BasicNode, Entry, EntryPayload, LogId, RaftNetwork, RaftNetworkFactory, SnapshotMeta,
+    StorageError, StorageIOError, StoredMembership, Vote,
+};
+use reqwest::Client;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Applied, Fanout, HubEvent};
+
+pub type HubId = u64;
+
+openraft::declare_raft_types!(
+    pub TypeConfig:
+        D = HubEvent,
+        R = Applied,
+        NodeId = HubId,
+        Node = BasicNode,
+);
+
+pub type Raft = openraft::Raft<TypeConfig>;
+
+pub async fn start(id: HubId, fanout: Fanout) -> anyhow::Result<Raft> {
+    let config = openraft::Config {
+        heartbeat_interval: 250,
+        election_timeout_min: 1000,
+        election_timeout_max: 2000,
+        ..Default::default()
+    };
+    let (log_store, state_machine) = Adaptor::new(Store::new(fanout));
+    Ok(Raft::new(
+        id,
+        Arc::new(config.validate()?),
+        Network(Client::new()),
+        log_store,
+        state_machine,
+    )
+    .await?)
+}
+
+// the internal rpc endpoints used between hub instances, plus the administration endpoints
+// for forming the group
+pub fn router() -> Router<Raft> {
+    Router::new()
+        .route("/raft/append", post(append))
+        .route("/raft/vote", post(vote))
+        .route("/raft/snapshot", post(snapshot))
+        .route("/raft/init", post(init))
+        .route("/raft/add-learner", post(add_learner))
+        .route("/raft/change-membership", post(change_membership))
+        .route("/raft/metrics", get(metrics))
+}
+
+async fn append(
+    raft: State<Raft>,
+    Json(request): Json<AppendEntriesRequest<TypeConfig>>,
+) -> Json<Result<AppendEntriesResponse<HubId>, RaftError<HubId>>> {
+    Json(raft.append_entries(request).await)
+}
+
+async fn vote(
+    raft: State<Raft>,
+    Json(request): Json<VoteRequest<HubId>>,
+) -> Json<Result<VoteResponse<HubId>, RaftError<HubId>>> {
+    Json(raft.vote(request).await)
+}
+
+async fn snapshot(
+    raft: State<Raft>,
+    Json(request): Json<InstallSnapshotRequest<TypeConfig>>,
+) -> Json<Result<InstallSnapshotResponse<HubId>, RaftError<HubId, InstallSnapshotError>>> {
+    Json(raft.install_snapshot(request).await)
+}
+
+// the administration endpoints reply with the debug formatted outcome. they are only for
+// operators bootstrapping the group by hand, e.g.
+// curl -X POST localhost:3000/raft/init -H 'content-type: application/json' \
+//     -d '{"1": {"addr": "localhost:3000"}}'
+async fn init(raft: State<Raft>, Json(members): Json<BTreeMap<HubId, BasicNode>>) -> String {
+    format!("{:?}", raft.initialize(members).await)
+}
+
+async fn add_learner(raft: State<Raft>, Json((id, node)): Json<(HubId, BasicNode)>) -> String {
+    format!("{:?}", raft.add_learner(id, node, true).await)
+}
+
+async fn change_membership(raft: State<Raft>, Json(ids): Json<Vec<HubId>>) -> String {
+    format!("{:?}", raft.change_membership(ids, false).await)
+}
+
+async fn metrics(raft: State<Raft>) -> String {
+    format!("{:?}", raft.metrics().borrow())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateMachine {
+    last_applied: Option<LogId<HubId>>,
+    membership: StoredMembership<HubId, BasicNode>,
+    events: Vec<HubEvent>,
+}
+
+#[derive(Default)]
+struct StoreState {
+    vote: Option<Vote<HubId>>,
+    log: BTreeMap<u64, Entry<TypeConfig>>,
+    last_purged: Option<LogId<HubId>>,
+    state_machine: StateMachine,
+    snapshot: Option<(SnapshotMeta<HubId, BasicNode>, Vec<u8>)>,
+    snapshot_index: u64,
+}
+
+// everything is kept in memory: a restarted instance rejoins the group empty and catches up from
+// the others through log replication or snapshot installation
+#[derive(Clone)]
+struct Store {
+    state: Arc<Mutex<StoreState>>,
+    fanout: Fanout,
+}
+
+impl Store {
+    fn new(fanout: Fanout) -> Self {
+        Self {
+            state: Default::default(),
+            fanout,
+        }
+    }
+}
+
+impl RaftLogReader<TypeConfig> for Store {
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug + Send>(
+        &mut self,
+        range: RB,
+    ) -> Result<Vec<Entry<TypeConfig>>, StorageError<HubId>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .log
+            .range(range)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+}
+
+impl RaftSnapshotBuilder<TypeConfig> for Store {
+    async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<HubId>> {
+        let mut state = self.state.lock().unwrap();
+        let data = serde_json::to_vec(&state.state_machine)
+            .map_err(|err| StorageIOError::read_state_machine(&err))?;
+        state.snapshot_index += 1;
+        let meta = SnapshotMeta {
+            last_log_id: state.state_machine.last_applied,
+            last_membership: state.state_machine.membership.clone(),
+            snapshot_id: format!(
+                "{}-{}",
+                state
+                    .state_machine
+                    .last_applied
+                    .map(|log_id| log_id.index)
+                    .unwrap_or_default(),
+                state.snapshot_index
+            ),
+        };
+        state.snapshot = Some((meta.clone(), data.clone()));
+        Ok(Snapshot {
+            meta,
+            snapshot: Box::new(Cursor::new(data)),
+        })
+    }
+}
+
+impl RaftStorage<TypeConfig> for Store {
+    type LogReader = Self;
+    type SnapshotBuilder = Self;
+
+    async fn save_vote(&mut self, vote: &Vote<HubId>) -> Result<(), StorageError<HubId>> {
+        self.state.lock().unwrap().vote = Some(*vote);
+        Ok(())
+    }
+
+    async fn read_vote(&mut self) -> Result<Option<Vote<HubId>>, StorageError<HubId>> {
+        Ok(self.state.lock().unwrap().vote)
+    }
+
+    async fn get_log_state(&mut self) -> Result<LogState<TypeConfig>, StorageError<HubId>> {
+        let state = self.state.lock().unwrap();
+        let last_purged_log_id = state.last_purged;
+        let last_log_id = state
+            .log
+            .values()
+            .next_back()
+            .map(|entry| entry.log_id)
+            .or(last_purged_log_id);
+        Ok(LogState {
+            last_purged_log_id,
+            last_log_id,
+        })
+    }
+
+    async fn get_log_reader(&mut self) -> Self::LogReader {
+        self.clone()
+    }
+
+    async fn append_to_log<I>(&mut self, entries: I) -> Result<(), StorageError<HubId>>
+    where
+        I: IntoIterator<Item = Entry<TypeConfig>> + Send,
+    {
+        let mut state = self.state.lock().unwrap();
+        for entry in entries {
+            state.log.insert(entry.log_id.index, entry);
+        }
+        Ok(())
+    }
+
+    async fn delete_conflict_logs_since(
+        &mut self,
+        log_id: LogId<HubId>,
+    ) -> Result<(), StorageError<HubId>> {
+        self.state.lock().unwrap().log.split_off(&log_id.index);
+        Ok(())
+    }
+
+    async fn purge_logs_upto(&mut self, log_id: LogId<HubId>) -> Result<(), StorageError<HubId>> {
+        let mut state = self.state.lock().unwrap();
+        state.log = state.log.split_off(&(log_id.index + 1));
+        state.last_purged = Some(log_id);
+        Ok(())
+    }
+
+    async fn last_applied_state(
+        &mut self,
+    ) -> Result<(Option<LogId<HubId>>, StoredMembership<HubId, BasicNode>), StorageError<HubId>>
+    {
+        let state = self.state.lock().unwrap();
+        Ok((
+            state.state_machine.last_applied,
+            state.state_machine.membership.clone(),
+        ))
+    }
+
+    async fn apply_to_state_machine(
+        &mut self,
+        entries: &[Entry<TypeConfig>],
+    ) -> Result<Vec<Applied>, StorageError<HubId>> {
+        let mut state = self.state.lock().unwrap();
+        let mut applied = Vec::with_capacity(entries.len());
+        for entry in entries {
+            state.state_machine.last_applied = Some(entry.log_id);
+            match &entry.payload {
+                EntryPayload::Blank => applied.push(Applied::default()),
+                EntryPayload::Normal(event) => {
+                    applied.push(self.fanout.apply(event.clone()));
+                    state.state_machine.events.push(event.clone())
+                }
+                EntryPayload::Membership(membership) => {
+                    applied.push(Applied::default());
+                    state.state_machine.membership =
+                        StoredMembership::new(Some(entry.log_id), membership.clone())
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    async fn get_snapshot_builder(&mut self) -> Self::SnapshotBuilder {
+        self.clone()
+    }
+
+    async fn begin_receiving_snapshot(
+        &mut self,
+    ) -> Result<Box<Cursor<Vec<u8>>>, StorageError<HubId>> {
+        Ok(Box::new(Cursor::new(Vec::new())))
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        meta: &SnapshotMeta<HubId, BasicNode>,
+        snapshot: Box<Cursor<Vec<u8>>>,
+    ) -> Result<(), StorageError<HubId>> {
+        let data = snapshot.into_inner();
+        let state_machine = serde_json::from_slice::<StateMachine>(&data)
+            .map_err(|err| StorageIOError::read_snapshot(Some(meta.signature()), &err))?;
+        // the subscribers of this instance have missed everything covered by the snapshot that is
+        // not applied yet. replay them so the instance ends up serving the same stream as the
+        // others (modulo the usual watch channel coalescing)
+        let mut state = self.state.lock().unwrap();
+        for event in state_machine
+            .events
+            .iter()
+            .skip(state.state_machine.events.len())
+        {
+            self.fanout.apply(event.clone());
+        }
+        state.state_machine = state_machine;
+        state.snapshot = Some((meta.clone(), data));
+        Ok(())
+    }
+
+    async fn get_current_snapshot(
+        &mut self,
+    ) -> Result<Option<Snapshot<TypeConfig>>, StorageError<HubId>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.snapshot.as_ref().map(|(meta, data)| Snapshot {
+            meta: meta.clone(),
+            snapshot: Box::new(Cursor::new(data.clone())),
+        }))
+    }
+}
+
+struct Network(Client);
+
+struct NetworkConnection {
+    client: Client,
+    target: HubId,
+    addr: String,
+}
+
+impl NetworkConnection {
+    async fn send<
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        E: std::error::Error + DeserializeOwned,
+    >(
+        &self,
+        path: &str,
+        request: Req,
+    ) -> Result<Resp, RPCError<HubId, BasicNode, E>> {
+        let response = self
+            .client
+            .post(format!("http://{}/raft/{path}", self.addr))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|err| RPCError::Network(NetworkError::new(&err)))?;
+        let result = response
+            .json::<Result<Resp, E>>()
+            .await
+            .map_err(|err| RPCError::Network(NetworkError::new(&err)))?;
+        result.map_err(|err| RPCError::RemoteError(RemoteError::new(self.target, err)))
+    }
+}
+
+impl RaftNetworkFactory<TypeConfig> for Network {
+    type Network = NetworkConnection;
+
+    async fn new_client(&mut self, target: HubId, node: &BasicNode) -> Self::Network {
+        NetworkConnection {
+            client: self.0.clone(),
+            target,
+            addr: node.addr.clone(),
+        }
+    }
+}
+
+impl RaftNetwork<TypeConfig> for NetworkConnection {
+    async fn append_entries(
+        &mut self,
+        rpc: AppendEntriesRequest<TypeConfig>,
+        _: RPCOption,
+    ) -> Result<AppendEntriesResponse<HubId>, RPCError<HubId, BasicNode, RaftError<HubId>>> {
+        self.send("append", rpc).await
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        rpc: InstallSnapshotRequest<TypeConfig>,
+        _: RPCOption,
+    ) -> Result<
+        InstallSnapshotResponse<HubId>,
+        RPCError<HubId, BasicNode, RaftError<HubId, InstallSnapshotError>>,
+    > {
+        self.send("snapshot", rpc).await
+    }
+
+    async fn vote(
+        &mut self,
+        rpc: VoteRequest<HubId>,
+        _: RPCOption,
+    ) -> Result<VoteResponse<HubId>, RPCError<HubId, BasicNode, RaftError<HubId>>> {
+        self.send("vote", rpc).await
+    }
+}
@@ -0,0 +1,184 @@
+// resuming the gossip and the chain subscriptions, on another member of a raft group or on the
+// same instance after a restart, so a subscriber that fails over misses as little as possible
+// every event delivered through the server-sent events is numbered by its position in the log of
+// the hub (see `history`) as the `id` of the event, which is the same on every member of a raft
+// group. a subscriber reconnecting with `Last-Event-ID` first receives the kept events numbered
+// after it, and then the live ones that are not replayed already
+// only the latest message of every stage of a task is kept, so a message that is superseded
+// since, e.g. of an earlier chunk of a stream, is skipped rather than replayed, and so are the
+// results that are pruned and the tasks that are expired by the garbage collection. the replay is
+// best effort, just like the live channels, which coalesce the messages under bursts (see `load`)
+// a live channel coalesces the messages a slow subscriber has not received yet, and the message
+// after those is marked as `missed`. the server-sent events then carry a `gap` event right before
+// it, upon which the subscriber may resume after the last event it has received to receive the
+// missed ones, as the `HttpTransport` does
+// a subscriber waiting on a filtered chain subscription, e.g. for the result of its own task, may
+// not receive any event before it disconnects, so it has nothing to resume after. a chain
+// subscription that is not resumed therefore starts with a `resume` event whose id is a token for
+// the position of the log as the subscription starts, and reconnecting with that token as
+// `Last-Event-ID` replays every result applied since, e.g. during the reconnection. the tokens
+// are opaque to the subscribers, and the ids of the messages keep resuming as before
+// the websocket subscriptions are not numbered, so they cannot be resumed
+use axum::http::HeaderMap;
+use tokio_stream::{Stream, StreamExt as _};
+
+use crate::{
+    blob::BlobStore,
+    crypto::CryptoSuite,
+    history::{EventKind, HistoryEvent},
+    StageSource, TaskId,
+};
+
+use super::{
+    backfill::result_key,
+    challenge::kept_gossip,
+    history::{histories, size},
+    ChainMessage, GossipMessage,
+};
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+pub const GAP_EVENT: &str = "gap";
+
+pub const RESUME_EVENT: &str = "resume";
+
+// of the position `from`, i.e. resuming at the event numbered `from`
+pub fn token(from: u64) -> String {
+    format!("p{from}")
+}
+
+// a message as delivered, along with its number in the log, `None` for a message that is applied
+// again, e.g. when a raft member replays its log
+#[derive(Debug, Clone)]
+pub struct Numbered<M> {
+    pub seq: Option<u64>,
+    pub message: M,
+    // the subscriber has missed messages before this one
+    pub missed: bool,
+}
+
+impl<M> Numbered<M> {
+    pub fn new(seq: Option<u64>, message: M) -> Self {
+        Self {
+            seq,
+            message,
+            missed: false,
+        }
+    }
+
+    pub fn unnumbered(message: M) -> Self {
+        Self::new(None, message)
+    }
+}
+
+// the messages that `keep`, where a message that is not kept passes its mark of the missed
+// messages on to the next one that is
+pub fn keeping<M: Send + 'static>(
+    messages: impl Stream<Item = Numbered<M>> + Send + 'static,
+    mut keep: impl FnMut(&Numbered<M>) -> bool + Send + 'static,
+) -> impl Stream<Item = Numbered<M>> + Send + 'static {
+    let mut missed = false;
+    messages.filter_map(move |mut message| {
+        missed |= message.missed;
+        if !keep(&message) {
+            return None;
+        }
+        message.missed = std::mem::take(&mut missed);
+        Some(message)
+    })
+}
+
+// the number of the first event to replay, if the subscriber resumes: the one after the last event
+// it has received, or the position of its token
+pub fn resume_from(headers: &HeaderMap) -> anyhow::Result<Option<u64>> {
+    let Some(value) = headers.get(LAST_EVENT_ID) else {
+        return Ok(None);
+    };
+    let value = value.to_str()?;
+    // a subscriber that has not received any numbered event yet
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.strip_prefix('p') {
+        Some(from) => Ok(Some(from.parse()?)),
+        None => Ok(Some(value.parse::<u64>()? + 1)),
+    }
+}
+
+// the kept events numbered from `from` on, in order, whose messages `load` still finds
+fn replay<M>(
+    blobs: &dyn BlobStore,
+    from: u64,
+    load: impl Fn(TaskId, &HistoryEvent) -> Option<M>,
+) -> anyhow::Result<Vec<Numbered<M>>> {
+    // nothing to scan through for a subscriber that is up to date
+    if size(blobs)? <= from {
+        return Ok(Vec::new());
+    }
+    let mut events = histories(blobs)?
+        .into_iter()
+        .flat_map(|(id, events)| events.into_iter().map(move |event| (id, event)))
+        .filter(|(_, event)| event.seq.is_some_and(|seq| seq >= from))
+        .collect::<Vec<_>>();
+    events.sort_by_key(|(_, event)| event.seq);
+    Ok(events
+        .into_iter()
+        .filter_map(|(id, event)| Some(Numbered::new(event.seq, load(id, &event)?)))
+        .collect())
+}
+
+// whether the kept message is still the one of the event
+fn same(event: &HistoryEvent, kept: &HistoryEvent) -> bool {
+    event.chunk == kept.chunk && event.payload == kept.payload
+}
+
+pub fn gossip(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    from: u64,
+) -> anyhow::Result<Vec<Numbered<GossipMessage>>> {
+    replay(blobs, from, |id, event| {
+        // a migration publishes the pending message again, under the version migrated to
+        if !matches!(event.kind, EventKind::Gossip | EventKind::Migration) {
+            return None;
+        }
+        let source = match &event.stage {
+            None => StageSource::Start,
+            Some(stage) => StageSource::Name(stage.clone()),
+        };
+        let message = kept_gossip(blobs, id, &source).ok()?;
+        same(event, &HistoryEvent::gossip(0, crypto, &message)).then_some(message)
+    })
+}
+
+pub fn chain(
+    blobs: &dyn BlobStore,
+    crypto: &dyn CryptoSuite,
+    from: u64,
+) -> anyhow::Result<Vec<Numbered<ChainMessage>>> {
+    replay(blobs, from, |id, event| {
+        if !matches!(event.kind, EventKind::Result | EventKind::Expiry) {
+            return None;
+        }
+        let message = blobs.get(&result_key(id, event.chunk)).ok()??;
+        let message = serde_json::from_slice::<ChainMessage>(&message).ok()?;
+        same(event, &HistoryEvent::result(0, crypto, &message)).then_some(message)
+    })
+}
+
+// the replayed messages, then the live ones numbered after them. the live ones must be subscribed
+// before the replay is read, so nothing applied in between is missed
+pub fn resumed<M: Send + 'static>(
+    from: u64,
+    replayed: Vec<Numbered<M>>,
+    live: impl Stream<Item = Numbered<M>> + Send + 'static,
+) -> impl Stream<Item = Numbered<M>> + Send + 'static {
+    let next = replayed
+        .iter()
+        .filter_map(|message| message.seq)
+        .max()
+        .map_or(from, |last| last + 1);
+    tokio_stream::iter(replayed).chain(keeping(live, move |message| {
+        message.seq.is_none_or(|seq| seq >= next)
+    }))
+}
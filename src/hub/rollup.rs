@@ -0,0 +1,264 @@
+// rolling up the attribution of the finished tasks into a summary per epoch, so the reward systems
+// consume compact aggregates rather than replaying every result. the epochs are fixed windows of
+// time, the n-th covering `[n * length, (n + 1) * length)` seconds since the unix epoch, and a task
+// falls into the epoch its final result (or its expiry) is applied in, by the kept events (see
+// `history`). the summary of an epoch holds, by node
+// * the stages it produced, by stage name, of the tasks that are not reverted
+// * its weighted credit, i.e. the sum of its shares (see `attribution::shares`) of those tasks
+// * its failures: the challenged stages of the reverted tasks and the stages failing their
+//   audits (see `audit`) that it produced, and the expired tasks it was assigned the next stage of
+// with an epoch length, the hub rolls up the epochs once they have ended and the longest challenge
+// window of the registered workflows has passed too, so the reverts are accounted for, and keeps
+// the summaries at `epochs/<n>`, which the garbage collection does not collect. so the retention
+// must be longer than an epoch and the window, or the tasks expire before they are rolled up.
+// `GET /attribution/epochs/:n` serves the summary of an epoch, and 404 for one not rolled up yet
+// the rollups are local to every hub instance, which applies the events at slightly different
+// times, so a task finishing right at the end of an epoch may fall into another one elsewhere
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::{
+    attribution,
+    blob::BlobStore,
+    history::{EventKind, HistoryEvent},
+    AuditVerdict, Chunk, NodeId, TaskId,
+};
+
+use super::{audit, backfill, challenge, history::histories, ledger, ChainMessage, Shared};
+
+const EPOCHS_PREFIX: &str = "epochs";
+
+// the first epoch that is not rolled up yet
+const NEXT_KEY: &str = "epochs/next";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NodeSummary {
+    pub stages: BTreeMap<String, u64>,
+    pub credit: f64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EpochSummary {
+    pub epoch: u64,
+    // seconds since the unix epoch
+    pub start: u64,
+    pub end: u64,
+    // the tasks finished within the epoch, of which some are reverted or expired
+    pub tasks: u64,
+    pub reverted: u64,
+    pub expired: u64,
+    pub nodes: BTreeMap<NodeId, NodeSummary>,
+}
+
+impl EpochSummary {
+    fn new(epoch: u64, length: u64) -> Self {
+        Self {
+            epoch,
+            start: epoch * length,
+            end: (epoch + 1) * length,
+            ..Default::default()
+        }
+    }
+
+    fn node(&mut self, node: NodeId) -> &mut NodeSummary {
+        self.nodes.entry(node).or_default()
+    }
+}
+
+fn epoch_key(epoch: u64) -> String {
+    format!("{EPOCHS_PREFIX}/{epoch}")
+}
+
+fn next_epoch(blobs: &dyn BlobStore) -> anyhow::Result<Option<u64>> {
+    match blobs.get(NEXT_KEY)? {
+        Some(next) => Ok(Some(std::str::from_utf8(&next)?.parse()?)),
+        None => Ok(None),
+    }
+}
+
+// `None` if the epoch is not rolled up yet. an epoch rolled up without any finished task is not
+// kept, and is summarized as empty
+pub fn summary(
+    blobs: &dyn BlobStore,
+    length: Duration,
+    epoch: u64,
+) -> anyhow::Result<Option<EpochSummary>> {
+    if let Some(summary) = blobs.get(&epoch_key(epoch))? {
+        return Ok(Some(serde_json::from_slice(&summary)?));
+    }
+    Ok(next_epoch(blobs)?
+        .is_some_and(|next| epoch < next)
+        .then(|| EpochSummary::new(epoch, length.as_secs())))
+}
+
+pub async fn periodically(shared: Shared, length: Duration) {
+    let mut interval = interval(length);
+    loop {
+        interval.tick().await;
+        let shared = shared.clone();
+        let rolled = tokio::task::spawn_blocking(move || run(&shared, length)).await;
+        match rolled {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => info!("rolled up {count} epochs"),
+            Ok(Err(err)) => warn!("failed to roll up epochs: {err}"),
+            Err(err) => warn!("failed to roll up epochs: {err}"),
+        }
+    }
+}
+
+// the terminal event of a task, i.e. of its final result or its expiry
+fn terminal(events: &[HistoryEvent]) -> Option<&HistoryEvent> {
+    events.iter().find(|event| match event.kind {
+        EventKind::Result => event.chunk.is_none_or(|chunk| chunk.last),
+        EventKind::Expiry => true,
+        EventKind::Gossip | EventKind::Challenge | EventKind::Migration => false,
+    })
+}
+
+// the number of epochs rolled up
+fn run(shared: &Shared, length: Duration) -> anyhow::Result<u64> {
+    let blobs = &*shared.blobs;
+    let length = length.as_secs();
+    let grace = shared
+        .task
+        .read()
+        .unwrap()
+        .versions
+        .values()
+        .filter_map(|task| task.challenge_window)
+        .max()
+        .unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    // the epochs ending before are closed
+    let closed = now.saturating_sub(grace) / length;
+
+    let mut finished = BTreeMap::<_, Vec<_>>::new();
+    for (id, events) in histories(blobs)? {
+        if let Some(event) = terminal(&events).cloned() {
+            let epoch = event.at / 1_000_000 / length;
+            finished.entry(epoch).or_default().push((id, events, event))
+        }
+    }
+    let next = match next_epoch(blobs)? {
+        Some(next) => next,
+        // the first run starts with the earliest epoch any kept task finished in
+        None => finished
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(closed)
+            .min(closed),
+    };
+    let mut count = 0;
+    for epoch in next..closed {
+        if let Some(tasks) = finished.remove(&epoch) {
+            let mut summary = EpochSummary::new(epoch, length);
+            for (id, events, terminal) in tasks {
+                roll_up(shared, id, &events, &terminal, &mut summary)?
+            }
+            blobs.put(&epoch_key(epoch), serde_json::to_vec(&summary)?.into())?;
+        }
+        blobs.put(NEXT_KEY, (epoch + 1).to_string().into())?;
+        count += 1
+    }
+    Ok(count)
+}
+
+// the producer of the stage by the latest event of it
+fn producer(events: &[HistoryEvent], stage: &str) -> Option<NodeId> {
+    events
+        .iter()
+        .rev()
+        .filter(|event| matches!(event.kind, EventKind::Gossip | EventKind::Result))
+        .find(|event| event.stage.as_deref() == Some(stage))?
+        .producer
+}
+
+fn roll_up(
+    shared: &Shared,
+    id: TaskId,
+    events: &[HistoryEvent],
+    terminal: &HistoryEvent,
+    summary: &mut EpochSummary,
+) -> anyhow::Result<()> {
+    let blobs = &*shared.blobs;
+    summary.tasks += 1;
+    if let Some(challenge) = challenge::reverted(blobs, id)? {
+        summary.reverted += 1;
+        if let Some(node) = producer(events, &challenge.stage) {
+            summary.node(node).failures += 1
+        }
+        return Ok(());
+    }
+
+    for audit in audit::audits(blobs, id)? {
+        if let (AuditVerdict::Failed, Some(node)) = (audit.verdict, audit.request.producer) {
+            summary.node(node).failures += 1
+        }
+    }
+    if terminal.kind == EventKind::Expiry {
+        summary.expired += 1;
+        // the node assigned the stage after the last completed one
+        let assignee = events
+            .iter()
+            .rev()
+            .find(|event| {
+                event.kind == EventKind::Gossip
+                    && event.chunk.is_none()
+                    && event.stage == terminal.stage
+            })
+            .and_then(|event| event.assignee);
+        if let Some(node) = assignee {
+            summary.node(node).failures += 1
+        }
+    }
+
+    let mut produced = HashMap::<_, HashMap<_, u64>>::new();
+    for event in events {
+        if let (EventKind::Gossip | EventKind::Result, Some(stage), Some(node)) =
+            (event.kind, &event.stage, event.producer)
+        {
+            *produced.entry(node).or_default().entry(stage).or_default() += 1
+        }
+    }
+    for (node, stages) in produced {
+        let node = summary.node(node);
+        for (stage, count) in stages {
+            *node.stages.entry(stage.clone()).or_default() += count
+        }
+    }
+
+    for (node, share) in shares(shared, id, terminal.chunk)? {
+        summary.node(node).credit += share
+    }
+    Ok(())
+}
+
+// of the final result, as computed when it was pruned if it is
+fn shares(
+    shared: &Shared,
+    id: TaskId,
+    chunk: Option<Chunk>,
+) -> anyhow::Result<BTreeMap<NodeId, f64>> {
+    let blobs = &*shared.blobs;
+    let Some(result) = blobs.get(&backfill::result_key(id, chunk))? else {
+        return Ok(Default::default());
+    };
+    let Ok(result) = serde_json::from_slice::<ChainMessage>(&result) else {
+        return Ok(ledger::pruned(blobs, id)?
+            .map(|header| header.attribution)
+            .unwrap_or_default());
+    };
+    let Some(task) = shared.task.read().unwrap().get(result.workflow) else {
+        return Ok(Default::default());
+    };
+    // a result whose shares cannot be computed credits nobody, as for its header
+    Ok(attribution::shares(&task, &result).unwrap_or_default())
+}
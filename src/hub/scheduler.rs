@@ -0,0 +1,124 @@
+// assigning the stages of tasks to workers, instead of letting every worker of a stage execute
+// every task of it, which wastes all but one of the executions once a stage has several workers
+// the messages are still fanned out to everyone, and the assignment is stamped into them for the
+// workers to pick their own. only the workers that report their status (`WorkerStatus`) are
+// scheduled, and a stage without any of them falls back to the broadcast model
+// among the live workers of the stage that carry the labels required by the workflow, the
+// preference goes to
+// * the worker that executed the previous chunk of the same streaming task at the stage, which
+//   holds the state of the stream
+// * the worker that executed the previous stage of the task, which already holds the input
+// * the least loaded worker
+// the scheduler state is local to the hub instance that accepts the writes, i.e. the leader of a
+// raft group, and is rebuilt from the status reports after a leader change
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{NodeId, StageSource, TaskId, TaskStage, WorkerStatus, Workflow};
+
+// a worker reports every second, so missing a few reports means it is gone
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    status: WorkerStatus,
+    // the assignments since the last report, which the reported load does not cover yet
+    assigned: u32,
+    #[serde(skip)]
+    last_seen: Instant,
+}
+
+impl Entry {
+    fn load(&self) -> u32 {
+        self.status.load + self.assigned
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    workers: HashMap<NodeId, Entry>,
+    assignments: HashMap<(TaskId, String), NodeId>,
+}
+
+impl Scheduler {
+    pub fn report(&mut self, status: WorkerStatus) {
+        self.workers.insert(
+            status.id,
+            Entry {
+                status,
+                assigned: 0,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    pub fn workers(&self) -> impl Serialize + '_ {
+        &self.workers
+    }
+
+    pub fn assign<C, I>(&mut self, task: &Workflow, message: &TaskStage<C, I>) -> Option<NodeId> {
+        // the next stage of a branching stage is the branch taken
+        let task = &*task.taken(&message.branches);
+        let stage = task.next_stage(&message.source)?;
+        self.workers
+            .retain(|_, entry| entry.last_seen.elapsed() < LIVENESS_TIMEOUT);
+        let required = task.affinity.get(stage);
+        // a routed task only goes to a worker of its shard
+        let route = message.routes.get(stage);
+        // the chunks of a task stay with the worker of the earlier ones, and so do the messages a
+        // joining stage executes upon together
+        let joining = task
+            .upstream(stage)
+            .is_some_and(|upstream| upstream.len() > 1);
+        let sticky = (message.chunk.is_some() || joining)
+            .then(|| self.assignments.get(&(message.id, stage.into())))
+            .flatten();
+        let local = match &message.source {
+            StageSource::Start => None,
+            StageSource::Name(name) => self.assignments.get(&(message.id, name.clone())),
+        };
+        let (&id, entry) = self
+            .workers
+            .iter_mut()
+            .filter(|(_, entry)| {
+                entry.status.stages.contains(stage)
+                    && required.is_none_or(|required| required.is_subset(&entry.status.labels))
+                    && route
+                        .is_none_or(|route| entry.status.shards.get(stage) == Some(&route.shard))
+            })
+            .min_by_key(|(id, entry)| {
+                (Some(*id) != sticky, Some(*id) != local, entry.load(), **id)
+            })?;
+        entry.assigned += 1;
+        self.assignments.insert((message.id, stage.into()), id);
+        Some(id)
+    }
+
+    // the live workers of the stage, and how many of them carry the labels the workflow requires
+    pub fn coverage(&mut self, task: &Workflow, stage: &str) -> (usize, usize) {
+        self.workers
+            .retain(|_, entry| entry.last_seen.elapsed() < LIVENESS_TIMEOUT);
+        let required = task.affinity.get(stage);
+        let workers = self
+            .workers
+            .values()
+            .filter(|entry| entry.status.stages.contains(stage))
+            .collect::<Vec<_>>();
+        let labeled = workers
+            .iter()
+            .filter(|entry| {
+                required.is_none_or(|required| required.is_subset(&entry.status.labels))
+            })
+            .count();
+        (workers.len(), labeled)
+    }
+
+    // forget the assignments of a task that will not be executed any further
+    pub fn finish(&mut self, id: TaskId) {
+        self.assignments.retain(|(other_id, _), _| *other_id != id)
+    }
+}
@@ -0,0 +1,269 @@
+// the throughput of the hub for dashboards, aggregated as the events are applied instead of by
+// replaying the streams: the tasks submitted, completed and failed, the mean latency of every
+// stage, the workers producing stages and the depths of the queues, over a rolling window
+// the counts are kept in buckets of a second for the longest window, so a report sums at most as
+// many buckets however busy the hub is. the latency of a stage is the time from applying the
+// latest of the messages it executes upon to applying its own, as this hub instance sees it, and
+// the queue of a stage is the running tasks whose messages it executes upon are all applied but
+// its own is not. a task without progress for the longest window is dropped from the queues. a
+// failed task is an expired one, and a reverted one is counted on its own
+// every member of a raft group applies the same events, so the members agree on the counts up to
+// the times they apply them at. the counts start over when the hub restarts
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{history, NodeId, OrdinaryClock, StageSource, TaskId, Workflow, WorkflowDigest};
+
+use super::{ChainMessage, GossipMessage};
+
+const BUCKET: Duration = Duration::from_secs(1);
+
+// the longest window, i.e. how long the buckets are kept
+pub const MAX_WINDOW: u64 = 3600;
+
+const DEFAULT_WINDOW: u64 = 60;
+
+#[derive(Debug, Default)]
+struct Bucket {
+    submitted: u64,
+    completed: u64,
+    failed: u64,
+    reverted: u64,
+    // the count, and the count and the total latency in milliseconds of the ones timed
+    stages: HashMap<String, (u64, u64, u64)>,
+    producers: HashSet<NodeId>,
+}
+
+// a running task, or a chunk of a streaming one
+#[derive(Debug)]
+struct Running {
+    workflow: Option<WorkflowDigest>,
+    applied: HashMap<StageSource, Instant>,
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    start: Instant,
+    // by the seconds since the start, the latest last
+    buckets: VecDeque<(u64, Bucket)>,
+    running: HashMap<(TaskId, Option<u64>), Running>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    // in seconds, up to `MAX_WINDOW`
+    window: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsReport {
+    window: u64,
+    submitted: u64,
+    completed: u64,
+    failed: u64,
+    reverted: u64,
+    // the completed tasks per second
+    throughput: f64,
+    running: usize,
+    // the distinct producers of the stages within the window
+    active_workers: usize,
+    stages: BTreeMap<String, StageStats>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StageStats {
+    completed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean_latency_ms: Option<f64>,
+    queued: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            buckets: Default::default(),
+            running: Default::default(),
+        }
+    }
+}
+
+impl Stats {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_secs() / BUCKET.as_secs()
+    }
+
+    fn bucket(&mut self) -> &mut Bucket {
+        let now = self.now();
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(second, _)| second + MAX_WINDOW <= now)
+        {
+            self.buckets.pop_front();
+        }
+        if self.buckets.back().is_none_or(|(second, _)| *second != now) {
+            self.buckets.push_back((now, Default::default()));
+            // e.g. a task without a deadline that is abandoned
+            let stalled = Duration::from_secs(MAX_WINDOW);
+            self.running.retain(|_, running| {
+                running
+                    .applied
+                    .values()
+                    .max()
+                    .is_some_and(|applied| applied.elapsed() < stalled)
+            })
+        }
+        &mut self.buckets.back_mut().unwrap().1
+    }
+
+    // `task` is the workflow version of the message, if it is known
+    pub fn gossip(&mut self, task: Option<&Workflow>, message: &GossipMessage) {
+        let key = (message.id, message.chunk.map(|chunk| chunk.seq));
+        match &message.source {
+            StageSource::Start => {
+                if message.chunk.is_none_or(|chunk| chunk.seq == 0) {
+                    self.bucket().submitted += 1
+                }
+                self.running.insert(
+                    key,
+                    Running {
+                        workflow: message.workflow,
+                        applied: [(StageSource::Start, Instant::now())].into(),
+                    },
+                );
+            }
+            StageSource::Name(stage) => self.stage(key, task, stage, &message.clocks),
+        }
+    }
+
+    // the last stage of a task publishes its output with the result
+    pub fn result(&mut self, task: Option<&Workflow>, result: &ChainMessage) {
+        let key = (result.id, result.chunk.map(|chunk| chunk.seq));
+        if let (None, Some(stage)) = (&result.expired, task.and_then(|task| task.stages.last())) {
+            self.stage(key, task, stage, &result.clocks)
+        }
+        self.running.remove(&key);
+        if result.chunk.is_some_and(|chunk| !chunk.last) {
+            return;
+        }
+        let bucket = self.bucket();
+        match result.expired {
+            Some(_) => bucket.failed += 1,
+            None => bucket.completed += 1,
+        }
+    }
+
+    fn stage(
+        &mut self,
+        key: (TaskId, Option<u64>),
+        task: Option<&Workflow>,
+        stage: &str,
+        clocks: &HashMap<String, OrdinaryClock>,
+    ) {
+        let now = Instant::now();
+        let Some(running) = self.running.get_mut(&key) else {
+            return;
+        };
+        let source = StageSource::Name(stage.into());
+        // a stage published again, e.g. by a racing worker, is counted once
+        if running.applied.contains_key(&source) {
+            return;
+        }
+        let latency = task
+            .and_then(|task| task.sources(stage))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|source| running.applied.get(source))
+            .max()
+            .map(|upstream| now.duration_since(*upstream).as_millis() as u64);
+        running.applied.insert(source, now);
+        let producer = history::producer(clocks, stage);
+        let bucket = self.bucket();
+        let (count, timed, total) = bucket.stages.entry(stage.into()).or_default();
+        *count += 1;
+        if let Some(latency) = latency {
+            *timed += 1;
+            *total += latency
+        }
+        bucket.producers.extend(producer)
+    }
+
+    pub fn revert(&mut self) {
+        self.bucket().reverted += 1
+    }
+
+    // `workflows` resolves the versions of the running tasks, for their queues
+    pub fn report(
+        &mut self,
+        query: &StatsQuery,
+        workflows: impl Fn(Option<WorkflowDigest>) -> Option<Arc<Workflow>>,
+    ) -> StatsReport {
+        let window = query.window.unwrap_or(DEFAULT_WINDOW).clamp(1, MAX_WINDOW);
+        self.bucket();
+        let now = self.now();
+        let mut report = StatsReport {
+            window,
+            submitted: 0,
+            completed: 0,
+            failed: 0,
+            reverted: 0,
+            throughput: 0.,
+            running: self.running.len(),
+            active_workers: 0,
+            stages: Default::default(),
+        };
+        let mut producers = HashSet::<NodeId>::new();
+        let mut latencies = HashMap::<&str, (u64, u64)>::new();
+        for (_, bucket) in self
+            .buckets
+            .iter()
+            .filter(|(second, _)| second + window > now)
+        {
+            report.submitted += bucket.submitted;
+            report.completed += bucket.completed;
+            report.failed += bucket.failed;
+            report.reverted += bucket.reverted;
+            producers.extend(&bucket.producers);
+            for (stage, (count, timed, total)) in &bucket.stages {
+                report.stages.entry(stage.clone()).or_default().completed += count;
+                let latency = latencies.entry(stage).or_default();
+                latency.0 += timed;
+                latency.1 += total
+            }
+        }
+        for (stage, (timed, total)) in latencies {
+            if timed > 0 {
+                report.stages.get_mut(stage).unwrap().mean_latency_ms =
+                    Some(total as f64 / timed as f64)
+            }
+        }
+        for running in self.running.values() {
+            let Some(task) = workflows(running.workflow) else {
+                continue;
+            };
+            for stage in &task.stages {
+                let ready = task.sources(stage).is_some_and(|sources| {
+                    sources
+                        .iter()
+                        .all(|source| running.applied.contains_key(source))
+                });
+                if ready
+                    && !running
+                        .applied
+                        .contains_key(&StageSource::Name(stage.clone()))
+                {
+                    report.stages.entry(stage.clone()).or_default().queued += 1
+                }
+            }
+        }
+        report.active_workers = producers.len();
+        report.throughput = report.completed as f64 / window as f64;
+        report
+    }
+}
@@ -0,0 +1,122 @@
+// dry-running a workflow definition before any task is submitted against it
+// `POST /workflows/validate` takes a definition, which does not have to be loaded, along with the
+// expected sizes of the task input and the stage outputs, and reports
+// * the problems of the definition itself (see `Workflow::validate`), and the result hooks it names
+//   that the hub does not have
+// * the warnings about the deployment, e.g. a stage no live worker executes, or a challenge window
+//   without anything to decide the challenges
+// * the flow of the payloads between the stages under the inline size limit of the hub
+// nothing is kept, and the workers are only known to the hub that schedules them, i.e. the leader
+// of a raft group
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blob::CHUNK_SIZE, hex, StageSource, Workflow};
+
+use super::{hook, Shared};
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub workflow: Workflow,
+    // in bytes, a hop without a declared size is left out of the flow
+    #[serde(default)]
+    pub input_size: Option<u64>,
+    #[serde(default)]
+    pub output_sizes: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    // the workflow version the definition would be loaded as
+    version: String,
+    problems: Vec<String>,
+    warnings: Vec<String>,
+    flow: Vec<Hop>,
+    // what every subscriber receives per task, and what is uploaded into the blob store instead
+    inline_bytes: u64,
+    offloaded_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Hop {
+    // `start` for the task input
+    from: String,
+    // the stages executing upon the payload, which it is published to once, or `chain` for the
+    // task result
+    to: Vec<String>,
+    size: u64,
+    // the number of chunks the payload is offloaded in, if it exceeds the inline size limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunks: Option<u64>,
+}
+
+pub fn validate(shared: &Shared, request: Request) -> Report {
+    let task = request.workflow;
+    let mut problems = task.validate();
+    for stage in request.output_sizes.keys() {
+        if !task.stages.contains(stage) {
+            problems.push(format!("output size of unknown stage {stage}"))
+        }
+    }
+    for hook in hook::missing(&shared.fanout.hooks, &task) {
+        problems.push(format!("result hook {hook} is not registered with the hub"))
+    }
+
+    let mut warnings = Vec::new();
+    {
+        let mut scheduler = shared.scheduler.lock().unwrap();
+        for stage in task.stages.iter().collect::<BTreeSet<_>>() {
+            match scheduler.coverage(&task, stage) {
+                (0, _) => warnings.push(format!("no live worker reports stage {stage}")),
+                (_, 0) => warnings.push(format!(
+                    "no live worker of stage {stage} carries the labels {:?}",
+                    task.affinity[stage]
+                )),
+                _ => {}
+            }
+        }
+    }
+    if task.challenge_window.is_some() && shared.reexecutor.is_none() {
+        warnings.push("the hub has no re-executor to decide the challenges".into())
+    }
+
+    let mut flow = Vec::new();
+    let sources = [(StageSource::Start, request.input_size)]
+        .into_iter()
+        .chain(task.stages.iter().map(|stage| {
+            (
+                StageSource::Name(stage.clone()),
+                request.output_sizes.get(stage).copied(),
+            )
+        }));
+    for (source, size) in sources {
+        let Some(size) = size else { continue };
+        let (from, to) = match &source {
+            StageSource::Start => (String::from("start"), task.downstream(&source)),
+            StageSource::Name(stage) if task.stages.last() == Some(stage) => {
+                (stage.clone(), vec!["chain"])
+            }
+            StageSource::Name(stage) => (stage.clone(), task.downstream(&source)),
+        };
+        let to = to.into_iter().map(Into::into).collect();
+        let chunks = (size > shared.max_inline_size as u64).then(|| size.div_ceil(CHUNK_SIZE as _));
+        flow.push(Hop {
+            from,
+            to,
+            size,
+            chunks,
+        })
+    }
+    let (offloaded, inline) = flow
+        .iter()
+        .partition::<Vec<_>, _>(|hop| hop.chunks.is_some());
+    Report {
+        version: hex(&task.digest(&*shared.crypto)),
+        problems,
+        warnings,
+        inline_bytes: inline.iter().map(|hop| hop.size).sum(),
+        offloaded_bytes: offloaded.iter().map(|hop| hop.size).sum(),
+        flow,
+    }
+}
@@ -0,0 +1,107 @@
+// where the hub verifies the gossip messages, i.e. the clocks up to the stage of a message and the
+// proof of the clock of it, as far as the workflow selects (see `Verification`). the results
+// proposed to the chain are always verified in full, and the workers verify what they receive, so
+// verifying the gossip only refuses a bad message earlier, at a cost the deployment places where
+// it has the capacity
+// * `skip` trusts the verification of the workers and of the result on `POST /chain/propose`
+// * `inline` verifies within the publish request
+// * `offload` hands the verification to a pool of dedicated verifier threads through a bounded
+//   queue, which the publish waits on, so the expensive proofs do not hold up the request handling.
+//   a full queue refuses the publish with 503 and a `Retry-After`, as an overloaded hub does (see
+//   `load`)
+use std::{
+    sync::{
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GossipVerification {
+    #[default]
+    Skip,
+    Inline,
+    Offload,
+}
+
+pub const DEFAULT_THREADS: usize = 2;
+
+const QUEUE_CAPACITY: usize = 256;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// the queue of the pool is full
+#[derive(Debug)]
+pub struct Saturated;
+
+impl std::fmt::Display for Saturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "verifiers are saturated")
+    }
+}
+
+impl std::error::Error for Saturated {}
+
+pub struct Pool {
+    queue: SyncSender<Job>,
+}
+
+impl Pool {
+    pub fn new(threads: usize) -> anyhow::Result<Self> {
+        let (queue, jobs) = sync_channel::<Job>(QUEUE_CAPACITY);
+        let jobs = Arc::new(Mutex::new(jobs));
+        for index in 0..threads.max(1) {
+            let jobs = jobs.clone();
+            thread::Builder::new()
+                .name(format!("verifier-{index}"))
+                .spawn(move || {
+                    // the pool is dropped along with the hub
+                    while let Ok(job) = {
+                        let jobs = jobs.lock().unwrap();
+                        jobs.recv()
+                    } {
+                        job()
+                    }
+                })?;
+        }
+        Ok(Self { queue })
+    }
+
+    // the verification as a job of the pool, which fails with `Saturated` if the queue is full
+    pub async fn verify(
+        &self,
+        verify: impl FnOnce() -> anyhow::Result<()> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        let job = Box::new(move || {
+            let _ = sender.send(verify());
+        });
+        match self.queue.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => return Err(Saturated.into()),
+            Err(TrySendError::Disconnected(_)) => anyhow::bail!("verifiers are gone"),
+        }
+        receiver.await?
+    }
+}
+
+pub enum Verifier {
+    Skip,
+    Inline,
+    Offload(Pool),
+}
+
+impl Verifier {
+    pub fn new(verification: GossipVerification, threads: usize) -> anyhow::Result<Self> {
+        Ok(match verification {
+            GossipVerification::Skip => Self::Skip,
+            GossipVerification::Inline => Self::Inline,
+            GossipVerification::Offload => Self::Offload(Pool::new(threads)?),
+        })
+    }
+}
@@ -0,0 +1,553 @@
+// interval tree clocks (almeida, baquero and fonte, 2008): a causality part whose size follows the
+// number of the nodes active at once rather than of every node that ever executed a stage, for
+// long-running deployments whose workers come and go
+// every node owns a part of the unit interval, its `Id`, and an event inflates the `Event` tree
+// over the part of the node. a new node forks the id of an existing one, and a node leaving joins
+// its id back into one that stays (`Id::fork`, `Id::join`), so the trees merge their branches
+// again and the clocks shrink back, while an ordinary clock keeps an entry of every node forever
+// the clocks carry the event trees only, i.e. they are the anonymous stamps of the paper, and
+// compare by them. an interval says nothing about who owns it, so these clocks cannot tell the
+// producers of the stages: like the scalar clocks, they have no entries (see
+// `attribution::Causality`), so they verify in the messages of a task by their own order, while
+// the attribution is left to the ordinary ones
+use std::{
+    cmp::{max, min, Ordering},
+    marker::PhantomData,
+    sync::OnceLock,
+};
+
+use derive_where::derive_where;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attribution::Causality, Binding, ClockClientContext, ClockContext, Error, OrdinaryClock,
+    Predecessor,
+};
+
+// the deepest trees accepted, far beyond what the fork and join of the nodes active at once make,
+// as the operations on the trees recurse
+const MAX_DEPTH: usize = 64;
+
+// the weight `grow` puts on extending the tree over inflating an existing branch
+const GROW_COST: usize = 1000;
+
+// the part of the unit interval a node owns: none, all, or the parts of the two halves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "IdRepr", into = "IdRepr")]
+pub enum Id {
+    Zero,
+    One,
+    Node(Box<Id>, Box<Id>),
+}
+
+// `0`, `1` or `[left, right]`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum IdRepr {
+    Leaf(u8),
+    Node(Box<Id>, Box<Id>),
+}
+
+impl TryFrom<IdRepr> for Id {
+    type Error = anyhow::Error;
+
+    fn try_from(repr: IdRepr) -> anyhow::Result<Self> {
+        match repr {
+            IdRepr::Leaf(0) => Ok(Self::Zero),
+            IdRepr::Leaf(1) => Ok(Self::One),
+            IdRepr::Leaf(leaf) => anyhow::bail!("invalid id leaf {leaf}"),
+            IdRepr::Node(left, right) => Ok(Self::Node(left, right)),
+        }
+    }
+}
+
+impl From<Id> for IdRepr {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::Zero => Self::Leaf(0),
+            Id::One => Self::Leaf(1),
+            Id::Node(left, right) => Self::Node(left, right),
+        }
+    }
+}
+
+impl Id {
+    // of the first node, which all the others are forked from
+    pub fn seed() -> Self {
+        Self::One
+    }
+
+    fn node(left: Self, right: Self) -> Self {
+        match (left, right) {
+            (Self::Zero, Self::Zero) => Self::Zero,
+            (Self::One, Self::One) => Self::One,
+            (left, right) => Self::Node(left.into(), right.into()),
+        }
+    }
+
+    // splits the part in two, one for the node and one for a new node
+    pub fn fork(&self) -> (Self, Self) {
+        match self {
+            Self::Zero => (Self::Zero, Self::Zero),
+            Self::One => (
+                Self::Node(Self::One.into(), Self::Zero.into()),
+                Self::Node(Self::Zero.into(), Self::One.into()),
+            ),
+            Self::Node(left, right) if **left == Self::Zero => {
+                let (first, second) = right.fork();
+                (
+                    Self::node(Self::Zero, first),
+                    Self::node(Self::Zero, second),
+                )
+            }
+            Self::Node(left, right) if **right == Self::Zero => {
+                let (first, second) = left.fork();
+                (
+                    Self::node(first, Self::Zero),
+                    Self::node(second, Self::Zero),
+                )
+            }
+            Self::Node(left, right) => (
+                Self::node((**left).clone(), Self::Zero),
+                Self::node(Self::Zero, (**right).clone()),
+            ),
+        }
+    }
+
+    // the parts of two nodes, e.g. of one leaving and of one taking over its part. the parts of
+    // distinct nodes never overlap
+    pub fn join(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(match (self, other) {
+            (Self::Zero, id) | (id, Self::Zero) => id.clone(),
+            (Self::Node(l1, r1), Self::Node(l2, r2)) => Self::node(l1.join(l2)?, r1.join(r2)?),
+            _ => anyhow::bail!("ids overlap"),
+        })
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Self::Zero | Self::One => 0,
+            Self::Node(left, right) => 1 + max(left.depth(), right.depth()),
+        }
+    }
+}
+
+// the events of the intervals: a count over the whole interval, and the counts over its halves on
+// top of it. the counts saturate rather than overflow on hostile trees
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Event {
+    Leaf(u32),
+    Node(u32, Box<Event>, Box<Event>),
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::Leaf(0)
+    }
+}
+
+impl Event {
+    fn base(&self) -> u32 {
+        match self {
+            Self::Leaf(n) | Self::Node(n, ..) => *n,
+        }
+    }
+
+    fn lift(self, m: u32) -> Self {
+        match self {
+            Self::Leaf(n) => Self::Leaf(n.saturating_add(m)),
+            Self::Node(n, left, right) => Self::Node(n.saturating_add(m), left, right),
+        }
+    }
+
+    fn sink(self, m: u32) -> Self {
+        match self {
+            Self::Leaf(n) => Self::Leaf(n - m),
+            Self::Node(n, left, right) => Self::Node(n - m, left, right),
+        }
+    }
+
+    // of children in normal form
+    fn node(n: u32, left: Self, right: Self) -> Self {
+        match (left, right) {
+            (Self::Leaf(l), Self::Leaf(r)) if l == r => Self::Leaf(n.saturating_add(l)),
+            (left, right) => {
+                let m = min(left.min(), right.min());
+                Self::Node(
+                    n.saturating_add(m),
+                    left.sink(m).into(),
+                    right.sink(m).into(),
+                )
+            }
+        }
+    }
+
+    // the counts are the same everywhere only for a leaf, and one of the halves is down to the
+    // count of the node, i.e. the trees are not bigger than they need to be
+    pub fn is_normal(&self) -> bool {
+        match self {
+            Self::Leaf(_) => true,
+            Self::Node(_, left, right) => {
+                left.is_normal()
+                    && right.is_normal()
+                    && !matches!((&**left, &**right), (Self::Leaf(l), Self::Leaf(r)) if l == r)
+                    && min(left.base(), right.base()) == 0
+            }
+        }
+    }
+
+    pub fn min(&self) -> u32 {
+        match self {
+            Self::Leaf(n) => *n,
+            Self::Node(n, left, right) => n.saturating_add(min(left.min(), right.min())),
+        }
+    }
+
+    pub fn max(&self) -> u32 {
+        match self {
+            Self::Leaf(n) => *n,
+            Self::Node(n, left, right) => n.saturating_add(max(left.max(), right.max())),
+        }
+    }
+
+    // whether every count is at most the one of `other`, i.e. the events happen before or equal
+    pub fn leq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Leaf(n1), _) => *n1 <= other.base(),
+            (Self::Node(n1, l1, r1), Self::Leaf(n2)) => {
+                n1 <= n2 && l1.clone().lift(*n1).leq(other) && r1.clone().lift(*n1).leq(other)
+            }
+            (Self::Node(n1, l1, r1), Self::Node(n2, l2, r2)) => {
+                n1 <= n2
+                    && l1.clone().lift(*n1).leq(&l2.clone().lift(*n2))
+                    && r1.clone().lift(*n1).leq(&r2.clone().lift(*n2))
+            }
+        }
+    }
+
+    // the least events after or equal to both
+    pub fn join(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Self::Leaf(n1), Self::Leaf(n2)) => Self::Leaf(max(*n1, *n2)),
+            (Self::Leaf(n1), _) => {
+                Self::Node(*n1, Default::default(), Default::default()).join(other)
+            }
+            (_, Self::Leaf(n2)) => {
+                self.join(&Self::Node(*n2, Default::default(), Default::default()))
+            }
+            (Self::Node(n1, ..), Self::Node(n2, ..)) if n1 > n2 => other.join(self),
+            (Self::Node(n1, l1, r1), Self::Node(n2, l2, r2)) => Self::node(
+                *n1,
+                l1.join(&l2.clone().lift(n2 - n1)),
+                r1.join(&r2.clone().lift(n2 - n1)),
+            ),
+        }
+    }
+
+    // an event of the node owning `id`, which the events inflate over the part of the id
+    pub fn event(&self, id: &Id) -> anyhow::Result<Self> {
+        anyhow::ensure!(*id != Id::Zero, "anonymous node has no events");
+        let filled = self.fill(id);
+        if filled != *self {
+            return Ok(filled);
+        }
+        Ok(self.grow(id).0)
+    }
+
+    // inflates the counts over the part of the id, up to the counts beside it, without growing
+    // the tree
+    fn fill(&self, id: &Id) -> Self {
+        match (id, self) {
+            (Id::Zero, _) => self.clone(),
+            (Id::One, _) => Self::Leaf(self.max()),
+            (_, Self::Leaf(_)) => self.clone(),
+            (Id::Node(il, ir), Self::Node(n, left, right)) => match (&**il, &**ir) {
+                (Id::One, ir) => {
+                    let right = right.fill(ir);
+                    let left = Self::Leaf(max(left.max(), right.min()));
+                    Self::node(*n, left, right)
+                }
+                (il, Id::One) => {
+                    let left = left.fill(il);
+                    let right = Self::Leaf(max(right.max(), left.min()));
+                    Self::node(*n, left, right)
+                }
+                (il, ir) => Self::node(*n, left.fill(il), right.fill(ir)),
+            },
+        }
+    }
+
+    // inflates a single count over the part of the id, growing the tree as little as possible,
+    // along with the cost
+    fn grow(&self, id: &Id) -> (Self, usize) {
+        match (id, self) {
+            (Id::One, Self::Leaf(n)) => (Self::Leaf(n.saturating_add(1)), 0),
+            (_, Self::Leaf(n)) => {
+                let (grown, cost) = Self::Node(*n, Default::default(), Default::default()).grow(id);
+                (grown, cost + GROW_COST)
+            }
+            (Id::Node(il, ir), Self::Node(n, left, right)) => {
+                if **il == Id::Zero {
+                    let (right, cost) = right.grow(ir);
+                    return (Self::Node(*n, left.clone(), right.into()), cost + 1);
+                }
+                if **ir == Id::Zero {
+                    let (left, cost) = left.grow(il);
+                    return (Self::Node(*n, left.into(), right.clone()), cost + 1);
+                }
+                let (grown_left, left_cost) = left.grow(il);
+                let (grown_right, right_cost) = right.grow(ir);
+                if left_cost < right_cost {
+                    (
+                        Self::Node(*n, grown_left.into(), right.clone()),
+                        left_cost + 1,
+                    )
+                } else {
+                    (
+                        Self::Node(*n, left.clone(), grown_right.into()),
+                        right_cost + 1,
+                    )
+                }
+            }
+            // `event` rules out an anonymous id, and a whole id is filled rather than grown
+            (Id::Zero | Id::One, Self::Node(..)) => unreachable!(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Self::Leaf(_) => 0,
+            Self::Node(_, left, right) => 1 + max(left.depth(), right.depth()),
+        }
+    }
+
+    // a deterministic encoding for signing, in preorder
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Leaf(n) => {
+                buf.push(0);
+                buf.extend(n.to_be_bytes())
+            }
+            Self::Node(n, left, right) => {
+                buf.push(1);
+                buf.extend(n.to_be_bytes());
+                left.encode_into(buf);
+                right.encode_into(buf)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ItcClock {
+    pub event: Event,
+}
+
+impl ItcClock {
+    pub fn new_genesis() -> Self {
+        Self::default()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.event.encode()
+    }
+}
+
+impl PartialOrd for ItcClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.event.leq(&other.event), other.event.leq(&self.event)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+impl PartialEq for ItcClock {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// no entries at all, see above
+impl Causality for ItcClock {
+    fn causality(&self) -> &OrdinaryClock {
+        static EMPTY: OnceLock<OrdinaryClock> = OnceLock::new();
+        EMPTY.get_or_init(OrdinaryClock::default)
+    }
+}
+
+// like `OrdinaryClientContext`, nothing is proven, but the trees must be in normal form and of a
+// bounded depth, so a peer cannot make the others recurse deep or compare bloated trees
+#[derive(Debug)]
+#[derive_where(Default)]
+pub struct ItcClientContext<O>(PhantomData<O>);
+
+impl<O> ItcClientContext<O> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the output is not bound by the clock
+    fn verify_clock(&self, clock: &ItcClock) -> anyhow::Result<()> {
+        let depth = clock.event.depth();
+        anyhow::ensure!(depth <= MAX_DEPTH, "clock is {depth} levels deep");
+        anyhow::ensure!(clock.event.is_normal(), "clock is not in normal form");
+        Ok(())
+    }
+}
+
+impl<O> ClockClientContext for ItcClientContext<O> {
+    type Clock = ItcClock;
+    type Output = O;
+
+    fn verify(&self, clock: &Self::Clock, _: &Self::Output, _: &Binding) -> Result<(), Error> {
+        self.verify_clock(clock).map_err(Error::ProofInvalid)
+    }
+}
+
+// of the node owning `id`, which is forked off the id of a node already running
+#[derive(Debug, ClockClientContext)]
+pub struct ItcContext<I, O> {
+    id: Id,
+    #[client_context]
+    client: ItcClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> ItcContext<I, O> {
+    pub fn new(id: Id) -> anyhow::Result<Self> {
+        anyhow::ensure!(id != Id::Zero, "anonymous node cannot prove clocks");
+        let depth = id.depth();
+        anyhow::ensure!(depth <= MAX_DEPTH, "id is {depth} levels deep");
+        Ok(Self {
+            id,
+            client: ItcClientContext::new(),
+            _input: PhantomData,
+        })
+    }
+
+    // e.g. to join it into the id of another node when this one leaves
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+}
+
+impl<I, O> ClockContext for ItcContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        _: &Self::Output,
+        _: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let mut event = Event::default();
+        for (clock, ..) in predecessors {
+            self.client
+                .verify_clock(clock)
+                .map_err(Error::ProofInvalid)?;
+            event = event.join(&clock.event)
+        }
+        Ok(ItcClock {
+            event: event.event(&self.id).map_err(Error::Proving)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{StageSource, TaskStage, Workflow};
+
+    fn prove(context: &ItcContext<Bytes, Bytes>, predecessors: &[&ItcClock]) -> ItcClock {
+        let (input, binding) = (Bytes::new(), Binding::new(1, "stage"));
+        let predecessors = predecessors
+            .iter()
+            .map(|clock| (*clock, &input, &binding))
+            .collect::<Vec<_>>();
+        context.prove(&predecessors, &input, &binding).unwrap()
+    }
+
+    #[test]
+    fn forks_and_joins_ids() {
+        let (left, right) = Id::seed().fork();
+        assert_ne!(left, right);
+        assert!(left.join(&left).is_err());
+        assert_eq!(left.join(&right).unwrap(), Id::seed());
+        let (first, second) = right.fork();
+        assert_eq!(first.join(&second).unwrap(), right);
+        assert_eq!(
+            left.join(&first).unwrap().join(&second).unwrap(),
+            Id::seed()
+        );
+        assert_eq!(Id::Zero.fork(), (Id::Zero, Id::Zero));
+    }
+
+    #[test]
+    fn orders_events() {
+        let (left, right) = Id::seed().fork();
+        let (left, right) = (
+            ItcContext::new(left).unwrap(),
+            ItcContext::new(right).unwrap(),
+        );
+        let genesis = ItcClock::new_genesis();
+        let a = prove(&left, &[&genesis]);
+        assert!(a > genesis);
+        assert!(a.event.is_normal());
+        // the events of distinct nodes upon the same clock are concurrent
+        let b = prove(&right, &[&genesis]);
+        assert_eq!(a.partial_cmp(&b), None);
+        // a join happens after both, and an event upon it after the join
+        let joined = ItcClock {
+            event: a.event.join(&b.event),
+        };
+        assert!(joined > a && joined > b);
+        let c = prove(&left, &[&a, &b]);
+        assert!(c > joined);
+        assert!(ItcContext::<Bytes, Bytes>::new(Id::Zero).is_err());
+    }
+
+    #[test]
+    fn refuses_bloated_trees() {
+        let context = ItcClientContext::<Bytes>::new();
+        let bloated = ItcClock {
+            event: Event::Node(0, Event::Leaf(1).into(), Event::Leaf(1).into()),
+        };
+        let binding = Binding::new(1, "stage");
+        assert!(context.verify(&bloated, &Bytes::new(), &binding).is_err());
+    }
+
+    #[test]
+    fn verifies_stage() {
+        let task: Workflow = serde_json::from_str(r#"{"stages": ["first", "second"]}"#).unwrap();
+        let (left, right) = Id::seed().fork();
+        let (left, right) = (
+            ItcContext::new(left).unwrap(),
+            ItcContext::new(right).unwrap(),
+        );
+        let first = prove(&left, &[&ItcClock::new_genesis()]);
+        let second = prove(&right, &[&first]);
+        let mut message = TaskStage::start(1, &Bytes::new()).unwrap();
+        message.source = StageSource::Name("second".into());
+        message.clocks =
+            HashMap::from([("first".into(), first.clone()), ("second".into(), second)]);
+        let context = ItcClientContext::new();
+        message.verify(&task, &context).unwrap();
+        // the second stage does not happen after the first one
+        message.clocks.insert("second".into(), first);
+        assert!(message.verify(&task, &context).is_err());
+    }
+}
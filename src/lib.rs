@@ -1,9 +1,76 @@
-use std::{cmp::Ordering, collections::HashMap, marker::PhantomData};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{self, Display, Formatter},
+    future::Future,
+    marker::PhantomData,
+    ops::ControlFlow,
+    path::Path,
+};
 
+use bytes::Bytes;
 use derive_more::{Deref, DerefMut};
 use derive_where::derive_where;
 use serde::{Deserialize, Serialize};
 
+use crate::{attribution::Causality, crypto::CryptoSuite, payload::Payload};
+
+// for the derives, which refer to this crate as `::pohb` wherever they are used
+extern crate self as pohb;
+
+// the boilerplate of the clocks composed of a causality part and a proof part, see `pohb_derive`
+pub use pohb_derive::{Clock, ClockClientContext};
+
+pub mod attribution;
+pub mod blob;
+#[cfg(feature = "bls")]
+pub mod bls;
+pub mod branching;
+pub mod compression;
+#[cfg(feature = "network")]
+pub mod config;
+pub mod consistency;
+pub mod crypto;
+pub mod delta;
+pub mod history;
+pub mod hlc;
+#[cfg(feature = "network")]
+pub mod hub;
+pub mod itc;
+pub mod looping;
+pub mod matrix;
+pub mod notary;
+pub mod outputs;
+pub mod payload;
+pub mod poh;
+#[cfg(feature = "pq")]
+pub mod pq;
+pub mod protocol;
+pub mod routing;
+pub mod scalar;
+pub mod schema;
+#[cfg(feature = "network")]
+pub mod secrets;
+pub mod signed;
+pub mod signer;
+#[cfg(feature = "network")]
+pub mod simulation;
+#[cfg(feature = "snark")]
+pub mod snark;
+pub mod tee;
+#[cfg(feature = "threshold")]
+pub mod threshold;
+#[cfg(feature = "network")]
+pub mod transport;
+#[cfg(feature = "vdf")]
+pub mod vdf;
+pub mod vectors;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "network")]
+pub mod worker;
+
 pub trait ClockClientContext {
     // clock value type, which usually consist a "causality part" for comparing and ordering and a
     // "proof part" for bring trust to both the order and a computation result
@@ -42,30 +109,106 @@ pub trait ClockClientContext {
     // immediate preceding computation stage (and produced the clock value), and in the currently
     // imagined scenario we probably don't care who performed any stage including the last stage
     // at all
-    fn verify(&self, clock: &Self::Clock, output: &Self::Output) -> anyhow::Result<()>;
+    // a clock that fails is an `Error::ProofInvalid`, so the callers tell it apart without
+    // matching on the message
+    // `binding` is the stage of the task the clock is expected to be proven for. a clock with a
+    // proof part commits to the one it is proven for (see `ClockContext::prove`), and fails against
+    // any other one, so a clock produced for one stage cannot be replayed as the clock of another
+    // stage or task. a clock without a proof part has nothing to commit to it and ignores it
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error>;
+
+    // `Ok(())` only when every `(clock, output, binding)` verifies. a context whose clocks are
+    // proven in batches (see `ClockContext::prove_batch`) may check the proof shared by a batch
+    // only once
+    fn verify_batch(
+        &self,
+        batch: &[VerifyRequest<'_, Self::Clock, Self::Output>],
+    ) -> Result<(), Error> {
+        batch
+            .iter()
+            .try_for_each(|(clock, output, binding)| self.verify(clock, output, binding))
+    }
 }
 
+// the arguments of one `ClockClientContext::verify` in a batch
+pub type VerifyRequest<'a, C, O> = (&'a C, &'a O, &'a Binding);
+
+// a predecessor of a clock to prove, i.e. its clock, the input it proves and the stage it is
+// proven for
+pub type Predecessor<'a, C, I> = (&'a C, &'a I, &'a Binding);
+
+// the arguments of one `ClockContext::prove` in a batch, i.e. the predecessors, the output and
+// the binding
+pub type ProofRequest<'a, C, I, O> = (&'a [Predecessor<'a, C, I>], &'a O, &'a Binding);
+
 pub trait ClockContext: ClockClientContext {
     type Input;
 
     // `Ok(clock)` only when both
-    // * everything in `predecessors` is as expected. this probably means for all `(clock, input)`
-    //   in `predecessors`, `self.verify(clock, input)` returned `Ok(())`
+    // * everything in `predecessors` is as expected. this probably means for all
+    //   `(clock, input, binding)` in `predecessors`, `self.verify(clock, input, binding)` returned
+    //   `Ok(())`
     // * `output` is the expected computation result of all inputs given in `predecessors`
     // the returned `clock` should be verifiable and happens after all clock values in
-    // `predecessors`, i.e. `matches!(clock.partial_cmp(&other_clock), Some(Ordering::Greater))` for
-    // all `other_clock` in `predecessors`
+    // `predecessors`, i.e. `clock.compare(other_clock) == ClockOrdering::After` for all
+    // `other_clock` in `predecessors`
     // there may be more desired input for a clock context to produce a clock value e.g. peer's own
-    // identity, the performed computation stage etc. those are considered as static data of a clock
-    // context and should be passed in during initializing the context
+    // identity, the performed computation etc. those are considered as static data of a clock
+    // context and should be passed in during initializing the context. the stage of the task the
+    // clock is produced for is not, since a context proves for every task it executes: it is
+    // `binding`, which the proof part of the returned clock commits to
+    // a predecessor that fails the verification is an `Error::ProofInvalid`, and a proof part that
+    // cannot be made, e.g. with the signer unreachable, is an `Error::Proving`
     fn prove(
         &self,
-        predecessors: &[(&Self::Clock, &Self::Input)],
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
         output: &Self::Output,
-    ) -> anyhow::Result<Self::Clock>;
-    // TODO make this into an asynchronous interface, as the clock proving may not be instant
-    // current stabilized async trait method is crappy, i would prefer to add a closure parameter
-    // and pass a oneshot sender with it
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error>;
+
+    // the clocks of several independent computations at once, in the same order, each as if it
+    // is proven by `prove`. a context with an expensive proof part may amortize it over the batch,
+    // e.g. with one signature over all of them
+    fn prove_batch(
+        &self,
+        batch: &[ProofRequest<'_, Self::Clock, Self::Input, Self::Output>],
+    ) -> Result<Vec<Self::Clock>, Error> {
+        batch
+            .iter()
+            .map(|(predecessors, output, binding)| self.prove(predecessors, output, binding))
+            .collect()
+    }
+
+    // the asynchronous counterparts of `prove` and `prove_batch`, which the worker awaits. a
+    // context that waits for its proof part, e.g. on a remote signer, a TEE or a SNARK prover,
+    // overrides them to do so without blocking the worker meanwhile. by default the clocks are
+    // proven in place
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send
+    where
+        Self::Clock: Send,
+    {
+        std::future::ready(self.prove(predecessors, output, binding))
+    }
+
+    fn prove_batch_async(
+        &self,
+        batch: &[ProofRequest<'_, Self::Clock, Self::Input, Self::Output>],
+    ) -> impl Future<Output = Result<Vec<Self::Clock>, Error>> + Send
+    where
+        Self::Clock: Send,
+    {
+        std::future::ready(self.prove_batch(batch))
+    }
 }
 
 // id of the computation nodes
@@ -74,11 +217,37 @@ pub type NodeId = u32;
 
 pub type TaskId = u32;
 
+// the stage of a task a clock is proven for, see `ClockClientContext::verify`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub task: TaskId,
+    pub stage: String,
+}
+
+impl Binding {
+    pub fn new(task: TaskId, stage: impl Into<String>) -> Self {
+        Self {
+            task,
+            stage: stage.into(),
+        }
+    }
+
+    // the bytes a proof part commits to, which are unambiguous for any stage name
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.task.to_be_bytes().to_vec();
+        bytes.extend((self.stage.len() as u64).to_be_bytes());
+        bytes.extend(self.stage.as_bytes());
+        bytes
+    }
+}
+
 // the untrusted reference clock that lacks the "proof part"
 // not suitable for directly used, but can be composed as the "causality part"
 // i.e. the be delegated for implementing `PartialOrd`
+// the entries are kept in the order of the node ids, so the iteration, the serialized form and the
+// debug output are the same for the equal clocks
 #[derive(Debug, Clone, Default, Deref, DerefMut, Serialize, Deserialize)]
-pub struct OrdinaryClock(pub HashMap<NodeId, u32>);
+pub struct OrdinaryClock(pub BTreeMap<NodeId, u32>);
 
 impl OrdinaryClock {
     pub fn new_genesis() -> Self {
@@ -86,45 +255,190 @@ impl OrdinaryClock {
     }
 
     pub fn new<'a>(deps: impl Iterator<Item = &'a Self>, id: NodeId) -> Self {
-        let mut value = HashMap::new();
+        let mut value = Self::merge(deps);
+        *value.entry(id).or_default() += 1;
+        value
+    }
+
+    // the least clock that happens after or equal to every one of `deps`
+    pub fn merge<'a>(deps: impl Iterator<Item = &'a Self>) -> Self {
+        let mut value = BTreeMap::new();
         for dep in deps {
             for (other_id, seq) in &**dep {
                 let the_seq = value.entry(*other_id).or_default();
                 *the_seq = u32::max(*the_seq, *seq)
             }
         }
-        *value.entry(id).or_default() += 1;
         Self(value)
     }
 
+    // the least clock that happens after or equal to both, i.e. the pointwise max, without
+    // advancing any entry as `new` does
+    pub fn join(&self, other: &Self) -> Self {
+        Self::merge([self, other].into_iter())
+    }
+
+    // the greatest clock that happens before or equal to both, i.e. the pointwise min
+    pub fn meet(&self, other: &Self) -> Self {
+        Self(
+            self.iter()
+                .filter_map(|(id, seq)| {
+                    let seq = (*seq).min(other.get(id).copied().unwrap_or_default());
+                    (seq != 0).then_some((*id, seq))
+                })
+                .collect(),
+        )
+    }
+
+    // whether the clock happens after or equal to `other`, i.e. has seen every event it has
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.ge(other)
+    }
+
     pub fn is_genesis(&self) -> bool {
         self.values().all(|seq| *seq == 0)
     }
+
+    // drops the zero entries, which compare the same as the absent ones
+    pub fn compact(&mut self) {
+        self.retain(|_, seq| *seq != 0)
+    }
+
+    // drops the entries of the retired nodes along with the zero ones, see `Membership`
+    pub fn prune(&mut self, membership: &Membership) {
+        self.retain(|id, seq| *seq != 0 && !membership.is_retired(*id))
+    }
+
+    // the ordering of the clocks as if both were pruned against `membership`
+    pub fn compare_pruned(&self, other: &Self, membership: &Membership) -> ClockOrdering {
+        let kept = |(id, _): &(&NodeId, &u32)| !membership.is_retired(**id);
+        compare_entries(self.iter().filter(kept), other.iter().filter(kept)).into()
+    }
+
+    // the canonical encoding for signing and hashing: the nonzero entries in the order of the node
+    // ids, each as the node id and the count in 4 bytes big endian. the zero entries compare as
+    // absent, so they are left out, and the equal clocks encode the same whether compacted or not
+    pub fn encode(&self) -> Vec<u8> {
+        self.iter()
+            .filter(|(_, seq)| **seq != 0)
+            .flat_map(|(id, seq)| [id.to_be_bytes(), seq.to_be_bytes()])
+            .flatten()
+            .collect()
+    }
+
+    // the sha-256 digest of the canonical encoding, fixed rather than of the crypto suite so every
+    // node agrees on it, e.g. for anchoring the clocks in a chain
+    pub fn digest(&self) -> crypto::Digest {
+        crypto::HashAlgorithm::Sha256.digest(&self.encode())
+    }
 }
 
-impl PartialOrd for OrdinaryClock {
-    fn ge(&self, other: &Self) -> bool {
-        other.iter().all(|(other_id, other_seq)| {
-            self.get(other_id).copied().unwrap_or_default() >= *other_seq
-        })
+// the ordering of two clocks by a single pass over their entries in the order of the node ids,
+// where an absent entry counts as zero, which ends as soon as each is ahead of the other somewhere
+fn compare_entries<'a>(
+    mut entries: impl Iterator<Item = (&'a NodeId, &'a u32)>,
+    mut other_entries: impl Iterator<Item = (&'a NodeId, &'a u32)>,
+) -> Option<Ordering> {
+    let (mut ahead, mut behind) = (false, false);
+    let (mut entry, mut other_entry) = (entries.next(), other_entries.next());
+    loop {
+        let (seq, other_seq) = match (entry, other_entry) {
+            (None, None) => break,
+            (Some((_, seq)), None) => {
+                entry = entries.next();
+                (*seq, 0)
+            }
+            (None, Some((_, other_seq))) => {
+                other_entry = other_entries.next();
+                (0, *other_seq)
+            }
+            (Some((id, seq)), Some((other_id, other_seq))) => match id.cmp(other_id) {
+                Ordering::Less => {
+                    entry = entries.next();
+                    (*seq, 0)
+                }
+                Ordering::Greater => {
+                    other_entry = other_entries.next();
+                    (0, *other_seq)
+                }
+                Ordering::Equal => {
+                    (entry, other_entry) = (entries.next(), other_entries.next());
+                    (*seq, *other_seq)
+                }
+            },
+        };
+        ahead |= seq > other_seq;
+        behind |= seq < other_seq;
+        if ahead && behind {
+            return None;
+        }
     }
+    Some(match (ahead, behind) {
+        (false, false) => Ordering::Equal,
+        (true, _) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+    })
+}
 
+impl PartialOrd for OrdinaryClock {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self.ge(other), other.ge(self)) {
-            (true, true) => Some(Ordering::Equal),
-            (true, false) => Some(Ordering::Greater),
-            (false, true) => Some(Ordering::Less),
-            (false, false) => None,
-        }
+        compare_entries(self.iter(), other.iter())
     }
 }
 
 impl PartialEq for OrdinaryClock {
     fn eq(&self, other: &Self) -> bool {
-        matches!(self.partial_cmp(other), Some(Ordering::Equal))
+        self.compare(other) == ClockOrdering::Equal
     }
 }
 
+// how a clock value relates to another one. the clocks are partially ordered, and two clocks of
+// which neither happens before the other are concurrent, e.g. the clocks of two stages executed
+// independently upon the same input, which is a case of its own rather than a failed comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockOrdering {
+    Before,
+    After,
+    Equal,
+    Concurrent,
+}
+
+impl From<Option<Ordering>> for ClockOrdering {
+    fn from(ordering: Option<Ordering>) -> Self {
+        match ordering {
+            Some(Ordering::Less) => Self::Before,
+            Some(Ordering::Greater) => Self::After,
+            Some(Ordering::Equal) => Self::Equal,
+            None => Self::Concurrent,
+        }
+    }
+}
+
+// reads as the relation in between, e.g. `format!("clock is {ordering} the previous clock")`
+impl Display for ClockOrdering {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Before => "before",
+            Self::After => "after",
+            Self::Equal => "equal to",
+            Self::Concurrent => "concurrent with",
+        })
+    }
+}
+
+// for every clock that is `PartialOrd`, which is how the clocks implement the comparison
+pub trait CompareClock: PartialOrd {
+    fn compare(&self, other: &Self) -> ClockOrdering {
+        self.partial_cmp(other).into()
+    }
+
+    fn happens_after(&self, other: &Self) -> bool {
+        self.compare(other) == ClockOrdering::After
+    }
+}
+
+impl<C: PartialOrd + ?Sized> CompareClock for C {}
+
 #[derive(Debug)]
 #[derive_where(Default)]
 pub struct OrdinaryClientContext<O>(PhantomData<O>);
@@ -139,7 +453,7 @@ impl<O> ClockClientContext for OrdinaryClientContext<O> {
     type Clock = OrdinaryClock;
     type Output = O;
 
-    fn verify(&self, _: &Self::Clock, &_: &Self::Output) -> anyhow::Result<()> {
+    fn verify(&self, _: &Self::Clock, &_: &Self::Output, _: &Binding) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -157,7 +471,7 @@ impl<I, O> ClockClientContext for OrdinaryContext<I, O> {
     type Clock = OrdinaryClock;
     type Output = O;
 
-    fn verify(&self, _: &Self::Clock, &_: &Self::Output) -> anyhow::Result<()> {
+    fn verify(&self, _: &Self::Clock, &_: &Self::Output, _: &Binding) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -167,23 +481,797 @@ impl<I, O> ClockContext for OrdinaryContext<I, O> {
 
     fn prove(
         &self,
-        predecessors: &[(&Self::Clock, &Self::Input)],
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
         _: &Self::Output,
-    ) -> anyhow::Result<Self::Clock> {
+        _: &Binding,
+    ) -> Result<Self::Clock, Error> {
         Ok(OrdinaryClock::new(
-            predecessors.iter().map(|(clock, _)| *clock),
+            predecessors.iter().map(|(clock, ..)| *clock),
             self.0,
         ))
     }
 }
 
-// TODO extend into a DAG (or even general graph) representation
-#[derive(Debug, Deserialize)]
+// the stages form a DAG: every stage executes upon the outputs of its upstream stages (see
+// `depends`), which are listed before it, and the last stage is the one whose output is the task
+// result. without `depends` the stages form a chain in the listed order
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Workflow {
     pub stages: Vec<String>,
+    // the upstream stages of a stage, if not only the stage listed before it. an empty list
+    // executes the stage upon the task input, as the first stage is. a stage with several upstream
+    // stages joins them: it executes once all of their outputs are there, upon all of them encoded
+    // as named outputs by the upstream stages (see `outputs`), and its clock happens after each of
+    // theirs. several stages upon the same stage execute upon the same output, concurrently
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub depends: BTreeMap<String, Vec<String>>,
+    // the allowlisted program versions of stages, as hex encoded program digests. a stage without
+    // an entry may run any program version
+    // this is a part of the workflow definition, so replacing the allowlist starts a new workflow
+    // version, and tasks started under the old version keep being accepted with the old allowlist
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub programs: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub canaries: BTreeMap<String, Canary>,
+    // how many chunks of a streaming task are between two checkpoint results on the chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint_interval: Option<u64>,
+    // the labels a worker must carry to be assigned a stage, e.g. `"hash": ["gpu"]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub affinity: BTreeMap<String, BTreeSet<String>>,
+    // the most executions of a stage at a time across all its workers, e.g. of a stage calling a
+    // rate-limited external API, which the hub enforces by leasing the executions (see `Lease`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub concurrency: BTreeMap<String, u32>,
+    // how the stages are weighted in the attribution, flat if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weights: Option<attribution::StageWeights>,
+    // with a challenge window (in seconds) the workflow is optimistic: its results are accepted
+    // as final only once the window passes without a successful `Challenge` against them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge_window: Option<u64>,
+    // the most seconds a task may take from its start to its result, after which the hub expires
+    // it with a `TaskExpired` record on the chain instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+    // the stages whose output is a pure function of the program and the input, e.g. the ones
+    // executed as WebAssembly modules, which may be re-executed to check the recorded outputs
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub deterministic: BTreeSet<String>,
+    // the names of the outputs of the last stage, which then writes them encoded as one output,
+    // see `outputs`. empty for a single opaque output
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub outputs: BTreeSet<String>,
+    // how strictly the messages are verified, by the hub and the workers alike
+    #[serde(default, skip_serializing_if = "Verification::is_strict")]
+    pub verification: Verification,
+    // the workers record the digest of the output of every stage along the task (see
+    // `TaskResult::output_digests`), so an auditor holding the intermediate outputs can verify the
+    // clock of every stage rather than of the output stage only, see `TaskResult::audit`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub audit: bool,
+    // the hooks the hub runs on the results before delivering them to the subscribers, in order,
+    // by the names they are registered with (see `hub::ResultHook`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<String>,
+    // what the first stage accepts as the task input, which the hub checks on submission
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<schema::InputSpec>,
+    // the stages whose messages are routed to one of their workers by a key of the payload, see
+    // `routing`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub routing: BTreeMap<String, routing::Routing>,
+    // the stages deciding which one of two of their downstream stages executes, by a predicate
+    // over their output, see `branching`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub branching: BTreeMap<String, branching::Branch>,
+    // the stages executing again upon their own output, at most a bounded number of times, see
+    // `looping`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub looping: BTreeMap<String, looping::Loop>,
+    // what the exit codes of the program of a stage mean, beyond the convention, e.g. `"grep":
+    // {"1": "success"}`. see `Workflow::exit_outcome`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub exit_codes: BTreeMap<String, BTreeMap<i32, StageOutcome>>,
+}
+
+// what a stage program exiting with a code means to the worker executing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageOutcome {
+    // the output is taken as is
+    Success,
+    // the execution is tried again, e.g. after a timeout of an external service
+    Retryable,
+    // the task is given up on and its failure is reported
+    Permanent,
+    // the task is given up on as well, and its message is dead-lettered for inspection
+    InvalidInput,
+}
+
+impl Workflow {
+    pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 16;
+
+    // by the exit codes of the stage, then by the convention of `sysexits.h`: 0 is a success, 75
+    // (`EX_TEMPFAIL`) is retryable, 65 (`EX_DATAERR`) is an invalid input and any other code is a
+    // permanent failure. a program killed by a signal has no code, and is retried
+    pub fn exit_outcome(&self, stage: &str, code: Option<i32>) -> StageOutcome {
+        let Some(code) = code else {
+            return StageOutcome::Retryable;
+        };
+        if let Some(outcome) = self
+            .exit_codes
+            .get(stage)
+            .and_then(|codes| codes.get(&code))
+        {
+            return *outcome;
+        }
+        match code {
+            0 => StageOutcome::Success,
+            75 => StageOutcome::Retryable,
+            65 => StageOutcome::InvalidInput,
+            _ => StageOutcome::Permanent,
+        }
+    }
+
+    // the stages the stage executes upon, empty for a stage executing upon the task input. `None`
+    // for an unknown stage
+    pub fn upstream(&self, stage: &str) -> Option<Vec<&str>> {
+        let index = self.stages.iter().position(|other| other == stage)?;
+        Some(match self.depends.get(stage) {
+            Some(upstream) => upstream.iter().map(String::as_str).collect(),
+            None => index
+                .checked_sub(1)
+                .map(|index| self.stages[index].as_str())
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    // the sources of the messages the stage executes upon, i.e. the upstream stages or the start
+    pub fn sources(&self, stage: &str) -> Option<Vec<StageSource>> {
+        let upstream = self.upstream(stage)?;
+        if upstream.is_empty() {
+            return Some(vec![StageSource::Start]);
+        }
+        Some(
+            upstream
+                .into_iter()
+                .map(|stage| StageSource::Name(stage.into()))
+                .collect(),
+        )
+    }
+
+    // the stages that consume the messages from `source`, in the listed order. empty if `source` is
+    // the last stage
+    pub fn downstream(&self, source: &StageSource) -> Vec<&str> {
+        self.stages
+            .iter()
+            .map(String::as_str)
+            .filter(|stage| {
+                self.sources(stage)
+                    .is_some_and(|sources| sources.contains(source))
+            })
+            .collect()
+    }
+
+    // the stage that consumes the messages from `source`, `None` if `source` is the last stage or
+    // its messages are consumed by several stages
+    pub fn next_stage(&self, source: &StageSource) -> Option<&str> {
+        match self.downstream(source)[..] {
+            [stage] => Some(stage),
+            _ => None,
+        }
+    }
+
+    // the stage along with every stage it transitively executes upon, in the listed order. `None`
+    // for an unknown stage
+    pub fn ancestors(&self, stage: &str) -> Option<Vec<&str>> {
+        self.upstream(stage)?;
+        let mut ancestors = BTreeSet::from([stage]);
+        // the upstream stages are listed before, so a pass from the stage backwards covers them
+        for other in self.stages.iter().rev() {
+            if ancestors.contains(other.as_str()) {
+                ancestors.extend(self.upstream(other).unwrap_or_default())
+            }
+        }
+        Some(
+            self.stages
+                .iter()
+                .map(String::as_str)
+                .filter(|stage| ancestors.contains(stage))
+                .collect(),
+        )
+    }
+
+    // of the completed stages, the ones no other completed stage transitively executes upon, i.e.
+    // the furthest completed stage of every branch, in the listed order
+    pub fn frontier(&self, completed: &BTreeSet<&str>) -> Vec<&str> {
+        let upstream = completed
+            .iter()
+            .flat_map(|stage| {
+                let ancestors = self.ancestors(stage).unwrap_or_default();
+                ancestors.into_iter().filter(move |other| other != stage)
+            })
+            .collect::<BTreeSet<_>>();
+        self.stages
+            .iter()
+            .map(String::as_str)
+            .filter(|stage| completed.contains(stage) && !upstream.contains(stage))
+            .collect()
+    }
+
+    // the clock the stage executes upon, i.e. the merge of the causality parts of the clocks of its
+    // upstream stages, genesis for a stage executing upon the task input
+    pub fn upstream_clock<C: Causality>(
+        &self,
+        stage: &str,
+        clocks: &HashMap<String, C>,
+    ) -> Result<OrdinaryClock, Error> {
+        let upstream = self.upstream(stage).ok_or(Error::WorkflowMismatch(format!(
+            "stage {stage} is not in the workflow"
+        )))?;
+        let clocks = upstream
+            .into_iter()
+            .map(|stage| {
+                clocks
+                    .get(stage)
+                    .map(Causality::causality)
+                    .ok_or(Error::MissingClock(stage.into()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(OrdinaryClock::merge(clocks.into_iter()))
+    }
+
+    // the stages not executed under the branch decisions of a task (see `branching`): the branch
+    // a decision does not take, and every stage executing upon skipped stages only. nothing after
+    // a branching stage that has not decided yet is skipped
+    pub fn skipped(&self, branches: &HashMap<String, String>) -> BTreeSet<&str> {
+        let mut skipped = BTreeSet::new();
+        for stage in &self.stages {
+            let upstream = self.upstream(stage).unwrap_or_default();
+            let not_taken = upstream.iter().any(|prev_stage| {
+                self.branching.get(*prev_stage).is_some_and(|branch| {
+                    branch.targets().contains(&stage.as_str())
+                        && branches
+                            .get(*prev_stage)
+                            .is_some_and(|taken| taken != stage)
+                })
+            });
+            if not_taken
+                || (!upstream.is_empty()
+                    && upstream
+                        .iter()
+                        .all(|prev_stage| skipped.contains(prev_stage)))
+            {
+                skipped.insert(stage.as_str());
+            }
+        }
+        skipped
+    }
+
+    // the workflow as the task executes it under its branch decisions, i.e. without the skipped
+    // stages, and with a stage joining several branches executing upon the taken ones only. the
+    // verification and the execution of the task walk this one
+    pub fn taken(&self, branches: &HashMap<String, String>) -> Cow<'_, Self> {
+        let skipped = self.skipped(branches);
+        if skipped.is_empty() {
+            return Cow::Borrowed(self);
+        }
+        let mut taken = self.clone();
+        taken
+            .stages
+            .retain(|stage| !skipped.contains(stage.as_str()));
+        taken.depends = taken
+            .stages
+            .iter()
+            .map(|stage| {
+                let upstream = self.upstream(stage).unwrap_or_default();
+                let upstream = upstream
+                    .into_iter()
+                    .filter(|prev_stage| !skipped.contains(prev_stage))
+                    .map(String::from)
+                    .collect();
+                (stage.clone(), upstream)
+            })
+            .collect();
+        Cow::Owned(taken)
+    }
+
+    pub fn checkpoint_interval(&self) -> u64 {
+        self.checkpoint_interval
+            .unwrap_or(Self::DEFAULT_CHECKPOINT_INTERVAL)
+            .max(1)
+    }
+
+    // the static problems of the definition, which would otherwise only show up once tasks are run
+    // against it, if ever, e.g. an allowlist entry for a misspelled stage that restricts nothing.
+    // empty for a sound workflow
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.stages.is_empty() {
+            problems.push("workflow has no stages".into())
+        }
+        let mut stages = BTreeSet::new();
+        for stage in &self.stages {
+            // stage names are path segments of the blob keys and the endpoints
+            if stage.is_empty() || stage.contains('/') || stage == "." || stage == ".." {
+                problems.push(format!("stage name {stage:?} is not a path segment"))
+            }
+            if !stages.insert(stage) {
+                problems.push(format!("stage {stage} is listed more than once"))
+            }
+        }
+        let mut unknown = |field: &str, stage: &String| {
+            if !stages.contains(stage) {
+                problems.push(format!("{field} of unknown stage {stage}"))
+            }
+        };
+        for stage in self.programs.keys() {
+            unknown("programs", stage)
+        }
+        for stage in self.canaries.keys() {
+            unknown("canaries", stage)
+        }
+        for stage in self.affinity.keys() {
+            unknown("affinity", stage)
+        }
+        for stage in self.concurrency.keys() {
+            unknown("concurrency", stage)
+        }
+        for stage in &self.deterministic {
+            unknown("deterministic", stage)
+        }
+        for stage in self.routing.keys() {
+            unknown("routing", stage)
+        }
+        for stage in self.branching.keys() {
+            unknown("branching", stage)
+        }
+        for stage in self.looping.keys() {
+            unknown("looping", stage)
+        }
+        for stage in self.depends.keys() {
+            unknown("depends", stage)
+        }
+        for stage in self.exit_codes.keys() {
+            unknown("exit_codes", stage)
+        }
+        if let Some(attribution::StageWeights::Cost(costs)) = &self.weights {
+            for stage in costs.keys() {
+                unknown("weights", stage)
+            }
+        }
+        for (stage, programs) in &self.programs {
+            for program in programs {
+                if program.len() != 64 || !program.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    problems.push(format!(
+                        "malformed program version {program} of stage {stage}"
+                    ))
+                }
+            }
+        }
+        for (stage, canary) in &self.canaries {
+            if canary.percent > 100 {
+                problems.push(format!(
+                    "canary of stage {stage} routes more than 100 percent"
+                ))
+            }
+        }
+        if let Some(attribution::StageWeights::Cost(costs)) = &self.weights {
+            for (stage, cost) in costs {
+                if !cost.is_finite() || *cost < 0. {
+                    problems.push(format!("invalid weight {cost} of stage {stage}"))
+                }
+            }
+        }
+        for (stage, codes) in &self.exit_codes {
+            if codes
+                .get(&0)
+                .is_some_and(|outcome| *outcome != StageOutcome::Success)
+            {
+                problems.push(format!("exit code 0 of stage {stage} is always a success"))
+            }
+        }
+        for (stage, limit) in &self.concurrency {
+            if *limit == 0 {
+                problems.push(format!("concurrency of stage {stage} is zero"))
+            }
+        }
+        for (stage, routing) in &self.routing {
+            problems.extend(routing.validate(stage))
+        }
+        for (stage, branch) in &self.branching {
+            problems.extend(branch.validate(stage, self))
+        }
+        for (stage, repeat) in &self.looping {
+            problems.extend(repeat.validate(stage, self))
+        }
+        // listing the upstream stages before keeps the stages acyclic
+        for (stage, upstream) in &self.depends {
+            let Some(index) = self.stages.iter().position(|other| other == stage) else {
+                continue;
+            };
+            let mut seen = BTreeSet::new();
+            for other in upstream {
+                if !self.stages[..index].contains(other) {
+                    problems.push(format!(
+                        "stage {stage} depends on {other}, which is not listed before it"
+                    ))
+                }
+                if !seen.insert(other) {
+                    problems.push(format!("stage {stage} depends on {other} more than once"))
+                }
+            }
+            // the stage is executed upon all the outputs, which have no single route
+            if upstream.len() > 1 && self.routing.contains_key(stage) {
+                problems.push(format!(
+                    "routing of stage {stage}, which joins several stages"
+                ))
+            }
+        }
+        for stage in self.stages.iter().rev().skip(1) {
+            if self
+                .downstream(&StageSource::Name(stage.clone()))
+                .is_empty()
+            {
+                problems.push(format!(
+                    "no stage executes upon stage {stage}, so its output is not a part of the result"
+                ))
+            }
+        }
+        if self.checkpoint_interval == Some(0) {
+            problems.push("checkpoint interval is zero".into())
+        }
+        if self.deadline == Some(0) {
+            problems.push("deadline is zero".into())
+        }
+        if self.outputs.iter().any(String::is_empty) {
+            problems.push("empty output name".into())
+        }
+        if let Some(input) = &self.input {
+            problems.extend(input.validate())
+        }
+        problems
+    }
+}
+
+// the position of a message in a streaming task, whose input is an unbounded sequence of chunks
+// (e.g. the frames of a video feed) published by the client under the same task id
+// every stage processes the chunks one by one in order, and proves the clock of a chunk upon both
+// the clock of the chunk from the previous stage and its own clock of the previous chunk, so the
+// clocks of a stage advance per chunk, and the clock of a chunk happens after everything before
+// it. as the result the chain does not need every chunk's result: it carries a checkpoint result
+// every `checkpoint_interval` chunks (and for the last chunk if the stream ever ends), which
+// attests the whole stream up to the checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub seq: u64,
+    #[serde(default)]
+    pub last: bool,
 }
 
+// the declaration of a client that the output of its task becomes the input of another task,
+// which starts under another workflow once the task result is accepted. the hub wires the handoff
+// and keeps the lineage, so `GET /tasks/<task id>/lineage` of the downstream task covers the
+// upstream task as well. only ordinary tasks can be handed off
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handoff {
+    pub workflow: WorkflowDigest,
+    pub id: TaskId,
+    // where the output of the downstream task goes in turn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub then: Option<Box<Handoff>>,
+}
+
+impl Chunk {
+    pub fn is_checkpoint(&self, task: &Workflow) -> bool {
+        self.last || (self.seq + 1).is_multiple_of(task.checkpoint_interval())
+    }
+}
+
+// rolling out a new program version of a stage: a worker executing a task routed to the canary
+// runs both the stable and the canary program, and reports the difference between their outputs
+// to the hub. the canary's output goes on to the rest of the workflow (and eventually the chain)
+// unless `exclude` is set, in which case the stable output is used and the canary only runs in
+// the shadow
+// the routing is decided by the task id alone, so every worker agrees on which tasks are routed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Canary {
+    pub percent: u32,
+    #[serde(default)]
+    pub exclude: bool,
+}
+
+impl Canary {
+    pub fn routes(&self, id: TaskId) -> bool {
+        id % 100 < self.percent
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryReport {
+    pub id: TaskId,
+    pub stage: String,
+    pub stable: ProgramDigest,
+    pub canary: ProgramDigest,
+    pub stable_len: usize,
+    pub canary_len: usize,
+    // the offset of the first differing byte, `None` if the outputs are identical
+    pub diff_offset: Option<usize>,
+}
+
+// a fraud proof against a result of an optimistic workflow: the output of the stage of the task
+// as the challenger re-executes it, which differs from the recorded one. the hub re-executes the
+// stage itself to decide, so this only works for deterministic stages, and only for ordinary tasks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    pub id: TaskId,
+    pub stage: String,
+    pub output: Bytes,
+}
+
+// what a stage of a task has executed, as the hub keeps it for re-execution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub program: ProgramDigest,
+    pub input: Bytes,
+    pub output: Bytes,
+}
+
+// a stage of an accepted result that the hub samples for a quality audit. an auditor fetches the
+// `StageRecord` of it, re-executes or scores it, and submits an `AuditOutcome`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRequest {
+    pub id: TaskId,
+    pub stage: String,
+    pub program: ProgramDigest,
+    // the node that executed the stage, by its clock. `None` if no single node advanced it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditVerdict {
+    Passed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditOutcome {
+    pub id: TaskId,
+    pub stage: String,
+    pub auditor: NodeId,
+    pub verdict: AuditVerdict,
+    // the quality of the output between 0 and 1, for the stages that are scored rather than
+    // re-executed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+// an outcome as the hub records it, along with what was audited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Audit {
+    #[serde(flatten)]
+    pub request: AuditRequest,
+    pub auditor: NodeId,
+    pub verdict: AuditVerdict,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+impl CanaryReport {
+    pub fn diff(stable_output: &[u8], canary_output: &[u8]) -> Option<usize> {
+        stable_output
+            .iter()
+            .zip(canary_output)
+            .position(|(stable, canary)| stable != canary)
+            .or((stable_output.len() != canary_output.len())
+                .then_some(stable_output.len().min(canary_output.len())))
+    }
+}
+
+// liveness of a long-running stage, reported by the stage program and forwarded by the worker as
+// is. it carries no clock and proves nothing, and the hub neither verifies nor replicates it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressEvent {
+    pub id: TaskId,
+    pub stage: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
+    pub message: String,
+}
+
+// identifies a version of a workflow definition, so a task keeps being verified against the
+// definition it started under even if the definition is replaced while the task is in flight
+pub type WorkflowDigest = crypto::Digest;
+
+impl Workflow {
+    pub fn digest(&self, crypto: &dyn CryptoSuite) -> WorkflowDigest {
+        crypto.digest(&serde_json::to_vec(self).expect("workflow is serializable"))
+    }
+
+    // the workflow described in the file, in yaml for a `.yaml` or `.yml` file (with the `yaml`
+    // feature), in toml for a `.toml` one (with the `toml` feature), and in json otherwise. the
+    // digest does not depend on the format
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        Self::parse(path, &std::fs::read_to_string(path)?)
+    }
+
+    // the description read from the file at `path` already, e.g. asynchronously, see `from_path`
+    pub fn parse(path: &Path, description: &str) -> anyhow::Result<Self> {
+        let task = match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => serde_yaml::from_str(description)?,
+            #[cfg(not(feature = "yaml"))]
+            Some("yaml" | "yml") => {
+                anyhow::bail!("{} is yaml, which needs the yaml feature", path.display())
+            }
+            #[cfg(feature = "toml")]
+            Some("toml") => toml::from_str(description)?,
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => {
+                anyhow::bail!("{} is toml, which needs the toml feature", path.display())
+            }
+            _ => serde_json::from_str(description)?,
+        };
+        Ok(task)
+    }
+}
+
+// the version of a stage program, which is the digest of the program itself
+pub type ProgramDigest = crypto::Digest;
+
+pub fn program_digest(crypto: &dyn CryptoSuite, program: &[u8]) -> ProgramDigest {
+    crypto.digest(program)
+}
+
+// the digest of the output of a stage as executed, i.e. before it is compressed or offloaded,
+// recorded under a workflow that audits. fixed rather than of the crypto suite, as the workers
+// need not hold one
+pub fn output_digest(output: &[u8]) -> crypto::Digest {
+    crypto::HashAlgorithm::Sha256.digest(output)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn unhex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        hex.len().is_multiple_of(2) && hex.is_ascii(),
+        "invalid hex string {hex}"
+    );
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+// decides which program versions are acceptable for a stage of a workflow
+// the default policy is the allowlist written in the workflow definition, while a hub can plug in
+// anything else e.g. an allowlist maintained on chain
+pub trait ProgramPolicy {
+    fn accept(&self, task: &Workflow, stage: &str, program: &ProgramDigest) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct Allowlist;
+
+impl ProgramPolicy for Allowlist {
+    fn accept(&self, task: &Workflow, stage: &str, program: &ProgramDigest) -> anyhow::Result<()> {
+        let Some(allowed) = task.programs.get(stage) else {
+            return Ok(());
+        };
+        let program = hex(program);
+        anyhow::ensure!(
+            allowed
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&program)),
+            "program version {program} is not allowed for stage {stage}"
+        );
+        Ok(())
+    }
+}
+
+// every stage that has been executed must have recorded an acceptable program version, while the
+// stages that are not executed yet are not concerned
+fn verify_programs<C>(
+    clocks: &HashMap<String, C>,
+    programs: &HashMap<String, ProgramDigest>,
+    task: &Workflow,
+    policy: &(impl ProgramPolicy + ?Sized),
+) -> Result<(), Error> {
+    for stage in clocks.keys() {
+        match programs.get(stage) {
+            Some(program) => policy
+                .accept(task, stage, program)
+                .map_err(|err| Error::WorkflowMismatch(err.to_string()))?,
+            None if task.programs.contains_key(stage) => {
+                return Err(Error::WorkflowMismatch(format!(
+                    "missing program version of stage {stage}"
+                )))
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+// under a workflow that audits, every executed stage has the digest of its output recorded
+fn verify_output_digests<C>(
+    clocks: &HashMap<String, C>,
+    output_digests: &HashMap<String, crypto::Digest>,
+    task: &Workflow,
+) -> Result<(), Error> {
+    if !task.audit {
+        return Ok(());
+    }
+    match task
+        .stages
+        .iter()
+        .find(|stage| clocks.contains_key(*stage) && !output_digests.contains_key(*stage))
+    {
+        Some(stage) => Err(Error::WorkflowMismatch(format!(
+            "missing output digest of stage {stage}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+// what a worker tells the hub's scheduler about itself, periodically as the liveness signal
+// a node may serve several stages, e.g. by embedding several `Worker`s with the same id, which
+// lets the scheduler keep the consecutive stages of a task on the same node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub id: NodeId,
+    pub stages: BTreeSet<String>,
+    #[serde(default)]
+    pub labels: BTreeSet<String>,
+    // the shards it serves of the stages that are routed, see `routing`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shards: BTreeMap<String, u32>,
+    // the number of tasks the worker is executing or has queued
+    pub load: u32,
+}
+
+// what a worker asks the hub for before executing a stage with a concurrency limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    pub id: TaskId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<WorkflowDigest>,
+    pub stage: String,
+}
+
+// the permission to execute the stage once, which expires unless renewed within the ttl (in
+// milliseconds), so the slot of a crashed worker is freed eventually
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Lease {
+    pub lease: u64,
+    pub ttl: u64,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    fn is_normal(&self) -> bool {
+        *self == Self::Normal
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StageSource {
     Start,
     Name(String),
@@ -191,36 +1279,563 @@ pub enum StageSource {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskStage<C, I> {
+    #[serde(default = "protocol::default_version")]
+    pub version: u32,
     pub id: TaskId,
+    // the workflow version this task is running under. the client may leave it empty when
+    // publishing the start stage, and the hub fills in its current version
+    #[serde(default)]
+    pub workflow: Option<WorkflowDigest>,
     pub source: StageSource,
+    // `None` for an ordinary task, which has exactly one input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
+    // the worker the hub's scheduler assigns the next stage to, `None` if every worker of the
+    // stage may execute it, which is the case when no scheduled worker is available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<NodeId>,
+    // only declared on the start stage, and only acted on by the hub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handoff: Option<Handoff>,
+    // only declared on the start stage: the client accepts the kept result of an earlier task of
+    // the same input under the same workflow version, which the hub responds with instead of
+    // running the task, see `GET /cache/<workflow version>/<input digest>`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub accept_cached: bool,
+    // only declared on the start stage, for the subscribers to filter the results of the task by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+    // only declared on the start stage, deciding which new tasks an overloaded hub sheds first
+    #[serde(default, skip_serializing_if = "Priority::is_normal")]
+    pub priority: Priority,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    // the time (in seconds since the unix epoch) the task must finish by, declared on the start
+    // stage only. the hub replaces it with the earlier of it and the deadline of the workflow, if
+    // any, and stamps the later stages with the one it tracks for the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
     pub input: I,
+    // the input is offloaded into the blob store when it is too large to be inlined, in which
+    // case `input` is empty and must be reassembled from this before anything else
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<blob::BlobRef>,
+    // of the input, `None` for opaque bytes, see `payload`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // the input is compressed in transit, and must be decompressed after it is reassembled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<compression::Compression>,
     pub clocks: HashMap<String, C>,
+    // the program version each executed stage has run
+    // the ordinary clocks cannot bind the recorded versions, so for them this is merely a claim of
+    // the executing workers. a proof-carrying clock context is expected to take the program digest
+    // as part of its static data, and to only produce clocks committing to the digest it runs
+    #[serde(default)]
+    pub programs: HashMap<String, ProgramDigest>,
+    // the digests of the outputs of the executed stages, under a workflow that audits, see
+    // `output_digest`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub output_digests: HashMap<String, crypto::Digest>,
+    // the digests of the execution logs of the stages that have written any, which are uploaded
+    // to the hub and served at `GET /tasks/<task id>/logs/<stage>`. they are diagnostics for
+    // debugging after the fact, so nothing verifies them
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub logs: HashMap<String, crypto::Digest>,
+    // the execution time of each executed stage in milliseconds (of the latest chunk for a
+    // streaming task), which the attribution weighs the stages by if the workflow measures them
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub elapsed: HashMap<String, u64>,
+    // the routing decisions of the routed stages, see `routing`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub routes: HashMap<String, routing::Route>,
+    // the branch decisions of the executed branching stages, i.e. the stage each of them takes,
+    // see `branching`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub branches: HashMap<String, String>,
+    // the clocks of the earlier iterations of the executed looping stages, in order, so a stage
+    // executing n times has n - 1 of them, see `looping`
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    pub iterations: HashMap<String, Vec<C>>,
+    // stamped by the hub on a message every worker of the next stage may execute: the highest
+    // clocks of the task it knows of where they are not the clocks of the message, see
+    // `TaskStage::stale`. they are hints for saving compute, so nothing verifies them
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    pub hints: HashMap<String, C>,
+    // `clocks` only holds the clocks that are new or changed against the base the delta names,
+    // see `delta`. the hub reconstructs the full clocks on publish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_delta: Option<delta::ClockDelta>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskResult<C, O> {
+    #[serde(default = "protocol::default_version")]
+    pub version: u32,
     pub id: TaskId,
+    #[serde(default)]
+    pub workflow: Option<WorkflowDigest>,
+    // for a streaming task the result is a checkpoint at this chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
     pub output: O,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<blob::BlobRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<compression::Compression>,
     pub clocks: HashMap<String, C>,
+    #[serde(default)]
+    pub programs: HashMap<String, ProgramDigest>,
+    // the digests of the outputs of the executed stages, including the output stage, under a
+    // workflow that audits, see `TaskResult::audit`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub output_digests: HashMap<String, crypto::Digest>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub logs: HashMap<String, crypto::Digest>,
+    // the execution time of each executed stage in milliseconds (of the latest chunk for a
+    // streaming task), which the attribution weighs the stages by if the workflow measures them
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub elapsed: HashMap<String, u64>,
+    // the routing decisions of the routed stages, see `routing`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub routes: HashMap<String, routing::Route>,
+    // the branch decisions of the executed branching stages, i.e. the stage each of them takes,
+    // see `branching`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub branches: HashMap<String, String>,
+    // the clocks of the earlier iterations of the executed looping stages, in order, so a stage
+    // executing n times has n - 1 of them, see `looping`
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    pub iterations: HashMap<String, Vec<C>>,
+    // the task has missed its deadline, and this is the terminal record of it in place of a
+    // result, with an empty output and the clocks of the stages completed by then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired: Option<TaskExpired>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskExpired {
+    // in seconds since the unix epoch
+    pub deadline: u64,
+    // the last completed stage in the listed order. the record carries the clocks of the furthest
+    // completed stage of every branch along with the ones before them. `None` if no stage has
+    // completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+}
+
+// how much of a message is verified beyond the clock of its output, as the workflow selects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    // the other clocks may be missing or unordered, e.g. when the intermediate stages are trusted
+    // or their clocks are stripped to save space
+    Minimal,
+    // every clock up to the output stage must be present and happen after the previous one
+    #[default]
+    Strict,
+    // in addition every stage must be advanced by a single producer, which identifies the node
+    // that executed it, and must record the program version it executed
+    Paranoid,
+}
+
+impl Verification {
+    fn is_strict(&self) -> bool {
+        *self == Self::Strict
+    }
+}
+
+// the bounds of the clocks in a message, checked by the hub on publish and by the workers on
+// receipt before any clock is compared, so a publisher cannot stall every subscriber with a clock
+// of millions of entries. an honest clock has an entry per node that has executed a stage of the
+// task chain, and a message has a clock per stage of its workflow, along with the clocks of the
+// earlier iterations of its looping stages (see `looping`), which count against the limits as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockLimits {
+    // per clock
+    pub max_entries: usize,
+    // per message
+    pub max_clocks: usize,
+    pub oversize: OversizePolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    // an oversized message is rejected as is
+    #[default]
+    Reject,
+    // what does not change the verification is dropped first, i.e. the clocks of the stages
+    // outside the workflow, and the zero entries of the ordinary clocks where the clocks are
+    // ordinary (the entries of the other clocks may be signed over). a message still oversized
+    // after that is rejected
+    Compact,
+}
+
+impl Default for ClockLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1024,
+            max_clocks: 256,
+            oversize: Default::default(),
+        }
+    }
+}
+
+impl ClockLimits {
+    // `Ok(())` if the clocks are within the limits, after compacting them under `Compact`. the
+    // iterations of a stage outside the workflow are kept, as the verification refuses them
+    pub fn enforce<C: Causality>(
+        &self,
+        clocks: &mut HashMap<String, C>,
+        iterations: &HashMap<String, Vec<C>>,
+        task: &Workflow,
+    ) -> anyhow::Result<()> {
+        if self.oversize == OversizePolicy::Compact {
+            clocks.retain(|stage, _| task.stages.contains(stage))
+        }
+        let count = clocks.len() + iterations.values().map(Vec::len).sum::<usize>();
+        anyhow::ensure!(
+            count <= self.max_clocks,
+            "message carries {count} clocks, more than the limit of {}",
+            self.max_clocks
+        );
+        for (stage, clock) in clocks.iter() {
+            let entries = clock.causality().len();
+            anyhow::ensure!(
+                entries <= self.max_entries,
+                "clock of stage {stage} has {entries} entries, more than the limit of {}",
+                self.max_entries
+            );
+        }
+        for (stage, earlier) in iterations {
+            for (iteration, clock) in earlier.iter().enumerate() {
+                let entries = clock.causality().len();
+                anyhow::ensure!(
+                    entries <= self.max_entries,
+                    "clock of iteration {} of stage {stage} has {entries} entries, more than the \
+                    limit of {}",
+                    iteration + 1,
+                    self.max_entries
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// a snapshot of the nodes that are retired for good, e.g. ephemeral workers that are torn down, so
+// the clocks of the long task chains do not keep an entry for every node that ever executed a
+// stage. pruning drops the retired entries from the ordinary clocks (see `OrdinaryClock::prune`),
+// and the clocks are compared with the retired entries ignored (see `verify_pruned`). a stage
+// executed by a retired node then no longer advances its clock past the upstream ones, so that
+// ordering is tolerated as equal, and the stage is not attributed to a producer. the hub and the
+// workers must agree on the snapshot, which only ever grows, since a clock pruned against a node
+// cannot be compared as if the node were still counted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    pub retired: BTreeSet<NodeId>,
+}
+
+impl Membership {
+    pub fn is_retired(&self, id: NodeId) -> bool {
+        self.retired.contains(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.retired.is_empty()
+    }
+}
+
+// the ways a message fails the verification, a clock context fails to prove or verify a clock,
+// or a hub fails to take a message, so the callers can branch on them, e.g. the hub answers each
+// with its own status. the errors of the other fallible calls are not classified, and the errors
+// of this type convert into `anyhow::Error` as usual, from which they can be downcast
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // the clock of the stage is required by the workflow but not in the message
+    #[error("missing clock value of stage {0}")]
+    MissingClock(String),
+    // the clocks are in the message but not ordered as the stages of the workflow, or a stage is
+    // not advanced by a single producer where the workflow requires that
+    #[error("{0}")]
+    OrderViolation(String),
+    // the clock context refuses the proof of a clock, see `ClockClientContext::verify`
+    #[error("invalid proof: {0}")]
+    ProofInvalid(anyhow::Error),
+    // the clock context cannot prove a clock, e.g. its signer or prover is unavailable, see
+    // `ClockContext::prove`
+    #[error("failed to prove: {0}")]
+    Proving(anyhow::Error),
+    // the message does not fit the workflow, e.g. a program version that is not allowed
+    #[error("{0}")]
+    WorkflowMismatch(String),
+    // the hub refuses the request, see `transport`
+    #[error("{0}")]
+    Transport(anyhow::Error),
 }
 
-fn verify<C: PartialOrd, O>(
+// everything a message fails in the verification, rather than the first failure `verify` stops
+// at, so the hub can answer with actionable diagnostics and the clients can render which stage
+// broke the causality. see `TaskStage::report` and `TaskResult::report`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    // the stage the finding is about, if any, e.g. not for the routes taken
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    pub kind: FindingKind,
+    pub message: String,
+}
+
+// the kinds of `Error` the verification raises
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    MissingClock,
+    OrderViolation,
+    ProofInvalid,
+    WorkflowMismatch,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn push(&mut self, stage: Option<&str>, err: Error) {
+        let kind = match &err {
+            Error::MissingClock(_) => FindingKind::MissingClock,
+            Error::OrderViolation(_) => FindingKind::OrderViolation,
+            Error::ProofInvalid(_) => FindingKind::ProofInvalid,
+            // the verification does not raise proving or transport errors
+            Error::WorkflowMismatch(_) | Error::Proving(_) | Error::Transport(_) => {
+                FindingKind::WorkflowMismatch
+            }
+        };
+        self.findings.push(Finding {
+            stage: stage.map(Into::into),
+            kind,
+            message: err.to_string(),
+        })
+    }
+
+    // the clock findings against the output stage, along with the clock of the output stage if
+    // it is there. the findings already reported, e.g. against another stage sharing the same
+    // ancestors, are not reported again
+    fn inspect<'a, C: PartialOrd + Causality>(
+        &mut self,
+        clocks: &'a HashMap<String, C>,
+        programs: &HashMap<String, ProgramDigest>,
+        output_stage: &str,
+        task: &Workflow,
+        membership: &Membership,
+    ) -> Option<&'a C> {
+        let reported = self.findings.len();
+        let clock = inspect_clocks(
+            clocks,
+            programs,
+            output_stage,
+            task,
+            membership,
+            &mut |stage, err| {
+                self.push(stage, err);
+                ControlFlow::Continue(())
+            },
+        );
+        let mut index = reported;
+        while index < self.findings.len() {
+            let finding = &self.findings[index];
+            let repeated = self.findings[..reported]
+                .iter()
+                .any(|other| other.stage == finding.stage && other.message == finding.message);
+            if repeated {
+                self.findings.remove(index);
+            } else {
+                index += 1
+            }
+        }
+        match clock {
+            ControlFlow::Continue(clock) => clock,
+            ControlFlow::Break(()) => None,
+        }
+    }
+
+    // the proof of the output, which is verified whenever the clock of the output stage is there,
+    // regardless of the clock findings
+    fn verify_output<C, O>(
+        &mut self,
+        binding: &Binding,
+        clock: &C,
+        output: &O,
+        context: &impl ClockClientContext<Clock = C, Output = O>,
+    ) {
+        if let Err(err) = context.verify(clock, output, binding) {
+            self.push(Some(&binding.stage), err)
+        }
+    }
+}
+
+fn verify<C: PartialOrd + Causality, O>(
     clocks: &HashMap<String, C>,
-    output_stage: &str,
+    programs: &HashMap<String, ProgramDigest>,
+    binding: &Binding,
     output: &O,
     task: &Workflow,
     context: &impl ClockClientContext<Clock = C, Output = O>,
-) -> anyhow::Result<()> {
-    let mut prev_clock = None;
-    for stage in &task.stages {
-        let clock = clocks
-            .get(stage)
-            .ok_or(anyhow::format_err!("missing clock value of stage {stage}"))?;
-        if let Some(prev_clock) = prev_clock {
-            anyhow::ensure!(matches!(
-                clock.partial_cmp(prev_clock),
-                Some(Ordering::Greater)
-            ));
+    membership: &Membership,
+) -> Result<(), Error> {
+    context.verify(
+        verify_clocks(clocks, programs, &binding.stage, task, membership)?,
+        output,
+        binding,
+    )
+}
+
+// the clock of the output stage, once the clocks of the stages it executes upon are verified as
+// far as the workflow selects, without the output. every clock happens after the clocks of the
+// upstream stages, along the edges of the workflow, while the clocks of the stages executing upon
+// the same output are concurrent with one another
+fn verify_clocks<'a, C: PartialOrd + Causality>(
+    clocks: &'a HashMap<String, C>,
+    programs: &HashMap<String, ProgramDigest>,
+    output_stage: &str,
+    task: &Workflow,
+    membership: &Membership,
+) -> Result<&'a C, Error> {
+    let mut first = None;
+    let clock = inspect_clocks(
+        clocks,
+        programs,
+        output_stage,
+        task,
+        membership,
+        &mut |_, err| {
+            first = Some(err);
+            ControlFlow::Break(())
+        },
+    );
+    match (first, clock) {
+        (Some(err), _) => Err(err),
+        (None, ControlFlow::Continue(Some(clock))) => Ok(clock),
+        _ => unreachable!("the ancestors of a stage include the stage"),
+    }
+}
+
+// whether the clock happens after the previous one, with the retired entries ignored. the producer
+// of the clock may be retired and pruned, so the clocks are then tolerated as equal, unless the
+// clock has no entries to prune in the first place, e.g. a scalar one
+pub(crate) fn follows<C: PartialOrd + Causality>(
+    clock: &C,
+    prev: &C,
+    membership: &Membership,
+) -> Result<(), ClockOrdering> {
+    let pruned = !membership.is_empty() && !clock.causality().is_empty();
+    let ordering = match pruned {
+        false => clock.compare(prev),
+        true => clock
+            .causality()
+            .compare_pruned(prev.causality(), membership),
+    };
+    match ordering {
+        ClockOrdering::After => Ok(()),
+        ClockOrdering::Equal if pruned => Ok(()),
+        ordering => Err(ordering),
+    }
+}
+
+// the pass of `verify_clocks` that goes on past the findings, handing each to `found` along with
+// the stage it is about, until `found` breaks. a stage whose clock is missing is reported once,
+// and the checks that need its clock are skipped rather than reported again. the clock of the
+// output stage, if present
+fn inspect_clocks<'a, C: PartialOrd + Causality>(
+    clocks: &'a HashMap<String, C>,
+    programs: &HashMap<String, ProgramDigest>,
+    output_stage: &str,
+    task: &Workflow,
+    membership: &Membership,
+    found: &mut dyn FnMut(Option<&str>, Error) -> ControlFlow<()>,
+) -> ControlFlow<(), Option<&'a C>> {
+    let Some(ancestors) = task.ancestors(output_stage) else {
+        found(
+            None,
+            Error::WorkflowMismatch(format!("stage {output_stage} is not in the workflow")),
+        )?;
+        return ControlFlow::Continue(None);
+    };
+    let mut output_clock = None;
+    for stage in ancestors {
+        if task.verification == Verification::Minimal && stage != output_stage {
+            continue;
+        }
+        let Some(clock) = clocks.get(stage) else {
+            found(Some(stage), Error::MissingClock(stage.into()))?;
+            continue;
+        };
+        let upstream = task.upstream(stage).unwrap_or_default();
+        if task.verification != Verification::Minimal {
+            // the missing clocks of the upstream stages are reported on their own, as they are
+            // listed before
+            for prev_stage in &upstream {
+                let Some(prev) = clocks.get(*prev_stage) else {
+                    continue;
+                };
+                match follows(clock, prev, membership) {
+                    Ok(()) => {}
+                    Err(ClockOrdering::Concurrent) => found(
+                        Some(stage),
+                        Error::OrderViolation(format!(
+                            "clock of stage {stage} is concurrent with the clock of stage \
+                            {prev_stage}, so the stage is not executed upon the output of the \
+                            upstream stage"
+                        )),
+                    )?,
+                    Err(ordering) => found(
+                        Some(stage),
+                        Error::OrderViolation(format!(
+                            "clock of stage {stage} is {ordering} the clock of stage \
+                            {prev_stage} instead of after it"
+                        )),
+                    )?,
+                }
+            }
+        }
+        if task.verification == Verification::Paranoid {
+            if upstream
+                .iter()
+                .all(|prev_stage| clocks.contains_key(*prev_stage))
+            {
+                match task.upstream_clock(stage, clocks) {
+                    Ok(upstream) => {
+                        // nothing to attribute the stage to if its producer is pruned
+                        let pruned = !membership.is_empty()
+                            && !clock.causality().is_empty()
+                            && clock.causality().compare_pruned(&upstream, membership)
+                                == ClockOrdering::Equal;
+                        if !pruned {
+                            if let Err(err) = attribution::producer(clock.causality(), &upstream) {
+                                found(
+                                    Some(stage),
+                                    Error::OrderViolation(format!("stage {stage}: {err}")),
+                                )?
+                            }
+                        }
+                    }
+                    Err(err) => found(Some(stage), err)?,
+                }
+            }
+            if !programs.contains_key(stage) {
+                found(
+                    Some(stage),
+                    Error::WorkflowMismatch(format!("missing program version of stage {stage}")),
+                )?
+            }
         }
         // we only need to verify the last clock value, and we also can only verify the last clock
         // value: we don't have the necessary immediate results to verify the other clocks
@@ -232,37 +1847,634 @@ fn verify<C: PartialOrd, O>(
         // of them (really? cannot say for sure), because we don't even know whether those clocks
         // are verifiable or not. so including those clock are kind of pointless under current setup
         if stage == output_stage {
-            return context.verify(clock, output);
+            output_clock = Some(clock)
+        }
+    }
+    ControlFlow::Continue(output_clock)
+}
+
+impl<I> TaskStage<OrdinaryClock, I> {
+    // the clocks and the hints against `membership`, see `Membership`
+    pub fn prune(&mut self, membership: &Membership) {
+        let iterations = self.iterations.values_mut().flatten();
+        for clock in self
+            .clocks
+            .values_mut()
+            .chain(self.hints.values_mut())
+            .chain(iterations)
+        {
+            clock.prune(membership)
+        }
+    }
+}
+
+impl<O> TaskResult<OrdinaryClock, O> {
+    pub fn prune(&mut self, membership: &Membership) {
+        let iterations = self.iterations.values_mut().flatten();
+        for clock in self.clocks.values_mut().chain(iterations) {
+            clock.prune(membership)
         }
-        prev_clock = Some(clock)
     }
-    anyhow::bail!("unreachable")
 }
 
 impl<C: PartialOrd, I> TaskStage<C, I> {
+    // why executing the stage upon the message is bound to be wasted, by the hints of the hub:
+    // another worker has executed the stage of the task already, or the message builds upon an
+    // execution of an earlier stage that the hub has seen a higher clock than, e.g. of a racing
+    // worker that published first. `None` if nothing is known against it
+    pub fn stale(&self, stage: &str) -> Option<String> {
+        if self.hints.contains_key(stage) {
+            return Some(format!("stage {stage} is executed already"));
+        }
+        self.hints.iter().find_map(|(upstream, hint)| {
+            let clock = self.clocks.get(upstream)?;
+            (clock.compare(hint) != ClockOrdering::After).then(|| {
+                format!(
+                    "clock of stage {upstream} is {} the highest one known",
+                    clock.compare(hint)
+                )
+            })
+        })
+    }
+}
+
+impl<C: PartialOrd + Causality, I> TaskStage<C, I> {
     pub fn verify(
         &self,
         task: &Workflow,
         context: &impl ClockClientContext<Clock = C, Output = I>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), Error> {
+        self.verify_pruned(task, context, &Membership::default())
+    }
+
+    // tolerating the clocks pruned against `membership`
+    pub fn verify_pruned(
+        &self,
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = I>,
+        membership: &Membership,
+    ) -> Result<(), Error> {
+        branching::verify_branches(&self.clocks, &self.branches, task)?;
+        let task = &*task.taken(&self.branches);
+        looping::verify_iterations(&self.clocks, &self.iterations, task, membership)?;
+        routing::verify_routes(
+            &self.clocks,
+            &self.routes,
+            &task.downstream(&self.source),
+            task,
+        )?;
+        verify_output_digests(&self.clocks, &self.output_digests, task)?;
         match &self.source {
             StageSource::Start => Ok(()),
-            StageSource::Name(last_stage) => {
-                verify(&self.clocks, last_stage, &self.input, task, context)
+            StageSource::Name(last_stage) => verify(
+                &self.clocks,
+                &self.programs,
+                &Binding::new(self.id, last_stage),
+                &self.input,
+                task,
+                context,
+                membership,
+            ),
+        }
+    }
+
+    // everything `verify_pruned` finds, rather than the first of it
+    pub fn report(
+        &self,
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = I>,
+        membership: &Membership,
+    ) -> VerificationReport {
+        let mut report = VerificationReport::default();
+        if let Err(err) = branching::verify_branches(&self.clocks, &self.branches, task) {
+            report.push(None, err)
+        }
+        let task = &*task.taken(&self.branches);
+        if let Err(err) =
+            looping::verify_iterations(&self.clocks, &self.iterations, task, membership)
+        {
+            report.push(None, err)
+        }
+        if let Err(err) = routing::verify_routes(
+            &self.clocks,
+            &self.routes,
+            &task.downstream(&self.source),
+            task,
+        ) {
+            report.push(None, err)
+        }
+        if let Err(err) = verify_output_digests(&self.clocks, &self.output_digests, task) {
+            report.push(None, err)
+        }
+        if let StageSource::Name(last_stage) = &self.source {
+            let clock = report.inspect(&self.clocks, &self.programs, last_stage, task, membership);
+            if let Some(clock) = clock {
+                let binding = Binding::new(self.id, last_stage);
+                report.verify_output(&binding, clock, &self.input, context)
             }
         }
+        report
+    }
+
+    pub fn verify_programs(
+        &self,
+        task: &Workflow,
+        policy: &(impl ProgramPolicy + ?Sized),
+    ) -> Result<(), Error> {
+        verify_programs(&self.clocks, &self.programs, task, policy)
     }
 }
 
-impl<C: PartialOrd, O> TaskResult<C, O> {
+impl<C: PartialOrd + Causality, O> TaskResult<C, O> {
     pub fn verify(
         &self,
         task: &Workflow,
         context: &impl ClockClientContext<Clock = C, Output = O>,
-    ) -> anyhow::Result<()> {
-        match task.stages.last() {
+    ) -> Result<(), Error> {
+        self.verify_pruned(task, context, &Membership::default())
+    }
+
+    // tolerating the clocks pruned against `membership`
+    pub fn verify_pruned(
+        &self,
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = O>,
+        membership: &Membership,
+    ) -> Result<(), Error> {
+        match self.output_clock(task, membership)? {
+            Some((clock, binding)) => context.verify(clock, &self.output, &binding),
             None => Ok(()),
-            Some(last_stage) => verify(&self.clocks, last_stage, &self.output, task, context),
         }
     }
+
+    // the verification of several results at once, in the same order, each as if by
+    // `verify_pruned`, with the proofs of their outputs verified in one `verify_batch` of the
+    // context. a batch failing as a whole is verified one by one to tell the results that fail
+    pub fn verify_batch(
+        results: &[&Self],
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = O>,
+        membership: &Membership,
+    ) -> Vec<Result<(), Error>> {
+        let clocks = results
+            .iter()
+            .map(|result| result.output_clock(task, membership))
+            .collect::<Vec<_>>();
+        let batch = results
+            .iter()
+            .zip(&clocks)
+            .filter_map(|(result, clock)| {
+                let (clock, binding) = clock.as_ref().ok()?.as_ref()?;
+                Some((*clock, &result.output, binding))
+            })
+            .collect::<Vec<_>>();
+        let proven = context.verify_batch(&batch).is_ok();
+        results
+            .iter()
+            .zip(clocks)
+            .map(|(result, clock)| match clock? {
+                Some((clock, binding)) if !proven => {
+                    context.verify(clock, &result.output, &binding)
+                }
+                _ => Ok(()),
+            })
+            .collect()
+    }
+
+    // the furthest completed stage of every branch of an expired task, whose clocks are verified
+    // along with the ones of their ancestors. the stage the record names must be one of them
+    fn expired_frontier<'a>(&self, expired: &'a TaskExpired, task: &'a Workflow) -> Vec<&'a str> {
+        let completed = task
+            .stages
+            .iter()
+            .map(String::as_str)
+            .filter(|stage| self.clocks.contains_key(*stage))
+            .chain(expired.stage.as_deref())
+            .collect();
+        let mut frontier = task.frontier(&completed);
+        // a stage not in the workflow fails the verification of its clocks
+        frontier.extend(
+            expired
+                .stage
+                .as_deref()
+                .filter(|stage| !task.stages.iter().any(|other| other == stage)),
+        );
+        frontier
+    }
+
+    // the clock whose proof binds the output, along with the stage it is expected to be proven
+    // for, once the clocks are verified as far as the workflow selects, if there is an output to
+    // verify
+    fn output_clock(
+        &self,
+        task: &Workflow,
+        membership: &Membership,
+    ) -> Result<Option<(&C, Binding)>, Error> {
+        branching::verify_branches(&self.clocks, &self.branches, task)?;
+        let task = &*task.taken(&self.branches);
+        looping::verify_iterations(&self.clocks, &self.iterations, task, membership)?;
+        routing::verify_routes(&self.clocks, &self.routes, &[], task)?;
+        verify_output_digests(&self.clocks, &self.output_digests, task)?;
+        // there is no output to verify, only the clocks of the completed stages
+        if let Some(expired) = &self.expired {
+            for stage in self.expired_frontier(expired, task) {
+                verify_clocks(&self.clocks, &self.programs, stage, task, membership)?;
+            }
+            return Ok(None);
+        }
+        match task.stages.last() {
+            None => Ok(None),
+            Some(last_stage) => {
+                let clock =
+                    verify_clocks(&self.clocks, &self.programs, last_stage, task, membership)?;
+                Ok(Some((clock, Binding::new(self.id, last_stage))))
+            }
+        }
+    }
+
+    // everything `verify_pruned` finds, rather than the first of it
+    pub fn report(
+        &self,
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = O>,
+        membership: &Membership,
+    ) -> VerificationReport {
+        let mut report = VerificationReport::default();
+        if let Err(err) = branching::verify_branches(&self.clocks, &self.branches, task) {
+            report.push(None, err)
+        }
+        let task = &*task.taken(&self.branches);
+        if let Err(err) =
+            looping::verify_iterations(&self.clocks, &self.iterations, task, membership)
+        {
+            report.push(None, err)
+        }
+        if let Err(err) = routing::verify_routes(&self.clocks, &self.routes, &[], task) {
+            report.push(None, err)
+        }
+        if let Err(err) = verify_output_digests(&self.clocks, &self.output_digests, task) {
+            report.push(None, err)
+        }
+        // only the clocks of the completed stages of an expired task, see `output_clock`
+        if let Some(expired) = &self.expired {
+            for stage in self.expired_frontier(expired, task) {
+                report.inspect(&self.clocks, &self.programs, stage, task, membership);
+            }
+            return report;
+        }
+        if let Some(output_stage) = task.stages.last() {
+            let clock =
+                report.inspect(&self.clocks, &self.programs, output_stage, task, membership);
+            if let Some(clock) = clock {
+                let binding = Binding::new(self.id, output_stage);
+                report.verify_output(&binding, clock, &self.output, context)
+            }
+        }
+        report
+    }
+
+    pub fn verify_programs(
+        &self,
+        task: &Workflow,
+        policy: &(impl ProgramPolicy + ?Sized),
+    ) -> Result<(), Error> {
+        verify_programs(&self.clocks, &self.programs, task, policy)
+    }
+}
+
+impl<C: PartialOrd + Causality, O: AsRef<[u8]>> TaskResult<C, O> {
+    // the verification of a result under a workflow that audits, for the high-assurance ones: on
+    // top of `verify_pruned`, the clock of every executed stage is verified against its output
+    // rather than the clock of the output stage only. the outputs of the other stages are given in
+    // `outputs`, e.g. as the stage records of the hub, and each must be the one recorded in
+    // `output_digests`
+    pub fn audit(
+        &self,
+        task: &Workflow,
+        context: &impl ClockClientContext<Clock = C, Output = O>,
+        membership: &Membership,
+        outputs: &HashMap<String, O>,
+    ) -> Result<(), Error> {
+        if !task.audit {
+            return Err(Error::WorkflowMismatch(
+                "workflow does not record the outputs of its stages".into(),
+            ));
+        }
+        self.verify_pruned(task, context, membership)?;
+        for stage in &task.stages {
+            // e.g. not executed yet by the deadline
+            let Some(clock) = self.clocks.get(stage) else {
+                continue;
+            };
+            let output = match outputs.get(stage) {
+                Some(output) => output,
+                None if self.expired.is_none() && Some(stage) == task.stages.last() => &self.output,
+                None => {
+                    return Err(Error::ProofInvalid(anyhow::format_err!(
+                        "output of stage {stage} is not given"
+                    )))
+                }
+            };
+            if Some(&output_digest(output.as_ref())) != self.output_digests.get(stage) {
+                return Err(Error::ProofInvalid(anyhow::format_err!(
+                    "output of stage {stage} is not the recorded one"
+                )));
+            }
+            context
+                .verify(clock, output, &Binding::new(self.id, stage))
+                .map_err(|err| match err {
+                    Error::ProofInvalid(err) => {
+                        Error::ProofInvalid(anyhow::format_err!("stage {stage}: {err}"))
+                    }
+                    err => err,
+                })?
+        }
+        Ok(())
+    }
+}
+
+impl<C> TaskStage<C, Bytes> {
+    // the start stage of a task under the current workflow version of the hub, which is an ordinary
+    // task unless a `chunk` is set
+    pub fn start<P: Payload>(id: TaskId, input: &P) -> anyhow::Result<Self> {
+        Ok(Self {
+            version: protocol::VERSION,
+            id,
+            workflow: None,
+            source: StageSource::Start,
+            chunk: None,
+            assignee: None,
+            handoff: None,
+            accept_cached: false,
+            submitter: None,
+            priority: Default::default(),
+            labels: Default::default(),
+            deadline: None,
+            input: input.encode()?,
+            blob: None,
+            content_type: P::CONTENT_TYPE.map(Into::into),
+            compression: None,
+            clocks: Default::default(),
+            programs: Default::default(),
+            output_digests: Default::default(),
+            logs: Default::default(),
+            elapsed: Default::default(),
+            routes: Default::default(),
+            branches: Default::default(),
+            iterations: Default::default(),
+            hints: Default::default(),
+            clock_delta: None,
+        })
+    }
+
+    // the input must be reassembled first if it is offloaded. it must not decompress beyond
+    // `max_size` bytes, see `compression`
+    pub fn decompress(&mut self, max_size: u64) -> anyhow::Result<()> {
+        self.input =
+            compression::decompress(self.input.clone(), self.compression.take(), max_size)?;
+        Ok(())
+    }
+
+    // the input must be reassembled and decompressed first
+    pub fn decode_input<P: Payload>(&self) -> anyhow::Result<P> {
+        P::decode_typed(self.input.clone(), self.content_type.as_deref())
+    }
+}
+
+impl<C> TaskResult<C, Bytes> {
+    // the output must be reassembled and decompressed first
+    pub fn named_outputs(&self, task: &Workflow) -> anyhow::Result<outputs::NamedOutputs> {
+        outputs::named(task, &self.output)
+    }
+
+    // the output must be reassembled first if it is offloaded. it must not decompress beyond
+    // `max_size` bytes, see `compression`
+    pub fn decompress(&mut self, max_size: u64) -> anyhow::Result<()> {
+        self.output =
+            compression::decompress(self.output.clone(), self.compression.take(), max_size)?;
+        Ok(())
+    }
+
+    // the output must be reassembled and decompressed first
+    pub fn decode_output<P: Payload>(&self) -> anyhow::Result<P> {
+        P::decode_typed(self.output.clone(), self.content_type.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(entries: &[(NodeId, u32)]) -> OrdinaryClock {
+        OrdinaryClock(entries.iter().copied().collect())
+    }
+
+    fn workflow(json: &str) -> Workflow {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // the clocks of the earlier iterations count against the limits along with the others
+    #[test]
+    fn limits_iteration_clocks() {
+        let task = workflow(r#"{"stages": ["refine"], "looping": {"refine": {"max": 1000}}}"#);
+        let limits = ClockLimits {
+            max_entries: 2,
+            max_clocks: 4,
+            oversize: OversizePolicy::Compact,
+        };
+        let mut clocks = HashMap::from([("refine".to_string(), clock(&[(1, 4)]))]);
+        let iterations = |earlier: Vec<OrdinaryClock>| HashMap::from([("refine".into(), earlier)]);
+        let earlier = (1..4).map(|seq| clock(&[(1, seq)])).collect::<Vec<_>>();
+        limits
+            .enforce(&mut clocks, &iterations(earlier.clone()), &task)
+            .unwrap();
+        let mut more = earlier.clone();
+        more.push(clock(&[(1, 4)]));
+        assert!(limits
+            .enforce(&mut clocks, &iterations(more), &task)
+            .is_err());
+        let mut wide = earlier;
+        wide[0] = clock(&[(1, 1), (2, 1), (3, 1)]);
+        assert!(limits
+            .enforce(&mut clocks, &iterations(wide), &task)
+            .is_err());
+    }
+
+    #[test]
+    fn compares_clocks() {
+        let ordering = |a: &[(NodeId, u32)], b: &[(NodeId, u32)]| clock(a).compare(&clock(b));
+        assert_eq!(ordering(&[], &[]), ClockOrdering::Equal);
+        assert_eq!(
+            ordering(&[(1, 1), (2, 3)], &[(1, 1), (2, 3)]),
+            ClockOrdering::Equal
+        );
+        assert_eq!(ordering(&[(1, 1)], &[(1, 2)]), ClockOrdering::Before);
+        assert_eq!(ordering(&[(1, 2)], &[(1, 1)]), ClockOrdering::After);
+        assert_eq!(ordering(&[], &[(3, 1)]), ClockOrdering::Before);
+        assert_eq!(ordering(&[(3, 1)], &[]), ClockOrdering::After);
+        // an entry absent on either side, before, between and after the others
+        assert_eq!(
+            ordering(&[(2, 1)], &[(1, 1), (2, 1)]),
+            ClockOrdering::Before
+        );
+        assert_eq!(
+            ordering(&[(1, 1), (3, 1)], &[(1, 1), (2, 1), (3, 1)]),
+            ClockOrdering::Before
+        );
+        assert_eq!(ordering(&[(1, 1), (2, 1)], &[(1, 1)]), ClockOrdering::After);
+        assert_eq!(ordering(&[(1, 1)], &[(2, 1)]), ClockOrdering::Concurrent);
+        assert_eq!(
+            ordering(&[(1, 2), (2, 1)], &[(1, 1), (2, 2)]),
+            ClockOrdering::Concurrent
+        );
+        assert_eq!(
+            ordering(&[(1, 1), (3, 1)], &[(2, 1), (3, 2)]),
+            ClockOrdering::Concurrent
+        );
+    }
+
+    // a zero entry compares as an absent one
+    #[test]
+    fn compares_zero_entries_as_absent() {
+        let ordering = |a: &[(NodeId, u32)], b: &[(NodeId, u32)]| clock(a).compare(&clock(b));
+        assert_eq!(ordering(&[(1, 0)], &[]), ClockOrdering::Equal);
+        assert_eq!(ordering(&[], &[(1, 0), (2, 0)]), ClockOrdering::Equal);
+        assert_eq!(
+            ordering(&[(1, 1), (2, 0)], &[(1, 1), (3, 0)]),
+            ClockOrdering::Equal
+        );
+        assert_eq!(ordering(&[(1, 0)], &[(1, 1)]), ClockOrdering::Before);
+        assert_eq!(
+            ordering(&[(1, 1), (2, 0)], &[(2, 1)]),
+            ClockOrdering::Concurrent
+        );
+        assert!(clock(&[(1, 0), (2, 0)]).is_genesis());
+    }
+
+    #[test]
+    fn compares_pruned_clocks() {
+        let membership = Membership {
+            retired: [2].into(),
+        };
+        let (a, b) = (clock(&[(1, 1), (2, 2)]), clock(&[(1, 1), (2, 1)]));
+        assert_eq!(a.compare(&b), ClockOrdering::After);
+        assert_eq!(a.compare_pruned(&b, &membership), ClockOrdering::Equal);
+        let (a, b) = (clock(&[(1, 2), (2, 1)]), clock(&[(1, 1), (2, 2)]));
+        assert_eq!(a.compare_pruned(&b, &membership), ClockOrdering::After);
+    }
+
+    #[test]
+    fn encodes_equal_clocks_alike() {
+        let compact = clock(&[(1, 1), (3, 2)]);
+        let padded = clock(&[(0, 0), (1, 1), (2, 0), (3, 2), (4, 0)]);
+        assert_eq!(compact, padded);
+        assert_eq!(compact.encode(), padded.encode());
+        assert_eq!(compact.digest(), padded.digest());
+        assert_eq!(
+            clock(&[(1, 0)]).digest(),
+            OrdinaryClock::new_genesis().digest()
+        );
+        assert_ne!(compact.digest(), clock(&[(1, 1), (3, 3)]).digest());
+    }
+
+    #[test]
+    fn joins_and_meets_as_a_lattice() {
+        let clocks = [
+            OrdinaryClock::new_genesis(),
+            clock(&[(1, 2)]),
+            clock(&[(1, 1), (2, 3)]),
+            clock(&[(1, 2), (2, 3), (3, 0)]),
+            clock(&[(2, 1), (3, 4)]),
+        ];
+        for a in &clocks {
+            assert_eq!(a.join(a), *a);
+            assert_eq!(a.meet(a), *a);
+            for b in &clocks {
+                let join = a.join(b);
+                let meet = a.meet(b);
+                assert_eq!(join, b.join(a));
+                assert_eq!(meet, b.meet(a));
+                assert!(join.dominates(a) && join.dominates(b));
+                assert!(a.dominates(&meet) && b.dominates(&meet));
+                // the least upper and greatest lower bounds
+                for c in &clocks {
+                    if c.dominates(a) && c.dominates(b) {
+                        assert!(c.dominates(&join))
+                    }
+                    if a.dominates(c) && b.dominates(c) {
+                        assert!(meet.dominates(c))
+                    }
+                }
+                assert_eq!(a.dominates(b), join == *a);
+                assert_eq!(a.dominates(b), meet == *b);
+            }
+        }
+        let a = clock(&[(1, 1), (2, 3)]);
+        let b = clock(&[(2, 1), (3, 4)]);
+        assert_eq!(a.join(&b), clock(&[(1, 1), (2, 3), (3, 4)]));
+        assert_eq!(a.meet(&b), clock(&[(2, 1)]));
+        assert!(!a.dominates(&b) && !b.dominates(&a));
+        assert!(a.meet(&clock(&[(3, 1)])).is_genesis())
+    }
+
+    #[test]
+    fn fans_out_to_parallel_stages() {
+        let task = workflow(
+            r#"{
+                "stages": ["transcode", "thumbnail", "subtitles", "publish"],
+                "depends": {"subtitles": ["transcode"], "publish": ["thumbnail", "subtitles"]}
+            }"#,
+        );
+        let transcode = StageSource::Name("transcode".into());
+        assert_eq!(task.downstream(&transcode), ["thumbnail", "subtitles"]);
+        assert_eq!(task.next_stage(&transcode), None);
+        assert_eq!(task.upstream("subtitles"), Some(vec!["transcode"]));
+
+        let verify = |clocks: &HashMap<String, OrdinaryClock>, stage| {
+            verify_clocks(
+                clocks,
+                &HashMap::new(),
+                stage,
+                &task,
+                &Membership::default(),
+            )
+            .map(|_| ())
+        };
+        let mut clocks = HashMap::from([
+            ("transcode".to_string(), clock(&[(1, 1)])),
+            ("thumbnail".to_string(), clock(&[(1, 1), (2, 1)])),
+            ("subtitles".to_string(), clock(&[(1, 1), (3, 1)])),
+        ]);
+        // either branch verifies on its own, concurrent with the other one
+        verify(&clocks, "thumbnail").unwrap();
+        verify(&clocks, "subtitles").unwrap();
+        clocks.insert("publish".into(), clock(&[(1, 1), (2, 1), (3, 1), (4, 1)]));
+        verify(&clocks, "publish").unwrap();
+
+        // a branch not executed upon the shared stage
+        for subtitles in [clock(&[(3, 1)]), clock(&[(1, 1)])] {
+            let mut clocks = clocks.clone();
+            clocks.insert("subtitles".into(), subtitles);
+            assert!(matches!(
+                verify(&clocks, "subtitles"),
+                Err(Error::OrderViolation(_))
+            ));
+            assert!(matches!(
+                verify(&clocks, "publish"),
+                Err(Error::OrderViolation(_))
+            ));
+            verify(&clocks, "thumbnail").unwrap();
+        }
+        // the join executed upon one branch only
+        clocks.insert("publish".into(), clock(&[(1, 1), (2, 1), (4, 1)]));
+        assert!(matches!(
+            verify(&clocks, "publish"),
+            Err(Error::OrderViolation(_))
+        ));
+        clocks.remove("subtitles");
+        assert!(matches!(
+            verify(&clocks, "publish"),
+            Err(Error::MissingClock(_))
+        ))
+    }
 }
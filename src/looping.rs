@@ -0,0 +1,156 @@
+// bounded loops of a workflow: a looping stage executes again upon its own output until a predicate
+// over the output holds, or for at most `max` executions, e.g. a refinement step of an ml pipeline
+// that stops once the model converges
+// the predicate is the one of a branch (see `branching`): a value of the output equals the
+// expected one. without a predicate the stage executes `max` times. the worker of the stage runs
+// the iterations itself, proving the clock of every iteration upon the previous one before the
+// next, and publishes the last output only, along with the clocks of the earlier iterations, whose
+// number tells how many times the stage executed. the verification requires the clocks of the
+// iterations to strictly increase up to the clock of the stage, and the hub checks that a stage
+// stopping before `max` stops on an output the predicate holds for
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "network")]
+use crate::StageSource;
+use crate::{
+    attribution::Causality, branching, follows, Error, Membership, Verification, Workflow,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Loop {
+    // the executions of the stage at most, including the first
+    pub max: u32,
+    // the json pointer of the value, e.g. `/converged`. the whole output if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    // the value the loop stops on, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<Value>,
+}
+
+impl Loop {
+    pub fn validate(&self, stage: &str, task: &Workflow) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.max == 0 {
+            problems.push(format!("stage {stage} loops for no executions"))
+        }
+        if let Some(key) = &self.key {
+            if self.until.is_none() {
+                problems.push(format!(
+                    "loop key of stage {stage} without a value to stop on"
+                ))
+            }
+            if !key.is_empty() && !key.starts_with('/') {
+                problems.push(format!(
+                    "loop key {key:?} of stage {stage} is not a json pointer"
+                ))
+            }
+        }
+        // a re-execution executes the stage once
+        if task.deterministic.contains(stage) {
+            problems.push(format!(
+                "looping stage {stage} cannot be re-executed as deterministic"
+            ))
+        }
+        problems
+    }
+
+    // whether the stage stops after `executed` executions, the last of which has the output
+    pub fn done(&self, output: &[u8], executed: u32) -> anyhow::Result<bool> {
+        if executed >= self.max {
+            return Ok(true);
+        }
+        match &self.until {
+            None => Ok(false),
+            Some(until) => Ok(branching::value(self.key.as_deref(), output)? == *until),
+        }
+    }
+}
+
+// the earlier iterations of every looping stage are within its bounds, and their clocks strictly
+// increase from after the upstream clocks up to the clock of the stage
+pub(crate) fn verify_iterations<C: PartialOrd + Causality>(
+    clocks: &HashMap<String, C>,
+    iterations: &HashMap<String, Vec<C>>,
+    task: &Workflow,
+    membership: &Membership,
+) -> Result<(), Error> {
+    for (stage, earlier) in iterations {
+        let repeat = task
+            .looping
+            .get(stage)
+            .ok_or(Error::WorkflowMismatch(format!(
+                "iterations of stage {stage}, which does not loop"
+            )))?;
+        let clock = clocks.get(stage).ok_or(Error::WorkflowMismatch(format!(
+            "iterations of stage {stage}, which is not executed"
+        )))?;
+        let executed = earlier.len() + 1;
+        if executed > repeat.max as usize {
+            return Err(Error::WorkflowMismatch(format!(
+                "stage {stage} executes {executed} times, more than {}",
+                repeat.max
+            )));
+        }
+        if task.verification == Verification::Minimal {
+            continue;
+        }
+        // the first iteration executes upon the upstream stages, whose clocks are verified along
+        // with the one of the stage
+        let first = earlier.first().unwrap_or(clock);
+        for prev_stage in task.upstream(stage).unwrap_or_default() {
+            let Some(prev) = clocks.get(prev_stage) else {
+                continue;
+            };
+            if let Err(ordering) = follows(first, prev, membership) {
+                return Err(Error::OrderViolation(format!(
+                    "clock of the first iteration of stage {stage} is {ordering} the clock of \
+                    stage {prev_stage} instead of after it"
+                )));
+            }
+        }
+        let sequence = earlier.iter().chain([clock]).collect::<Vec<_>>();
+        for (iteration, pair) in sequence.windows(2).enumerate() {
+            if let Err(ordering) = follows(pair[1], pair[0], membership) {
+                return Err(Error::OrderViolation(format!(
+                    "clock of iteration {} of stage {stage} is {ordering} the clock of iteration \
+                    {} instead of after it",
+                    iteration + 2,
+                    iteration + 1
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// a looping stage the message is from must only stop before its bound on an output the predicate
+// holds for
+#[cfg(feature = "network")]
+pub(crate) fn check_iterations<C>(
+    source: &StageSource,
+    iterations: &HashMap<String, Vec<C>>,
+    payload: &[u8],
+    task: &Workflow,
+) -> Result<(), Error> {
+    let StageSource::Name(stage) = source else {
+        return Ok(());
+    };
+    let Some(repeat) = task.looping.get(stage) else {
+        return Ok(());
+    };
+    let executed = iterations.get(stage).map_or(0, Vec::len) + 1;
+    let done = repeat
+        .done(payload, executed as _)
+        .map_err(|err| Error::WorkflowMismatch(err.to_string()))?;
+    if !done {
+        return Err(Error::WorkflowMismatch(format!(
+            "stage {stage} stops after {executed} of {} executions before its condition holds",
+            repeat.max
+        )));
+    }
+    Ok(())
+}
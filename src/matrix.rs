@@ -0,0 +1,128 @@
+// matrix clocks: what a node knows of what every node knows, i.e. a vector clock per node (its
+// row), for the garbage collection of the gossip. a row holds the clocks the node is known to have
+// seen, e.g. merged from the clocks of the messages it published, since a node only publishes upon
+// what it has seen. the minimum of the rows is the stable frontier (see `frontier`): every node
+// has seen the events up to it, so a task stage whose clock is within the frontier is seen by all
+// (see `is_stable`) and can be discarded without any node still waiting on it
+// a member that is not heard from yet has an empty row, which pins the frontier at nothing, so the
+// frontier only advances once every member is. the members are fixed by the snapshot the clock is
+// created with, and a retired member is dropped with `prune` (see `Membership`) so its row no
+// longer holds the frontier back
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Membership, NodeId, OrdinaryClock};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MatrixClock {
+    // the node keeping the clock, whose own row is its own knowledge
+    pub node: NodeId,
+    pub rows: BTreeMap<NodeId, OrdinaryClock>,
+}
+
+impl MatrixClock {
+    pub fn new(node: NodeId, members: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut rows = members
+            .into_iter()
+            .map(|member| (member, OrdinaryClock::default()))
+            .collect::<BTreeMap<_, _>>();
+        rows.entry(node).or_default();
+        Self { node, rows }
+    }
+
+    pub fn own(&self) -> &OrdinaryClock {
+        &self.rows[&self.node]
+    }
+
+    // `node` has seen `clock`, and so has the keeping node by now. a node that is not a member is
+    // ignored, as it holds no row
+    pub fn observe(&mut self, node: NodeId, clock: &OrdinaryClock) {
+        for member in [node, self.node] {
+            if let Some(row) = self.rows.get_mut(&member) {
+                *row = row.join(clock)
+            }
+        }
+    }
+
+    // the knowledge of another node, e.g. carried along with its message: every row is at least
+    // what the other node knows of it, and the keeping node has now seen what the other one has
+    pub fn merge(&mut self, other: &Self) {
+        for (member, row) in &mut self.rows {
+            if let Some(known) = other.rows.get(member) {
+                *row = row.join(known)
+            }
+        }
+        if let Some(known) = other.rows.get(&other.node) {
+            self.observe(other.node, known)
+        }
+    }
+
+    // the least of the rows, entry by entry, which every member has seen
+    pub fn frontier(&self) -> OrdinaryClock {
+        let mut rows = self.rows.values();
+        let first = rows.next().cloned().unwrap_or_default();
+        rows.fold(first, |frontier, row| frontier.meet(row))
+    }
+
+    // whether every member has seen the event of `clock`
+    pub fn is_stable(&self, clock: &OrdinaryClock) -> bool {
+        self.frontier().dominates(clock)
+    }
+
+    // drops the rows of the retired members, along with their entries in the other rows. the
+    // keeping node keeps its own row regardless
+    pub fn prune(&mut self, membership: &Membership) {
+        let node = self.node;
+        self.rows
+            .retain(|member, _| *member == node || !membership.is_retired(*member));
+        for row in self.rows.values_mut() {
+            row.prune(membership)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(entries: &[(NodeId, u32)]) -> OrdinaryClock {
+        OrdinaryClock(entries.iter().copied().collect())
+    }
+
+    #[test]
+    fn frontier_is_the_least_of_the_rows() {
+        let mut matrix = MatrixClock::new(0, [1, 2]);
+        matrix.observe(1, &clock(&[(0, 2), (1, 3), (2, 1)]));
+        matrix.observe(2, &clock(&[(0, 1), (1, 4), (2, 2)]));
+        // the own row has seen both
+        assert_eq!(*matrix.own(), clock(&[(0, 2), (1, 4), (2, 2)]));
+        assert_eq!(matrix.frontier(), clock(&[(0, 1), (1, 3), (2, 1)]));
+        assert!(matrix.is_stable(&clock(&[(1, 3)])));
+        assert!(!matrix.is_stable(&clock(&[(1, 4)])));
+        assert!(!matrix.is_stable(&clock(&[(0, 1), (2, 2)])))
+    }
+
+    #[test]
+    fn unheard_member_holds_the_frontier() {
+        let mut matrix = MatrixClock::new(0, [1, 2, 3]);
+        matrix.observe(1, &clock(&[(0, 2), (1, 3)]));
+        matrix.observe(2, &clock(&[(0, 1), (1, 4), (2, 2)]));
+        assert!(matrix.rows[&3].is_genesis());
+        assert!(matrix.frontier().is_genesis());
+        assert!(!matrix.is_stable(&clock(&[(0, 1)])));
+        // a node outside the members holds no row
+        matrix.observe(4, &clock(&[(4, 1)]));
+        assert!(!matrix.rows.contains_key(&4));
+
+        matrix.observe(3, &clock(&[(0, 1), (3, 1)]));
+        assert_eq!(matrix.frontier(), clock(&[(0, 1)]));
+        let mut pruned = MatrixClock::new(0, [1, 2, 3]);
+        pruned.observe(1, &clock(&[(0, 2), (1, 3)]));
+        pruned.observe(2, &clock(&[(0, 1), (1, 4), (2, 2)]));
+        pruned.prune(&Membership {
+            retired: [3].into(),
+        });
+        assert_eq!(pruned.frontier(), clock(&[(0, 1), (1, 3)]))
+    }
+}
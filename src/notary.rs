@@ -0,0 +1,391 @@
+// the notarizations of task results, for auditors verifying a result offline, long after the fact
+// and without access to the hub
+// the hub appends the digest of every result it accepts, as it is kept, to its ledger, a merkle
+// tree in the shape of RFC 6962 (certificate transparency), which stands in for the blocks of a
+// chain. a notarization bundles a result as it is kept, the workflow it is verified against, the
+// offloaded output if any, the head of the ledger (its size and root) and the inclusion proof of
+// the result under the head, and is signed by the hub as a whole. so an auditor trusting the key
+// of the hub learns that the hub accepted the result at the position, and can verify the result
+// itself just like the hub did
+// the head is computed at the time of the notarization, so notarizations of different times have
+// different heads, all of which cover the earlier results
+// once the body of a result is pruned (see `hub::gc`), the hub notarizes its retained header
+// instead, which proves the inclusion of the digest of the result. whoever still holds the result
+// as it was kept, e.g. from an earlier notarization, checks it against the digest and verifies it
+// as usual
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attribution::producer,
+    blob::{CHUNK_SIZE, DEFAULT_MAX_BLOB_SIZE},
+    crypto::{CryptoSuite, Digest},
+    Allowlist, Challenge, Chunk, NodeId, OrdinaryClientContext, OrdinaryClock, TaskId, TaskResult,
+    Workflow, WorkflowDigest,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerHead {
+    pub size: u64,
+    pub root: Digest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    // the json encoded result exactly as it is kept, whose digest is the leaf of the ledger
+    pub result: String,
+    pub workflow: Workflow,
+    // the offloaded output of the result, reassembled but not decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    pub head: LedgerHead,
+    // the position of the result in the ledger
+    pub index: u64,
+    // the sibling hashes from the leaf up to the root
+    pub path: Vec<Digest>,
+    // the successful challenge, if the result is reverted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverted: Option<Challenge>,
+    // seconds since the unix epoch
+    pub notarized_at: u64,
+}
+
+// what the hub retains of a result once its body is pruned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultHeader {
+    pub id: TaskId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<WorkflowDigest>,
+    // of the result as it was kept, i.e. its leaf in the ledger
+    pub digest: Digest,
+    // the position of the result in the ledger
+    pub index: u64,
+    // the shares of the contributors (see `attribution::shares`), empty for a checkpoint of a
+    // streaming task, a reverted result, or where the shares cannot be computed
+    pub attribution: BTreeMap<NodeId, f64>,
+    pub expired: bool,
+    pub reverted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderBundle {
+    pub header: ResultHeader,
+    pub head: LedgerHead,
+    // the sibling hashes from the leaf up to the root
+    pub path: Vec<Digest>,
+    // seconds since the unix epoch
+    pub notarized_at: u64,
+}
+
+// of either a `Bundle` or a `HeaderBundle`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notarization {
+    pub suite: String,
+    pub public_key: Vec<u8>,
+    // the json encoded bundle, kept as the text that is signed
+    pub bundle: String,
+    pub signature: Vec<u8>,
+}
+
+fn leaf_hash(crypto: &dyn CryptoSuite, leaf: &Digest) -> Digest {
+    crypto.digest(&[&[0][..], leaf].concat())
+}
+
+fn node_hash(crypto: &dyn CryptoSuite, left: &Digest, right: &Digest) -> Digest {
+    crypto.digest(&[&[1][..], left, right].concat())
+}
+
+// the largest power of two smaller than the size, which must be at least 2
+fn split(size: u64) -> u64 {
+    1 << (63 - (size - 1).leading_zeros())
+}
+
+pub fn root(crypto: &dyn CryptoSuite, leaves: &[Digest]) -> Digest {
+    match leaves {
+        [] => crypto.digest(&[]),
+        [leaf] => leaf_hash(crypto, leaf),
+        _ => {
+            let (left, right) = leaves.split_at(split(leaves.len() as _) as _);
+            node_hash(crypto, &root(crypto, left), &root(crypto, right))
+        }
+    }
+}
+
+pub fn prove(crypto: &dyn CryptoSuite, leaves: &[Digest], index: usize) -> Vec<Digest> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split(leaves.len() as _) as usize;
+    let (mut path, sibling) = if index < k {
+        (
+            prove(crypto, &leaves[..k], index),
+            root(crypto, &leaves[k..]),
+        )
+    } else {
+        (
+            prove(crypto, &leaves[k..], index - k),
+            root(crypto, &leaves[..k]),
+        )
+    };
+    path.push(sibling);
+    path
+}
+
+fn climb(
+    crypto: &dyn CryptoSuite,
+    index: u64,
+    size: u64,
+    hash: Digest,
+    path: &[Digest],
+) -> anyhow::Result<Digest> {
+    if size == 1 {
+        anyhow::ensure!(path.is_empty(), "inclusion proof is too long");
+        return Ok(hash);
+    }
+    let k = split(size);
+    let (sibling, path) = path
+        .split_last()
+        .ok_or(anyhow::format_err!("inclusion proof is too short"))?;
+    if index < k {
+        Ok(node_hash(
+            crypto,
+            &climb(crypto, index, k, hash, path)?,
+            sibling,
+        ))
+    } else {
+        Ok(node_hash(
+            crypto,
+            sibling,
+            &climb(crypto, index - k, size - k, hash, path)?,
+        ))
+    }
+}
+
+// the root of the tree of the size that the inclusion proof of the leaf at the index leads to
+pub fn root_of(
+    crypto: &dyn CryptoSuite,
+    leaf: &Digest,
+    index: u64,
+    size: u64,
+    path: &[Digest],
+) -> anyhow::Result<Digest> {
+    anyhow::ensure!(index < size, "leaf index out of the tree");
+    climb(crypto, index, size, leaf_hash(crypto, leaf), path)
+}
+
+pub fn verify_inclusion(
+    crypto: &dyn CryptoSuite,
+    leaf: &Digest,
+    index: u64,
+    path: &[Digest],
+    head: &LedgerHead,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(index < head.size, "leaf index out of the ledger");
+    let root = root_of(crypto, leaf, index, head.size, path)?;
+    anyhow::ensure!(
+        root == head.root,
+        "inclusion proof does not lead to the root"
+    );
+    Ok(())
+}
+
+// a verified notarization, with the output of the result reassembled and decompressed
+#[derive(Debug)]
+pub struct Notarized {
+    pub result: TaskResult<OrdinaryClock, Bytes>,
+    pub workflow: Workflow,
+    pub head: LedgerHead,
+    pub reverted: Option<Challenge>,
+    pub notarized_at: u64,
+}
+
+fn verify_signature(
+    notarization: &Notarization,
+    crypto: &dyn CryptoSuite,
+    public_key: &[u8],
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        notarization.suite == crypto.name(),
+        "notarized with crypto suite {}",
+        notarization.suite
+    );
+    anyhow::ensure!(
+        notarization.public_key == public_key,
+        "notarized by another key"
+    );
+    crypto.verify(
+        public_key,
+        notarization.bundle.as_bytes(),
+        &notarization.signature,
+    )
+}
+
+// everything is checked offline against the trusted key of the hub. the workflow is verified with
+// its own program allowlist, while the hub may have applied a stricter policy. a reverted result
+// still verifies, so the caller must check `reverted`
+pub fn verify(
+    notarization: &Notarization,
+    crypto: &dyn CryptoSuite,
+    public_key: &[u8],
+) -> anyhow::Result<Notarized> {
+    verify_signature(notarization, crypto, public_key)?;
+    let bundle = serde_json::from_str::<Bundle>(&notarization.bundle)?;
+    verify_inclusion(
+        crypto,
+        &crypto.digest(bundle.result.as_bytes()),
+        bundle.index,
+        &bundle.path,
+        &bundle.head,
+    )?;
+    let mut result = serde_json::from_str::<TaskResult<OrdinaryClock, Bytes>>(&bundle.result)?;
+    anyhow::ensure!(
+        result.chunk.is_none(),
+        "only the results of ordinary tasks are notarized"
+    );
+    anyhow::ensure!(
+        result
+            .workflow
+            .is_none_or(|workflow| workflow == bundle.workflow.digest(crypto)),
+        "result is not of the bundled workflow"
+    );
+    if let Some(blob) = result.blob.take() {
+        let output = bundle
+            .output
+            .ok_or(anyhow::format_err!("missing offloaded output"))?;
+        anyhow::ensure!(output.len() as u64 == blob.size, "blob size mismatch");
+        anyhow::ensure!(
+            output.chunks(CHUNK_SIZE).len() == blob.chunks.len()
+                && output
+                    .chunks(CHUNK_SIZE)
+                    .zip(&blob.chunks)
+                    .all(|(chunk, digest)| crypto.digest(chunk) == *digest),
+            "offloaded output does not match its chunks"
+        );
+        result.output = output
+    }
+    result.decompress(DEFAULT_MAX_BLOB_SIZE)?;
+    result.verify(&bundle.workflow, &OrdinaryClientContext::default())?;
+    if !bundle.workflow.outputs.is_empty() && result.expired.is_none() {
+        result.named_outputs(&bundle.workflow)?;
+    }
+    result.verify_programs(&bundle.workflow, &Allowlist)?;
+    Ok(Notarized {
+        result,
+        workflow: bundle.workflow,
+        head: bundle.head,
+        reverted: bundle.reverted,
+        notarized_at: bundle.notarized_at,
+    })
+}
+
+// the notarization of a retained header, checked offline against the trusted key of the hub as
+// well. only the inclusion of the digest is proven, the attribution is as the hub computed it
+pub fn verify_header(
+    notarization: &Notarization,
+    crypto: &dyn CryptoSuite,
+    public_key: &[u8],
+) -> anyhow::Result<HeaderBundle> {
+    verify_signature(notarization, crypto, public_key)?;
+    let bundle = serde_json::from_str::<HeaderBundle>(&notarization.bundle)?;
+    verify_inclusion(
+        crypto,
+        &bundle.header.digest,
+        bundle.header.index,
+        &bundle.path,
+        &bundle.head,
+    )?;
+    Ok(bundle)
+}
+
+// a worker's claim that it contributed a stage to an accepted task, for reward systems that credit
+// the workers without asking the hub. it carries the notarization of the result, which holds every
+// clock of the task, along with the entry of the node in the clock of the stage and the final
+// clock, i.e. the clock of the last completed stage, so the claim can be read without decoding the
+// bundle. a third party checks it offline against the trusted key of the hub (see
+// `verify_contribution`), which proves that the hub accepted the result in its ledger and that the
+// node is the producer of the stage, the one node advancing its clock over the stage before (see
+// `attribution::producer`)
+// the node ids are not bound to keys under the ordinary clocks, so the reward system has to know
+// which node id belongs to whom, and a worker claiming across restarts keeps its node id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributionProof {
+    pub node: NodeId,
+    pub stage: String,
+    pub entry: u32,
+    pub clock: OrdinaryClock,
+    pub notarization: Notarization,
+}
+
+// the notarized contribution of the node to the stage, where the notarization is checked against
+// its own key only. the party that is claimed to must verify the proof against the key it trusts
+pub fn contribution(
+    notarization: Notarization,
+    crypto: &dyn CryptoSuite,
+    node: NodeId,
+    stage: &str,
+) -> anyhow::Result<ContributionProof> {
+    let notarized = verify(&notarization, crypto, &notarization.public_key)?;
+    let (entry, clock) = contributed(&notarized, node, stage)?;
+    Ok(ContributionProof {
+        node,
+        stage: stage.into(),
+        entry,
+        clock,
+        notarization,
+    })
+}
+
+// the entry of the node in the clock of the stage, and the final clock
+fn contributed(
+    notarized: &Notarized,
+    node: NodeId,
+    stage: &str,
+) -> anyhow::Result<(u32, OrdinaryClock)> {
+    anyhow::ensure!(notarized.reverted.is_none(), "result is reverted");
+    let (task, result) = (&notarized.workflow, &notarized.result);
+    let task = &*task.taken(&result.branches);
+    anyhow::ensure!(
+        task.stages.iter().any(|other| other == stage),
+        "unknown stage {stage}"
+    );
+    let clock = result
+        .clocks
+        .get(stage)
+        .ok_or(anyhow::format_err!("stage {stage} is not completed"))?;
+    let previous = task.upstream_clock(stage, &result.clocks)?;
+    let producer = producer(clock, &previous)?;
+    anyhow::ensure!(
+        producer == node,
+        "stage {stage} is produced by {producer:08x}"
+    );
+    let last = task
+        .stages
+        .iter()
+        .rev()
+        .find_map(|stage| result.clocks.get(stage))
+        .ok_or(anyhow::format_err!("no stage is completed"))?;
+    Ok((clock[&node], last.clone()))
+}
+
+// everything is checked offline against the trusted key of the hub, as `verify` does, and the
+// claimed entry and final clock against the notarized result
+pub fn verify_contribution(
+    proof: &ContributionProof,
+    crypto: &dyn CryptoSuite,
+    public_key: &[u8],
+) -> anyhow::Result<Notarized> {
+    let notarized = verify(&proof.notarization, crypto, public_key)?;
+    let (entry, clock) = contributed(&notarized, proof.node, &proof.stage)?;
+    anyhow::ensure!(
+        proof.entry == entry,
+        "entry {} of node {:08x} instead of {entry}",
+        proof.entry,
+        proof.node
+    );
+    anyhow::ensure!(proof.clock.0 == clock.0, "final clock mismatch");
+    Ok(notarized)
+}
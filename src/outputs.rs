@@ -0,0 +1,80 @@
+// multiple named outputs of a task, e.g. a transcript, its summary and their embeddings
+// a workflow declaring `outputs` has its last stage write all of them in the encoding below as its
+// one output, which is what the clock of the stage proves, so every named output is covered by the
+// proof part of the final clock just like a single output is, and nothing else about the clocks or
+// the messages changes
+// the encoding is canonical: the number of outputs, then for every output in the order of the
+// names, the length of the name, the name, the length of the output and the output. the lengths
+// are big endian, of 4 bytes except for the output's of 8 bytes
+// e.g. a python stage program writes them with
+//   stdout.buffer.write(struct.pack('>I', len(outputs)))
+//   for name, output in sorted(outputs.items()):
+//       stdout.buffer.write(struct.pack('>I', len(name)) + name.encode())
+//       stdout.buffer.write(struct.pack('>Q', len(output)) + output)
+use std::collections::BTreeMap;
+
+use bytes::{BufMut as _, Bytes, BytesMut};
+
+use crate::Workflow;
+
+pub type NamedOutputs = BTreeMap<String, Bytes>;
+
+pub fn encode(outputs: &NamedOutputs) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u32(outputs.len() as _);
+    for (name, output) in outputs {
+        buf.put_u32(name.len() as _);
+        buf.put_slice(name.as_bytes());
+        buf.put_u64(output.len() as _);
+        buf.put_slice(output)
+    }
+    buf.freeze()
+}
+
+fn take(buf: &mut Bytes, len: usize) -> anyhow::Result<Bytes> {
+    anyhow::ensure!(buf.len() >= len, "truncated named outputs");
+    Ok(buf.split_to(len))
+}
+
+fn take_len<const N: usize>(buf: &mut Bytes) -> anyhow::Result<usize> {
+    let mut len = [0; 8];
+    len[8 - N..].copy_from_slice(&take(buf, N)?);
+    Ok(u64::from_be_bytes(len).try_into()?)
+}
+
+// the outputs share the memory of the encoded one
+pub fn decode(encoded: &Bytes) -> anyhow::Result<NamedOutputs> {
+    let mut buf = encoded.clone();
+    let count = take_len::<4>(&mut buf)?;
+    let mut outputs = NamedOutputs::new();
+    for _ in 0..count {
+        let len = take_len::<4>(&mut buf)?;
+        let name = String::from_utf8(take(&mut buf, len)?.into())?;
+        anyhow::ensure!(
+            outputs
+                .last_key_value()
+                .is_none_or(|(last, _)| *last < name),
+            "named outputs are not in the order of their names"
+        );
+        let len = take_len::<8>(&mut buf)?;
+        outputs.insert(name, take(&mut buf, len)?);
+    }
+    anyhow::ensure!(buf.is_empty(), "trailing bytes after named outputs");
+    Ok(outputs)
+}
+
+// the outputs of a result under a workflow declaring them, which must be exactly the declared ones
+pub fn named(task: &Workflow, output: &Bytes) -> anyhow::Result<NamedOutputs> {
+    anyhow::ensure!(
+        !task.outputs.is_empty(),
+        "workflow declares no named outputs"
+    );
+    let outputs = decode(output)?;
+    anyhow::ensure!(
+        outputs.keys().eq(&task.outputs),
+        "named outputs {:?} are not the declared {:?}",
+        outputs.keys().collect::<Vec<_>>(),
+        task.outputs
+    );
+    Ok(outputs)
+}
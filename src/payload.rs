@@ -0,0 +1,55 @@
+// the typed payloads of the stages, for rust-native stages exchanging structured data
+// a payload still travels as bytes, which are what the clocks prove and what the hub fans out,
+// offloads and keeps, so nothing but the two ends of a payload cares about its type. the messages
+// carry the content type of their payload, `None` for opaque bytes, so the receiver can tell
+// whether the payload is of the type it expects before decoding it
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub trait Payload: Sized {
+    const CONTENT_TYPE: Option<&'static str>;
+
+    fn encode(&self) -> anyhow::Result<Bytes>;
+
+    fn decode(payload: Bytes) -> anyhow::Result<Self>;
+
+    // fails if the payload is declared to be of another content type
+    fn decode_typed(payload: Bytes, content_type: Option<&str>) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            content_type == Self::CONTENT_TYPE,
+            "payload of content type {content_type:?} is not of {:?}",
+            Self::CONTENT_TYPE
+        );
+        Self::decode(payload)
+    }
+}
+
+impl Payload for Bytes {
+    const CONTENT_TYPE: Option<&'static str> = None;
+
+    fn encode(&self) -> anyhow::Result<Bytes> {
+        Ok(self.clone())
+    }
+
+    fn decode(payload: Bytes) -> anyhow::Result<Self> {
+        Ok(payload)
+    }
+}
+
+// any serde type, encoded as json
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+pub const JSON: &str = "application/json";
+
+impl<T: Serialize + DeserializeOwned> Payload for Json<T> {
+    const CONTENT_TYPE: Option<&'static str> = Some(JSON);
+
+    fn encode(&self) -> anyhow::Result<Bytes> {
+        Ok(serde_json::to_vec(&self.0)?.into())
+    }
+
+    fn decode(payload: Bytes) -> anyhow::Result<Self> {
+        Ok(Self(serde_json::from_slice(&payload)?))
+    }
+}
@@ -0,0 +1,215 @@
+// clocks whose entries are hash chains, in the manner of proof of history: the entry of every node
+// is its count along with the running hash of the events it advanced its count on, so a node
+// cannot rewrite or fork its history without it showing. two clocks holding different hashes for
+// the same count of a node (see `conflicts`) are the evidence that the node forked its history,
+// e.g. minted clocks of the same position for two different outputs
+// the link the producer appends is carried along with the clock: the hash its chain is at before,
+// the digests of the predecessor clocks and the digest of the output. verifying recomputes the new
+// hash of the producer from the link, so a clock is only accepted along with the output its link
+// commits to. the new hash also commits to the stage the clock is produced for, which is not
+// carried but given by the verifier, so the clock is not accepted for another stage. the hashes of
+// the other nodes are the ones merged from the predecessors, which the producer checks as it proves
+// the chains prove the integrity of the histories and their order, not who appends to them or the
+// computation of the stages, so they are meant to be combined with a context that does, e.g. by
+// running the stages on trusted hosts
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{Digest, HashAlgorithm},
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+// the hash of a chain before its first link
+pub const GENESIS: Digest = [0; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    // the producer, whose count the link advances
+    pub node: NodeId,
+    // the hash of the chain of the producer before the link, as merged from the predecessors
+    pub previous: Digest,
+    // of the predecessor clocks, in the order of the predecessors (see `clock_digest`)
+    pub predecessors: Vec<Digest>,
+    pub output: Digest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct PohClock {
+    // the counts of the chains
+    #[causality]
+    pub clock: OrdinaryClock,
+    // the running hashes of the chains at their counts
+    pub tips: BTreeMap<NodeId, Digest>,
+    pub link: Link,
+}
+
+impl Link {
+    fn encode(&self) -> Vec<u8> {
+        let mut message = self.node.to_be_bytes().to_vec();
+        message.extend(self.previous);
+        message.extend((self.predecessors.len() as u32).to_be_bytes());
+        for digest in &self.predecessors {
+            message.extend(digest)
+        }
+        message.extend(self.output);
+        message
+    }
+}
+
+// the hash of the chain after the link, which is the `count`th one, for the stage of `binding`
+fn append(hash: HashAlgorithm, link: &Link, binding: &Binding, count: u32) -> Digest {
+    let mut message = link.encode();
+    message.extend(binding.encode());
+    message.extend(count.to_be_bytes());
+    hash.digest(&message)
+}
+
+// of the whole clock, i.e. along with the hashes and the link
+fn clock_digest(hash: HashAlgorithm, clock: &PohClock) -> Digest {
+    let mut message = clock.clock.encode();
+    for (node, tip) in &clock.tips {
+        message.extend(node.to_be_bytes());
+        message.extend(tip)
+    }
+    message.extend(clock.link.encode());
+    hash.digest(&message)
+}
+
+// the nodes that the clocks hold different hashes of at the same count for, i.e. that forked
+pub fn conflicts(a: &PohClock, b: &PohClock) -> Vec<NodeId> {
+    a.tips
+        .iter()
+        .filter(|(node, tip)| {
+            a.clock.get(node) == b.clock.get(node)
+                && b.tips.get(node).is_some_and(|other| other != *tip)
+        })
+        .map(|(node, _)| *node)
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct PohClientContext<O> {
+    hash: HashAlgorithm,
+    _output: PhantomData<O>,
+}
+
+impl<O> PohClientContext<O> {
+    pub fn new(hash: HashAlgorithm) -> Self {
+        Self {
+            hash,
+            _output: PhantomData,
+        }
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &PohClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            clock.clock.len() == clock.tips.len()
+                && clock.tips.keys().all(|node| clock.clock.contains_key(node)),
+            "clock does not hold a hash for every count"
+        );
+        let link = &clock.link;
+        let count = clock.clock.get(&link.node).copied().unwrap_or_default();
+        anyhow::ensure!(count > 0, "link of node {:08x} is not counted", link.node);
+        anyhow::ensure!(
+            link.output == self.hash.digest(output),
+            "link does not commit to the output"
+        );
+        anyhow::ensure!(
+            clock.tips[&link.node] == append(self.hash, link, binding, count),
+            "hash of node {:08x} is not the one of the link",
+            link.node
+        );
+        Ok(())
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for PohClientContext<O> {
+    type Clock = PohClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+#[derive(Debug, ClockClientContext)]
+pub struct PohContext<I, O> {
+    id: NodeId,
+    #[client_context]
+    client: PohClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> PohContext<I, O> {
+    pub fn new(id: NodeId, client: PohClientContext<O>) -> Self {
+        Self {
+            id,
+            client,
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for PohContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let hash = self.client.hash;
+        // the latest hash of every chain, which the predecessors must agree on at the same count
+        let mut tips = BTreeMap::<NodeId, (u32, Digest)>::new();
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?;
+            for (node, tip) in &clock.tips {
+                let count = clock.clock[node];
+                match tips.get(node) {
+                    Some((other, _)) if *other > count => {}
+                    Some((other, other_tip)) if *other == count && other_tip != tip => {
+                        return Err(Error::OrderViolation(format!(
+                            "predecessors hold conflicting histories of node {node:08x}"
+                        )));
+                    }
+                    _ => {
+                        tips.insert(*node, (count, *tip));
+                    }
+                }
+            }
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let link = Link {
+            node: self.id,
+            previous: tips.get(&self.id).map_or(GENESIS, |(_, tip)| *tip),
+            predecessors: predecessors
+                .iter()
+                .map(|(clock, ..)| clock_digest(hash, clock))
+                .collect(),
+            output: hash.digest(output.as_ref()),
+        };
+        let mut tips = tips
+            .into_iter()
+            .map(|(node, (_, tip))| (node, tip))
+            .collect::<BTreeMap<_, _>>();
+        tips.insert(self.id, append(hash, &link, binding, clock[&self.id]));
+        Ok(PohClock { clock, tips, link })
+    }
+}
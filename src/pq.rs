@@ -0,0 +1,481 @@
+// post-quantum signed clocks, for deployments that keep attributions around for long enough to
+// worry about the classical signatures being forged some day
+// the signature schemes are ML-DSA (a.k.a. Dilithium) and SLH-DSA (a.k.a. SPHINCS+), provided as
+// a `CryptoSuite` so they can also replace the classical schemes everywhere else. the trade-off is
+// size: a ML-DSA-65 clock carries ~5KB of proof part and a SLH-DSA-128s one ~8KB, so this is not
+// the default
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{collections::HashSet, marker::PhantomData, str::FromStr, sync::Arc};
+
+use fips204::{
+    ml_dsa_65,
+    traits::{SerDes as _, Signer as _, Verifier as _},
+};
+use fips205::{
+    slh_dsa_sha2_128s,
+    traits::{SerDes as _, Signer as _, Verifier as _},
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "network")]
+use crate::signer::sign_blocking;
+use crate::{
+    crypto::{CryptoSuite, Digest, HashAlgorithm},
+    notary,
+    signer::Signer,
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+    ProofRequest, VerifyRequest,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PqSignatureAlgorithm {
+    #[default]
+    MlDsa65,
+    SlhDsaSha2_128s,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PqSuite {
+    pub hash: HashAlgorithm,
+    pub signature: PqSignatureAlgorithm,
+}
+
+// the context string of FIPS 204/205 signatures, for domain separation against signatures made
+// with the same keys elsewhere
+const SIGNING_CONTEXT: &[u8] = b"pohb";
+
+impl FromStr for PqSuite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, signature) = s
+            .split_once('-')
+            .ok_or(anyhow::format_err!("expect <hash>-<signature>, got {s}"))?;
+        let hash = hash.parse()?;
+        let signature = match signature {
+            "mldsa65" => PqSignatureAlgorithm::MlDsa65,
+            "slhdsa128s" => PqSignatureAlgorithm::SlhDsaSha2_128s,
+            _ => anyhow::bail!("unknown signature algorithm {signature}"),
+        };
+        Ok(Self { hash, signature })
+    }
+}
+
+fn bytes<const N: usize>(bytes: &[u8]) -> anyhow::Result<[u8; N]> {
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::format_err!("expect {N} bytes, got {}", bytes.len()))
+}
+
+impl CryptoSuite for PqSuite {
+    fn name(&self) -> String {
+        let signature = match self.signature {
+            PqSignatureAlgorithm::MlDsa65 => "mldsa65",
+            PqSignatureAlgorithm::SlhDsaSha2_128s => "slhdsa128s",
+        };
+        format!("{}-{signature}", self.hash.name())
+    }
+
+    fn digest(&self, data: &[u8]) -> Digest {
+        self.hash.digest(data)
+    }
+
+    fn generate_key(&self) -> Vec<u8> {
+        match self.signature {
+            PqSignatureAlgorithm::MlDsa65 => {
+                let (_, secret_key) = ml_dsa_65::try_keygen().expect("system rng available");
+                secret_key.into_bytes().to_vec()
+            }
+            PqSignatureAlgorithm::SlhDsaSha2_128s => {
+                let (_, secret_key) =
+                    slh_dsa_sha2_128s::try_keygen().expect("system rng available");
+                secret_key.into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn public_key(&self, secret_key: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(match self.signature {
+            PqSignatureAlgorithm::MlDsa65 => {
+                ml_dsa_65::PrivateKey::try_from_bytes(bytes(secret_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .get_public_key()
+                    .into_bytes()
+                    .to_vec()
+            }
+            PqSignatureAlgorithm::SlhDsaSha2_128s => {
+                slh_dsa_sha2_128s::PrivateKey::try_from_bytes(&bytes(secret_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .get_public_key()
+                    .into_bytes()
+                    .to_vec()
+            }
+        })
+    }
+
+    fn sign(&self, secret_key: &[u8], message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(match self.signature {
+            PqSignatureAlgorithm::MlDsa65 => {
+                ml_dsa_65::PrivateKey::try_from_bytes(bytes(secret_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .try_sign(message, SIGNING_CONTEXT)
+                    .map_err(anyhow::Error::msg)?
+                    .to_vec()
+            }
+            PqSignatureAlgorithm::SlhDsaSha2_128s => {
+                slh_dsa_sha2_128s::PrivateKey::try_from_bytes(&bytes(secret_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .try_sign(message, SIGNING_CONTEXT, true)
+                    .map_err(anyhow::Error::msg)?
+                    .to_vec()
+            }
+        })
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        let verified = match self.signature {
+            PqSignatureAlgorithm::MlDsa65 => {
+                ml_dsa_65::PublicKey::try_from_bytes(bytes(public_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .verify(message, &bytes(signature)?, SIGNING_CONTEXT)
+            }
+            PqSignatureAlgorithm::SlhDsaSha2_128s => {
+                slh_dsa_sha2_128s::PublicKey::try_from_bytes(&bytes(public_key)?)
+                    .map_err(anyhow::Error::msg)?
+                    .verify(message, &bytes(signature)?, SIGNING_CONTEXT)
+            }
+        };
+        anyhow::ensure!(verified, "invalid signature");
+        Ok(())
+    }
+}
+
+// the causality part is an ordinary clock, and the proof part is the producer's signature over
+// the causality part and the digest of the output it is produced for
+// a clock proven in a batch (see `PqContext::prove_batch`) shares the signature with the other
+// clocks of the batch instead: the signature is over the root of a merkle tree (in the shape of
+// the ledger, see `notary`) whose leaves are the digests of what each clock would be signed over
+// alone, and the clock carries its opening in the tree
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct PqClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<BatchOpening>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchOpening {
+    pub index: u64,
+    pub size: u64,
+    // the sibling hashes from the leaf up to the root
+    pub path: Vec<Digest>,
+}
+
+fn signed_message(
+    crypto: &PqSuite,
+    clock: &OrdinaryClock,
+    binding: &Binding,
+    output: &[u8],
+) -> Vec<u8> {
+    let mut message = clock.encode();
+    message.extend(binding.encode());
+    message.extend(crypto.digest(output));
+    message
+}
+
+// the single messages are longer than the prefix plus a digest, as the binding alone takes 12
+// bytes besides the stage name, so the prefix keeps a root from being mistaken for one
+const BATCH_PREFIX: &[u8] = b"pohb-batch";
+
+fn batch_message(root: &Digest) -> Vec<u8> {
+    [BATCH_PREFIX, root].concat()
+}
+
+// the verifier trusts a known set of producer keys, e.g. the registered workers
+#[derive(Debug)]
+pub struct PqClientContext<O> {
+    crypto: PqSuite,
+    public_keys: HashSet<Vec<u8>>,
+    _output: PhantomData<O>,
+}
+
+impl<O> PqClientContext<O> {
+    pub fn new(crypto: PqSuite, public_keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            crypto,
+            public_keys: public_keys.into_iter().collect(),
+            _output: PhantomData,
+        }
+    }
+
+    // what the signature of the clock is over
+    fn signed(&self, clock: &PqClock, output: &[u8], binding: &Binding) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(
+            self.public_keys.contains(&clock.public_key),
+            "clock is signed by unknown key"
+        );
+        let message = signed_message(&self.crypto, &clock.clock, binding, output);
+        let Some(batch) = &clock.batch else {
+            return Ok(message);
+        };
+        let root = notary::root_of(
+            &self.crypto,
+            &self.crypto.digest(&message),
+            batch.index,
+            batch.size,
+            &batch.path,
+        )?;
+        Ok(batch_message(&root))
+    }
+
+    // every distinct signature is verified once, so the clocks of a batch cost one verification
+    // altogether
+    fn verify_bytes<'a>(
+        &self,
+        batch: impl IntoIterator<Item = (&'a PqClock, &'a [u8], &'a Binding)>,
+    ) -> anyhow::Result<()> {
+        let mut verified = HashSet::new();
+        for (clock, output, binding) in batch {
+            let message = self.signed(clock, output, binding)?;
+            // a failed verification fails the whole batch, so the key is taken up front
+            if !verified.insert((
+                &clock.public_key,
+                &clock.signature,
+                self.crypto.digest(&message),
+            )) {
+                continue;
+            }
+            self.crypto
+                .verify(&clock.public_key, &message, &clock.signature)?
+        }
+        Ok(())
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for PqClientContext<O> {
+    type Clock = PqClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes([(clock, output.as_ref(), binding)])
+            .map_err(Error::ProofInvalid)
+    }
+
+    fn verify_batch(
+        &self,
+        batch: &[VerifyRequest<'_, Self::Clock, Self::Output>],
+    ) -> Result<(), Error> {
+        self.verify_bytes(
+            batch
+                .iter()
+                .map(|(clock, output, binding)| (*clock, output.as_ref(), *binding)),
+        )
+        .map_err(Error::ProofInvalid)
+    }
+}
+
+#[derive(Debug, ClockClientContext)]
+pub struct PqContext<I, O> {
+    id: NodeId,
+    signer: Arc<dyn Signer>,
+    #[client_context]
+    client: PqClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> PqContext<I, O> {
+    pub fn new(
+        id: NodeId,
+        crypto: PqSuite,
+        signer: Arc<dyn Signer>,
+        public_keys: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Self {
+        Self {
+            id,
+            signer,
+            client: PqClientContext::new(crypto, public_keys),
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> PqContext<I, O> {
+    // the clock and the message it is signed over
+    fn unsigned(
+        &self,
+        predecessors: &[Predecessor<'_, PqClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<(OrdinaryClock, Vec<u8>), Error> {
+        self.client
+            .verify_bytes(
+                predecessors
+                    .iter()
+                    .map(|(clock, input, binding)| (*clock, input.as_ref(), *binding)),
+            )
+            .map_err(Error::ProofInvalid)?;
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let message = signed_message(&self.client.crypto, &clock, binding, output.as_ref());
+        Ok((clock, message))
+    }
+
+    // the clocks of the batch and the leaves of the tree over their messages, whose root is signed
+    fn unsigned_batch(
+        &self,
+        batch: &[ProofRequest<'_, PqClock, I, O>],
+    ) -> Result<(Vec<OrdinaryClock>, Vec<Digest>), Error> {
+        let crypto = &self.client.crypto;
+        self.client
+            .verify_bytes(batch.iter().flat_map(|(predecessors, ..)| {
+                predecessors
+                    .iter()
+                    .map(|(clock, input, binding)| (*clock, input.as_ref(), *binding))
+            }))
+            .map_err(Error::ProofInvalid)?;
+        let clocks = batch
+            .iter()
+            .map(|(predecessors, ..)| {
+                OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id)
+            })
+            .collect::<Vec<_>>();
+        let leaves = clocks
+            .iter()
+            .zip(batch)
+            .map(|(clock, (_, output, binding))| {
+                crypto.digest(&signed_message(crypto, clock, binding, output.as_ref()))
+            })
+            .collect::<Vec<_>>();
+        Ok((clocks, leaves))
+    }
+}
+
+fn batch_clocks(
+    crypto: &PqSuite,
+    clocks: Vec<OrdinaryClock>,
+    leaves: &[Digest],
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+) -> Vec<PqClock> {
+    clocks
+        .into_iter()
+        .enumerate()
+        .map(|(index, clock)| PqClock {
+            clock,
+            public_key: public_key.clone(),
+            signature: signature.clone(),
+            batch: Some(BatchOpening {
+                index: index as _,
+                size: leaves.len() as _,
+                path: notary::prove(crypto, leaves, index),
+            }),
+        })
+        .collect()
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for PqContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let (clock, message) = self.unsigned(predecessors, output, binding)?;
+        Ok(PqClock {
+            signature: self.signer.sign(&message).map_err(Error::Proving)?,
+            public_key: self.signer.public_key().to_vec(),
+            clock,
+            batch: None,
+        })
+    }
+
+    // one signature for the whole batch, which is where the time goes, while every clock grows by
+    // its opening of a few digests
+    fn prove_batch(
+        &self,
+        batch: &[ProofRequest<'_, Self::Clock, Self::Input, Self::Output>],
+    ) -> Result<Vec<Self::Clock>, Error> {
+        match batch {
+            [] => return Ok(Vec::new()),
+            [(predecessors, output, binding)] => {
+                return Ok(vec![self.prove(predecessors, output, binding)?])
+            }
+            _ => {}
+        }
+        let (clocks, leaves) = self.unsigned_batch(batch)?;
+        let crypto = &self.client.crypto;
+        let signature = self
+            .signer
+            .sign(&batch_message(&notary::root(crypto, &leaves)))
+            .map_err(Error::Proving)?;
+        let public_key = self.signer.public_key().to_vec();
+        Ok(batch_clocks(crypto, clocks, &leaves, public_key, signature))
+    }
+
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unsigned = self.unsigned(predecessors, output, binding);
+        let signer = self.signer.clone();
+        async move {
+            let (clock, message) = unsigned?;
+            Ok(PqClock {
+                signature: sign_blocking(signer.clone(), message)
+                    .await
+                    .map_err(Error::Proving)?,
+                public_key: signer.public_key().to_vec(),
+                clock,
+                batch: None,
+            })
+        }
+    }
+
+    #[cfg(feature = "network")]
+    fn prove_batch_async(
+        &self,
+        batch: &[ProofRequest<'_, Self::Clock, Self::Input, Self::Output>],
+    ) -> impl Future<Output = Result<Vec<Self::Clock>, Error>> + Send {
+        // as in `prove_batch`, a single clock is signed as if it is proven alone
+        let single = match batch {
+            [(predecessors, output, binding)] => {
+                Some(self.prove_async(predecessors, output, binding))
+            }
+            _ => None,
+        };
+        let unsigned = (batch.len() > 1).then(|| self.unsigned_batch(batch));
+        let crypto = self.client.crypto;
+        let signer = self.signer.clone();
+        async move {
+            if let Some(single) = single {
+                return Ok(vec![single.await?]);
+            }
+            let Some(unsigned) = unsigned else {
+                return Ok(Vec::new());
+            };
+            let (clocks, leaves) = unsigned?;
+            let message = batch_message(&notary::root(&crypto, &leaves));
+            let signature = sign_blocking(signer.clone(), message)
+                .await
+                .map_err(Error::Proving)?;
+            let public_key = signer.public_key().to_vec();
+            Ok(batch_clocks(
+                &crypto, clocks, &leaves, public_key, signature,
+            ))
+        }
+    }
+}
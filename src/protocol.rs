@@ -0,0 +1,153 @@
+// versioning of the messages exchanged through the hub
+// every message carries the version it is encoded in, and every request to the hub carries the
+// version the requester speaks in the `HEADER` header (a requester without the header is assumed
+// to speak version 1, which predates versioning). the hub speaks the current version and the one
+// before it: it up-converts incoming messages of the previous version, and down-converts the
+// messages it fans out to subscribers of the previous version. so a fleet can be upgraded one
+// worker at a time, as long as no more than two versions are ever live at the same time
+// the conversion works on json values, since the rust types of older versions are not kept
+//
+// version history
+// * 1: the initial format
+// * 2: `version` and `workflow` fields added to `TaskStage` and `TaskResult`
+// optional fields that a peer of the same version may safely ignore are added without bumping the
+// version, as long as the peer ignoring them keeps working for the messages it can handle
+// * `chunk` of `TaskStage` and `TaskResult`, only present for streaming tasks, which are only
+//   published once all workers of the workflow are able to handle them
+// * `logs` of `TaskStage` and `TaskResult`
+// * `blob` of `TaskStage` and `TaskResult`, which a peer only sees when a payload exceeds the
+//   inline size limit that all peers of a deployment agree on
+// * `assignee` of `TaskStage`, only stamped when scheduled workers are registered
+// * `handoff` of `TaskStage`, which only the hub acts on
+// * `elapsed` of `TaskStage` and `TaskResult`
+// * `accept_cached` of `TaskStage`, which only the hub acts on
+// * `content_type` of `TaskStage` and `TaskResult`, only present for typed payloads, which are
+//   only exchanged between stages that expect them
+// * `submitter` and `labels` of `TaskStage`, which only the hub acts on
+// * `compression` of `TaskStage` and `TaskResult`, which a peer must not ignore, so a worker only
+//   compresses once enabled by the operator and advertised by the hub in the `Handshake`
+// * `deadline` of `TaskStage`, which only the hub acts on
+// * `expired` of `TaskResult`, which a peer must not ignore, since the record carries no output,
+//   but which only the hub emits, and only for the tasks with a deadline
+// * `routes` of `TaskStage` and `TaskResult`, which a peer must not ignore, but which only appear
+//   for the workflows routing a stage, whose workers must all handle the routing
+// * `hints` of `TaskStage`, only stamped on the messages that are not assigned, which a worker
+//   predating them just executes anyway
+// * `clock_delta` of `TaskStage`, which a peer must not ignore, so a worker only publishes deltas
+//   once enabled by the operator and advertised by the hub in the `Handshake`, see `delta`
+// * `output_digests` of `TaskStage` and `TaskResult`, which a peer must not drop, but which only
+//   appear for the workflows that audit, whose workers must all record them
+// * `branches` of `TaskStage` and `TaskResult`, which a peer must not ignore, but which only
+//   appear for the workflows with a branching stage, whose workers must all follow the branches
+// * `iterations` of `TaskStage` and `TaskResult`, which a peer must not drop, but which only
+//   appear for the workflows with a looping stage, whose workers must all record them
+// * the `SEQ_HEADER` of the response to a publish, which the peers predating it ignore. it is
+//   absent for a message kept already, as well as from a hub predating it
+#[cfg(feature = "network")]
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::compression::{self, Compression};
+
+pub const VERSION: u32 = 2;
+pub const MIN_VERSION: u32 = VERSION - 1;
+pub const HEADER: &str = "pohb-protocol-version";
+// the sequence number of an applied event in the history of the hub, which the hub answers a
+// publish with, see `hub::PublishAck`
+pub const SEQ_HEADER: &str = "pohb-seq";
+
+pub fn default_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub min_version: u32,
+    // the payload compressions the hub handles, none for a hub predating compression
+    #[serde(default)]
+    pub compressions: Vec<Compression>,
+    // whether the hub reconstructs the clocks published as deltas, see `delta`
+    #[serde(default)]
+    pub clock_deltas: bool,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            min_version: MIN_VERSION,
+            compressions: compression::SUPPORTED.to_vec(),
+            clock_deltas: true,
+        }
+    }
+}
+
+pub fn parse_header(value: Option<&str>) -> anyhow::Result<u32> {
+    let version = match value {
+        Some(value) => value.parse()?,
+        None => default_version(),
+    };
+    anyhow::ensure!(
+        (MIN_VERSION..=VERSION).contains(&version),
+        "unsupported protocol version {version}, expect {MIN_VERSION} to {VERSION}"
+    );
+    Ok(version)
+}
+
+// bring a `TaskStage` or `TaskResult` encoded in any supported version to the current version
+pub fn upgrade(message: &mut Value) -> anyhow::Result<()> {
+    let object = message
+        .as_object_mut()
+        .ok_or(anyhow::format_err!("message is not an object"))?;
+    let version = match object.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or(anyhow::format_err!("malformed version"))? as u32,
+        None => default_version(),
+    };
+    match version {
+        VERSION => {}
+        1 => {
+            object.insert("version".into(), VERSION.into());
+        }
+        _ => anyhow::bail!("unsupported protocol version {version}"),
+    }
+    Ok(())
+}
+
+// encode a `TaskStage` or `TaskResult` of the current version for a peer speaking `version`
+pub fn downgrade(message: &mut Value, version: u32) -> anyhow::Result<()> {
+    let object = message
+        .as_object_mut()
+        .ok_or(anyhow::format_err!("message is not an object"))?;
+    match version {
+        VERSION => {}
+        1 => {
+            object.remove("version");
+            object.remove("workflow");
+        }
+        _ => anyhow::bail!("unsupported protocol version {version}"),
+    }
+    Ok(())
+}
+
+// the negotiation step performed by workers and clients before talking to a hub
+#[cfg(feature = "network")]
+pub async fn handshake(client: &Client, hub: &str) -> anyhow::Result<Handshake> {
+    let handshake = client
+        .get(format!("{hub}/protocol"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Handshake>()
+        .await?;
+    anyhow::ensure!(
+        (handshake.min_version..=handshake.version).contains(&VERSION),
+        "hub speaks protocol version {} to {}, but we speak {VERSION}",
+        handshake.min_version,
+        handshake.version
+    );
+    Ok(handshake)
+}
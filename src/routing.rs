@@ -0,0 +1,108 @@
+// routing the messages of a stage to one of its workers by a key derived from the payload, e.g.
+// sharding the tasks across stateful workers that each keep the state of a part of the key space
+// a routed stage has a number of shards, and every worker of it serves one of them (see
+// `Worker::shard`). the shard of a message is the first 8 bytes of the sha-256 digest of its key
+// (big endian) modulo the number of shards, where the key is the whole payload, or the value at a
+// json pointer into the payload decoded as json: a string as its bytes, anything else as its json
+// encoding. the digest is fixed rather than of the crypto suite, so every node agrees on the shards
+// the routing decision is recorded in the message as the route of the stage: the worker of the
+// stage before computes it from its raw output (the hub, from the task input for the first stage),
+// and the worker of the routed stage records the shard it serves as it executes it. the hub checks
+// the route of every gossip message against its payload, and the verification refuses a message
+// routed to a stage, or executed at a shard, other than the decision. as the program versions, the
+// executed shard is merely a claim of the worker under the ordinary clocks
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+
+use crate::{Error, Workflow};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Routing {
+    pub shards: u32,
+    // the json pointer of the key, e.g. `/user/id`. the whole payload if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub shard: u32,
+    // the shard of the worker that executed the stage, `None` until it is executed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executed: Option<u32>,
+}
+
+impl Routing {
+    pub fn validate(&self, stage: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.shards == 0 {
+            problems.push(format!("routing of stage {stage} has no shards"))
+        }
+        if let Some(key) = &self.key {
+            if !key.is_empty() && !key.starts_with('/') {
+                problems.push(format!(
+                    "routing key {key:?} of stage {stage} is not a json pointer"
+                ))
+            }
+        }
+        problems
+    }
+
+    // the shard of the payload, which must be reassembled and decompressed
+    pub fn shard(&self, payload: &[u8]) -> anyhow::Result<u32> {
+        let digest = match &self.key {
+            None => Sha256::digest(payload),
+            Some(pointer) => {
+                let payload = serde_json::from_slice::<Value>(payload)
+                    .map_err(|err| anyhow::format_err!("payload is not json to route by: {err}"))?;
+                let key = payload.pointer(pointer).ok_or(anyhow::format_err!(
+                    "payload has no routing key at {pointer}"
+                ))?;
+                match key {
+                    Value::String(key) => Sha256::digest(key),
+                    key => Sha256::digest(serde_json::to_vec(key)?),
+                }
+            }
+        };
+        let prefix = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        Ok((prefix % u64::from(self.shards.max(1))) as _)
+    }
+}
+
+// every executed routed stage must have been executed at its shard, and a message consumed by
+// routed stages (`next`) must carry the routes of them
+pub(crate) fn verify_routes<C>(
+    clocks: &HashMap<String, C>,
+    routes: &HashMap<String, Route>,
+    next: &[&str],
+    task: &Workflow,
+) -> Result<(), Error> {
+    for (stage, routing) in &task.routing {
+        let executed = clocks.contains_key(stage);
+        if !executed && !next.contains(&stage.as_str()) {
+            continue;
+        }
+        let route = routes.get(stage).ok_or(Error::WorkflowMismatch(format!(
+            "missing route of stage {stage}"
+        )))?;
+        if route.shard >= routing.shards {
+            return Err(Error::WorkflowMismatch(format!(
+                "stage {stage} is routed to shard {} of {}",
+                route.shard, routing.shards
+            )));
+        }
+        if executed && route.executed != Some(route.shard) {
+            return Err(Error::WorkflowMismatch(match route.executed {
+                Some(shard) => format!(
+                    "stage {stage} is executed at shard {shard} instead of {}",
+                    route.shard
+                ),
+                None => format!("missing executed shard of stage {stage}"),
+            }));
+        }
+    }
+    Ok(())
+}
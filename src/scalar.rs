@@ -0,0 +1,119 @@
+// lamport clocks: a single counter, one more than the highest of the predecessors, for the
+// deployments that only need the stages of a task ordered and want the smallest clocks on the
+// wire, a plain number per stage
+// the counters are totally ordered, so a scalar clock cannot tell concurrent stages apart, and it
+// has no entries to tell the producers of the stages by (see `attribution::Causality`): the
+// `paranoid` verification and the attribution fail on these clocks, and there is nothing to prune
+// or compact. like the ordinary clocks they prove nothing about the computation, so they are meant
+// to be combined with trusted hosts
+use std::{marker::PhantomData, sync::OnceLock};
+
+use derive_where::derive_where;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attribution::Causality, Binding, ClockClientContext, ClockContext, Error, OrdinaryClock,
+    Predecessor,
+};
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ScalarClock(pub u64);
+
+impl ScalarClock {
+    // fails on a predecessor at the highest counter, which nothing comes after
+    pub fn new<'a>(deps: impl Iterator<Item = &'a Self>) -> anyhow::Result<Self> {
+        let latest = deps.map(|dep| dep.0).max().unwrap_or_default();
+        Ok(Self(latest.checked_add(1).ok_or(anyhow::format_err!(
+            "clock {latest} cannot be advanced"
+        ))?))
+    }
+}
+
+// no entries at all, see above
+impl Causality for ScalarClock {
+    fn causality(&self) -> &OrdinaryClock {
+        static EMPTY: OnceLock<OrdinaryClock> = OnceLock::new();
+        EMPTY.get_or_init(OrdinaryClock::default)
+    }
+}
+
+#[derive(Debug)]
+#[derive_where(Default)]
+pub struct ScalarClientContext<O>(PhantomData<O>);
+
+impl<O> ScalarClientContext<O> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<O> ClockClientContext for ScalarClientContext<O> {
+    type Clock = ScalarClock;
+    type Output = O;
+
+    fn verify(&self, _: &Self::Clock, _: &Self::Output, _: &Binding) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, ClockClientContext)]
+#[derive_where(Default)]
+pub struct ScalarContext<I, O> {
+    #[client_context]
+    client: ScalarClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> ScalarContext<I, O> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<I, O> ClockContext for ScalarContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        _: &Self::Output,
+        _: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        ScalarClock::new(predecessors.iter().map(|(clock, ..)| *clock)).map_err(Error::Proving)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prove(predecessors: &[ScalarClock]) -> Result<ScalarClock, Error> {
+        let (input, binding) = ((), Binding::new(1, "stage"));
+        let predecessors = predecessors
+            .iter()
+            .map(|clock| (clock, &input, &binding))
+            .collect::<Vec<_>>();
+        ScalarContext::<(), ()>::new().prove(&predecessors, &(), &binding)
+    }
+
+    #[test]
+    fn advances_past_predecessors() {
+        assert_eq!(prove(&[]).unwrap(), ScalarClock(1));
+        assert_eq!(
+            prove(&[ScalarClock(3), ScalarClock(7)]).unwrap(),
+            ScalarClock(8)
+        );
+    }
+
+    // a hostile predecessor at the highest counter
+    #[test]
+    fn refuses_overflow() {
+        assert!(matches!(
+            prove(&[ScalarClock(1), ScalarClock(u64::MAX)]),
+            Err(Error::Proving(_))
+        ));
+    }
+}
@@ -0,0 +1,310 @@
+// what the first stage of a workflow accepts as the task input, which the hub checks as a task is
+// submitted, so a malformed input is refused with a descriptive error up front instead of failing
+// the script of some stage opaquely later
+// the schema is the subset of json schema that covers the common shapes of an input:
+// * `type`, one of `object`, `array`, `string`, `number`, `integer`, `boolean` and `null`, or a
+//   list of them
+// * `enum`, the only values allowed
+// * `properties`, `required` and `additionalProperties` (only `false`, or a schema) of an object
+// * `items`, `minItems` and `maxItems` of an array
+// * `minLength`, `maxLength` of a string and `minimum`, `maximum` of a number
+// any other keyword is refused when the workflow is validated rather than silently ignored, so a
+// schema never checks less than its author expects
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::payload::JSON;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputSpec {
+    // in bytes, of the input as the first stage receives it, i.e. reassembled and decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    // the content type the input must be declared with, see `payload`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // the input is json satisfying the schema, which the input may also be declared as opaque
+    // bytes for, e.g. by the `client` binary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+}
+
+// how an input falls short of the spec, each with the description of the problem
+#[derive(Debug)]
+pub enum Violation {
+    TooLarge(String),
+    ContentType(String),
+    Schema(String),
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge(message) | Self::ContentType(message) | Self::Schema(message) => {
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+const TYPES: [&str; 7] = [
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+const KEYWORDS: [&str; 14] = [
+    "type",
+    "enum",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minLength",
+    "maxLength",
+    "minimum",
+    "maximum",
+    // annotations, which do not constrain anything
+    "title",
+    "description",
+];
+
+impl InputSpec {
+    // the problems of the spec itself, see `Workflow::validate`
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(schema) = &self.schema {
+            check_schema(schema, "input schema", &mut problems);
+            if self
+                .content_type
+                .as_deref()
+                .is_some_and(|content_type| content_type != JSON)
+            {
+                problems.push(format!("input schema requires content type {JSON}"))
+            }
+        }
+        problems
+    }
+
+    // `size` is of the input as submitted, which is checked first so an oversized input is not
+    // reassembled just to be refused
+    pub fn check_size(&self, size: u64) -> Result<(), Violation> {
+        match self.max_size {
+            Some(max_size) if size > max_size => Err(Violation::TooLarge(format!(
+                "input of {size} bytes exceeds the limit of {max_size} bytes of the workflow"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // whether `input` has to be reassembled and decompressed for `check`
+    pub fn inspects_input(&self) -> bool {
+        self.max_size.is_some() || self.schema.is_some()
+    }
+
+    pub fn check(&self, content_type: Option<&str>, input: &[u8]) -> Result<(), Violation> {
+        let fits = match (&self.content_type, &self.schema) {
+            (Some(expected), _) => content_type == Some(expected),
+            (None, Some(_)) => content_type.is_none_or(|content_type| content_type == JSON),
+            (None, None) => true,
+        };
+        if !fits {
+            return Err(Violation::ContentType(format!(
+                "input of content type {content_type:?} is not of {:?}",
+                self.content_type.as_deref().unwrap_or(JSON)
+            )));
+        }
+        self.check_size(input.len() as _)?;
+        if let Some(schema) = &self.schema {
+            let value = serde_json::from_slice::<Value>(input)
+                .map_err(|err| Violation::Schema(format!("input is not json: {err}")))?;
+            matches(schema, &value, "").map_err(Violation::Schema)?
+        }
+        Ok(())
+    }
+}
+
+fn check_schema(schema: &Value, at: &str, problems: &mut Vec<String>) {
+    let Value::Object(schema) = schema else {
+        problems.push(format!("{at} is not an object"));
+        return;
+    };
+    for keyword in schema.keys() {
+        if !KEYWORDS.contains(&keyword.as_str()) {
+            problems.push(format!("{at} has unsupported keyword {keyword}"))
+        }
+    }
+    match schema.get("type") {
+        None => {}
+        Some(Value::String(name)) if TYPES.contains(&name.as_str()) => {}
+        Some(Value::Array(names))
+            if names
+                .iter()
+                .all(|name| name.as_str().is_some_and(|name| TYPES.contains(&name))) => {}
+        Some(_) => problems.push(format!("{at} has an unknown type")),
+    }
+    if schema.get("enum").is_some_and(|values| !values.is_array()) {
+        problems.push(format!("{at} has an enum that is not a list"))
+    }
+    match schema.get("properties") {
+        None => {}
+        Some(Value::Object(properties)) => {
+            for (name, property) in properties {
+                check_schema(property, &format!("{at} property {name}"), problems)
+            }
+        }
+        Some(_) => problems.push(format!("{at} has properties that are not an object")),
+    }
+    if schema.get("required").is_some_and(|required| {
+        required
+            .as_array()
+            .is_none_or(|names| names.iter().any(|name| !name.is_string()))
+    }) {
+        problems.push(format!(
+            "{at} has required properties that are not a list of names"
+        ))
+    }
+    match schema.get("additionalProperties") {
+        None | Some(Value::Bool(false)) => {}
+        Some(additional) => {
+            check_schema(additional, &format!("{at} additional properties"), problems)
+        }
+    }
+    if let Some(items) = schema.get("items") {
+        check_schema(items, &format!("{at} items"), problems)
+    }
+    for bound in ["minItems", "maxItems", "minLength", "maxLength"] {
+        if schema.get(bound).is_some_and(|bound| !bound.is_u64()) {
+            problems.push(format!("{at} has a {bound} that is not a count"))
+        }
+    }
+    for bound in ["minimum", "maximum"] {
+        if schema.get(bound).is_some_and(|bound| !bound.is_number()) {
+            problems.push(format!("{at} has a {bound} that is not a number"))
+        }
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// the schema is assumed to be valid, i.e. to have passed `check_schema`. `at` is the json pointer
+// of the value within the input, for the error
+fn matches(schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+    let empty = Map::new();
+    let schema = schema.as_object().unwrap_or(&empty);
+    let place = if at.is_empty() { "/" } else { at };
+    if let Some(expected) = schema.get("type") {
+        let actual = type_of(value);
+        let allowed = |name: &Value| {
+            name.as_str()
+                .is_some_and(|name| name == actual || (name == "number" && actual == "integer"))
+        };
+        let fits = match expected {
+            Value::Array(names) => names.iter().any(allowed),
+            name => allowed(name),
+        };
+        if !fits {
+            return Err(format!("{place} is {actual}, expected {expected}"));
+        }
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.contains(value) {
+            return Err(format!(
+                "{place} is not one of {}",
+                Value::Array(values.clone())
+            ));
+        }
+    }
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+    match value {
+        Value::Object(object) => {
+            let properties = schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .unwrap_or(&empty);
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !object.contains_key(name) {
+                    return Err(format!("{place} misses the required property {name}"));
+                }
+            }
+            for (name, property) in object {
+                let at = format!("{at}/{}", name.replace('~', "~0").replace('/', "~1"));
+                match (properties.get(name), schema.get("additionalProperties")) {
+                    (Some(property_schema), _) => matches(property_schema, property, &at)?,
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(format!("{place} has the unexpected property {name}"))
+                    }
+                    (None, Some(additional)) => matches(additional, property, &at)?,
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if bound("minItems").is_some_and(|min| (items.len() as u64) < min) {
+                return Err(format!(
+                    "{place} has fewer than {} items",
+                    bound("minItems").unwrap()
+                ));
+            }
+            if bound("maxItems").is_some_and(|max| items.len() as u64 > max) {
+                return Err(format!(
+                    "{place} has more than {} items",
+                    bound("maxItems").unwrap()
+                ));
+            }
+            if let Some(items_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    matches(items_schema, item, &format!("{at}/{index}"))?
+                }
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count() as u64;
+            if bound("minLength").is_some_and(|min| length < min) {
+                return Err(format!(
+                    "{place} is shorter than {} characters",
+                    bound("minLength").unwrap()
+                ));
+            }
+            if bound("maxLength").is_some_and(|max| length > max) {
+                return Err(format!(
+                    "{place} is longer than {} characters",
+                    bound("maxLength").unwrap()
+                ));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+                if number < minimum {
+                    return Err(format!("{place} is less than {minimum}"));
+                }
+            }
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+                if number > maximum {
+                    return Err(format!("{place} is greater than {maximum}"));
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+// per-stage secrets, e.g. the api keys of the external services a stage calls, delivered by the
+// hub to the authorized workers at execution time instead of being baked into every worker host
+// the secrets never appear in the gossip. a worker asks the hub for the secrets of its stage with
+// a request signed by its worker key, along with a fresh x25519 key, and the hub seals the values
+// to that key (x25519, then chacha20-poly1305) if the worker key is among the readers of the
+// stage. so the values stay confidential over a plain http hub url, and a replayed request is
+// answered with values only the original requester can open
+// where the hub takes the secrets from is a `SecretStore`, `DirSecrets` by default. the worker
+// side is a `SecretSource`, which `worker::ScriptExecutor` passes to the script as environment
+// variables, see `HubSecrets`
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    fs,
+    future::Future,
+    io::ErrorKind,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chacha20poly1305::{aead::Aead as _, ChaCha20Poly1305, KeyInit as _, Nonce};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{
+    crypto::CryptoSuite,
+    hex,
+    signer::{sign_blocking, Signer},
+    transport::HubTransport,
+    unhex,
+};
+
+// the secrets of a stage, and the public worker keys allowed to read them
+#[derive(Debug, Clone, Default)]
+pub struct StageSecrets {
+    pub readers: BTreeSet<Vec<u8>>,
+    pub values: BTreeMap<String, String>,
+}
+
+pub trait SecretStore: Debug + Send + Sync {
+    // empty for a stage without secrets
+    fn stage_secrets(&self, stage: &str) -> anyhow::Result<StageSecrets>;
+}
+
+// a directory per stage under the root, holding a file per secret whose name is the name of the
+// secret, and the `readers` file with a hex encoded public worker key per line. the files are read
+// on every request, so a secret is rotated or a worker revoked by editing the files
+#[derive(Debug)]
+pub struct DirSecrets {
+    root: PathBuf,
+}
+
+const READERS_FILE: &str = "readers";
+
+impl DirSecrets {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl SecretStore for DirSecrets {
+    fn stage_secrets(&self, stage: &str) -> anyhow::Result<StageSecrets> {
+        anyhow::ensure!(
+            !stage.is_empty() && !stage.starts_with('.') && !stage.contains(['/', '\\']),
+            "invalid stage name {stage}"
+        );
+        let entries = match fs::read_dir(self.root.join(stage)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Default::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut secrets = StageSecrets::default();
+        for entry in entries {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // hidden files are e.g. the leftovers of editors
+            if name.starts_with('.') || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(entry.path())?;
+            if name == READERS_FILE {
+                for line in content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                {
+                    secrets.readers.insert(unhex(line)?);
+                }
+            } else if name.contains('=') {
+                warn!("skip secret {name} of stage {stage}: not a valid variable name")
+            } else {
+                // the trailing newline editors add is not part of the secret
+                let value = content.strip_suffix('\n').unwrap_or(&content);
+                secrets.values.insert(name, value.into());
+            }
+        }
+        Ok(secrets)
+    }
+}
+
+// what a worker sends to `POST /secrets`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretRequest {
+    pub stage: String,
+    // the public worker key, checked against the readers of the stage
+    pub public_key: Vec<u8>,
+    // the x25519 key the values are sealed to, fresh for every request
+    pub ephemeral_key: [u8; 32],
+    // of `signed_message`, with the worker key
+    pub signature: Vec<u8>,
+}
+
+// the values, encrypted with the key agreed between the ephemeral keys of the hub and the worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedSecrets {
+    pub ephemeral_key: [u8; 32],
+    pub nonce: [u8; 12],
+    pub ciphertext: Vec<u8>,
+}
+
+const SIGNING_CONTEXT: &[u8] = b"pohb-secrets-request";
+
+const KEY_CONTEXT: &str = "pohb secrets 2024-06 sealing key";
+
+fn signed_message(stage: &str, ephemeral_key: &[u8; 32]) -> Vec<u8> {
+    [SIGNING_CONTEXT, stage.as_bytes(), &[0], ephemeral_key].concat()
+}
+
+// bound to both public keys, so the ciphertext cannot be passed off as sealed to another key
+fn cipher(shared: &[u8; 32], hub_key: &[u8; 32], worker_key: &[u8; 32]) -> ChaCha20Poly1305 {
+    let key = blake3::derive_key(KEY_CONTEXT, &[&shared[..], hub_key, worker_key].concat());
+    ChaCha20Poly1305::new(&key.into())
+}
+
+// the hub side of a request, refused unless it is signed by one of the readers of the stage. a
+// stage without secrets has no readers, and any worker gets the empty values
+pub fn seal(
+    store: &dyn SecretStore,
+    crypto: &dyn CryptoSuite,
+    request: &SecretRequest,
+) -> anyhow::Result<SealedSecrets> {
+    crypto.verify(
+        &request.public_key,
+        &signed_message(&request.stage, &request.ephemeral_key),
+        &request.signature,
+    )?;
+    let secrets = store.stage_secrets(&request.stage)?;
+    anyhow::ensure!(
+        secrets.values.is_empty() || secrets.readers.contains(&request.public_key),
+        "worker key {} is not a reader of the secrets of stage {}",
+        hex(&request.public_key),
+        request.stage
+    );
+    let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+    let ephemeral_key = PublicKey::from(&secret).to_bytes();
+    let shared = secret.diffie_hellman(&PublicKey::from(request.ephemeral_key));
+    let nonce = rand::random::<[u8; 12]>();
+    let ciphertext = cipher(shared.as_bytes(), &ephemeral_key, &request.ephemeral_key)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            &*serde_json::to_vec(&secrets.values)?,
+        )
+        .map_err(|_| anyhow::format_err!("failed to seal secrets"))?;
+    Ok(SealedSecrets {
+        ephemeral_key,
+        nonce,
+        ciphertext,
+    })
+}
+
+pub type Secrets = Arc<BTreeMap<String, String>>;
+
+// where an executor takes the secrets of its stage from, right before each execution
+pub trait SecretSource: Debug + Send + Sync {
+    fn secrets(&self) -> Pin<Box<dyn Future<Output = anyhow::Result<Secrets>> + Send + '_>>;
+}
+
+// fetched values are reused for this long, so a rotation reaches the workers within it
+pub const SECRETS_TTL: Duration = Duration::from_secs(60);
+
+// fetches the secrets of the stage from the hub, as a reader with the key of the signer
+#[derive(Debug)]
+pub struct HubSecrets<T> {
+    transport: T,
+    stage: String,
+    signer: Arc<dyn Signer>,
+    cached: Mutex<Option<(Instant, Secrets)>>,
+}
+
+impl<T> HubSecrets<T> {
+    pub fn new(transport: T, stage: impl Into<String>, signer: Arc<dyn Signer>) -> Self {
+        Self {
+            transport,
+            stage: stage.into(),
+            signer,
+            cached: Default::default(),
+        }
+    }
+}
+
+impl<T: HubTransport> HubSecrets<T> {
+    async fn fetch(&self) -> anyhow::Result<Secrets> {
+        let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+        let ephemeral_key = PublicKey::from(&secret).to_bytes();
+        let message = signed_message(&self.stage, &ephemeral_key);
+        let request = SecretRequest {
+            stage: self.stage.clone(),
+            public_key: self.signer.public_key().to_vec(),
+            ephemeral_key,
+            signature: sign_blocking(self.signer.clone(), message).await?,
+        };
+        let sealed = self.transport.stage_secrets(&request).await?;
+        let shared = secret.diffie_hellman(&PublicKey::from(sealed.ephemeral_key));
+        let plaintext = cipher(shared.as_bytes(), &sealed.ephemeral_key, &ephemeral_key)
+            .decrypt(Nonce::from_slice(&sealed.nonce), &*sealed.ciphertext)
+            .map_err(|_| anyhow::format_err!("failed to open secrets"))?;
+        let values = serde_json::from_slice::<BTreeMap<String, String>>(&plaintext)?;
+        // only the names, the values are never logged
+        info!(
+            "fetched secrets {:?} of stage {}",
+            values.keys().collect::<Vec<_>>(),
+            self.stage
+        );
+        Ok(Arc::new(values))
+    }
+}
+
+impl<T: HubTransport + Debug> SecretSource for HubSecrets<T> {
+    fn secrets(&self) -> Pin<Box<dyn Future<Output = anyhow::Result<Secrets>> + Send + '_>> {
+        Box::pin(async move {
+            let mut cached = self.cached.lock().await;
+            if let Some((fetched, secrets)) = &*cached {
+                if fetched.elapsed() < SECRETS_TTL {
+                    return Ok(secrets.clone());
+                }
+            }
+            let secrets = self.fetch().await?;
+            *cached = Some((Instant::now(), secrets.clone()));
+            Ok(secrets)
+        })
+    }
+}
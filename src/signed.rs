@@ -0,0 +1,179 @@
+// ed25519 signed clocks, the simplest clocks that actually prove anything: unlike the ordinary
+// context, which takes every clock as is, the verifier only accepts the clocks signed by the
+// producers it knows, e.g. the registered workers
+// the causality part is an ordinary clock, and the proof part is the producer's signature over the
+// causality part, the stage it is produced for and the digest of the output, so neither can be
+// altered, nor the clock be reused for another output or stage. see `pq` for the same with
+// post-quantum signatures
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "network")]
+use crate::signer::sign_blocking;
+use crate::{
+    crypto::{CryptoSuite as _, HashAlgorithm, SignatureAlgorithm, StandardSuite},
+    signer::Signer,
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct SignedClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+fn signed_message(
+    crypto: &StandardSuite,
+    clock: &OrdinaryClock,
+    binding: &Binding,
+    output: &[u8],
+) -> Vec<u8> {
+    let mut message = clock.encode();
+    message.extend(binding.encode());
+    message.extend(crypto.digest(output));
+    message
+}
+
+#[derive(Debug)]
+pub struct SignedClientContext<O> {
+    crypto: StandardSuite,
+    public_keys: HashSet<Vec<u8>>,
+    _output: PhantomData<O>,
+}
+
+impl<O> SignedClientContext<O> {
+    // the output is digested with `hash` for signing
+    pub fn new(hash: HashAlgorithm, public_keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            crypto: StandardSuite {
+                hash,
+                signature: SignatureAlgorithm::Ed25519,
+            },
+            public_keys: public_keys.into_iter().collect(),
+            _output: PhantomData,
+        }
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &SignedClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.public_keys.contains(&clock.public_key),
+            "clock is signed by unknown key"
+        );
+        self.crypto.verify(
+            &clock.public_key,
+            &signed_message(&self.crypto, &clock.clock, binding, output),
+            &clock.signature,
+        )
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for SignedClientContext<O> {
+    type Clock = SignedClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+// the signer must hold an ed25519 key, whose public key is usually among the known ones, so the
+// clocks of this producer pass the verification of the others
+#[derive(Debug, ClockClientContext)]
+pub struct SignedContext<I, O> {
+    id: NodeId,
+    signer: Arc<dyn Signer>,
+    #[client_context]
+    client: SignedClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> SignedContext<I, O> {
+    pub fn new(
+        id: NodeId,
+        hash: HashAlgorithm,
+        signer: Arc<dyn Signer>,
+        public_keys: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Self {
+        Self {
+            id,
+            signer,
+            client: SignedClientContext::new(hash, public_keys),
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> SignedContext<I, O> {
+    // the clock and the message it is signed over
+    fn unsigned(
+        &self,
+        predecessors: &[Predecessor<'_, SignedClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<(OrdinaryClock, Vec<u8>), Error> {
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let message = signed_message(&self.client.crypto, &clock, binding, output.as_ref());
+        Ok((clock, message))
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for SignedContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let (clock, message) = self.unsigned(predecessors, output, binding)?;
+        Ok(SignedClock {
+            signature: self.signer.sign(&message).map_err(Error::Proving)?,
+            public_key: self.signer.public_key().to_vec(),
+            clock,
+        })
+    }
+
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unsigned = self.unsigned(predecessors, output, binding);
+        let signer = self.signer.clone();
+        async move {
+            let (clock, message) = unsigned?;
+            Ok(SignedClock {
+                signature: sign_blocking(signer.clone(), message)
+                    .await
+                    .map_err(Error::Proving)?,
+                public_key: signer.public_key().to_vec(),
+                clock,
+            })
+        }
+    }
+}
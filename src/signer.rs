@@ -0,0 +1,206 @@
+// where the signing key of a clock producer lives
+// a proof-carrying clock context never touches the key itself but asks a `Signer`, so production
+// workers can keep their keys in a hardware security module (PKCS#11, with the `pkcs11` feature)
+// or a hardware wallet driven by its companion program, instead of on the disk of the worker host
+// signing with hardware can be slow. the signing contexts (`signed` and `pq`) sign on a blocking
+// thread when the worker awaits their proving (`ClockContext::prove_async`), so the worker goes on
+// with other tasks meanwhile
+use std::{
+    fmt::Debug,
+    io::Write as _,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use crate::crypto::CryptoSuite;
+
+pub trait Signer: Debug + Send + Sync {
+    fn public_key(&self) -> &[u8];
+
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+// on a blocking thread of the runtime, see above
+#[cfg(feature = "network")]
+pub async fn sign_blocking(signer: Arc<dyn Signer>, message: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || signer.sign(&message)).await?
+}
+
+// the key is held in memory, which is what to use for development and testing
+#[derive(Debug)]
+pub struct LocalSigner {
+    crypto: Arc<dyn CryptoSuite>,
+    secret_key: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl LocalSigner {
+    pub fn new(crypto: Arc<dyn CryptoSuite>, secret_key: Vec<u8>) -> anyhow::Result<Self> {
+        Ok(Self {
+            public_key: crypto.public_key(&secret_key)?,
+            crypto,
+            secret_key,
+        })
+    }
+}
+
+impl Signer for LocalSigner {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.crypto.sign(&self.secret_key, message)
+    }
+}
+
+// the signing is delegated to an external program, which reads the message from stdin and writes
+// the signature to stdout. this is how hardware wallets are usually driven, and the program is
+// expected to take care of the device interaction, e.g. asking the operator for confirmation
+// the public key is configured along with the program, since it is needed for every clock and
+// querying the device each time would be too slow
+#[derive(Debug)]
+pub struct CommandSigner {
+    program: PathBuf,
+    args: Vec<String>,
+    public_key: Vec<u8>,
+}
+
+impl CommandSigner {
+    pub fn new(program: impl Into<PathBuf>, args: Vec<String>, public_key: Vec<u8>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+            public_key,
+        }
+    }
+}
+
+impl Signer for CommandSigner {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(message)?;
+        let output = child.wait_with_output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "signer exits with {}",
+            output.status
+        );
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11Signer;
+
+#[cfg(feature = "pkcs11")]
+mod pkcs11 {
+    use std::{path::Path, sync::Mutex};
+
+    use cryptoki::{
+        context::{CInitializeArgs, Pkcs11},
+        mechanism::Mechanism,
+        object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+        session::{Session, UserType},
+        types::AuthPin,
+    };
+    use sha2::{Digest as _, Sha256};
+
+    use crate::crypto::SignatureAlgorithm;
+
+    use super::Signer;
+
+    // signs with a key pair stored in a token, which is found by its label
+    // the signatures are made compatible with the ones of `StandardSuite`: ed25519 signs the message
+    // as is, while secp256k1 signs the sha256 prehash with the low-s normalized form
+    #[derive(Debug)]
+    pub struct Pkcs11Signer {
+        session: Mutex<Session>,
+        key: ObjectHandle,
+        algorithm: SignatureAlgorithm,
+        public_key: Vec<u8>,
+    }
+
+    impl Pkcs11Signer {
+        pub fn new(
+            module: impl AsRef<Path>,
+            token_label: &str,
+            pin: &str,
+            key_label: &str,
+            algorithm: SignatureAlgorithm,
+        ) -> anyhow::Result<Self> {
+            let pkcs11 = Pkcs11::new(module)?;
+            pkcs11.initialize(CInitializeArgs::OsThreads)?;
+            let mut slot = None;
+            for other_slot in pkcs11.get_slots_with_token()? {
+                if pkcs11.get_token_info(other_slot)?.label() == token_label {
+                    slot = Some(other_slot);
+                    break;
+                }
+            }
+            let slot = slot.ok_or(anyhow::format_err!("no token labeled {token_label}"))?;
+            let session = pkcs11.open_ro_session(slot)?;
+            session.login(UserType::User, Some(&AuthPin::new(pin.into())))?;
+            let find = |class| {
+                session
+                    .find_objects(&[
+                        Attribute::Class(class),
+                        Attribute::Label(key_label.as_bytes().to_vec()),
+                    ])?
+                    .into_iter()
+                    .next()
+                    .ok_or(anyhow::format_err!("no key labeled {key_label}"))
+            };
+            let key = find(ObjectClass::PRIVATE_KEY)?;
+            let public_key = find(ObjectClass::PUBLIC_KEY)?;
+            let Some(Attribute::EcPoint(point)) = session
+                .get_attributes(public_key, &[AttributeType::EcPoint])?
+                .pop()
+            else {
+                anyhow::bail!("public key has no ec point")
+            };
+            // the point is a DER octet string, which some modules omit for ed25519 keys
+            let public_key = match (algorithm, &*point) {
+                (SignatureAlgorithm::Ed25519, [0x04, 0x20, point @ ..])
+                | (SignatureAlgorithm::Secp256k1, [0x04, 0x41, point @ ..]) => point.to_vec(),
+                _ => point,
+            };
+            Ok(Self {
+                session: Mutex::new(session),
+                key,
+                algorithm,
+                public_key,
+            })
+        }
+    }
+
+    impl Signer for Pkcs11Signer {
+        fn public_key(&self) -> &[u8] {
+            &self.public_key
+        }
+
+        fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+            let session = self.session.lock().unwrap();
+            Ok(match self.algorithm {
+                SignatureAlgorithm::Ed25519 => {
+                    session.sign(&Mechanism::Eddsa, self.key, message)?
+                }
+                SignatureAlgorithm::Secp256k1 => {
+                    let signature =
+                        session.sign(&Mechanism::Ecdsa, self.key, &Sha256::digest(message))?;
+                    let signature = k256::ecdsa::Signature::from_slice(&signature)?;
+                    signature.normalize_s().unwrap_or(signature).to_vec()
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// running a whole workflow within one process, with an in-memory hub and a worker for every stage
+// driven through `InMemoryTransport`, as the `dev` and the `examples` binaries do. nothing is
+// deployed or kept, and every task goes through the same hub, workers, blobs and verification as in
+// a deployment, only without sockets
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use tokio_stream::StreamExt as _;
+use tracing::warn;
+
+use crate::{
+    blob::{self, BlobStore, MemoryBlobStore, DEFAULT_MAX_BLOB_SIZE, DEFAULT_MAX_INLINE_SIZE},
+    crypto::CryptoSuite,
+    hub::{Hub, Store},
+    transport::{HubTransport as _, InMemoryTransport},
+    worker::{ScriptExecutor, StageExecutor, Worker},
+    OrdinaryClock, OrdinaryContext, TaskId, TaskResult, TaskStage, Workflow,
+};
+
+pub struct Simulation {
+    task: Workflow,
+    hub: Hub,
+    blobs: Arc<MemoryBlobStore>,
+    transport: InMemoryTransport,
+}
+
+impl Simulation {
+    pub async fn new(task: Workflow, crypto: Arc<dyn CryptoSuite>) -> anyhow::Result<Self> {
+        let blobs = Arc::new(MemoryBlobStore::default());
+        let hub = Hub::builder()
+            .workflow(task.clone())
+            .store(Store::Local)
+            .crypto(crypto)
+            .blobs(blobs.clone())
+            .build()
+            .await?;
+        let transport = InMemoryTransport::new(&hub);
+        Ok(Self {
+            task,
+            hub,
+            blobs,
+            transport,
+        })
+    }
+
+    pub fn blobs(&self) -> &dyn BlobStore {
+        &*self.blobs
+    }
+
+    // returns once the worker of the stage is subscribed. the worker keeps running along with the
+    // runtime
+    pub async fn spawn(
+        &self,
+        stage: &str,
+        executor: impl StageExecutor + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        let context = OrdinaryContext::<Bytes, Bytes>::new(rand::random());
+        let worker = Arc::new(Worker::new(
+            self.task.clone(),
+            stage,
+            executor,
+            context,
+            InMemoryTransport::new(&self.hub),
+        )?);
+        let stage = stage.to_string();
+        let running = worker.clone();
+        tokio::spawn(async move {
+            if let Err(err) = running.run().await {
+                warn!("worker of stage {stage} stopped: {err}")
+            }
+        });
+        worker.subscribed().await;
+        Ok(())
+    }
+
+    // the result of a task of the input, with its output reassembled and decompressed. an input
+    // beyond the inline size limit is offloaded, as an output would be. a failing stage is only
+    // logged by its worker, and the task then never finishes
+    pub async fn run(
+        &self,
+        id: TaskId,
+        input: Bytes,
+    ) -> anyhow::Result<TaskResult<OrdinaryClock, Bytes>> {
+        let mut chain = self
+            .transport
+            .subscribe_chain::<TaskResult<OrdinaryClock, Bytes>>()
+            .await?;
+        let mut start = TaskStage::<OrdinaryClock, _>::start(id, &input)?;
+        if input.len() > DEFAULT_MAX_INLINE_SIZE {
+            start.blob = Some(blob::offload(&self.transport, &input).await?);
+            start.input = Bytes::new()
+        }
+        self.transport.publish_gossip(&start).await?;
+        let mut result = loop {
+            let Some(result) = chain.next().await else {
+                anyhow::bail!("event source exhausted before task finished")
+            };
+            let result = result?;
+            if result.id == id {
+                break result;
+            }
+        };
+        if let Some(blob) = result.blob.take() {
+            result.output = blob::reassemble(&self.transport, &blob, DEFAULT_MAX_BLOB_SIZE).await?
+        }
+        result.decompress(DEFAULT_MAX_BLOB_SIZE)?;
+        Ok(result)
+    }
+}
+
+// an example pipeline, i.e. a directory of its workflow description `task.json` (or `task.yaml`,
+// `task.yml`, `task.toml`, see `Workflow::from_path`), the `input` and the `expect`ed output, and a
+// program of every stage: `modules/<stage>.wasm`, executed with the `wasm` feature, or else
+// `scripts/<stage>`. see the `pipelines` binary
+pub struct Pipeline {
+    pub task: Workflow,
+    pub input: Bytes,
+    pub expect: Bytes,
+    dir: PathBuf,
+}
+
+impl Pipeline {
+    // the workflow description in the directory, in the first of the formats found
+    pub fn description(dir: &Path) -> Option<PathBuf> {
+        ["task.json", "task.yaml", "task.yml", "task.toml"]
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|description| description.exists())
+    }
+
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let dir = dir.canonicalize()?;
+        let description = Self::description(&dir).ok_or(anyhow::format_err!(
+            "no workflow description in {}",
+            dir.display()
+        ))?;
+        let task = Workflow::from_path(description)?;
+        let problems = task.validate();
+        anyhow::ensure!(
+            problems.is_empty(),
+            "invalid workflow: {}",
+            problems.join(", ")
+        );
+        Ok(Self {
+            task,
+            input: fs::read(dir.join("input"))?.into(),
+            expect: fs::read(dir.join("expect"))?.into(),
+            dir,
+        })
+    }
+
+    // the result of the pipeline end to end, as `Simulation::run` has it. `None` if a stage is a
+    // module, without the `wasm` feature to execute it
+    pub async fn run(
+        &self,
+        crypto: Arc<dyn CryptoSuite>,
+    ) -> anyhow::Result<Option<TaskResult<OrdinaryClock, Bytes>>> {
+        let (modules, scripts) = (self.dir.join("modules"), self.dir.join("scripts"));
+        let simulation = Simulation::new(self.task.clone(), crypto.clone()).await?;
+        for stage in &self.task.stages {
+            if modules.join(format!("{stage}.wasm")).exists() {
+                #[cfg(not(feature = "wasm"))]
+                return Ok(None);
+                #[cfg(feature = "wasm")]
+                {
+                    let executor = crate::wasm::WasmExecutor::new(&modules, stage, crypto.clone());
+                    simulation.spawn(stage, executor).await?;
+                    continue;
+                }
+            }
+            let executor = ScriptExecutor::new(&scripts, stage, crypto.clone());
+            simulation.spawn(stage, executor).await?
+        }
+        let result = simulation.run(rand::random(), self.input.clone()).await?;
+        Ok(Some(result))
+    }
+}
@@ -0,0 +1,466 @@
+// clocks proven by a zk-snark: the proof part is a groth16 proof (over bn254) that the circuit of
+// the stage maps the inputs to the output, so anyone holding the verifying key checks a clock
+// without re-executing the stage, and without trusting the producer, a hardware vendor or a
+// committee. the proof is 128 bytes and verifies in a few milliseconds whatever the stage computes
+// the statement of the proof is fixed here, as public inputs in this order: the binding of the
+// clock, i.e. the digest of the causality part, of the digests of the predecessor clocks and of the
+// stage the clock is produced for, the digest of every input in the order of the predecessors, and
+// the digest of the output. the computation of the stage is a `StageCircuit`, which is handed the
+// digests as allocated bytes and must constrain them to the digests (with the hash of the client
+// context) of the witness inputs and output, e.g. with the sha256 gadget of
+// `ark-crypto-primitives`. the binding is not for the circuit to use, it is bound to the proof by
+// the input consistency constraints groth16 adds for every public input
+// a circuit is identified by the digest of its verifying key, which the clock carries. the client
+// context holds the verifying key of every stage by its name, and verifies a clock with the key of
+// the stage it is bound to, so the circuit of one stage cannot prove the output of another. the
+// keys come from a circuit specific setup, see `setup`, whose randomness must be discarded (or
+// taken from a multi-party ceremony), as whoever keeps it can prove anything
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{collections::HashMap, fmt::Debug, marker::PhantomData, sync::Arc};
+
+use ark_bn254::{Bn254, Fr};
+use ark_ff::ToConstraintField;
+use ark_groth16::{Groth16, PreparedVerifyingKey};
+use ark_r1cs_std::uint8::UInt8;
+use ark_relations::r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError,
+};
+use ark_serialize::{CanonicalDeserialize as _, CanonicalSerialize as _};
+use ark_snark::SNARK as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{Digest, HashAlgorithm},
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+pub type ProvingKey = ark_groth16::ProvingKey<Bn254>;
+pub type VerifyingKey = ark_groth16::VerifyingKey<Bn254>;
+
+// the public digests as the circuit sees them, 32 bytes each
+#[derive(Debug)]
+pub struct Statement {
+    pub inputs: Vec<Vec<UInt8<Fr>>>,
+    pub output: Vec<UInt8<Fr>>,
+}
+
+// the private part, absent during the setup
+#[derive(Debug, Clone, Copy)]
+pub struct Witness<'a> {
+    pub inputs: &'a [Vec<u8>],
+    pub output: &'a [u8],
+}
+
+// the computation of a stage as constraints. the shape of the constraint system must not depend on
+// the witness, so a circuit usually fixes the sizes of the payloads it accepts
+pub trait StageCircuit: Debug + Send + Sync {
+    // the number of predecessors the stage executes upon, i.e. of the input digests
+    fn inputs(&self) -> usize;
+
+    fn generate_constraints(
+        &self,
+        cs: ConstraintSystemRef<Fr>,
+        statement: &Statement,
+        witness: Option<Witness<'_>>,
+    ) -> Result<(), SynthesisError>;
+}
+
+// a stage execution as the constraint synthesizer of groth16, with the public inputs allocated
+// ahead of the circuit of the stage
+struct Execution<'a> {
+    circuit: &'a dyn StageCircuit,
+    // the binding, the input digests and the output digest, zero during the setup
+    public: Vec<u8>,
+    witness: Option<Witness<'a>>,
+}
+
+impl ConstraintSynthesizer<Fr> for Execution<'_> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        // each digest is packed on its own, matching `public_inputs`
+        let mut digests = self
+            .public
+            .chunks(32)
+            .map(|digest| UInt8::new_input_vec(cs.clone(), digest))
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = digests.pop().ok_or(SynthesisError::AssignmentMissing)?;
+        let statement = Statement {
+            inputs: digests.split_off(1),
+            output,
+        };
+        self.circuit
+            .generate_constraints(cs, &statement, self.witness)
+    }
+}
+
+fn public_inputs(public: &[u8]) -> anyhow::Result<Vec<Fr>> {
+    let mut inputs = Vec::new();
+    for digest in public.chunks(32) {
+        inputs.extend(
+            ToConstraintField::<Fr>::to_field_elements(digest)
+                .ok_or(anyhow::format_err!("failed to pack public inputs"))?,
+        )
+    }
+    Ok(inputs)
+}
+
+// the keys of the circuit, from fresh randomness that is dropped right after
+pub fn setup(circuit: &dyn StageCircuit) -> anyhow::Result<(ProvingKey, VerifyingKey)> {
+    let execution = Execution {
+        circuit,
+        public: vec![0; 32 * (circuit.inputs() + 2)],
+        witness: None,
+    };
+    Ok(Groth16::<Bn254>::circuit_specific_setup(
+        execution,
+        &mut rand::rngs::OsRng,
+    )?)
+}
+
+// of the compressed encoding of the verifying key
+pub fn circuit_id(hash: HashAlgorithm, key: &VerifyingKey) -> anyhow::Result<Digest> {
+    let mut bytes = Vec::new();
+    key.serialize_compressed(&mut bytes)?;
+    Ok(hash.digest(&bytes))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct SnarkClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    // of the verifying key of the circuit, see `circuit_id`
+    pub circuit: Digest,
+    // of the clocks of the inputs, in the order of the predecessors (see `clock_digest`)
+    pub predecessors: Vec<Digest>,
+    // of the inputs, in the same order. a verifier holding the clocks of the predecessors checks
+    // them as the outputs of those, which proves the lineage up to the first stage
+    pub inputs: Vec<Digest>,
+    // the compressed groth16 proof
+    pub proof: Vec<u8>,
+}
+
+// of the whole clock, i.e. along with its proof
+fn clock_digest(hash: HashAlgorithm, clock: &SnarkClock) -> Digest {
+    let mut message = clock.clock.encode();
+    message.extend(clock.circuit);
+    message.extend(&clock.proof);
+    hash.digest(&message)
+}
+
+fn binding(
+    hash: HashAlgorithm,
+    clock: &OrdinaryClock,
+    predecessors: &[Digest],
+    stage: &Binding,
+) -> Digest {
+    let mut message = clock.encode();
+    message.extend((predecessors.len() as u32).to_be_bytes());
+    for digest in predecessors {
+        message.extend(digest)
+    }
+    message.extend(stage.encode());
+    hash.digest(&message)
+}
+
+fn public(hash: HashAlgorithm, binding: Digest, inputs: &[Digest], output: &[u8]) -> Vec<u8> {
+    let mut public = binding.to_vec();
+    for digest in inputs {
+        public.extend(digest)
+    }
+    public.extend(hash.digest(output));
+    public
+}
+
+#[derive(Debug)]
+pub struct SnarkClientContext<O> {
+    hash: HashAlgorithm,
+    // by the stage, along with the id of the circuit
+    keys: HashMap<String, (Digest, PreparedVerifyingKey<Bn254>)>,
+    _output: PhantomData<O>,
+}
+
+impl<O> SnarkClientContext<O> {
+    // the verifying keys of the circuits of the stages whose clocks are accepted, by the stage
+    pub fn new(
+        hash: HashAlgorithm,
+        keys: impl IntoIterator<Item = (String, VerifyingKey)>,
+    ) -> anyhow::Result<Self> {
+        let keys = keys
+            .into_iter()
+            .map(|(stage, key)| Ok((stage, (circuit_id(hash, &key)?, key.into()))))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            hash,
+            keys,
+            _output: PhantomData,
+        })
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &SnarkClock,
+        output: &[u8],
+        stage: &Binding,
+    ) -> anyhow::Result<()> {
+        let (circuit, key) = self
+            .keys
+            .get(&stage.stage)
+            .ok_or(anyhow::format_err!("no circuit of stage {}", stage.stage))?;
+        anyhow::ensure!(
+            clock.circuit == *circuit,
+            "clock is not proven by the circuit of stage {}",
+            stage.stage
+        );
+        anyhow::ensure!(
+            clock.inputs.len() == clock.predecessors.len(),
+            "{} input digests for {} predecessors",
+            clock.inputs.len(),
+            clock.predecessors.len()
+        );
+        let binding = binding(self.hash, &clock.clock, &clock.predecessors, stage);
+        let public = public(self.hash, binding, &clock.inputs, output);
+        let proof = ark_groth16::Proof::deserialize_compressed(&*clock.proof)?;
+        anyhow::ensure!(
+            Groth16::<Bn254>::verify_with_processed_vk(key, &public_inputs(&public)?, &proof)?,
+            "proof does not prove the clock and the output"
+        );
+        Ok(())
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for SnarkClientContext<O> {
+    type Clock = SnarkClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        stage: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), stage)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+// proves the executions of one stage, whose verifying key is usually among the ones of the client
+// context, so the clocks of this producer pass the verification of the others
+#[derive(Debug, ClockClientContext)]
+pub struct SnarkContext<I, O> {
+    id: NodeId,
+    circuit: Arc<dyn StageCircuit>,
+    key: Arc<ProvingKey>,
+    circuit_id: Digest,
+    #[client_context]
+    client: SnarkClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> SnarkContext<I, O> {
+    pub fn new(
+        id: NodeId,
+        circuit: Arc<dyn StageCircuit>,
+        key: Arc<ProvingKey>,
+        client: SnarkClientContext<O>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            id,
+            circuit_id: circuit_id(client.hash, &key.vk)?,
+            circuit,
+            key,
+            client,
+            _input: PhantomData,
+        })
+    }
+}
+
+// what is proven, along with the witness
+struct Unproven {
+    clock: OrdinaryClock,
+    predecessors: Vec<Digest>,
+    inputs: Vec<Digest>,
+    public: Vec<u8>,
+    witness: (Vec<Vec<u8>>, Vec<u8>),
+}
+
+impl Unproven {
+    fn prove(
+        self,
+        circuit: &dyn StageCircuit,
+        key: &ProvingKey,
+        circuit_id: Digest,
+    ) -> anyhow::Result<SnarkClock> {
+        anyhow::ensure!(
+            self.inputs.len() == circuit.inputs(),
+            "circuit takes {} inputs, not {}",
+            circuit.inputs(),
+            self.inputs.len()
+        );
+        let (inputs, output) = &self.witness;
+        let execution = || Execution {
+            circuit,
+            public: self.public.clone(),
+            witness: Some(Witness { inputs, output }),
+        };
+        // groth16 does not check that the witness satisfies the circuit, it just yields a proof
+        // that does not verify (or panics in a debug build)
+        let cs = ConstraintSystem::new_ref();
+        execution().generate_constraints(cs.clone())?;
+        anyhow::ensure!(
+            cs.is_satisfied()?,
+            "output is not the one the circuit computes from the inputs"
+        );
+        let proof = Groth16::<Bn254>::prove(key, execution(), &mut rand::rngs::OsRng)?;
+        let mut bytes = Vec::new();
+        proof.serialize_compressed(&mut bytes)?;
+        Ok(SnarkClock {
+            clock: self.clock,
+            circuit: circuit_id,
+            predecessors: self.predecessors,
+            inputs: self.inputs,
+            proof: bytes,
+        })
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> SnarkContext<I, O> {
+    fn unproven(
+        &self,
+        predecessors: &[Predecessor<'_, SnarkClock, I>],
+        output: &O,
+        stage: &Binding,
+    ) -> Result<Unproven, Error> {
+        for (clock, input, stage) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), stage)
+                .map_err(Error::ProofInvalid)?
+        }
+        let hash = self.client.hash;
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let digests = predecessors
+            .iter()
+            .map(|(clock, ..)| clock_digest(hash, clock))
+            .collect::<Vec<_>>();
+        let inputs = predecessors
+            .iter()
+            .map(|(_, input, _)| hash.digest(input.as_ref()))
+            .collect::<Vec<_>>();
+        let public = public(
+            hash,
+            binding(hash, &clock, &digests, stage),
+            &inputs,
+            output.as_ref(),
+        );
+        let witness = (
+            predecessors
+                .iter()
+                .map(|(_, input, _)| input.as_ref().to_vec())
+                .collect(),
+            output.as_ref().to_vec(),
+        );
+        Ok(Unproven {
+            clock,
+            predecessors: digests,
+            inputs,
+            public,
+            witness,
+        })
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for SnarkContext<I, O> {
+    type Input = I;
+
+    // fails rather than yielding a clock that does not verify if the output is not the one the
+    // circuit computes from the inputs
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        stage: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        self.unproven(predecessors, output, stage)?
+            .prove(&*self.circuit, &self.key, self.circuit_id)
+            .map_err(Error::Proving)
+    }
+
+    // proving takes seconds of cpu for circuits of a useful size, so it is done on a blocking
+    // thread
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        stage: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unproven = self.unproven(predecessors, output, stage);
+        let (circuit, key, circuit_id) = (self.circuit.clone(), self.key.clone(), self.circuit_id);
+        async move {
+            let unproven = unproven?;
+            tokio::task::spawn_blocking(move || unproven.prove(&*circuit, &key, circuit_id))
+                .await
+                .map_err(|err| Error::Proving(err.into()))?
+                .map_err(Error::Proving)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // constrains nothing beyond the statement, so it proves any output
+    #[derive(Debug)]
+    struct Open;
+
+    impl StageCircuit for Open {
+        fn inputs(&self) -> usize {
+            0
+        }
+
+        fn generate_constraints(
+            &self,
+            _: ConstraintSystemRef<Fr>,
+            _: &Statement,
+            _: Option<Witness<'_>>,
+        ) -> Result<(), SynthesisError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn verifies_by_circuit_of_stage() {
+        let hash = HashAlgorithm::default();
+        let (first_key, first_vk) = setup(&Open).unwrap();
+        let (_, second_vk) = setup(&Open).unwrap();
+        let client = || {
+            SnarkClientContext::<Vec<u8>>::new(
+                hash,
+                [
+                    ("first".into(), first_vk.clone()),
+                    ("second".into(), second_vk.clone()),
+                ],
+            )
+            .unwrap()
+        };
+        let context =
+            SnarkContext::<Vec<u8>, _>::new(1, Arc::new(Open), Arc::new(first_key), client())
+                .unwrap();
+        let output = b"output".to_vec();
+        let first = Binding::new(1, "first");
+        let clock = context.prove(&[], &output, &first).unwrap();
+        client().verify(&clock, &output, &first).unwrap();
+        assert!(client().verify(&clock, &b"other".to_vec(), &first).is_err());
+        // the circuit of the first stage proving the output of the second one
+        let second = Binding::new(1, "second");
+        let clock = context.prove(&[], &output, &second).unwrap();
+        assert!(client().verify(&clock, &output, &second).is_err());
+        let mut forged = clock.clone();
+        forged.circuit = circuit_id(hash, &second_vk).unwrap();
+        assert!(client().verify(&forged, &output, &second).is_err());
+        assert!(client()
+            .verify(&clock, &output, &Binding::new(1, "third"))
+            .is_err());
+    }
+}
@@ -0,0 +1,474 @@
+// clocks proven by a trusted execution environment: the proof part is a remote attestation quote
+// of the enclave (intel sgx) or confidential vm (amd sev-snp) the stage runs in, whose report data
+// binds the causality part, the digests of the predecessor clocks, the stage it is produced for and
+// the digest of the output.
+// the verifier only holds the measurements of the builds it expects of every stage, so a clock
+// passes only if the expected program of the stage it is bound to produced the output upon those
+// predecessors, which is the guarantee that `ClockContext::prove` asks for, without trusting the
+// operator of the host
+// the quote is taken from the hardware through a `Quoter`, e.g. `GramineQuoter` within a gramine
+// enclave or `TsmQuoter` within a sev-snp guest. checking that a quote is genuine, i.e. its
+// signature chain up to the root of intel or amd, takes the collateral of the vendor, so it is
+// left to a `QuoteVerifier`, e.g. a `CommandVerifier` running the verification tool of the
+// vendor. the measurement and the report data are then read from the quote here. the
+// `SimulatedQuoter` signs quotes of the same layout with an ordinary key, for development and
+// testing without the hardware
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs,
+    io::Write as _,
+    marker::PhantomData,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use ed25519_dalek::{Signer as _, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{Digest, HashAlgorithm},
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeeKind {
+    // an ecdsa quote of version 3, as gramine and the dcap libraries produce
+    Sgx,
+    // an attestation report of a sev-snp guest
+    SevSnp,
+}
+
+// the offsets of the fields within the quote, see the dcap quote format and the sev-snp firmware
+// abi specification
+const SGX_MEASUREMENT: usize = 112;
+const SGX_REPORT_DATA: usize = 368;
+const SGX_QUOTE_BODY: usize = 432;
+const SNP_REPORT_DATA: usize = 0x50;
+const SNP_MEASUREMENT: usize = 0x90;
+const SNP_REPORT: usize = 0x4a0;
+
+impl TeeKind {
+    // the measurement and the report data of the quote, which must be verified to be genuine
+    // before they mean anything
+    fn parse(self, quote: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+        let (measurement, report_data, size) = match self {
+            Self::Sgx => (
+                SGX_MEASUREMENT..SGX_MEASUREMENT + 32,
+                SGX_REPORT_DATA,
+                SGX_QUOTE_BODY,
+            ),
+            Self::SevSnp => (
+                SNP_MEASUREMENT..SNP_MEASUREMENT + 48,
+                SNP_REPORT_DATA,
+                SNP_REPORT,
+            ),
+        };
+        anyhow::ensure!(
+            quote.len() >= size,
+            "quote of {} bytes is truncated",
+            quote.len()
+        );
+        Ok((&quote[measurement], &quote[report_data..report_data + 64]))
+    }
+}
+
+// the caller owns the bytes of the quote, so the quoter is free to add what the kind allows
+// after the fixed fields, e.g. the certification data of an sgx quote
+pub trait Quoter: Debug + Send + Sync {
+    fn quote(&self, report_data: &[u8; 64]) -> anyhow::Result<Vec<u8>>;
+}
+
+// whether a quote is genuine, i.e. signed by a platform of the vendor with an acceptable tcb.
+// the fields of the quote are not checked by the verifier
+pub trait QuoteVerifier: Debug + Send + Sync {
+    fn verify(&self, quote: &[u8]) -> anyhow::Result<()>;
+}
+
+// the attestation device of gramine, within an sgx enclave built with remote attestation enabled
+#[derive(Debug)]
+pub struct GramineQuoter {
+    device: PathBuf,
+}
+
+impl Default for GramineQuoter {
+    fn default() -> Self {
+        Self {
+            device: "/dev/attestation".into(),
+        }
+    }
+}
+
+impl Quoter for GramineQuoter {
+    fn quote(&self, report_data: &[u8; 64]) -> anyhow::Result<Vec<u8>> {
+        fs::write(self.device.join("user_report_data"), report_data)?;
+        Ok(fs::read(self.device.join("quote"))?)
+    }
+}
+
+// the configfs-tsm interface of linux (6.7 and later), within a sev-snp guest. every quote goes
+// through an entry of its own, since the interface is not safe to share between concurrent
+// requests
+#[derive(Debug)]
+pub struct TsmQuoter {
+    reports: PathBuf,
+}
+
+impl Default for TsmQuoter {
+    fn default() -> Self {
+        Self {
+            reports: "/sys/kernel/config/tsm/report".into(),
+        }
+    }
+}
+
+impl Quoter for TsmQuoter {
+    fn quote(&self, report_data: &[u8; 64]) -> anyhow::Result<Vec<u8>> {
+        let entry = self
+            .reports
+            .join(format!("pohb-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&entry)?;
+        let quote = fs::write(entry.join("inblob"), report_data)
+            .and_then(|()| fs::read(entry.join("outblob")));
+        fs::remove_dir(&entry)?;
+        Ok(quote?)
+    }
+}
+
+// the quote is checked by an external program, which reads it from stdin and exits with zero if
+// it is genuine, e.g. a wrapper of the quote verification library of intel, or of `snpguest`
+// along with the certificates of amd. the program is run for every verification, so it should
+// cache the collateral it fetches
+#[derive(Debug)]
+pub struct CommandVerifier {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl CommandVerifier {
+    pub fn new(program: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+        }
+    }
+}
+
+impl QuoteVerifier for CommandVerifier {
+    fn verify(&self, quote: &[u8]) -> anyhow::Result<()> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(quote)?;
+        let output = child.wait_with_output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "quote verification exits with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(())
+    }
+}
+
+// sgx shaped quotes of a claimed measurement, signed with an ed25519 key held in memory, which is
+// what to use for development and testing. they prove nothing but the possession of the key
+#[derive(Debug)]
+pub struct SimulatedQuoter {
+    key: SigningKey,
+    measurement: [u8; 32],
+}
+
+impl SimulatedQuoter {
+    pub fn new(measurement: [u8; 32]) -> Self {
+        Self {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+            measurement,
+        }
+    }
+
+    // of the quotes of this quoter only
+    pub fn verifier(&self) -> SimulatedVerifier {
+        SimulatedVerifier {
+            key: self.key.verifying_key(),
+        }
+    }
+}
+
+impl Quoter for SimulatedQuoter {
+    fn quote(&self, report_data: &[u8; 64]) -> anyhow::Result<Vec<u8>> {
+        let mut quote = vec![0; SGX_QUOTE_BODY];
+        // version 3, with an ecdsa p-256 attestation key
+        quote[..4].copy_from_slice(&[3, 0, 2, 0]);
+        quote[SGX_MEASUREMENT..SGX_MEASUREMENT + 32].copy_from_slice(&self.measurement);
+        quote[SGX_REPORT_DATA..SGX_REPORT_DATA + 64].copy_from_slice(report_data);
+        let signature = self.key.sign(&quote).to_bytes();
+        quote.extend((signature.len() as u32).to_le_bytes());
+        quote.extend(signature);
+        Ok(quote)
+    }
+}
+
+#[derive(Debug)]
+pub struct SimulatedVerifier {
+    key: VerifyingKey,
+}
+
+impl QuoteVerifier for SimulatedVerifier {
+    fn verify(&self, quote: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            quote.len() == SGX_QUOTE_BODY + 4 + 64,
+            "not a simulated quote"
+        );
+        let signature = quote[SGX_QUOTE_BODY + 4..].try_into()?;
+        Ok(self
+            .key
+            .verify_strict(&quote[..SGX_QUOTE_BODY], &signature)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct TeeClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    // of the clocks of the inputs, in the order of the predecessors (see `clock_digest`), so a
+    // verifier holding those clocks, e.g. from the lineage of the task, can tell which of the
+    // concurrent executions of a stage the attested one consumed
+    pub predecessors: Vec<Digest>,
+    pub quote: Vec<u8>,
+}
+
+// of the whole clock, i.e. along with its quote
+fn clock_digest(hash: HashAlgorithm, clock: &TeeClock) -> Digest {
+    let mut message = clock.clock.encode();
+    message.extend(&clock.quote);
+    hash.digest(&message)
+}
+
+// the digest of what is attested occupies the first half of the report data, the rest is zero
+fn report_data(
+    hash: HashAlgorithm,
+    clock: &OrdinaryClock,
+    predecessors: &[Digest],
+    binding: &Binding,
+    output: &[u8],
+) -> [u8; 64] {
+    let mut message = clock.encode();
+    message.extend((predecessors.len() as u32).to_be_bytes());
+    for digest in predecessors {
+        message.extend(digest)
+    }
+    message.extend(binding.encode());
+    message.extend(hash.digest(output));
+    let mut report_data = [0; 64];
+    report_data[..32].copy_from_slice(&hash.digest(&message));
+    report_data
+}
+
+#[derive(Debug)]
+pub struct TeeClientContext<O> {
+    hash: HashAlgorithm,
+    kind: TeeKind,
+    // by the stage
+    measurements: HashMap<String, HashSet<Vec<u8>>>,
+    verifier: Arc<dyn QuoteVerifier>,
+    _output: PhantomData<O>,
+}
+
+impl<O> TeeClientContext<O> {
+    // the measurements are the ones of the expected builds of the stage programs, i.e. the
+    // mrenclave of an sgx enclave or the launch digest of a sev-snp guest, each along with the
+    // stage it is expected for. a stage may have several, e.g. during a rollout of a new build
+    pub fn new(
+        hash: HashAlgorithm,
+        kind: TeeKind,
+        measurements: impl IntoIterator<Item = (String, Vec<u8>)>,
+        verifier: Arc<dyn QuoteVerifier>,
+    ) -> Self {
+        let mut by_stage = HashMap::<_, HashSet<_>>::new();
+        for (stage, measurement) in measurements {
+            by_stage.entry(stage).or_default().insert(measurement);
+        }
+        Self {
+            hash,
+            kind,
+            measurements: by_stage,
+            verifier,
+            _output: PhantomData,
+        }
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &TeeClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        let (measurement, attested) = self.kind.parse(&clock.quote)?;
+        anyhow::ensure!(
+            self.measurements
+                .get(&binding.stage)
+                .is_some_and(|measurements| measurements.contains(measurement)),
+            "clock is attested by an unexpected measurement for stage {}",
+            binding.stage
+        );
+        anyhow::ensure!(
+            attested
+                == report_data(
+                    self.hash,
+                    &clock.clock,
+                    &clock.predecessors,
+                    binding,
+                    output
+                ),
+            "quote does not attest the clock and the output"
+        );
+        self.verifier.verify(&clock.quote)
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for TeeClientContext<O> {
+    type Clock = TeeClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+// runs within the trusted execution environment along with the stage program, whose measurement
+// is usually among the expected ones, so the clocks of this producer pass the verification of the
+// others
+#[derive(Debug, ClockClientContext)]
+pub struct TeeContext<I, O> {
+    id: NodeId,
+    quoter: Arc<dyn Quoter>,
+    #[client_context]
+    client: TeeClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> TeeContext<I, O> {
+    pub fn new(id: NodeId, quoter: Arc<dyn Quoter>, client: TeeClientContext<O>) -> Self {
+        Self {
+            id,
+            quoter,
+            client,
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> TeeContext<I, O> {
+    // the clock, the digests of its predecessors and the report data to quote
+    fn unquoted(
+        &self,
+        predecessors: &[Predecessor<'_, TeeClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<(OrdinaryClock, Vec<Digest>, [u8; 64]), Error> {
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let digests = predecessors
+            .iter()
+            .map(|(clock, ..)| clock_digest(self.client.hash, clock))
+            .collect::<Vec<_>>();
+        let report_data = report_data(self.client.hash, &clock, &digests, binding, output.as_ref());
+        Ok((clock, digests, report_data))
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for TeeContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let (clock, predecessors, report_data) = self.unquoted(predecessors, output, binding)?;
+        Ok(TeeClock {
+            quote: self.quoter.quote(&report_data).map_err(Error::Proving)?,
+            clock,
+            predecessors,
+        })
+    }
+
+    // a quote takes a round trip to the quoting enclave or the secure processor, so it is taken
+    // on a blocking thread
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unquoted = self.unquoted(predecessors, output, binding);
+        let quoter = self.quoter.clone();
+        async move {
+            let (clock, predecessors, report_data) = unquoted?;
+            let quote = tokio::task::spawn_blocking(move || quoter.quote(&report_data))
+                .await
+                .map_err(|err| Error::Proving(err.into()))?
+                .map_err(Error::Proving)?;
+            Ok(TeeClock {
+                clock,
+                predecessors,
+                quote,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_measurement_of_stage() {
+        let quoter = Arc::new(SimulatedQuoter::new([1; 32]));
+        let client = || {
+            TeeClientContext::<Vec<u8>>::new(
+                HashAlgorithm::default(),
+                TeeKind::Sgx,
+                [
+                    ("first".into(), vec![1; 32]),
+                    ("second".into(), vec![2; 32]),
+                ],
+                Arc::new(quoter.verifier()),
+            )
+        };
+        let context = TeeContext::<Vec<u8>, _>::new(1, quoter.clone(), client());
+        let output = b"output".to_vec();
+        let first = Binding::new(1, "first");
+        let clock = context.prove(&[], &output, &first).unwrap();
+        client().verify(&clock, &output, &first).unwrap();
+        assert!(client().verify(&clock, &b"other".to_vec(), &first).is_err());
+        // the enclave of the first stage attesting the output of the second one
+        let second = Binding::new(1, "second");
+        let clock = context.prove(&[], &output, &second).unwrap();
+        assert!(client().verify(&clock, &output, &second).is_err());
+        assert!(client()
+            .verify(&clock, &output, &Binding::new(1, "third"))
+            .is_err());
+    }
+}
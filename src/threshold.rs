@@ -0,0 +1,455 @@
+// threshold signed clocks, for stages executed under the supervision of a committee: the clock is
+// signed by the committee as a group, which takes at least `t` of its `n` members, so a single
+// malicious worker (or member) cannot mint clocks alone
+// the scheme is FROST over ed25519 (RFC 9591), whose signatures are plain ed25519 signatures under
+// the group public key. so the proof part is a 64-byte signature however large the committee is,
+// and the verifier only knows the group public key, not the members
+// the signature is over the same message as a `signed` clock is, i.e. the causality part, the stage
+// it is produced for and the digest of the output. the producer coordinates the two rounds of
+// FROST: the members commit to fresh nonces, then sign the message along with the commitments of
+// the others. a member is a `Participant`, which is where its own checks go, e.g. re-executing the
+// stage before signing, and which may be remote. the committee keys are usually generated by the
+// members together, with the distributed key generation of `frost_ed25519::keys::dkg`, or dealt for
+// development and testing (`LocalParticipant::dealt`)
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use frost_ed25519::{
+    self as frost,
+    keys::{IdentifierList, KeyPackage, PublicKeyPackage},
+    round1::{SigningCommitments, SigningNonces},
+    round2::SignatureShare,
+    Identifier, Signature, SigningPackage, VerifyingKey,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    crypto::HashAlgorithm, Binding, Clock, ClockClientContext, ClockContext, Error, NodeId,
+    OrdinaryClock, Predecessor,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct ThresholdClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    pub signature: Vec<u8>,
+}
+
+fn signed_message(
+    hash: HashAlgorithm,
+    clock: &OrdinaryClock,
+    binding: &Binding,
+    output: &[u8],
+) -> Vec<u8> {
+    let mut message = clock.encode();
+    message.extend(binding.encode());
+    message.extend(hash.digest(output));
+    message
+}
+
+#[derive(Debug)]
+pub struct ThresholdClientContext<O> {
+    hash: HashAlgorithm,
+    group_key: VerifyingKey,
+    _output: PhantomData<O>,
+}
+
+impl<O> ThresholdClientContext<O> {
+    // the output is digested with `hash` for signing
+    pub fn new(hash: HashAlgorithm, group_key: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            hash,
+            group_key: VerifyingKey::deserialize(group_key)?,
+            _output: PhantomData,
+        })
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &ThresholdClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        let signature = Signature::deserialize(&clock.signature)?;
+        let message = signed_message(self.hash, &clock.clock, binding, output);
+        Ok(self.group_key.verify(&message, &signature)?)
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for ThresholdClientContext<O> {
+    type Clock = ThresholdClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+// a member of the committee, answering the two rounds of a signing
+pub trait Participant: Debug + Send + Sync {
+    fn identifier(&self) -> Identifier;
+
+    // round one: the commitments to fresh nonces, which the participant keeps for round two
+    fn commit(&self) -> anyhow::Result<SigningCommitments>;
+
+    // round two: the share of the signature over the message of the package, with the nonces the
+    // participant committed to in it
+    fn sign(&self, package: &SigningPackage) -> anyhow::Result<SignatureShare>;
+}
+
+// the nonces of a signing that never reaches round two are dropped after this many later ones
+const PENDING_NONCES: usize = 64;
+
+// the key share is held in memory, which is what to use for development and testing. it signs
+// whatever it is asked to
+#[derive(Debug)]
+pub struct LocalParticipant {
+    key_package: KeyPackage,
+    pending: Mutex<Vec<(SigningCommitments, SigningNonces)>>,
+}
+
+impl LocalParticipant {
+    pub fn new(key_package: KeyPackage) -> Self {
+        Self {
+            key_package,
+            pending: Default::default(),
+        }
+    }
+
+    // the members of a committee of `participants` whose `threshold` sign together, and the public
+    // key package of the committee, with the shares dealt by a trusted party
+    pub fn dealt(
+        participants: u16,
+        threshold: u16,
+    ) -> anyhow::Result<(Vec<Self>, PublicKeyPackage)> {
+        let (shares, public_keys) = frost::keys::generate_with_dealer(
+            participants,
+            threshold,
+            IdentifierList::Default,
+            rand::thread_rng(),
+        )?;
+        let participants = shares
+            .into_values()
+            .map(|share| Ok(Self::new(share.try_into()?)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok((participants, public_keys))
+    }
+}
+
+impl Participant for LocalParticipant {
+    fn identifier(&self) -> Identifier {
+        *self.key_package.identifier()
+    }
+
+    fn commit(&self) -> anyhow::Result<SigningCommitments> {
+        let (nonces, commitments) =
+            frost::round1::commit(self.key_package.signing_share(), &mut rand::thread_rng());
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() == PENDING_NONCES {
+            pending.remove(0);
+        }
+        pending.push((commitments, nonces));
+        Ok(commitments)
+    }
+
+    // the nonces are used once, whether the signing succeeds or not
+    fn sign(&self, package: &SigningPackage) -> anyhow::Result<SignatureShare> {
+        let commitments = package
+            .signing_commitment(&self.identifier())
+            .ok_or(anyhow::format_err!("participant is not in the signing"))?;
+        let nonces = {
+            let mut pending = self.pending.lock().unwrap();
+            let index = pending
+                .iter()
+                .position(|(pending, _)| *pending == commitments)
+                .ok_or(anyhow::format_err!("unknown or used commitments"))?;
+            pending.remove(index).1
+        };
+        Ok(frost::round2::sign(package, &nonces, &self.key_package)?)
+    }
+}
+
+// the signature of the first `threshold` participants that commit. a participant failing to
+// commit is left out, while one failing to sign fails the signing, since the shares of the others
+// are bound to its commitments
+fn coordinate(
+    participants: &[Arc<dyn Participant>],
+    public_keys: &PublicKeyPackage,
+    threshold: usize,
+    message: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    let mut signers = Vec::new();
+    let mut commitments = BTreeMap::new();
+    for participant in participants {
+        if signers.len() == threshold {
+            break;
+        }
+        match participant.commit() {
+            Ok(committed) => {
+                commitments.insert(participant.identifier(), committed);
+                signers.push(participant)
+            }
+            Err(err) => warn!(
+                "leave out participant {:?}: {err}",
+                participant.identifier()
+            ),
+        }
+    }
+    anyhow::ensure!(
+        signers.len() == threshold,
+        "{} participants committed, expect {threshold}",
+        signers.len()
+    );
+    let package = SigningPackage::new(commitments, message);
+    let shares = signers
+        .iter()
+        .map(|participant| Ok((participant.identifier(), participant.sign(&package)?)))
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+    // the shares are verified against the verifying shares of the participants, and a wrong one
+    // is reported with the participant that sent it
+    Ok(frost::aggregate(&package, &shares, public_keys)?.serialize()?)
+}
+
+#[derive(Debug, ClockClientContext)]
+pub struct ThresholdContext<I, O> {
+    id: NodeId,
+    participants: Vec<Arc<dyn Participant>>,
+    public_keys: PublicKeyPackage,
+    threshold: usize,
+    #[client_context]
+    client: ThresholdClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> ThresholdContext<I, O> {
+    // the participants are the members this producer reaches, of the committee of the public key
+    // package, whose `threshold` is the one the keys are generated with
+    pub fn new(
+        id: NodeId,
+        hash: HashAlgorithm,
+        public_keys: PublicKeyPackage,
+        threshold: usize,
+        participants: Vec<Arc<dyn Participant>>,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            participants.len() >= threshold,
+            "{} participants for threshold {threshold}",
+            participants.len()
+        );
+        Ok(Self {
+            id,
+            participants,
+            client: ThresholdClientContext::new(hash, &public_keys.verifying_key().serialize()?)?,
+            public_keys,
+            threshold,
+            _input: PhantomData,
+        })
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ThresholdContext<I, O> {
+    // the clock and the message the committee signs
+    fn unsigned(
+        &self,
+        predecessors: &[Predecessor<'_, ThresholdClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<(OrdinaryClock, Vec<u8>), Error> {
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?
+        }
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let message = signed_message(self.client.hash, &clock, binding, output.as_ref());
+        Ok((clock, message))
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for ThresholdContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        let (clock, message) = self.unsigned(predecessors, output, binding)?;
+        Ok(ThresholdClock {
+            signature: coordinate(
+                &self.participants,
+                &self.public_keys,
+                self.threshold,
+                &message,
+            )
+            .map_err(Error::Proving)?,
+            clock,
+        })
+    }
+
+    // the rounds take as long as the slowest member answers, so they run on a blocking thread
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unsigned = self.unsigned(predecessors, output, binding);
+        let participants = self.participants.clone();
+        let (public_keys, threshold) = (self.public_keys.clone(), self.threshold);
+        async move {
+            let (clock, message) = unsigned?;
+            let signature = tokio::task::spawn_blocking(move || {
+                coordinate(&participants, &public_keys, threshold, &message)
+            })
+            .await
+            .map_err(|err| Error::Proving(err.into()))?
+            .map_err(Error::Proving)?;
+            Ok(ThresholdClock { clock, signature })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participants(committee: Vec<LocalParticipant>) -> Vec<Arc<dyn Participant>> {
+        committee
+            .into_iter()
+            .map(|participant| Arc::new(participant) as _)
+            .collect()
+    }
+
+    fn client(public_keys: &PublicKeyPackage) -> ThresholdClientContext<Vec<u8>> {
+        let group_key = public_keys.verifying_key().serialize().unwrap();
+        ThresholdClientContext::new(HashAlgorithm::default(), &group_key).unwrap()
+    }
+
+    #[test]
+    fn verifies_threshold_of_members() {
+        let (committee, public_keys) = LocalParticipant::dealt(3, 2).unwrap();
+        let mut members = participants(committee);
+        let (output, binding) = (b"output".to_vec(), Binding::new(1, "stage"));
+        // any two of the three
+        for excluded in 0..3 {
+            let mut signers = members.clone();
+            signers.remove(excluded);
+            let producer = ThresholdContext::<Vec<u8>, _>::new(
+                1,
+                HashAlgorithm::default(),
+                public_keys.clone(),
+                2,
+                signers,
+            )
+            .unwrap();
+            let clock = producer.prove(&[], &output, &binding).unwrap();
+            assert_eq!(clock.signature.len(), 64);
+            client(&public_keys)
+                .verify(&clock, &output, &binding)
+                .unwrap();
+            assert!(client(&public_keys)
+                .verify(&clock, &b"other".to_vec(), &binding)
+                .is_err());
+            assert!(client(&public_keys)
+                .verify(&clock, &output, &Binding::new(1, "other"))
+                .is_err())
+        }
+        let producer = ThresholdContext::<Vec<u8>, _>::new(
+            2,
+            HashAlgorithm::default(),
+            public_keys.clone(),
+            2,
+            members.split_off(1),
+        )
+        .unwrap();
+        let first = producer.prove(&[], &output, &binding).unwrap();
+        let predecessors = [(&first, &output, &binding)];
+        let next = producer
+            .prove(&predecessors, &output, &Binding::new(1, "next"))
+            .unwrap();
+        assert!(next.clock > first.clock);
+    }
+
+    #[test]
+    fn refuses_fewer_than_threshold() {
+        let (committee, public_keys) = LocalParticipant::dealt(3, 2).unwrap();
+        let mut members = participants(committee);
+        members.truncate(1);
+        assert!(ThresholdContext::<Vec<u8>, Vec<u8>>::new(
+            1,
+            HashAlgorithm::default(),
+            public_keys.clone(),
+            2,
+            members.clone()
+        )
+        .is_err());
+        // a producer claiming a lower threshold than the keys are generated with
+        let producer = ThresholdContext::<Vec<u8>, _>::new(
+            1,
+            HashAlgorithm::default(),
+            public_keys,
+            1,
+            members,
+        )
+        .unwrap();
+        let (output, binding) = (b"output".to_vec(), Binding::new(1, "stage"));
+        assert!(matches!(
+            producer.prove(&[], &output, &binding),
+            Err(Error::Proving(_))
+        ))
+    }
+
+    #[test]
+    fn refuses_foreign_group_key() {
+        let (committee, public_keys) = LocalParticipant::dealt(3, 2).unwrap();
+        let (_, foreign) = LocalParticipant::dealt(3, 2).unwrap();
+        let producer = ThresholdContext::<Vec<u8>, _>::new(
+            1,
+            HashAlgorithm::default(),
+            public_keys.clone(),
+            2,
+            participants(committee),
+        )
+        .unwrap();
+        let (output, binding) = (b"output".to_vec(), Binding::new(1, "stage"));
+        let clock = producer.prove(&[], &output, &binding).unwrap();
+        client(&public_keys)
+            .verify(&clock, &output, &binding)
+            .unwrap();
+        assert!(matches!(
+            client(&foreign).verify(&clock, &output, &binding),
+            Err(Error::ProofInvalid(_))
+        ));
+        // nor does the producer prove upon it
+        let (others, _) = LocalParticipant::dealt(3, 2).unwrap();
+        let producer = ThresholdContext::<Vec<u8>, _>::new(
+            1,
+            HashAlgorithm::default(),
+            foreign,
+            2,
+            participants(others),
+        )
+        .unwrap();
+        assert!(matches!(
+            producer.prove(&[(&clock, &output, &binding)], &output, &binding),
+            Err(Error::ProofInvalid(_))
+        ))
+    }
+}
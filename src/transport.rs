@@ -0,0 +1,1226 @@
+// how workers and clients talk to a hub. every transport carries the same versioned json messages
+// to the same hub endpoints, so the worker and client logic does not care which one is in use
+// * `HttpTransport` subscribes through server-sent events, which is what the binaries use, and
+//   fails over between the members of a replicated hub
+// * `WebSocketTransport` subscribes through websockets, for environments where long-lived
+//   responses are cut by proxies
+// * `InMemoryTransport` sends the requests to an embedded hub's router directly, without any
+//   socket, for simulations and tests running everything within one process
+// subscribing returns after the subscription is established, so anything published after that is
+// guaranteed to be observed. writes are request/response and always go through plain requests
+use std::{
+    fmt::{self, Display, Formatter},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderMap, Method, Request, StatusCode,
+    },
+    response::Response,
+    Router,
+};
+use bytes::Bytes;
+use reqwest::{Client, RequestBuilder};
+use reqwest_eventsource::{retry::Never, Error as EventSourceError, Event, EventSource};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{select, sync::mpsc, time::sleep};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt as _};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest as _, http::HeaderValue, Message};
+use tower::ServiceExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    crypto::Digest,
+    hex,
+    history::HistoryEvent,
+    hub::{ChainFilter, Hub},
+    notary::Notarization,
+    protocol,
+    secrets::{SealedSecrets, SecretRequest},
+    AuditOutcome, AuditRequest, CanaryReport, Challenge, Error, Lease, LeaseRequest, ProgressEvent,
+    StageRecord, TaskId, VerificationReport, WorkerStatus, Workflow, WorkflowDigest,
+};
+
+pub type Subscription<M> = Pin<Box<dyn Stream<Item = anyhow::Result<M>> + Send>>;
+
+// the hub responds to a published message with an empty body, unless it serves a cached result
+// an overloaded hub sheds new tasks, and the publish may be retried after the delay, see
+// `hub::load`. the error of a write is downcast to this to tell
+#[derive(Debug, Clone, Copy)]
+pub struct Overloaded {
+    pub retry_after: Duration,
+}
+
+impl Display for Overloaded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hub is overloaded, retry after {}s",
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+fn overloaded(status: StatusCode, headers: &HeaderMap) -> Option<Overloaded> {
+    if status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let retry_after = headers.get(RETRY_AFTER)?.to_str().ok()?.parse().ok()?;
+    Some(Overloaded {
+        retry_after: Duration::from_secs(retry_after),
+    })
+}
+
+// the sequence number the hub acknowledges a publish with, `None` for a message it has kept already,
+// or from a hub predating the acknowledgment
+fn acknowledged(headers: &HeaderMap) -> anyhow::Result<Option<u64>> {
+    headers
+        .get(protocol::SEQ_HEADER)
+        .map(|seq| anyhow::Ok(seq.to_str()?.parse()?))
+        .transpose()
+}
+
+// the hub refuses the later stages and the results of a task that has missed its deadline, see
+// `hub::deadline`. the error of a write is downcast to this to tell
+#[derive(Debug, Clone, Copy)]
+pub struct Expired;
+
+impl Display for Expired {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "task has expired")
+    }
+}
+
+impl std::error::Error for Expired {}
+
+// any other refusal of the hub, whose status is in the error
+fn refused(err: reqwest::Error) -> Error {
+    Error::Transport(err.into())
+}
+
+// a refusal of a write along with the reason the hub responds with, e.g. why it does not accept
+// the input of a task
+async fn explained(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.map_err(refused)?;
+    Err(Error::Transport(anyhow::format_err!(
+        "hub responds {status}: {body}"
+    )))
+}
+
+fn workflow_path(digest: Option<&WorkflowDigest>) -> String {
+    match digest {
+        None => "/workflows/current".into(),
+        Some(digest) => format!("/workflows/{}", hex(digest)),
+    }
+}
+
+fn cached_result<M: DeserializeOwned>(body: &[u8]) -> anyhow::Result<Option<M>> {
+    if body.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(body)?))
+}
+
+pub trait HubTransport: Send + Sync {
+    fn handshake(&self) -> impl Future<Output = anyhow::Result<protocol::Handshake>> + Send;
+
+    fn subscribe_gossip<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<Subscription<M>>> + Send;
+
+    fn subscribe_chain<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<Subscription<M>>> + Send;
+
+    // only the results passing the filter, which the hub evaluates
+    fn subscribe_chain_filtered<M: DeserializeOwned + Send + 'static>(
+        &self,
+        filter: &ChainFilter,
+    ) -> impl Future<Output = anyhow::Result<Subscription<M>>> + Send;
+
+    // returns once the hub has kept the message, with its sequence number, see `hub::PublishAck`
+    fn publish_gossip(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> impl Future<Output = anyhow::Result<Option<u64>>> + Send;
+
+    // returns once the hub has checked the message, trading the guarantee that it is kept for
+    // latency
+    fn publish_gossip_detached(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // publishes the start stage of a task that accepts a cached result, and returns the cached
+    // result if the hub responds with one, in which case the task does not run
+    fn submit_cached<M: DeserializeOwned + Send>(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> impl Future<Output = anyhow::Result<Option<M>>> + Send;
+
+    fn propose_chain(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn report_canary(
+        &self,
+        report: &CanaryReport,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn subscribe_progress(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<Subscription<ProgressEvent>>> + Send;
+
+    fn report_progress(
+        &self,
+        event: &ProgressEvent,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn report_status(
+        &self,
+        status: &WorkerStatus,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // returns the digest of the log, to be referenced from the messages
+    fn upload_log(
+        &self,
+        id: TaskId,
+        stage: &str,
+        log: Bytes,
+    ) -> impl Future<Output = anyhow::Result<Digest>> + Send;
+
+    fn upload_blob(&self, blob: Bytes) -> impl Future<Output = anyhow::Result<Digest>> + Send;
+
+    fn download_blob(&self, digest: &Digest) -> impl Future<Output = anyhow::Result<Bytes>> + Send;
+
+    fn stage_record(
+        &self,
+        id: TaskId,
+        stage: &str,
+    ) -> impl Future<Output = anyhow::Result<StageRecord>> + Send;
+
+    // the kept events of the task in order, see `history`
+    fn history(&self, id: TaskId)
+        -> impl Future<Output = anyhow::Result<Vec<HistoryEvent>>> + Send;
+
+    fn submit_challenge(
+        &self,
+        challenge: &Challenge,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // the stages sampled for audits, see `hub::audit`
+    fn subscribe_audits(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<Subscription<AuditRequest>>> + Send;
+
+    fn submit_audit(
+        &self,
+        outcome: &AuditOutcome,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // the version of the digest, or the current one, as the hub serves it. the definition is not
+    // checked against the digest here
+    fn workflow(
+        &self,
+        digest: Option<&WorkflowDigest>,
+    ) -> impl Future<Output = anyhow::Result<Workflow>> + Send;
+
+    // of the accepted result of an ordinary task, see `notary`
+    fn notarization(&self, id: TaskId)
+        -> impl Future<Output = anyhow::Result<Notarization>> + Send;
+
+    // `None` if the stage is at its concurrency limit, which is to be retried later
+    fn acquire_lease(
+        &self,
+        request: &LeaseRequest,
+    ) -> impl Future<Output = anyhow::Result<Option<Lease>>> + Send;
+
+    // false if the lease is not held anymore, e.g. it has expired
+    fn renew_lease(&self, lease: u64) -> impl Future<Output = anyhow::Result<bool>> + Send;
+
+    fn release_lease(&self, lease: u64) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    // refused unless the worker key of the request reads the secrets of the stage, see `secrets`
+    fn stage_secrets(
+        &self,
+        request: &SecretRequest,
+    ) -> impl Future<Output = anyhow::Result<SealedSecrets>> + Send;
+}
+
+// the hubs of a replicated deployment, any of which serves every request. the requests go to the
+// hub in use until it becomes unreachable, and then to the next hub that passes a health check.
+// a request that reaches a hub is never sent again, whatever the response, since a write may be
+// applied already
+// the subscriptions are reopened on the next healthy hub whenever they break, including when the
+// only hub restarts, and resume after the last numbered event they received, or at the token the
+// chain subscription starts with if they have received none (see `hub::resume`)
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    client: Client,
+    hubs: Arc<[String]>,
+    // the index of the hub in use, shared by the clones
+    current: Arc<AtomicUsize>,
+}
+
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+// precedes a message after some the hub has coalesced, see `hub::resume`
+const GAP_EVENT: &str = "gap";
+
+// carries a token to resume a chain subscription by before any message is received
+const RESUME_EVENT: &str = "resume";
+
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(8);
+
+const SUBSCRIPTION_CAPACITY: usize = 64;
+
+impl HttpTransport {
+    // `hub` is the base url e.g. `http://localhost:3000`, or a comma separated list of the base
+    // urls of the members of a replicated hub
+    pub fn new(client: Client, hub: impl Into<String>) -> Self {
+        let hubs = hub
+            .into()
+            .split(',')
+            .map(|hub| hub.trim().trim_end_matches('/').to_owned())
+            .filter(|hub| !hub.is_empty())
+            .collect::<Vec<_>>();
+        assert!(!hubs.is_empty(), "no hub url");
+        Self {
+            client,
+            hubs: hubs.into(),
+            current: Default::default(),
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.current.load(Relaxed)
+    }
+
+    async fn healthy(&self, hub: &str) -> bool {
+        self.client
+            .get(format!("{hub}/protocol"))
+            .timeout(HEALTH_TIMEOUT)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    }
+
+    // away from the hub `from`, to the next healthy one, unless another request has failed over
+    // already. the hub in use stays if none is healthy
+    async fn fail_over(&self, from: usize) {
+        for offset in 1..self.hubs.len() {
+            let next = (from + offset) % self.hubs.len();
+            if self.healthy(&self.hubs[next]).await {
+                if self
+                    .current
+                    .compare_exchange(from, next, Relaxed, Relaxed)
+                    .is_ok()
+                {
+                    warn!(
+                        "fail over from hub {} to {}",
+                        self.hubs[from], self.hubs[next]
+                    )
+                }
+                return;
+            }
+        }
+    }
+
+    // the request is built for the base url of a hub
+    async fn send(
+        &self,
+        request: impl Fn(&str) -> RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut attempts = self.hubs.len();
+        loop {
+            let index = self.current();
+            match request(&self.hubs[index]).send().await {
+                Err(err) if err.is_connect() && attempts > 1 => {
+                    attempts -= 1;
+                    self.fail_over(index).await
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn open(
+        &self,
+        path: &str,
+        last_event_id: Option<&str>,
+    ) -> anyhow::Result<(usize, EventSource)> {
+        let mut attempts = self.hubs.len();
+        loop {
+            let index = self.current();
+            let mut request = self
+                .client
+                .get(format!("{}{path}", self.hubs[index]))
+                .header(protocol::HEADER, protocol::VERSION);
+            if let Some(last_event_id) = last_event_id {
+                request = request.header(LAST_EVENT_ID, last_event_id)
+            }
+            let mut event_source = EventSource::new(request)?;
+            // reconnecting is up to `subscribe`, which may reconnect to another hub
+            event_source.set_retry_policy(Box::new(Never));
+            match event_source.next().await {
+                Some(Ok(Event::Open)) => return Ok((index, event_source)),
+                Some(Err(EventSourceError::Transport(_))) if attempts > 1 => {
+                    attempts -= 1;
+                    self.fail_over(index).await
+                }
+                Some(Err(err)) => return Err(err.into()),
+                _ => anyhow::bail!("event source is not opened"),
+            }
+        }
+    }
+
+    async fn subscribe<M: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+    ) -> anyhow::Result<Subscription<M>> {
+        let messages = self.subscribe_numbered(path, None).await?;
+        Ok(Box::pin(
+            messages.map(|message| message.map(|(_, message)| message)),
+        ))
+    }
+
+    // everything the hub finds verifying a result, without proposing it, see `VerificationReport`
+    pub async fn verify_result(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<VerificationReport> {
+        Ok(self.posted("/chain/verify", message).await?.json().await?)
+    }
+
+    // the messages along with their numbers in the log of the hub, resumed after the number
+    // `after` if any, e.g. for a mirror to pick up where it left off (see `hub::mirror`)
+    pub async fn subscribe_numbered<M: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+        after: Option<u64>,
+    ) -> anyhow::Result<Subscription<(Option<u64>, M)>> {
+        let mut last_event_id = after.map(|after| after.to_string());
+        let (mut hub, mut event_source) = self.open(path, last_event_id.as_deref()).await?;
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_CAPACITY);
+        let transport = self.clone();
+        let path = path.to_owned();
+        tokio::spawn(async move {
+            // the hub is fine, so the subscription is resumed on it
+            let mut missed = false;
+            loop {
+                let broken = loop {
+                    let event = select! {
+                        () = sender.closed() => return,
+                        event = event_source.next() => event,
+                    };
+                    match event {
+                        Some(Ok(Event::Open)) => {}
+                        // resuming replays the missed messages, unless nothing is received yet to
+                        // resume after
+                        Some(Ok(Event::Message(message))) if message.event == GAP_EVENT => {
+                            match &last_event_id {
+                                Some(last_event_id) => {
+                                    missed = true;
+                                    break anyhow::format_err!(
+                                        "messages after {last_event_id} are missed"
+                                    );
+                                }
+                                None => warn!("subscription {path} has missed messages"),
+                            }
+                        }
+                        Some(Ok(Event::Message(message))) if message.event == RESUME_EVENT => {
+                            if !message.id.is_empty() {
+                                last_event_id = Some(message.id)
+                            }
+                        }
+                        Some(Ok(Event::Message(message))) => {
+                            let seq = message.id.parse().ok();
+                            if !message.id.is_empty() {
+                                last_event_id = Some(message.id)
+                            }
+                            let message = serde_json::from_str(&message.data)
+                                .map(|message| (seq, message))
+                                .map_err(Into::into);
+                            if sender.send(message).await.is_err() {
+                                return;
+                            }
+                        }
+                        Some(Err(err)) => break anyhow::Error::from(err),
+                        None => break anyhow::format_err!("event source is closed"),
+                    }
+                };
+                warn!("subscription {path} is broken: {broken}");
+                let mut backoff = RECONNECT_BACKOFF;
+                loop {
+                    if !std::mem::take(&mut missed) {
+                        transport.fail_over(hub).await
+                    }
+                    match transport.open(&path, last_event_id.as_deref()).await {
+                        Ok(opened) => {
+                            (hub, event_source) = opened;
+                            info!("subscription {path} is resumed on {}", transport.hubs[hub]);
+                            break;
+                        }
+                        // the hub refuses the subscription itself, which reconnecting won't help
+                        Err(err)
+                            if err.downcast_ref().is_some_and(|err| {
+                                matches!(err, EventSourceError::InvalidStatusCode(status, _) if status.is_client_error())
+                            }) =>
+                        {
+                            let _ = sender.send(Err(err)).await;
+                            return;
+                        }
+                        Err(err) => warn!("failed to resume subscription {path}: {err}"),
+                    }
+                    select! {
+                        () = sender.closed() => return,
+                        () = sleep(backoff) => {}
+                    }
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF)
+                }
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(receiver)))
+    }
+
+    async fn post(&self, path: &str, message: &impl Serialize) -> anyhow::Result<()> {
+        self.posted(path, message).await?;
+        Ok(())
+    }
+
+    async fn posted(
+        &self,
+        path: &str,
+        message: &impl Serialize,
+    ) -> anyhow::Result<reqwest::Response> {
+        let response = self
+            .send(|hub| {
+                self.client
+                    .post(format!("{hub}{path}"))
+                    .header(protocol::HEADER, protocol::VERSION)
+                    .json(message)
+            })
+            .await?;
+        if let Some(overloaded) = overloaded(response.status(), response.headers()) {
+            return Err(overloaded.into());
+        }
+        if response.status() == StatusCode::GONE {
+            return Err(Expired.into());
+        }
+        Ok(explained(response).await?)
+    }
+}
+
+impl HubTransport for HttpTransport {
+    async fn handshake(&self) -> anyhow::Result<protocol::Handshake> {
+        let index = self.current();
+        match protocol::handshake(&self.client, &self.hubs[index]).await {
+            Err(err) if self.hubs.len() > 1 => {
+                warn!("failed to handshake with hub {}: {err}", self.hubs[index]);
+                self.fail_over(index).await;
+                protocol::handshake(&self.client, &self.hubs[self.current()]).await
+            }
+            result => result,
+        }
+    }
+
+    async fn subscribe_gossip<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/gossip").await
+    }
+
+    async fn subscribe_chain<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/chain").await
+    }
+
+    async fn subscribe_chain_filtered<M: DeserializeOwned + Send + 'static>(
+        &self,
+        filter: &ChainFilter,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe(&format!("/chain?{}", serde_urlencoded::to_string(filter)?))
+            .await
+    }
+
+    async fn publish_gossip(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<u64>> {
+        let response = self.posted("/gossip/publish", message).await?;
+        acknowledged(response.headers())
+    }
+
+    async fn publish_gossip_detached(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<()> {
+        self.post("/gossip/publish?ack=none", message).await
+    }
+
+    async fn submit_cached<M: DeserializeOwned + Send>(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<M>> {
+        let response = self
+            .send(|hub| {
+                self.client
+                    .post(format!("{hub}/gossip/publish"))
+                    .header(protocol::HEADER, protocol::VERSION)
+                    .json(message)
+            })
+            .await?;
+        if let Some(overloaded) = overloaded(response.status(), response.headers()) {
+            return Err(overloaded.into());
+        }
+        cached_result(&explained(response).await?.bytes().await?)
+    }
+
+    async fn propose_chain(&self, message: &(impl Serialize + Sync)) -> anyhow::Result<()> {
+        self.post("/chain/propose", message).await
+    }
+
+    async fn report_canary(&self, report: &CanaryReport) -> anyhow::Result<()> {
+        self.post("/canary/report", report).await
+    }
+
+    async fn subscribe_progress(&self) -> anyhow::Result<Subscription<ProgressEvent>> {
+        self.subscribe("/progress").await
+    }
+
+    async fn report_progress(&self, event: &ProgressEvent) -> anyhow::Result<()> {
+        self.post("/progress/report", event).await
+    }
+
+    async fn report_status(&self, status: &WorkerStatus) -> anyhow::Result<()> {
+        self.post("/scheduler/report", status).await
+    }
+
+    async fn upload_log(&self, id: TaskId, stage: &str, log: Bytes) -> anyhow::Result<Digest> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .put(format!("{hub}/tasks/{id}/logs/{stage}"))
+                    .header(protocol::HEADER, protocol::VERSION)
+                    .body(log.clone())
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn upload_blob(&self, blob: Bytes) -> anyhow::Result<Digest> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .post(format!("{hub}/blobs"))
+                    .header(protocol::HEADER, protocol::VERSION)
+                    .body(blob.clone())
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn download_blob(&self, digest: &Digest) -> anyhow::Result<Bytes> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .get(format!("{hub}/blobs/{}", hex(digest)))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .bytes()
+            .await?)
+    }
+
+    async fn stage_record(&self, id: TaskId, stage: &str) -> anyhow::Result<StageRecord> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .get(format!("{hub}/tasks/{id}/stages/{stage}"))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn history(&self, id: TaskId) -> anyhow::Result<Vec<HistoryEvent>> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .get(format!("{hub}/tasks/{id}/history"))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn submit_challenge(&self, challenge: &Challenge) -> anyhow::Result<()> {
+        self.post("/challenges/submit", challenge).await
+    }
+
+    async fn subscribe_audits(&self) -> anyhow::Result<Subscription<AuditRequest>> {
+        self.subscribe("/audits").await
+    }
+
+    async fn submit_audit(&self, outcome: &AuditOutcome) -> anyhow::Result<()> {
+        self.post("/audits/submit", outcome).await
+    }
+
+    async fn workflow(&self, digest: Option<&WorkflowDigest>) -> anyhow::Result<Workflow> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .get(format!("{hub}{}", workflow_path(digest)))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn notarization(&self, id: TaskId) -> anyhow::Result<Notarization> {
+        Ok(self
+            .send(|hub| {
+                self.client
+                    .get(format!("{hub}/tasks/{id}/notarize"))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?
+            .error_for_status()
+            .map_err(refused)?
+            .json()
+            .await?)
+    }
+
+    async fn acquire_lease(&self, request: &LeaseRequest) -> anyhow::Result<Option<Lease>> {
+        let response = self
+            .send(|hub| {
+                self.client
+                    .post(format!("{hub}/leases"))
+                    .header(protocol::HEADER, protocol::VERSION)
+                    .json(request)
+            })
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Ok(None);
+        }
+        Ok(Some(
+            response.error_for_status().map_err(refused)?.json().await?,
+        ))
+    }
+
+    async fn renew_lease(&self, lease: u64) -> anyhow::Result<bool> {
+        let response = self
+            .send(|hub| {
+                self.client
+                    .post(format!("{hub}/leases/{lease}/renew"))
+                    .header(protocol::HEADER, protocol::VERSION)
+            })
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status().map_err(refused)?;
+        Ok(true)
+    }
+
+    async fn release_lease(&self, lease: u64) -> anyhow::Result<()> {
+        self.send(|hub| {
+            self.client
+                .delete(format!("{hub}/leases/{lease}"))
+                .header(protocol::HEADER, protocol::VERSION)
+        })
+        .await?
+        .error_for_status()
+        .map_err(refused)?;
+        Ok(())
+    }
+
+    async fn stage_secrets(&self, request: &SecretRequest) -> anyhow::Result<SealedSecrets> {
+        Ok(self.posted("/secrets", request).await?.json().await?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSocketTransport {
+    http: HttpTransport,
+}
+
+impl WebSocketTransport {
+    // `hub` is the base url of plain http e.g. `http://localhost:3000`, or a list of them as for
+    // `HttpTransport`, and the websocket url is derived from it. a subscription goes to the hub in
+    // use as it is opened, but is not resumed once it breaks
+    pub fn new(client: Client, hub: impl Into<String>) -> Self {
+        Self {
+            http: HttpTransport::new(client, hub),
+        }
+    }
+
+    async fn subscribe<M: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+    ) -> anyhow::Result<Subscription<M>> {
+        let hub = &self.http.hubs[self.http.current()];
+        let mut request =
+            format!("{}{path}", hub.replacen("http", "ws", 1)).into_client_request()?;
+        request
+            .headers_mut()
+            .insert(protocol::HEADER, HeaderValue::from(protocol::VERSION));
+        let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+        Ok(Box::pin(socket.filter_map(|message| match message {
+            Ok(Message::Text(message)) => Some(serde_json::from_str(&message).map_err(Into::into)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err.into())),
+        })))
+    }
+}
+
+impl HubTransport for WebSocketTransport {
+    async fn handshake(&self) -> anyhow::Result<protocol::Handshake> {
+        self.http.handshake().await
+    }
+
+    async fn subscribe_gossip<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/gossip/ws").await
+    }
+
+    async fn subscribe_chain<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/chain/ws").await
+    }
+
+    async fn subscribe_chain_filtered<M: DeserializeOwned + Send + 'static>(
+        &self,
+        filter: &ChainFilter,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe(&format!(
+            "/chain/ws?{}",
+            serde_urlencoded::to_string(filter)?
+        ))
+        .await
+    }
+
+    async fn publish_gossip(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<u64>> {
+        self.http.publish_gossip(message).await
+    }
+
+    async fn publish_gossip_detached(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<()> {
+        self.http.publish_gossip_detached(message).await
+    }
+
+    async fn submit_cached<M: DeserializeOwned + Send>(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<M>> {
+        self.http.submit_cached(message).await
+    }
+
+    async fn propose_chain(&self, message: &(impl Serialize + Sync)) -> anyhow::Result<()> {
+        self.http.propose_chain(message).await
+    }
+
+    async fn report_canary(&self, report: &CanaryReport) -> anyhow::Result<()> {
+        self.http.report_canary(report).await
+    }
+
+    async fn subscribe_progress(&self) -> anyhow::Result<Subscription<ProgressEvent>> {
+        self.subscribe("/progress/ws").await
+    }
+
+    async fn report_progress(&self, event: &ProgressEvent) -> anyhow::Result<()> {
+        self.http.report_progress(event).await
+    }
+
+    async fn report_status(&self, status: &WorkerStatus) -> anyhow::Result<()> {
+        self.http.report_status(status).await
+    }
+
+    async fn upload_log(&self, id: TaskId, stage: &str, log: Bytes) -> anyhow::Result<Digest> {
+        self.http.upload_log(id, stage, log).await
+    }
+
+    async fn upload_blob(&self, blob: Bytes) -> anyhow::Result<Digest> {
+        self.http.upload_blob(blob).await
+    }
+
+    async fn download_blob(&self, digest: &Digest) -> anyhow::Result<Bytes> {
+        self.http.download_blob(digest).await
+    }
+
+    async fn stage_record(&self, id: TaskId, stage: &str) -> anyhow::Result<StageRecord> {
+        self.http.stage_record(id, stage).await
+    }
+
+    async fn history(&self, id: TaskId) -> anyhow::Result<Vec<HistoryEvent>> {
+        self.http.history(id).await
+    }
+
+    async fn submit_challenge(&self, challenge: &Challenge) -> anyhow::Result<()> {
+        self.http.submit_challenge(challenge).await
+    }
+
+    // the audit requests are few, so they are not worth a websocket endpoint
+    async fn subscribe_audits(&self) -> anyhow::Result<Subscription<AuditRequest>> {
+        self.http.subscribe_audits().await
+    }
+
+    async fn submit_audit(&self, outcome: &AuditOutcome) -> anyhow::Result<()> {
+        self.http.submit_audit(outcome).await
+    }
+
+    async fn workflow(&self, digest: Option<&WorkflowDigest>) -> anyhow::Result<Workflow> {
+        self.http.workflow(digest).await
+    }
+
+    async fn notarization(&self, id: TaskId) -> anyhow::Result<Notarization> {
+        self.http.notarization(id).await
+    }
+
+    async fn acquire_lease(&self, request: &LeaseRequest) -> anyhow::Result<Option<Lease>> {
+        self.http.acquire_lease(request).await
+    }
+
+    async fn renew_lease(&self, lease: u64) -> anyhow::Result<bool> {
+        self.http.renew_lease(lease).await
+    }
+
+    async fn release_lease(&self, lease: u64) -> anyhow::Result<()> {
+        self.http.release_lease(lease).await
+    }
+
+    async fn stage_secrets(&self, request: &SecretRequest) -> anyhow::Result<SealedSecrets> {
+        self.http.stage_secrets(request).await
+    }
+}
+
+async fn successful(response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if let Some(overloaded) = overloaded(status, response.headers()) {
+        return Err(overloaded.into());
+    }
+    if status == StatusCode::GONE {
+        return Err(Expired.into());
+    }
+    if !status.is_success() {
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        return Err(Error::Transport(anyhow::format_err!(
+            "hub responds {status}: {}",
+            String::from_utf8_lossy(&body)
+        ))
+        .into());
+    }
+    Ok(response)
+}
+
+// the requests still go through the hub's http handlers (and the server-sent events encoding), so
+// everything other than the socket is exercised as in a deployment
+#[derive(Clone)]
+pub struct InMemoryTransport {
+    router: Router,
+}
+
+impl InMemoryTransport {
+    pub fn new(hub: &Hub) -> Self {
+        Self {
+            router: hub.router(),
+        }
+    }
+
+    // whatever the hub responds, including the errors
+    async fn send(&self, method: Method, path: &str, body: Body) -> anyhow::Result<Response> {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(protocol::HEADER, protocol::VERSION)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)?;
+        Ok(self.router.clone().oneshot(request).await?)
+    }
+
+    async fn request(&self, method: Method, path: &str, body: Body) -> anyhow::Result<Response> {
+        successful(self.send(method, path, body).await?).await
+    }
+
+    async fn subscribe<M: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+    ) -> anyhow::Result<Subscription<M>> {
+        let response = self.request(Method::GET, path, Body::empty()).await?;
+        let mut body = response.into_body().into_data_stream();
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut buffer = Vec::new();
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        let _ = sender.send(Err(err.into())).await;
+                        return;
+                    }
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+                    let event = buffer.drain(..end + 2).collect::<Vec<_>>();
+                    let data = String::from_utf8_lossy(&event)
+                        .lines()
+                        .filter_map(|line| line.strip_prefix("data:"))
+                        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if data.is_empty() {
+                        continue;
+                    }
+                    if sender
+                        .send(serde_json::from_str(&data).map_err(Into::into))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(receiver)))
+    }
+
+    async fn post(&self, path: &str, message: &impl Serialize) -> anyhow::Result<()> {
+        self.posted(path, message).await?;
+        Ok(())
+    }
+
+    async fn posted(&self, path: &str, message: &impl Serialize) -> anyhow::Result<Response> {
+        let body = Body::from(serde_json::to_vec(message)?);
+        self.request(Method::POST, path, body).await
+    }
+}
+
+impl HubTransport for InMemoryTransport {
+    // the hub is built from the same crate, so it always speaks the same version
+    async fn handshake(&self) -> anyhow::Result<protocol::Handshake> {
+        Ok(Default::default())
+    }
+
+    async fn subscribe_gossip<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/gossip").await
+    }
+
+    async fn subscribe_chain<M: DeserializeOwned + Send + 'static>(
+        &self,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe("/chain").await
+    }
+
+    async fn subscribe_chain_filtered<M: DeserializeOwned + Send + 'static>(
+        &self,
+        filter: &ChainFilter,
+    ) -> anyhow::Result<Subscription<M>> {
+        self.subscribe(&format!("/chain?{}", serde_urlencoded::to_string(filter)?))
+            .await
+    }
+
+    async fn publish_gossip(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<u64>> {
+        let response = self.posted("/gossip/publish", message).await?;
+        acknowledged(response.headers())
+    }
+
+    async fn publish_gossip_detached(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<()> {
+        self.post("/gossip/publish?ack=none", message).await
+    }
+
+    async fn submit_cached<M: DeserializeOwned + Send>(
+        &self,
+        message: &(impl Serialize + Sync),
+    ) -> anyhow::Result<Option<M>> {
+        let body = Body::from(serde_json::to_vec(message)?);
+        let response = self.request(Method::POST, "/gossip/publish", body).await?;
+        cached_result(&to_bytes(response.into_body(), usize::MAX).await?)
+    }
+
+    async fn propose_chain(&self, message: &(impl Serialize + Sync)) -> anyhow::Result<()> {
+        self.post("/chain/propose", message).await
+    }
+
+    async fn report_canary(&self, report: &CanaryReport) -> anyhow::Result<()> {
+        self.post("/canary/report", report).await
+    }
+
+    async fn subscribe_progress(&self) -> anyhow::Result<Subscription<ProgressEvent>> {
+        self.subscribe("/progress").await
+    }
+
+    async fn report_progress(&self, event: &ProgressEvent) -> anyhow::Result<()> {
+        self.post("/progress/report", event).await
+    }
+
+    async fn report_status(&self, status: &WorkerStatus) -> anyhow::Result<()> {
+        self.post("/scheduler/report", status).await
+    }
+
+    async fn upload_log(&self, id: TaskId, stage: &str, log: Bytes) -> anyhow::Result<Digest> {
+        let response = self
+            .request(
+                Method::PUT,
+                &format!("/tasks/{id}/logs/{stage}"),
+                log.into(),
+            )
+            .await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn upload_blob(&self, blob: Bytes) -> anyhow::Result<Digest> {
+        let response = self.request(Method::POST, "/blobs", blob.into()).await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn download_blob(&self, digest: &Digest) -> anyhow::Result<Bytes> {
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/blobs/{}", hex(digest)),
+                Body::empty(),
+            )
+            .await?;
+        Ok(to_bytes(response.into_body(), usize::MAX).await?)
+    }
+
+    async fn stage_record(&self, id: TaskId, stage: &str) -> anyhow::Result<StageRecord> {
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/tasks/{id}/stages/{stage}"),
+                Body::empty(),
+            )
+            .await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn history(&self, id: TaskId) -> anyhow::Result<Vec<HistoryEvent>> {
+        let response = self
+            .request(Method::GET, &format!("/tasks/{id}/history"), Body::empty())
+            .await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn submit_challenge(&self, challenge: &Challenge) -> anyhow::Result<()> {
+        self.post("/challenges/submit", challenge).await
+    }
+
+    async fn subscribe_audits(&self) -> anyhow::Result<Subscription<AuditRequest>> {
+        self.subscribe("/audits").await
+    }
+
+    async fn submit_audit(&self, outcome: &AuditOutcome) -> anyhow::Result<()> {
+        self.post("/audits/submit", outcome).await
+    }
+
+    async fn workflow(&self, digest: Option<&WorkflowDigest>) -> anyhow::Result<Workflow> {
+        let response = self
+            .request(Method::GET, &workflow_path(digest), Body::empty())
+            .await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn notarization(&self, id: TaskId) -> anyhow::Result<Notarization> {
+        let response = self
+            .request(Method::GET, &format!("/tasks/{id}/notarize"), Body::empty())
+            .await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn acquire_lease(&self, request: &LeaseRequest) -> anyhow::Result<Option<Lease>> {
+        let body = Body::from(serde_json::to_vec(request)?);
+        let response = self.send(Method::POST, "/leases", body).await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Ok(None);
+        }
+        let body = to_bytes(successful(response).await?.into_body(), usize::MAX).await?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+
+    async fn renew_lease(&self, lease: u64) -> anyhow::Result<bool> {
+        let response = self
+            .send(
+                Method::POST,
+                &format!("/leases/{lease}/renew"),
+                Body::empty(),
+            )
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        successful(response).await?;
+        Ok(true)
+    }
+
+    async fn release_lease(&self, lease: u64) -> anyhow::Result<()> {
+        self.request(Method::DELETE, &format!("/leases/{lease}"), Body::empty())
+            .await?;
+        Ok(())
+    }
+
+    async fn stage_secrets(&self, request: &SecretRequest) -> anyhow::Result<SealedSecrets> {
+        let response = self.posted("/secrets", request).await?;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
@@ -0,0 +1,470 @@
+// clocks proving a minimum time after their predecessors: the proof part is the evaluation of a
+// verifiable delay function seeded by the causality part, the digests of the predecessor clocks,
+// the stage it is produced for and the digest of the output. the evaluation is a number of
+// sequential squarings, which parallel hardware does not speed up, while it is verified with a
+// couple of exponentiations. so a clock cannot be minted sooner than those squarings take after
+// its predecessors and its output exist, whatever the timestamps of the nodes claim, e.g. for a
+// hub to rate-limit the re-submissions of a stage
+// the function is the one of wesolowski, in the rsa group of the rsa-2048 challenge number, whose
+// factorization nobody is known to have, modulo the sign to rule out the elements of order two.
+// the proof says nothing about the computation of the stage, only about the time, so the client
+// context should require the iterations the fastest hardware expected takes for the minimum gap,
+// see `calibrate`
+#[cfg(feature = "network")]
+use std::future::Future;
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{Digest, HashAlgorithm},
+    Binding, Clock, ClockClientContext, ClockContext, Error, NodeId, OrdinaryClock, Predecessor,
+};
+
+const MODULUS: &str = "\
+    c7970ceedcc3b0754490201a7aa613cd73911081c790f5f1a8726f463550bb5b7ff0db8e1ea1189ec72f93d1650011\
+    bd721aeeacc2acde32a04107f0648c2813a31f5b0b7765ff8b44b4b6ffc93384b646eb09c7cf5e8592d40ea33c8003\
+    9f35b4f14a04b51f7bfd781be4d1673164ba8eb991c2c4d730bbbe35f592bdef524af7e8daefd26c66fc02c479af89\
+    d64d373f442709439de66ceb955f3ea37d5159f6135809f85334b5cb1813addc80cd05609f10ac6a95ad65872c9095\
+    25bdad32bc729592642920f24c61dc5b3c3b7923e56b16a4d9d373d8721f24a3fc0f1b3131f55615172866bccc30f9\
+    5054c824e733a5eb6817f7bc16399d48c6361cc7e5";
+
+// the size of the encoding of an element, i.e. of the modulus
+const ELEMENT_SIZE: usize = 256;
+
+// the rounds of miller-rabin for the challenge primes
+const PRIMALITY_ROUNDS: u64 = 32;
+
+fn modulus() -> BigUint {
+    BigUint::parse_bytes(MODULUS.as_bytes(), 16).unwrap()
+}
+
+// big endian, padded to `ELEMENT_SIZE`
+fn to_bytes(x: &BigUint) -> Vec<u8> {
+    let bytes = x.to_bytes_be();
+    [
+        &vec![0; ELEMENT_SIZE.saturating_sub(bytes.len())][..],
+        &bytes,
+    ]
+    .concat()
+}
+
+// the representative of the class of `x` and `-x`
+fn normalize(x: BigUint, modulus: &BigUint) -> BigUint {
+    let negated = modulus - &x;
+    x.min(negated)
+}
+
+// the seed expanded past the size of the modulus, so the element is close to uniform
+fn to_group(hash: HashAlgorithm, seed: &Digest, modulus: &BigUint) -> BigUint {
+    let expanded = (0..9u8)
+        .flat_map(|i| hash.digest(&[&seed[..], &[i]].concat()))
+        .collect::<Vec<_>>();
+    normalize(BigUint::from_bytes_be(&expanded) % modulus, modulus)
+}
+
+fn is_prime(hash: HashAlgorithm, n: &BigUint) -> bool {
+    let one = BigUint::from(1u8);
+    let two = BigUint::from(2u8);
+    if n < &two {
+        return false;
+    }
+    for p in [2u8, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37] {
+        if n == &BigUint::from(p) {
+            return true;
+        }
+        if (n % p) == BigUint::ZERO {
+            return false;
+        }
+    }
+    let minus_one = n - &one;
+    let shift = minus_one.trailing_zeros().unwrap_or_default();
+    let odd = &minus_one >> shift;
+    // the bases are derived from the candidate, so they are fixed for a given one
+    (0..PRIMALITY_ROUNDS).all(|round| {
+        let base = BigUint::from_bytes_be(
+            &hash.digest(&[&n.to_bytes_be()[..], &round.to_be_bytes()].concat()),
+        ) % (n - 3u8)
+            + &two;
+        let mut x = base.modpow(&odd, n);
+        if x == one || x == minus_one {
+            return true;
+        }
+        (1..shift).any(|_| {
+            x = x.modpow(&two, n);
+            x == minus_one
+        })
+    })
+}
+
+// the prime of 128 bits the evaluation is proven against, derived from the statement
+fn challenge(hash: HashAlgorithm, x: &BigUint, y: &BigUint, iterations: u64) -> BigUint {
+    let message = [&to_bytes(x)[..], &to_bytes(y), &iterations.to_be_bytes()].concat();
+    (0u64..)
+        .map(|counter| {
+            let digest = hash.digest(&[&message[..], &counter.to_be_bytes()].concat());
+            let mut candidate = [0; 16];
+            candidate.copy_from_slice(&digest[..16]);
+            candidate[0] |= 0x80;
+            candidate[15] |= 1;
+            BigUint::from_bytes_be(&candidate)
+        })
+        .find(|candidate| is_prime(hash, candidate))
+        .unwrap()
+}
+
+// `x` squared `iterations` times, along with the proof, i.e. `x` to the power of the quotient of
+// `2^iterations` by the challenge, which is computed bit by bit as the squarings go, so it takes
+// as many squarings again
+fn evaluate(hash: HashAlgorithm, x: &BigUint, iterations: u64) -> (BigUint, BigUint) {
+    let modulus = modulus();
+    let mut y = x.clone();
+    for _ in 0..iterations {
+        y = &y * &y % &modulus
+    }
+    let y = normalize(y, &modulus);
+    let l = challenge(hash, x, &y, iterations);
+    let (mut proof, mut remainder) = (BigUint::from(1u8), BigUint::from(1u8));
+    for _ in 0..iterations {
+        remainder <<= 1;
+        proof = &proof * &proof % &modulus;
+        if remainder >= l {
+            remainder -= &l;
+            proof = proof * x % &modulus
+        }
+    }
+    (y, normalize(proof, &modulus))
+}
+
+fn verify_evaluation(
+    hash: HashAlgorithm,
+    x: &BigUint,
+    iterations: u64,
+    y: &BigUint,
+    proof: &BigUint,
+) -> anyhow::Result<()> {
+    let modulus = modulus();
+    for element in [y, proof] {
+        anyhow::ensure!(
+            element > &BigUint::ZERO && element <= &(&modulus >> 1),
+            "evaluation is not an element of the group"
+        )
+    }
+    let l = challenge(hash, x, y, iterations);
+    let remainder = BigUint::from(2u8).modpow(&BigUint::from(iterations), &l);
+    let expected = proof.modpow(&l, &modulus) * x.modpow(&remainder, &modulus) % &modulus;
+    anyhow::ensure!(
+        normalize(expected, &modulus) == *y,
+        "proof does not prove the evaluation"
+    );
+    Ok(())
+}
+
+// the iterations this host squares within the duration, by a short sample
+pub fn calibrate(duration: Duration) -> u64 {
+    const SAMPLE: u32 = 10_000;
+    let modulus = modulus();
+    let mut x = to_group(HashAlgorithm::default(), &Default::default(), &modulus);
+    let start = Instant::now();
+    for _ in 0..SAMPLE {
+        x = &x * &x % &modulus
+    }
+    (duration.as_secs_f64() / start.elapsed().as_secs_f64() * SAMPLE as f64) as _
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Clock)]
+pub struct VdfClock {
+    #[causality]
+    pub clock: OrdinaryClock,
+    // of the clocks of the inputs, in the order of the predecessors (see `clock_digest`)
+    pub predecessors: Vec<Digest>,
+    pub iterations: u64,
+    // the evaluation and its proof, as big endian bytes of the size of the modulus
+    pub evaluation: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+// of the whole clock, i.e. along with its evaluation, so the delay of a clock is counted from the
+// evaluations of its predecessors
+fn clock_digest(hash: HashAlgorithm, clock: &VdfClock) -> Digest {
+    let mut message = clock.clock.encode();
+    message.extend(clock.iterations.to_be_bytes());
+    message.extend(&clock.evaluation);
+    message.extend(&clock.proof);
+    hash.digest(&message)
+}
+
+fn seed(
+    hash: HashAlgorithm,
+    clock: &OrdinaryClock,
+    predecessors: &[Digest],
+    binding: &Binding,
+    output: &[u8],
+) -> Digest {
+    let mut message = clock.encode();
+    message.extend((predecessors.len() as u32).to_be_bytes());
+    for digest in predecessors {
+        message.extend(digest)
+    }
+    message.extend(binding.encode());
+    message.extend(hash.digest(output));
+    hash.digest(&message)
+}
+
+#[derive(Debug)]
+pub struct VdfClientContext<O> {
+    hash: HashAlgorithm,
+    min_iterations: u64,
+    _output: PhantomData<O>,
+}
+
+impl<O> VdfClientContext<O> {
+    pub fn new(hash: HashAlgorithm, min_iterations: u64) -> Self {
+        Self {
+            hash,
+            min_iterations,
+            _output: PhantomData,
+        }
+    }
+
+    fn verify_bytes(
+        &self,
+        clock: &VdfClock,
+        output: &[u8],
+        binding: &Binding,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            clock.iterations >= self.min_iterations,
+            "clock is delayed by {} iterations instead of at least {}",
+            clock.iterations,
+            self.min_iterations
+        );
+        anyhow::ensure!(
+            clock.evaluation.len() == ELEMENT_SIZE && clock.proof.len() == ELEMENT_SIZE,
+            "evaluation is not encoded in {ELEMENT_SIZE} bytes"
+        );
+        let seed = seed(
+            self.hash,
+            &clock.clock,
+            &clock.predecessors,
+            binding,
+            output,
+        );
+        verify_evaluation(
+            self.hash,
+            &to_group(self.hash, &seed, &modulus()),
+            clock.iterations,
+            &BigUint::from_bytes_be(&clock.evaluation),
+            &BigUint::from_bytes_be(&clock.proof),
+        )
+    }
+}
+
+impl<O: AsRef<[u8]>> ClockClientContext for VdfClientContext<O> {
+    type Clock = VdfClock;
+    type Output = O;
+
+    fn verify(
+        &self,
+        clock: &Self::Clock,
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<(), Error> {
+        self.verify_bytes(clock, output.as_ref(), binding)
+            .map_err(Error::ProofInvalid)
+    }
+}
+
+#[derive(Debug, ClockClientContext)]
+pub struct VdfContext<I, O> {
+    id: NodeId,
+    iterations: u64,
+    #[client_context]
+    client: VdfClientContext<O>,
+    _input: PhantomData<I>,
+}
+
+impl<I, O> VdfContext<I, O> {
+    // the iterations of the clocks of this producer, which must be at least the ones the client
+    // context requires for them to pass the verification of the others
+    pub fn new(id: NodeId, iterations: u64, client: VdfClientContext<O>) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            iterations >= client.min_iterations,
+            "{iterations} iterations are below the required {}",
+            client.min_iterations
+        );
+        Ok(Self {
+            id,
+            iterations,
+            client,
+            _input: PhantomData,
+        })
+    }
+}
+
+// the clock, the digests of its predecessors and the element the evaluation starts from
+struct Unevaluated {
+    clock: OrdinaryClock,
+    predecessors: Vec<Digest>,
+    x: BigUint,
+}
+
+impl Unevaluated {
+    fn evaluate(self, hash: HashAlgorithm, iterations: u64) -> VdfClock {
+        let (y, proof) = evaluate(hash, &self.x, iterations);
+        VdfClock {
+            clock: self.clock,
+            predecessors: self.predecessors,
+            iterations,
+            evaluation: to_bytes(&y),
+            proof: to_bytes(&proof),
+        }
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> VdfContext<I, O> {
+    fn unevaluated(
+        &self,
+        predecessors: &[Predecessor<'_, VdfClock, I>],
+        output: &O,
+        binding: &Binding,
+    ) -> Result<Unevaluated, Error> {
+        for (clock, input, binding) in predecessors {
+            self.client
+                .verify_bytes(clock, input.as_ref(), binding)
+                .map_err(Error::ProofInvalid)?
+        }
+        let hash = self.client.hash;
+        let clock =
+            OrdinaryClock::new(predecessors.iter().map(|(clock, ..)| &clock.clock), self.id);
+        let digests = predecessors
+            .iter()
+            .map(|(clock, ..)| clock_digest(hash, clock))
+            .collect::<Vec<_>>();
+        let seed = seed(hash, &clock, &digests, binding, output.as_ref());
+        Ok(Unevaluated {
+            clock,
+            predecessors: digests,
+            x: to_group(hash, &seed, &modulus()),
+        })
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> ClockContext for VdfContext<I, O> {
+    type Input = I;
+
+    fn prove(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> Result<Self::Clock, Error> {
+        Ok(self
+            .unevaluated(predecessors, output, binding)?
+            .evaluate(self.client.hash, self.iterations))
+    }
+
+    // the evaluation takes the whole delay by design, so it is done on a blocking thread
+    #[cfg(feature = "network")]
+    fn prove_async(
+        &self,
+        predecessors: &[Predecessor<'_, Self::Clock, Self::Input>],
+        output: &Self::Output,
+        binding: &Binding,
+    ) -> impl Future<Output = Result<Self::Clock, Error>> + Send {
+        let unevaluated = self.unevaluated(predecessors, output, binding);
+        let (hash, iterations) = (self.client.hash, self.iterations);
+        async move {
+            let unevaluated = unevaluated?;
+            tokio::task::spawn_blocking(move || unevaluated.evaluate(hash, iterations))
+                .await
+                .map_err(|err| Error::Proving(err.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ITERATIONS: u64 = 64;
+
+    fn prove(output: &[u8]) -> (VdfClock, Binding) {
+        let client = VdfClientContext::new(HashAlgorithm::default(), ITERATIONS);
+        let context = VdfContext::<Vec<u8>, Vec<u8>>::new(1, ITERATIONS, client).unwrap();
+        let binding = Binding::new(1, "stage");
+        let clock = context.prove(&[], &output.to_vec(), &binding).unwrap();
+        (clock, binding)
+    }
+
+    fn verify(clock: &VdfClock, output: &[u8], binding: &Binding) -> Result<(), Error> {
+        VdfClientContext::new(HashAlgorithm::default(), ITERATIONS).verify(
+            clock,
+            &output.to_vec(),
+            binding,
+        )
+    }
+
+    #[test]
+    fn verifies_proven_clock() {
+        let (clock, binding) = prove(b"output");
+        verify(&clock, b"output", &binding).unwrap();
+        assert!(verify(&clock, b"other", &binding).is_err());
+        assert!(verify(&clock, b"output", &Binding::new(1, "other")).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_evaluation() {
+        let (clock, binding) = prove(b"output");
+        for tamper in [
+            |clock: &mut VdfClock| clock.evaluation[ELEMENT_SIZE - 1] ^= 1,
+            |clock: &mut VdfClock| clock.proof[ELEMENT_SIZE - 1] ^= 1,
+        ] {
+            let mut tampered = clock.clone();
+            tamper(&mut tampered);
+            assert!(verify(&tampered, b"output", &binding).is_err())
+        }
+    }
+
+    #[test]
+    fn rejects_too_few_iterations() {
+        let (clock, binding) = prove(b"output");
+        let client = VdfClientContext::new(HashAlgorithm::default(), ITERATIONS + 1);
+        assert!(client
+            .verify(&clock, &b"output".to_vec(), &binding)
+            .is_err());
+        let client = VdfClientContext::<Vec<u8>>::new(HashAlgorithm::default(), ITERATIONS + 1);
+        assert!(VdfContext::<Vec<u8>, _>::new(1, ITERATIONS, client).is_err());
+    }
+
+    // the negation of the evaluation squares to the same, but only the smaller one is accepted
+    #[test]
+    fn rejects_unnormalized_element() {
+        let (mut clock, binding) = prove(b"output");
+        let modulus = modulus();
+        let y = BigUint::from_bytes_be(&clock.evaluation);
+        assert!(y <= &modulus >> 1);
+        clock.evaluation = to_bytes(&(&modulus - y));
+        assert!(verify(&clock, b"output", &binding).is_err());
+    }
+
+    #[test]
+    fn tests_primality() {
+        let hash = HashAlgorithm::default();
+        let prime = |n: u128| is_prime(hash, &BigUint::from(n));
+        for n in [2, 3, 37, 41, 65537, (1 << 61) - 1, (1 << 127) - 1] {
+            assert!(prime(n), "{n} is prime")
+        }
+        for n in [0, 1, 4, 1369, 65535, (1 << 64) + 1] {
+            assert!(!prime(n), "{n} is composite")
+        }
+        // carmichael numbers, the last ones without a factor caught by the trial divisions
+        for n in [561, 1105, 1729, 56052361, 118901521] {
+            assert!(!prime(n), "{n} is a carmichael number")
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// the golden vectors of the wire format, which pin how the clocks, the stages and the results
+// already published to the chain are decoded and verified, so a change of the encoding cannot
+// silently stop them from verifying. a vector is a json file of `vectors/` as it was published,
+// along with what is expected of it
+// * a clock vector holds two clocks and how the first compares to the second
+// * a stage or a result vector holds the message in the protocol version it was published in,
+//   the workflow it is verified against, the digest of the workflow (i.e. its version, which the
+//   messages refer to), and the outcome of the verification
+// every vector must also round-trip: decoding and encoding it again gives back exactly the json
+// it is written in, for a message by up-converting it to the current version on the way in and
+// down-converting it back on the way out. so a field that is renamed, dropped or encoded another
+// way fails the vectors of the messages that carry it
+// the vectors are never edited once they are published, since that is what they protect. a new
+// field or version gets new vectors instead
+use std::{fmt, path::Path};
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    crypto::StandardSuite, hex, protocol, Allowlist, ClockOrdering, CompareClock as _, Error,
+    OrdinaryClientContext, OrdinaryClock, TaskResult, TaskStage, Workflow,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Vector {
+    pub description: String,
+    #[serde(flatten)]
+    pub kind: Kind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Kind {
+    Clock {
+        clock: Value,
+        other: Value,
+        ordering: Ordering,
+    },
+    Stage(Message),
+    Result(Message),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    pub workflow: Value,
+    // hex encoded, with the standard crypto suite
+    pub digest: String,
+    pub message: Value,
+    pub expect: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ordering {
+    Before,
+    After,
+    Equal,
+    Concurrent,
+}
+
+impl From<ClockOrdering> for Ordering {
+    fn from(ordering: ClockOrdering) -> Self {
+        match ordering {
+            ClockOrdering::Before => Self::Before,
+            ClockOrdering::After => Self::After,
+            ClockOrdering::Equal => Self::Equal,
+            ClockOrdering::Concurrent => Self::Concurrent,
+        }
+    }
+}
+
+// of the verification, by the variant of `Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Valid,
+    MissingClock,
+    OrderViolation,
+    ProofInvalid,
+    WorkflowMismatch,
+}
+
+impl From<&Result<(), Error>> for Outcome {
+    fn from(result: &Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => Self::Valid,
+            Err(Error::MissingClock(_)) => Self::MissingClock,
+            Err(Error::OrderViolation(_)) => Self::OrderViolation,
+            Err(Error::ProofInvalid(_)) => Self::ProofInvalid,
+            Err(Error::WorkflowMismatch(_)) => Self::WorkflowMismatch,
+            Err(Error::Transport(_)) => unreachable!("verification does not go through the hub"),
+            Err(Error::Proving(_)) => unreachable!("verification does not prove"),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// the vectors of the directory by their file names, in order
+pub fn load(dir: &Path) -> anyhow::Result<Vec<(String, Vector)>> {
+    let mut vectors = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let vector = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|err| anyhow::format_err!("malformed vector {}: {err}", path.display()))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        vectors.push((name.into_owned(), vector))
+    }
+    vectors.sort_by(|(name, _), (other_name, _)| name.cmp(other_name));
+    Ok(vectors)
+}
+
+pub fn check(vector: &Vector) -> anyhow::Result<()> {
+    match &vector.kind {
+        Kind::Clock {
+            clock,
+            other,
+            ordering,
+        } => {
+            let clock = round_trip::<OrdinaryClock>(clock, "clock")?;
+            let other = round_trip::<OrdinaryClock>(other, "other clock")?;
+            let actual = Ordering::from(clock.compare(&other));
+            anyhow::ensure!(
+                actual == *ordering,
+                "clock compares {actual:?} instead of {ordering:?}"
+            );
+            Ok(())
+        }
+        Kind::Stage(message) => {
+            check_message::<TaskStage<OrdinaryClock, Bytes>>(message, |stage, task| {
+                stage.verify(task, &OrdinaryClientContext::new())?;
+                stage.verify_programs(task, &Allowlist)
+            })
+        }
+        Kind::Result(message) => {
+            check_message::<TaskResult<OrdinaryClock, Bytes>>(message, |result, task| {
+                result.verify(task, &OrdinaryClientContext::new())?;
+                result.verify_programs(task, &Allowlist)
+            })
+        }
+    }
+}
+
+fn check_message<M: Serialize + DeserializeOwned>(
+    vector: &Message,
+    verify: impl Fn(&M, &Workflow) -> Result<(), Error>,
+) -> anyhow::Result<()> {
+    let task = round_trip::<Workflow>(&vector.workflow, "workflow")?;
+    let digest = hex(&task.digest(&StandardSuite::default()));
+    anyhow::ensure!(
+        digest == vector.digest,
+        "workflow digests to {digest} instead of {}",
+        vector.digest
+    );
+
+    if let Some(workflow) = vector.message.get("workflow") {
+        anyhow::ensure!(
+            *workflow == serde_json::to_value(task.digest(&StandardSuite::default()))?,
+            "message is not of the workflow"
+        )
+    }
+
+    let version = match vector.message.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or(anyhow::format_err!("malformed version"))? as u32,
+        None => protocol::default_version(),
+    };
+    let mut upgraded = vector.message.clone();
+    protocol::upgrade(&mut upgraded)?;
+    let message = serde_json::from_value::<M>(upgraded)
+        .map_err(|err| anyhow::format_err!("message does not decode: {err}"))?;
+    let mut downgraded = serde_json::to_value(&message)?;
+    protocol::downgrade(&mut downgraded, version)?;
+    anyhow::ensure!(
+        downgraded == vector.message,
+        "message does not down-convert back to version {version}: {downgraded}"
+    );
+
+    let outcome = Outcome::from(&verify(&message, &task));
+    anyhow::ensure!(
+        outcome == vector.expect,
+        "message verifies as {outcome} instead of {}",
+        vector.expect
+    );
+    Ok(())
+}
+
+fn round_trip<T: Serialize + DeserializeOwned>(value: &Value, what: &str) -> anyhow::Result<T> {
+    let decoded = serde_json::from_value::<T>(value.clone())
+        .map_err(|err| anyhow::format_err!("{what} does not decode: {err}"))?;
+    let encoded = serde_json::to_value(&decoded)?;
+    anyhow::ensure!(
+        encoded == *value,
+        "{what} does not encode back as it is: {encoded}"
+    );
+    Ok(decoded)
+}
@@ -0,0 +1,153 @@
+// stage programs as WebAssembly modules, executed in process by an interpreter
+// a module is given no imports, so it cannot observe anything but its input, and the interpreter
+// executes deterministically (e.g. with canonical NaNs), which makes the output a pure function of
+// the module and the input. so the stages run this way can be declared `deterministic` in the
+// workflow, and be re-executed by anyone: by the hub to decide challenges (`WasmReexecutor`) and
+// by the `checker` binary on samples of the chain
+// the abi: a module exports its `memory`, `alloc(len: i32) -> i32` that returns where to write
+// the input, and `run(ptr: i32, len: i32) -> i64` that returns where the output is, as
+// `ptr << 32 | len`
+// the execution is bounded by fuel (roughly one unit per instruction), so a module cannot hang the
+// worker or the hub
+use std::{future::Future, path::PathBuf, pin::Pin, sync::Arc};
+
+use bytes::Bytes;
+use tokio::{fs, task::spawn_blocking};
+use wasmi::{Config, Engine, Linker, Module, Store};
+
+use crate::{
+    crypto::CryptoSuite,
+    hub::Reexecutor,
+    program_digest,
+    worker::{Execution, Progress, StageExecutor},
+    ProgramDigest,
+};
+
+pub const DEFAULT_FUEL: u64 = 10_000_000_000;
+
+pub fn execute(module: &[u8], input: &[u8], fuel: u64) -> anyhow::Result<Bytes> {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, module)?;
+    let mut store = Store::new(&engine, ());
+    store.set_fuel(fuel)?;
+    let instance = Linker::new(&engine).instantiate_and_start(&mut store, &module)?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or(anyhow::format_err!("module exports no memory"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+    let run = instance.get_typed_func::<(i32, i32), i64>(&store, "run")?;
+    let len = i32::try_from(input.len())?;
+    let ptr = alloc.call(&mut store, len)?;
+    memory.write(&mut store, ptr as u32 as _, input)?;
+    let output = run.call(&mut store, (ptr, len))? as u64;
+    let mut buf = vec![0; output as u32 as _];
+    memory.read(&store, (output >> 32) as _, &mut buf)?;
+    Ok(buf.into())
+}
+
+// executes `<modules>/<stage>.wasm`, and `<modules>/<stage>.canary.wasm` for the canary
+#[derive(Debug, Clone)]
+pub struct WasmExecutor {
+    stable: PathBuf,
+    canary: PathBuf,
+    crypto: Arc<dyn CryptoSuite>,
+    fuel: u64,
+}
+
+impl WasmExecutor {
+    pub fn new(modules: impl Into<PathBuf>, stage: &str, crypto: Arc<dyn CryptoSuite>) -> Self {
+        let modules = modules.into();
+        Self {
+            stable: modules.join(format!("{stage}.wasm")),
+            canary: modules.join(format!("{stage}.canary.wasm")),
+            crypto,
+            fuel: DEFAULT_FUEL,
+        }
+    }
+
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+}
+
+async fn execute_module(
+    crypto: &dyn CryptoSuite,
+    path: &PathBuf,
+    input: &Bytes,
+    fuel: u64,
+) -> anyhow::Result<Execution> {
+    let module = fs::read(path).await?;
+    let program = program_digest(crypto, &module);
+    let input = input.clone();
+    let output = spawn_blocking(move || execute(&module, &input, fuel)).await??;
+    Ok(Execution {
+        program,
+        output,
+        content_type: None,
+        log: Bytes::new(),
+    })
+}
+
+impl StageExecutor for WasmExecutor {
+    // a module has no way to report progress
+    fn execute(
+        &self,
+        input: &Bytes,
+        _: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        execute_module(&*self.crypto, &self.stable, input, self.fuel)
+    }
+
+    fn execute_canary(
+        &self,
+        input: &Bytes,
+        _: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        execute_module(&*self.crypto, &self.canary, input, self.fuel)
+    }
+}
+
+// re-executes the modules of the same layout, as either the stable or the canary module,
+// whichever is of the recorded version
+#[derive(Debug, Clone)]
+pub struct WasmReexecutor {
+    modules: PathBuf,
+    crypto: Arc<dyn CryptoSuite>,
+    fuel: u64,
+}
+
+impl WasmReexecutor {
+    pub fn new(modules: impl Into<PathBuf>, crypto: Arc<dyn CryptoSuite>) -> Self {
+        Self {
+            modules: modules.into(),
+            crypto,
+            fuel: DEFAULT_FUEL,
+        }
+    }
+}
+
+impl Reexecutor for WasmReexecutor {
+    fn reexecute<'a>(
+        &'a self,
+        stage: &'a str,
+        program: &'a ProgramDigest,
+        input: Bytes,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Bytes>> + Send + 'a>> {
+        Box::pin(async move {
+            for name in [format!("{stage}.wasm"), format!("{stage}.canary.wasm")] {
+                let Ok(module) = fs::read(self.modules.join(name)).await else {
+                    continue;
+                };
+                if program_digest(&*self.crypto, &module) != *program {
+                    continue;
+                }
+                let fuel = self.fuel;
+                return spawn_blocking(move || execute(&module, &input, fuel)).await?;
+            }
+            anyhow::bail!("program version of stage {stage} is not available")
+        })
+    }
+}
@@ -0,0 +1,1500 @@
+// the execution side of a stage: subscribing to the gossip for the tasks that are ready for the
+// stage, executing them, proving the clocks and passing the results on. the `compute` binary is a
+// thin wrapper around it that executes scripts, and a service can embed it into its own runtime
+// with a `StageExecutor` that executes in process instead
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::{self, Display, Formatter},
+    future::Future,
+    marker::PhantomData,
+    os::fd::AsRawFd as _,
+    path::{Path, PathBuf},
+    pin::{pin, Pin},
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering::Relaxed},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::unix::pipe,
+    process::Command,
+    select,
+    sync::{mpsc, watch, Notify},
+    time::{interval, sleep},
+};
+use tokio_stream::StreamExt as _;
+use tracing::{info, warn};
+
+use crate::{
+    attribution::Causality,
+    blob::{offload, reassemble, BlobRef, DEFAULT_MAX_BLOB_SIZE, DEFAULT_MAX_INLINE_SIZE},
+    branching,
+    compression::{self, compress, Compression},
+    crypto::{CryptoSuite, Digest},
+    hex,
+    hub::{PublishAck, Reexecutor},
+    looping, output_digest, outputs,
+    payload::Payload,
+    program_digest, protocol,
+    routing::Route,
+    secrets::{SecretSource, SecretStore, Secrets},
+    transport::{Expired, HubTransport},
+    Binding, CanaryReport, ClockContext, ClockLimits, LeaseRequest, Membership, NodeId,
+    Predecessor, ProgramDigest, ProgressEvent, StageOutcome, StageSource, TaskId, TaskResult,
+    TaskStage, WorkerStatus, Workflow, WorkflowDigest,
+};
+
+#[derive(Debug, Clone)]
+pub struct Execution {
+    // the program version that actually runs
+    pub program: ProgramDigest,
+    pub output: Bytes,
+    // of the output, `None` for opaque bytes
+    pub content_type: Option<String>,
+    // the diagnostics written by the program, e.g. its stderr, empty if there is none
+    pub log: Bytes,
+}
+
+// a stage program that runs but exits with a non-zero code, whose log is still worth keeping for
+// debugging. what the code means is up to the workflow, see `Workflow::exit_outcome`, so the
+// output is kept as well
+#[derive(Debug)]
+pub struct ExecutionError {
+    pub status: ExitStatus,
+    pub program: ProgramDigest,
+    pub output: Bytes,
+    pub log: Bytes,
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "stage program exits with {}", self.status)
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+// the liveness reports of an execution, forwarded to the hub as progress events while the
+// execution goes on
+#[derive(Debug, Clone)]
+pub struct Progress(mpsc::UnboundedSender<String>);
+
+impl Progress {
+    pub fn report(&self, message: impl Into<String>) {
+        let _ = self.0.send(message.into());
+    }
+}
+
+pub trait StageExecutor {
+    fn execute(
+        &self,
+        input: &Bytes,
+        progress: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send;
+
+    // the new program version being rolled out, only called for the tasks routed to the canary
+    fn execute_canary(
+        &self,
+        input: &Bytes,
+        progress: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        let _ = (input, progress);
+        async { anyhow::bail!("no canary program") }
+    }
+}
+
+// executes `<scripts>/<stage>`, and `<scripts>/<stage>.canary` for the canary, which read the
+// input from stdin and write the output to stdout
+// a script may also write progress lines to file descriptor 3 (`PROGRESS_FD`), e.g.
+// `os.write(3, b"50%\n")` in python, each of which is reported as a progress event
+// with `secrets` the secrets of the stage are fetched before each execution and passed to the
+// script as environment variables of their names (see `secrets`). failing to fetch them, e.g. for
+// a worker key the hub does not list, fails the worker like a script that fails to start
+#[derive(Debug, Clone)]
+pub struct ScriptExecutor {
+    stable: PathBuf,
+    canary: PathBuf,
+    crypto: Arc<dyn CryptoSuite>,
+    secrets: Option<Arc<dyn SecretSource>>,
+}
+
+impl ScriptExecutor {
+    pub fn new(scripts: impl Into<PathBuf>, stage: &str, crypto: Arc<dyn CryptoSuite>) -> Self {
+        let scripts = scripts.into();
+        Self {
+            stable: scripts.join(stage),
+            canary: scripts.join(format!("{stage}.canary")),
+            crypto,
+            secrets: None,
+        }
+    }
+
+    pub fn secrets(mut self, secrets: Arc<dyn SecretSource>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    async fn execute_with_secrets(
+        &self,
+        program: &Path,
+        input: &[u8],
+        progress: Progress,
+    ) -> anyhow::Result<Execution> {
+        let secrets = match &self.secrets {
+            Some(secrets) => secrets.secrets().await?,
+            None => Default::default(),
+        };
+        execute_script(&*self.crypto, program, input, &secrets, progress).await
+    }
+}
+
+// digest the program on every execution instead of once on start, so the recorded version is
+// always the one that actually runs, even if the script is replaced in place
+async fn execute_script(
+    crypto: &dyn CryptoSuite,
+    program: &Path,
+    input: &[u8],
+    secrets: &Secrets,
+    progress: Progress,
+) -> anyhow::Result<Execution> {
+    let digest = program_digest(crypto, &fs::read(program).await?);
+    let (reader, writer) = std::io::pipe()?;
+    let mut command = Command::new(program);
+    command
+        .envs(secrets.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let writer_fd = writer.as_raw_fd();
+    // safety: only async-signal-safe `dup2` is called between fork and exec. the duplicated
+    // descriptor does not inherit the close-on-exec flag of the pipe, so it survives the exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::dup2(writer_fd, PROGRESS_FD) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    // the write end is only held by the script from now on, so reading ends when the script exits
+    drop(writer);
+    let mut lines = BufReader::new(pipe::Receiver::from_owned_fd(reader.into())?).lines();
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            progress.report(line)
+        }
+    });
+    child.stdin.take().unwrap().write_all(input).await?;
+    let output = child.wait_with_output().await?;
+    let log = Bytes::from(output.stderr);
+    if !output.status.success() {
+        return Err(ExecutionError {
+            status: output.status,
+            program: digest,
+            output: Bytes::from(output.stdout),
+            log,
+        }
+        .into());
+    }
+    Ok(Execution {
+        program: digest,
+        output: Bytes::from(output.stdout),
+        content_type: None,
+        log,
+    })
+}
+
+pub const PROGRESS_FD: i32 = 3;
+
+impl StageExecutor for ScriptExecutor {
+    fn execute(
+        &self,
+        input: &Bytes,
+        progress: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        self.execute_with_secrets(&self.stable, input, progress)
+    }
+
+    fn execute_canary(
+        &self,
+        input: &Bytes,
+        progress: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        self.execute_with_secrets(&self.canary, input, progress)
+    }
+}
+
+// re-executes the scripts of the same layout for the hub to decide challenges, as either the
+// stable or the canary program, whichever is of the recorded version, with the secrets of the
+// stage taken from the store of the hub if any
+#[derive(Debug, Clone)]
+pub struct ScriptReexecutor {
+    scripts: PathBuf,
+    crypto: Arc<dyn CryptoSuite>,
+    secrets: Option<Arc<dyn SecretStore>>,
+}
+
+impl ScriptReexecutor {
+    pub fn new(scripts: impl Into<PathBuf>, crypto: Arc<dyn CryptoSuite>) -> Self {
+        Self {
+            scripts: scripts.into(),
+            crypto,
+            secrets: None,
+        }
+    }
+
+    pub fn secrets(mut self, secrets: Arc<dyn SecretStore>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+}
+
+impl Reexecutor for ScriptReexecutor {
+    fn reexecute<'a>(
+        &'a self,
+        stage: &'a str,
+        program: &'a ProgramDigest,
+        input: Bytes,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Bytes>> + Send + 'a>> {
+        Box::pin(async move {
+            let executor = ScriptExecutor::new(&self.scripts, stage, self.crypto.clone());
+            let secrets = match &self.secrets {
+                Some(secrets) => Arc::new(secrets.stage_secrets(stage)?.values),
+                None => Default::default(),
+            };
+            for path in [&executor.stable, &executor.canary] {
+                let Ok(script) = fs::read(path).await else {
+                    continue;
+                };
+                if program_digest(&*self.crypto, &script) != *program {
+                    continue;
+                }
+                let (sender, _) = mpsc::unbounded_channel();
+                return Ok(
+                    execute_script(&*self.crypto, path, &input, &secrets, Progress(sender))
+                        .await?
+                        .output,
+                );
+            }
+            anyhow::bail!("program version of stage {stage} is not available")
+        })
+    }
+}
+
+// executes a closure within the process, under a program version chosen by the service, e.g. the
+// digest of its release tag
+// the closure takes and returns bytes, or with `typed` any payload types, e.g. `payload::Json`
+// of serde types
+#[derive(Debug, Clone)]
+pub struct FnExecutor<F, I = Bytes, O = Bytes> {
+    program: ProgramDigest,
+    f: F,
+    payload: PhantomData<fn(I) -> O>,
+}
+
+impl<F> FnExecutor<F> {
+    pub fn new(program: ProgramDigest, f: F) -> Self {
+        Self::typed(program, f)
+    }
+}
+
+impl<F, I, O> FnExecutor<F, I, O> {
+    pub fn typed(program: ProgramDigest, f: F) -> Self {
+        Self {
+            program,
+            f,
+            payload: PhantomData,
+        }
+    }
+}
+
+// the input is decoded regardless of its declared content type, since a stage is expected to
+// receive whatever the previous stage of its workflow produces
+impl<F, R, I, O> StageExecutor for FnExecutor<F, I, O>
+where
+    F: Fn(I, Progress) -> R + Sync,
+    R: Future<Output = anyhow::Result<O>> + Send,
+    I: Payload,
+    O: Payload,
+{
+    fn execute(
+        &self,
+        input: &Bytes,
+        progress: Progress,
+    ) -> impl Future<Output = anyhow::Result<Execution>> + Send {
+        let output = I::decode(input.clone()).map(|input| (self.f)(input, progress));
+        async move {
+            Ok(Execution {
+                program: self.program,
+                output: output?.await?.encode()?,
+                content_type: O::CONTENT_TYPE.map(Into::into),
+                log: Bytes::new(),
+            })
+        }
+    }
+}
+
+pub struct Worker<E, C: ClockContext, T> {
+    task: Arc<Workflow>,
+    stage: String,
+    // of the messages the stage executes upon, see `Workflow::sources`
+    sources: Vec<StageSource>,
+    // the versions fetched from the hub by digest, with the crypto suite checking them, see
+    // `registry`
+    registry: Option<Arc<dyn CryptoSuite>>,
+    workflows: Mutex<HashMap<WorkflowDigest, Arc<Workflow>>>,
+    executor: E,
+    context: C,
+    transport: T,
+    max_inline_size: usize,
+    max_blob_size: u64,
+    clock_limits: ClockLimits,
+    membership: Membership,
+    streams: Mutex<HashMap<TaskId, StreamState<C::Clock>>>,
+    joins: Mutex<Joins<C::Clock>>,
+    // the node id and affinity labels to report to the hub's scheduler
+    scheduled: Option<(NodeId, BTreeSet<String>)>,
+    // the shard of the stage this worker serves, if the workflow routes it, see `routing`
+    shard: Option<u32>,
+    publish_ack: PublishAck,
+    load: AtomicU32,
+    // as enabled, and as negotiated with the hub on handshake
+    compression: Option<Compression>,
+    negotiated: OnceLock<Option<Compression>>,
+    clock_deltas: bool,
+    negotiated_deltas: OnceLock<bool>,
+    subscribed: watch::Sender<bool>,
+    // the most clocks to prove at once, and the executed tasks waiting for theirs, see
+    // `batch_proofs`
+    batch_size: usize,
+    pending: Mutex<VecDeque<Proving<C::Clock>>>,
+    proving: Notify,
+    // of a retryable failure of the stage program, see `StageOutcome`
+    retries: u32,
+    dead_letters: Option<PathBuf>,
+    join_timeout: Duration,
+}
+
+// the messages of the upstream stages of a joining stage received so far, by the task, its
+// workflow version and the chunk, along with when the first of them is received and the deadline
+// of the task, if any, see `Worker::join`
+type Joins<C> = HashMap<
+    (TaskId, Option<WorkflowDigest>, Option<u64>),
+    (Instant, Option<u64>, Vec<TaskStage<C, Bytes>>),
+>;
+
+// the message a joining stage executes, along with the upstream stages and their outputs
+type Joined<C> = (TaskStage<C, Bytes>, Vec<(String, Bytes)>);
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+// of retrying to acquire a lease of a stage at its concurrency limit, doubling up to the max
+const LEASE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_LEASE_BACKOFF: Duration = Duration::from_secs(2);
+
+// of retrying a retryable failure of the stage program, doubling on every attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+pub const DEFAULT_RETRIES: u32 = 3;
+
+// of holding the messages of a joining stage for a task without a deadline
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(3600);
+
+// the own clock and output of the last processed chunk of an ongoing streaming task
+struct StreamState<C> {
+    seq: u64,
+    clock: C,
+    output: Bytes,
+}
+
+// an executed task waiting for its clock, with the program version, log and execution time of the
+// stage already added to the message
+struct Proving<C> {
+    message: TaskStage<C, Bytes>,
+    task: Arc<Workflow>,
+    // the upstream stages along with their outputs, empty for a stage executing upon the start
+    upstream: Vec<(String, Bytes)>,
+    previous: Option<StreamState<C>>,
+    output: Bytes,
+    content_type: Option<String>,
+    // the stage of the task the clock is proven for, and the ones the upstream clocks are
+    bindings: Vec<Binding>,
+    binding: Binding,
+}
+
+impl<C> Proving<C> {
+    fn new(
+        message: TaskStage<C, Bytes>,
+        stage: &str,
+        task: Arc<Workflow>,
+        upstream: Vec<(String, Bytes)>,
+        previous: Option<StreamState<C>>,
+        output: Bytes,
+        content_type: Option<String>,
+    ) -> Self {
+        let bindings = upstream
+            .iter()
+            .map(|(upstream, _)| Binding::new(message.id, upstream))
+            .collect();
+        Self {
+            binding: Binding::new(message.id, stage),
+            message,
+            task,
+            upstream,
+            previous,
+            output,
+            content_type,
+            bindings,
+        }
+    }
+
+    // the previous chunk of a streaming task, as the previous iteration of a looping stage, is of
+    // the same stage
+    fn predecessors(&self) -> Vec<Predecessor<'_, C, Bytes>> {
+        let mut predecessors = self
+            .upstream
+            .iter()
+            .zip(&self.bindings)
+            .map(|((stage, output), binding)| (&self.message.clocks[stage], output, binding))
+            .collect::<Vec<_>>();
+        if let Some(stream) = &self.previous {
+            predecessors.push((&stream.clock, &stream.output, &self.binding))
+        }
+        predecessors
+    }
+}
+
+impl<E, C, T> Worker<E, C, T>
+where
+    E: StageExecutor,
+    C: ClockContext<Input = Bytes, Output = Bytes>,
+    C::Clock: PartialOrd + Causality + Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    T: HubTransport,
+{
+    pub fn new(
+        task: Workflow,
+        stage: impl Into<String>,
+        executor: E,
+        context: C,
+        transport: T,
+    ) -> anyhow::Result<Self> {
+        let stage = stage.into();
+        let sources = task
+            .sources(&stage)
+            .ok_or(anyhow::format_err!("unknown stage {stage}"))?;
+        Ok(Self {
+            task: Arc::new(task),
+            stage,
+            sources,
+            registry: None,
+            workflows: Default::default(),
+            executor,
+            context,
+            transport,
+            max_inline_size: DEFAULT_MAX_INLINE_SIZE,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            clock_limits: Default::default(),
+            membership: Default::default(),
+            streams: Default::default(),
+            joins: Default::default(),
+            scheduled: None,
+            shard: None,
+            publish_ack: Default::default(),
+            load: Default::default(),
+            compression: None,
+            negotiated: OnceLock::new(),
+            clock_deltas: false,
+            negotiated_deltas: OnceLock::new(),
+            subscribed: watch::Sender::new(false),
+            batch_size: 1,
+            pending: Default::default(),
+            proving: Notify::new(),
+            retries: DEFAULT_RETRIES,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            dead_letters: None,
+        })
+    }
+
+    // must agree with the hub's limit
+    pub fn max_inline_size(mut self, size: usize) -> Self {
+        self.max_inline_size = size;
+        self
+    }
+
+    // should agree with the hub's limit, beyond which the offloaded inputs are dropped
+    pub fn max_blob_size(mut self, size: u64) -> Self {
+        self.max_blob_size = size;
+        self
+    }
+
+    // should agree with the hub's limits, which keep the oversized messages from the workers in
+    // the first place. only the clocks of the stages outside the workflow are compacted here,
+    // since the clocks may be signed over
+    pub fn clock_limits(mut self, limits: ClockLimits) -> Self {
+        self.clock_limits = limits;
+        self
+    }
+
+    // should agree with the hub's, which prunes the clocks against it on publish. the clocks are
+    // only verified tolerating the pruned entries here, see `Membership`
+    pub fn membership(mut self, membership: Membership) -> Self {
+        self.membership = membership;
+        self
+    }
+
+    // report to the hub's scheduler as the node of the id, so the tasks are assigned to this worker
+    // instead of being executed by every worker of the stage. an unscheduled worker only executes
+    // the tasks that are not assigned to anyone
+    pub fn scheduled(mut self, id: NodeId, labels: BTreeSet<String>) -> Self {
+        self.scheduled = Some((id, labels));
+        self
+    }
+
+    // only execute the tasks routed to the shard, for a stage the workflow routes (see `routing`).
+    // a worker without a shard executes nothing of a routed stage
+    pub fn shard(mut self, shard: Option<u32>) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    // whether to wait for the hub to keep every published output, by default, or only to check it.
+    // an output the hub fails to keep with `PublishAck::None` is lost along with its task, unless
+    // the deadline of the task expires it
+    pub fn publish_ack(mut self, ack: PublishAck) -> Self {
+        self.publish_ack = ack;
+        self
+    }
+
+    // compress the outputs in transit, if the hub handles the compression. only enable it once
+    // every peer of the deployment does, see `compression`
+    pub fn compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // publish only the clocks that are new or changed against the message executed upon, if the
+    // hub handles it, see `delta`. only enable it once every hub of the deployment does
+    pub fn clock_deltas(mut self, enabled: bool) -> Self {
+        self.clock_deltas = enabled;
+        self
+    }
+
+    // prove the clocks of up to `size` executed tasks at once, so a context with an expensive proof
+    // part (see `ClockContext::prove_batch`) amortizes it over many small tasks. a batch is proven
+    // and passed on while the next tasks execute, and the tasks executed meanwhile form the next
+    // batch, so nothing waits for a batch to fill up. a task is counted off the load and releases
+    // its lease once executed. the chunks of a streaming task are still proven one by one, since
+    // each is proven upon the clock of the previous one
+    pub fn batch_proofs(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    // how many more times the stage program is executed after a retryable failure, `DEFAULT_RETRIES`
+    // by default, before the task is given up on as after a permanent one
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    // how long the messages of a joining stage are held for while some of the upstream stages are
+    // missing, e.g. for a dead worker, `DEFAULT_JOIN_TIMEOUT` by default. a task with a deadline
+    // holds them until it expires instead
+    pub fn join_timeout(mut self, timeout: Duration) -> Self {
+        self.join_timeout = timeout;
+        self
+    }
+
+    // where the messages of the tasks given up on for an invalid input are written, as
+    // `<task id>-<stage>.json` (with the chunk sequence number for a streaming task), so they can
+    // be inspected and published again. without a directory they are only reported
+    pub fn dead_letters(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dead_letters = Some(dir.into());
+        self
+    }
+
+    // execute each task under the workflow version it references rather than the one the worker
+    // is created with, fetching the unknown versions from the hub and checking them against their
+    // digests. the tasks of a version without the stage are left to the other workers
+    pub fn registry(mut self, crypto: Arc<dyn CryptoSuite>) -> Self {
+        self.workflows = Mutex::new([(self.task.digest(&*crypto), self.task.clone())].into());
+        self.registry = Some(crypto);
+        self
+    }
+
+    // the workflow version of a message along with the sources of the stage in it, `None` if the
+    // version does not have the stage or cannot be fetched
+    async fn workflow(
+        &self,
+        digest: Option<WorkflowDigest>,
+    ) -> Option<(Arc<Workflow>, Vec<StageSource>)> {
+        let (Some(crypto), Some(digest)) = (&self.registry, digest) else {
+            return Some((self.task.clone(), self.sources.clone()));
+        };
+        let known = self.workflows.lock().unwrap().get(&digest).cloned();
+        let task = match known {
+            Some(task) => task,
+            None => match self.transport.workflow(Some(&digest)).await {
+                Ok(task) if task.digest(&**crypto) == digest => {
+                    info!("fetched workflow version {}", &hex(&digest)[..8]);
+                    let task = Arc::new(task);
+                    self.workflows.lock().unwrap().insert(digest, task.clone());
+                    task
+                }
+                Ok(_) => {
+                    warn!("fetched workflow does not match version {}", hex(&digest));
+                    return None;
+                }
+                Err(err) => {
+                    warn!("failed to fetch workflow version {}: {err}", hex(&digest));
+                    return None;
+                }
+            },
+        };
+        let sources = task.sources(&self.stage)?;
+        Some((task, sources))
+    }
+
+    // resolves once the running worker observes the gossip, so a task published from then on is
+    // not missed, e.g. by a simulation running the workers alongside the client
+    pub async fn subscribed(&self) {
+        let _ = self
+            .subscribed
+            .subscribe()
+            .wait_for(|subscribed| *subscribed)
+            .await;
+    }
+
+    // a payload that does not shrink is sent as is
+    fn compress(&self, payload: Bytes) -> anyhow::Result<(Bytes, Option<Compression>)> {
+        let Some(compression) = self.negotiated.get().copied().flatten() else {
+            return Ok((payload, None));
+        };
+        let compressed = compress(&payload, compression)?;
+        if compressed.len() >= payload.len() {
+            return Ok((payload, None));
+        }
+        Ok((compressed, Some(compression)))
+    }
+
+    async fn offload(&self, payload: Bytes) -> anyhow::Result<(Bytes, Option<BlobRef>)> {
+        if payload.len() <= self.max_inline_size {
+            return Ok((payload, None));
+        }
+        info!("offload payload of {} bytes", payload.len());
+        Ok((
+            Bytes::new(),
+            Some(offload(&self.transport, &payload).await?),
+        ))
+    }
+
+    // runs until the gossip subscription fails
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let handshake = self.transport.handshake().await?;
+        let compression = self.compression.filter(|compression| {
+            handshake.compressions.contains(compression)
+                && compression::SUPPORTED.contains(compression)
+        });
+        if let (Some(requested), None) = (self.compression, compression) {
+            warn!("{requested:?} compression is not handled here or by the hub, send uncompressed")
+        }
+        let _ = self.negotiated.set(compression);
+        if self.clock_deltas && !handshake.clock_deltas {
+            warn!("hub does not handle clock deltas, send full clocks")
+        }
+        let _ = self
+            .negotiated_deltas
+            .set(self.clock_deltas && handshake.clock_deltas);
+        let Some((id, labels)) = &self.scheduled else {
+            return select! {
+                result = self.receive() => result,
+                result = self.prove_batches() => result,
+            };
+        };
+        select! {
+            result = self.receive() => result,
+            result = self.prove_batches() => result,
+            result = self.report_status(*id, labels) => result,
+        }
+    }
+
+    // never returns without batching
+    async fn prove_batches(&self) -> anyhow::Result<()> {
+        if self.batch_size == 1 {
+            return std::future::pending().await;
+        }
+        loop {
+            self.proving.notified().await;
+            loop {
+                let batch = {
+                    let mut pending = self.pending.lock().unwrap();
+                    let size = pending.len().min(self.batch_size);
+                    pending.drain(..size).collect::<Vec<_>>()
+                };
+                if batch.is_empty() {
+                    break;
+                }
+                let clocks = {
+                    let predecessors = batch.iter().map(Proving::predecessors).collect::<Vec<_>>();
+                    let requests = predecessors
+                        .iter()
+                        .zip(&batch)
+                        .map(|(predecessors, proving)| {
+                            (&predecessors[..], &proving.output, &proving.binding)
+                        })
+                        .collect::<Vec<_>>();
+                    self.context.prove_batch_async(&requests).await?
+                };
+                anyhow::ensure!(
+                    clocks.len() == batch.len(),
+                    "proved {} clocks for a batch of {}",
+                    clocks.len(),
+                    batch.len()
+                );
+                info!("proved a batch of {} tasks", batch.len());
+                for (proving, clock) in batch.into_iter().zip(clocks) {
+                    self.publish(proving, clock).await?
+                }
+            }
+        }
+    }
+
+    // a failed report is not fatal, the scheduler just stops assigning to this worker until the
+    // reports get through again
+    async fn report_status(&self, id: NodeId, labels: &BTreeSet<String>) -> anyhow::Result<()> {
+        let mut interval = interval(STATUS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let status = WorkerStatus {
+                id,
+                stages: [self.stage.clone()].into(),
+                labels: labels.clone(),
+                shards: self
+                    .shard
+                    .map(|shard| (self.stage.clone(), shard))
+                    .into_iter()
+                    .collect(),
+                load: self.load.load(Relaxed),
+            };
+            if let Err(err) = self.transport.report_status(&status).await {
+                warn!("failed to report status: {err}")
+            }
+        }
+    }
+
+    async fn receive(&self) -> anyhow::Result<()> {
+        let mut gossip = self
+            .transport
+            .subscribe_gossip::<TaskStage<C::Clock, Bytes>>()
+            .await?;
+        info!("gossip initialized");
+        self.subscribed.send_replace(true);
+        while let Some(message) = gossip.next().await {
+            let mut message = message?;
+            let Some((task, sources)) = self.workflow(message.workflow).await else {
+                continue;
+            };
+            let id = self.scheduled.as_ref().map(|(id, _)| *id);
+            if !sources.contains(&message.source)
+                || message
+                    .assignee
+                    .is_some_and(|assignee| Some(assignee) != id)
+            {
+                continue;
+            }
+            // so is a task that a racing worker is ahead on, by the hints of the hub
+            if let Some(reason) = message.stale(&self.stage) {
+                info!("skip task {:08x}: {reason}", message.id);
+                continue;
+            }
+            // the tasks of the other shards are skipped before their inputs are fetched
+            if task.routing.contains_key(&self.stage) {
+                let Some(shard) = self.shard else {
+                    warn!(
+                        "skip task {:08x} of routed stage {} without a shard",
+                        message.id, self.stage
+                    );
+                    continue;
+                };
+                if message
+                    .routes
+                    .get(&self.stage)
+                    .is_some_and(|route| route.shard != shard)
+                {
+                    continue;
+                }
+            }
+            if let Err(err) =
+                self.clock_limits
+                    .enforce(&mut message.clocks, &message.iterations, &task)
+            {
+                warn!("reject gossip message of task {:08x}: {err}", message.id);
+                continue;
+            }
+            if let Some(blob) = message.blob.take() {
+                match reassemble(&self.transport, &blob, self.max_blob_size).await {
+                    Ok(input) => message.input = input,
+                    Err(err) => {
+                        warn!("failed to reassemble offloaded input: {err}");
+                        continue;
+                    }
+                }
+            }
+            if let Err(err) = message.decompress(self.max_blob_size) {
+                warn!("failed to decompress input: {err}");
+                continue;
+            }
+            if let Err(err) = message.verify_pruned(&task, &self.context, &self.membership) {
+                warn!("failed to verify gossip message: {err}");
+                continue;
+            }
+            if let Err(err) = self.check_route(&message, &task) {
+                warn!("reject gossip message of task {:08x}: {err}", message.id);
+                continue;
+            }
+            if let Err(err) =
+                branching::check_branch(&message.source, &message.branches, &message.input, &task)
+            {
+                warn!("reject gossip message of task {:08x}: {err}", message.id);
+                continue;
+            }
+            if let Err(err) = looping::check_iterations(
+                &message.source,
+                &message.iterations,
+                &message.input,
+                &task,
+            ) {
+                warn!("reject gossip message of task {:08x}: {err}", message.id);
+                continue;
+            }
+            // the stage is on a branch the task does not take, see `branching`
+            if task.taken(&message.branches).sources(&self.stage).is_none() {
+                continue;
+            }
+            let (message, upstream) = if sources.len() > 1 {
+                match self.join(message, &task) {
+                    Some(joined) => joined,
+                    None => continue,
+                }
+            } else {
+                let upstream = match &message.source {
+                    StageSource::Start => Vec::new(),
+                    StageSource::Name(stage) => vec![(stage.clone(), message.input.clone())],
+                };
+                (message, upstream)
+            };
+            match message.chunk {
+                None => info!("start execute for task {:08x}", message.id),
+                Some(chunk) => info!(
+                    "start execute for task {:08x} chunk {}",
+                    message.id, chunk.seq
+                ),
+            }
+            let request = task
+                .concurrency
+                .contains_key(&self.stage)
+                .then(|| LeaseRequest {
+                    id: message.id,
+                    workflow: message.workflow,
+                    stage: self.stage.clone(),
+                });
+            self.load.fetch_add(1, Relaxed);
+            let result = self
+                .leased(request, self.work(message, &task, upstream))
+                .await;
+            self.load.fetch_sub(1, Relaxed);
+            result?
+        }
+        Ok(())
+    }
+
+    // the message of every upstream stage of a joining stage is held until all of them are
+    // received, and then they are executed as one message upon their outputs encoded as named
+    // outputs by the upstream stages (see `outputs`), with the clocks and the records of all of
+    // them. `None` while some are still missing. of the branches of a task, only the taken ones
+    // are waited for, as far as the received messages tell which ones are taken. the messages of a
+    // task past its deadline (which the hub stamps on them) are dropped, as the task never joins
+    fn join(
+        &self,
+        message: TaskStage<C::Clock, Bytes>,
+        task: &Workflow,
+    ) -> Option<Joined<C::Clock>> {
+        let key = (
+            message.id,
+            message.workflow,
+            message.chunk.map(|chunk| chunk.seq),
+        );
+        let received = {
+            let mut joins = self.joins.lock().unwrap();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            joins.retain(|(id, ..), (received, deadline, _)| {
+                let held = match deadline {
+                    Some(deadline) => now < *deadline,
+                    None => received.elapsed() < self.join_timeout,
+                };
+                if !held {
+                    warn!("drop incomplete join of task {id:08x}")
+                }
+                held
+            });
+            let (_, deadline, received) = joins
+                .entry(key)
+                .or_insert_with(|| (Instant::now(), None, Vec::new()));
+            *deadline = deadline.or(message.deadline);
+            // a message published again replaces the earlier one
+            received.retain(|other| other.source != message.source);
+            received.push(message);
+            let branches = received
+                .iter()
+                .flat_map(|message| message.branches.clone())
+                .collect();
+            let sources = task.taken(&branches).sources(&self.stage)?;
+            if received.len() < sources.len() {
+                return None;
+            }
+            joins.remove(&key)?.2
+        };
+        let mut upstream = Vec::new();
+        let mut joined: Option<TaskStage<C::Clock, Bytes>> = None;
+        for message in received {
+            let StageSource::Name(stage) = &message.source else {
+                continue;
+            };
+            upstream.push((stage.clone(), message.input.clone()));
+            match &mut joined {
+                None => joined = Some(message),
+                Some(joined) => {
+                    joined.clocks.extend(message.clocks);
+                    joined.programs.extend(message.programs);
+                    joined.output_digests.extend(message.output_digests);
+                    joined.logs.extend(message.logs);
+                    joined.elapsed.extend(message.elapsed);
+                    joined.routes.extend(message.routes);
+                    joined.branches.extend(message.branches);
+                    joined.iterations.extend(message.iterations);
+                    joined.assignee = joined.assignee.or(message.assignee)
+                }
+            }
+        }
+        let mut joined = joined?;
+        upstream.sort();
+        joined.input = outputs::encode(&upstream.iter().cloned().collect());
+        joined.content_type = None;
+        Some((joined, upstream))
+    }
+
+    // the verified route of the stage must also be the decision on the input
+    fn check_route(
+        &self,
+        message: &TaskStage<C::Clock, Bytes>,
+        task: &Workflow,
+    ) -> anyhow::Result<()> {
+        let (Some(routing), Some(route)) = (
+            task.routing.get(&self.stage),
+            message.routes.get(&self.stage),
+        ) else {
+            return Ok(());
+        };
+        let shard = routing.shard(&message.input)?;
+        anyhow::ensure!(
+            route.shard == shard,
+            "input is routed to shard {} instead of {shard}",
+            route.shard
+        );
+        Ok(())
+    }
+
+    // waits for a lease of the stage if the workflow limits its concurrency, and keeps it renewed
+    // until the work is done. a lost lease does not abort the work, which is already under way
+    async fn leased(
+        &self,
+        request: Option<LeaseRequest>,
+        work: impl Future<Output = anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        let Some(request) = request else {
+            return work.await;
+        };
+        let mut backoff = LEASE_BACKOFF;
+        let lease = loop {
+            match self.transport.acquire_lease(&request).await {
+                Ok(Some(lease)) => break lease,
+                Ok(None) => {}
+                Err(err) => warn!("failed to acquire lease: {err}"),
+            }
+            // jittered, so the waiting workers do not retry in lockstep
+            sleep(backoff.mul_f64(0.5 + rand::random::<f64>())).await;
+            backoff = (backoff * 2).min(MAX_LEASE_BACKOFF)
+        };
+        let mut work = pin!(work);
+        let mut renewal = interval(Duration::from_millis(lease.ttl / 3));
+        renewal.tick().await;
+        let result = loop {
+            select! {
+                result = &mut work => break result,
+                _ = renewal.tick() => match self.transport.renew_lease(lease.lease).await {
+                    Ok(true) => {}
+                    Ok(false) => warn!("lease of task {:08x} is lost", request.id),
+                    Err(err) => warn!("failed to renew lease: {err}"),
+                },
+            }
+        };
+        if let Err(err) = self.transport.release_lease(lease.lease).await {
+            warn!("failed to release lease: {err}")
+        }
+        result
+    }
+
+    // progress events are best effort, so failing to forward them does not fail the execution
+    async fn forward_progress<F: Future<Output = anyhow::Result<Execution>>>(
+        &self,
+        message: &TaskStage<C::Clock, Bytes>,
+        execute: impl FnOnce(Progress) -> F,
+    ) -> anyhow::Result<Execution> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut execution = pin!(execute(Progress(sender)));
+        let forward = |line| async {
+            let event = ProgressEvent {
+                id: message.id,
+                stage: self.stage.clone(),
+                chunk: message.chunk,
+                message: line,
+            };
+            if let Err(err) = self.transport.report_progress(&event).await {
+                warn!("failed to report progress: {err}")
+            }
+        };
+        let result = loop {
+            select! {
+                result = &mut execution => break result,
+                Some(line) = receiver.recv() => forward(line).await,
+            }
+        };
+        while let Ok(line) = receiver.try_recv() {
+            forward(line).await
+        }
+        result
+    }
+
+    // logs are best effort as well
+    async fn upload_log(&self, id: TaskId, log: Bytes) -> Option<Digest> {
+        if log.is_empty() {
+            return None;
+        }
+        match self.transport.upload_log(id, &self.stage, log).await {
+            Ok(digest) => Some(digest),
+            Err(err) => {
+                warn!("failed to upload log: {err}");
+                None
+            }
+        }
+    }
+
+    // executes until the stage program succeeds, as far as the exit codes of the stage tell (see
+    // `Workflow::exit_outcome`), along with the time the successful execution takes. `None` once
+    // the task is given up on. a failure other than of the program exiting, e.g. it failing to
+    // start, is a failure of the worker
+    async fn attempt<F: Future<Output = anyhow::Result<Execution>>>(
+        &self,
+        message: &TaskStage<C::Clock, Bytes>,
+        task: &Workflow,
+        execute: impl Fn(Progress) -> F,
+    ) -> anyhow::Result<Option<(Execution, Duration)>> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempts = 0;
+        loop {
+            let start = Instant::now();
+            let execution = self.forward_progress(message, &execute).await;
+            let execution_time = start.elapsed();
+            let err = match execution {
+                Ok(execution) => return Ok(Some((execution, execution_time))),
+                Err(err) => err.downcast::<ExecutionError>()?,
+            };
+            let outcome = task.exit_outcome(&self.stage, err.status.code());
+            match outcome {
+                StageOutcome::Success => {
+                    let execution = Execution {
+                        program: err.program,
+                        output: err.output,
+                        content_type: None,
+                        log: err.log,
+                    };
+                    return Ok(Some((execution, execution_time)));
+                }
+                StageOutcome::Retryable if attempts < self.retries => {
+                    attempts += 1;
+                    warn!(
+                        "{err} for task {:08x}, retry in {backoff:?} ({attempts}/{})",
+                        message.id, self.retries
+                    );
+                    sleep(backoff).await;
+                    backoff *= 2
+                }
+                _ => {
+                    self.give_up(message, outcome, err).await;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    // the failure is reported to the hub as the last progress event of the stage, which the
+    // subscribers of the progress of the task observe, while the task itself is left to expire
+    async fn give_up(
+        &self,
+        message: &TaskStage<C::Clock, Bytes>,
+        outcome: StageOutcome,
+        err: ExecutionError,
+    ) {
+        warn!("give up task {:08x}: {err}", message.id);
+        if self.upload_log(message.id, err.log.clone()).await.is_some() {
+            info!("log of the failed execution is uploaded")
+        }
+        let reason = match outcome {
+            StageOutcome::InvalidInput => {
+                if let Err(err) = self.dead_letter(message).await {
+                    warn!("failed to dead-letter task {:08x}: {err}", message.id)
+                }
+                "invalid input"
+            }
+            _ => "failed",
+        };
+        let event = ProgressEvent {
+            id: message.id,
+            stage: self.stage.clone(),
+            chunk: message.chunk,
+            message: format!("{reason}: {err}"),
+        };
+        if let Err(err) = self.transport.report_progress(&event).await {
+            warn!("failed to report failure: {err}")
+        }
+    }
+
+    async fn dead_letter(&self, message: &TaskStage<C::Clock, Bytes>) -> anyhow::Result<()> {
+        let Some(dir) = &self.dead_letters else {
+            return Ok(());
+        };
+        let name = match message.chunk {
+            None => format!("{:08x}-{}.json", message.id, self.stage),
+            Some(chunk) => format!("{:08x}-{}-{}.json", message.id, self.stage, chunk.seq),
+        };
+        fs::create_dir_all(dir).await?;
+        fs::write(dir.join(&name), serde_json::to_vec(message)?).await?;
+        info!("dead-lettered task {:08x} as {name}", message.id);
+        Ok(())
+    }
+
+    async fn work(
+        &self,
+        mut message: TaskStage<C::Clock, Bytes>,
+        task: &Arc<Workflow>,
+        upstream: Vec<(String, Bytes)>,
+    ) -> anyhow::Result<()> {
+        let stage = &self.stage;
+        let previous = match message.chunk {
+            None => None,
+            Some(chunk) => {
+                let mut streams = self.streams.lock().unwrap();
+                match streams.get(&message.id) {
+                    Some(stream) if chunk.seq <= stream.seq => {
+                        warn!("skip chunk {} that is already processed", chunk.seq);
+                        return Ok(());
+                    }
+                    Some(stream) if chunk.seq != stream.seq + 1 => {
+                        warn!("chunks {} to {} are missing", stream.seq + 1, chunk.seq - 1)
+                    }
+                    _ => {}
+                }
+                streams.remove(&message.id)
+            }
+        };
+        let attempted = self
+            .attempt(&message, task, |progress| {
+                self.executor.execute(&message.input, progress)
+            })
+            .await?;
+        let Some((mut execution, execution_time)) = attempted else {
+            return Ok(());
+        };
+        if let Some(config) = task
+            .canaries
+            .get(stage)
+            .filter(|config| config.routes(message.id))
+        {
+            let attempted = self
+                .attempt(&message, task, |progress| {
+                    self.executor.execute_canary(&message.input, progress)
+                })
+                .await?;
+            let Some((canary, _)) = attempted else {
+                return Ok(());
+            };
+            let report = CanaryReport {
+                id: message.id,
+                stage: stage.clone(),
+                stable: execution.program,
+                canary: canary.program,
+                stable_len: execution.output.len(),
+                canary_len: canary.output.len(),
+                diff_offset: CanaryReport::diff(&execution.output, &canary.output),
+            };
+            match report.diff_offset {
+                None => info!("canary output matches"),
+                Some(offset) => warn!("canary output differs from offset {offset}"),
+            }
+            if let Err(err) = self.transport.report_canary(&report).await {
+                warn!("failed to report canary: {err}")
+            }
+            if !config.exclude {
+                execution = canary
+            }
+        }
+
+        let (output, content_type) = self
+            .record(&mut message, task, execution, execution_time)
+            .await;
+        let mut proving = Proving::new(
+            message,
+            stage,
+            task.clone(),
+            upstream,
+            previous,
+            output,
+            content_type,
+        );
+        // every iteration of a looping stage is proven upon the previous one
+        let looping = task.looping.get(stage);
+        if self.batch_size > 1 && proving.message.chunk.is_none() && looping.is_none() {
+            self.pending.lock().unwrap().push_back(proving);
+            self.proving.notify_one();
+            return Ok(());
+        }
+        let mut clock = self
+            .context
+            .prove_async(&proving.predecessors(), &proving.output, &proving.binding)
+            .await?;
+        if let Some(repeat) = looping {
+            let mut executed = 1;
+            while !repeat.done(&proving.output, executed)? {
+                let attempted = self
+                    .attempt(&proving.message, task, |progress| {
+                        self.executor.execute(&proving.output, progress)
+                    })
+                    .await?;
+                let Some((execution, execution_time)) = attempted else {
+                    return Ok(());
+                };
+                executed += 1;
+                let Proving {
+                    mut message,
+                    output,
+                    ..
+                } = proving;
+                let previous = StreamState {
+                    seq: message.chunk.map_or(0, |chunk| chunk.seq),
+                    clock: clock.clone(),
+                    output,
+                };
+                message
+                    .iterations
+                    .entry(stage.clone())
+                    .or_default()
+                    .push(clock);
+                let (output, content_type) = self
+                    .record(&mut message, task, execution, execution_time)
+                    .await;
+                proving = Proving::new(
+                    message,
+                    stage,
+                    task.clone(),
+                    Vec::new(),
+                    Some(previous),
+                    output,
+                    content_type,
+                );
+                clock = self
+                    .context
+                    .prove_async(&proving.predecessors(), &proving.output, &proving.binding)
+                    .await?;
+            }
+        }
+        self.publish(proving, clock).await
+    }
+
+    // the program version, the output digest, the log and the execution time of the stage are
+    // recorded in the message, the ones of the last iteration of a looping stage, with the
+    // execution times of its iterations added up. the output is left along with its content type
+    async fn record(
+        &self,
+        message: &mut TaskStage<C::Clock, Bytes>,
+        task: &Workflow,
+        execution: Execution,
+        execution_time: Duration,
+    ) -> (Bytes, Option<String>) {
+        let stage = &self.stage;
+        let Execution {
+            program,
+            output,
+            content_type,
+            log,
+        } = execution;
+        message.programs.insert(stage.clone(), program);
+        if task.audit {
+            message
+                .output_digests
+                .insert(stage.clone(), output_digest(&output));
+        }
+        if let (Some(route), Some(shard)) = (message.routes.get_mut(stage), self.shard) {
+            route.executed = Some(shard)
+        }
+        if let Some(log) = self.upload_log(message.id, log).await {
+            message.logs.insert(stage.clone(), log);
+        }
+        *message.elapsed.entry(stage.clone()).or_default() += execution_time.as_millis() as u64;
+        (output, content_type)
+    }
+
+    async fn publish(&self, proving: Proving<C::Clock>, clock: C::Clock) -> anyhow::Result<()> {
+        let stage = &self.stage;
+        let Proving {
+            message,
+            task,
+            output,
+            content_type,
+            ..
+        } = proving;
+        if let Some(chunk) = message.chunk.filter(|chunk| !chunk.last) {
+            self.streams.lock().unwrap().insert(
+                message.id,
+                StreamState {
+                    seq: chunk.seq,
+                    clock: clock.clone(),
+                    output: output.clone(),
+                },
+            );
+        }
+        let id = message.id;
+        // a join has several bases, so it carries the full clocks
+        let base = (self.negotiated_deltas.get() == Some(&true)
+            && task.upstream(stage).unwrap_or_default().len() <= 1)
+            .then(|| (message.source.clone(), message.clocks.clone()));
+        let mut clocks = message.clocks;
+        clocks.insert(stage.clone(), clock);
+        let published = if Some(stage) == task.stages.last() {
+            if message
+                .chunk
+                .is_some_and(|chunk| !chunk.is_checkpoint(&task))
+            {
+                return Ok(());
+            }
+            let (output, compression) = self.compress(output)?;
+            let (output, blob) = self.offload(output).await?;
+            let task_result = TaskResult {
+                version: protocol::VERSION,
+                id: message.id,
+                workflow: message.workflow,
+                chunk: message.chunk,
+                output,
+                blob,
+                content_type,
+                compression,
+                clocks,
+                programs: message.programs,
+                output_digests: message.output_digests,
+                logs: message.logs,
+                elapsed: message.elapsed,
+                routes: message.routes,
+                branches: message.branches,
+                iterations: message.iterations,
+                expired: None,
+            };
+            self.transport.propose_chain(&task_result).await
+        } else {
+            // the branch is decided, and the next stages are routed, by the raw output
+            let mut branches = message.branches;
+            if let Some(branch) = task.branching.get(stage) {
+                branches.insert(stage.clone(), branch.decide(&output)?.into());
+            }
+            let mut routes = message.routes;
+            let taken = task.taken(&branches);
+            for next in taken.downstream(&StageSource::Name(stage.clone())) {
+                if let Some(routing) = task.routing.get(next) {
+                    let route = Route {
+                        shard: routing.shard(&output)?,
+                        executed: None,
+                    };
+                    routes.insert(next.into(), route);
+                }
+            }
+            let (output, compression) = self.compress(output)?;
+            let (output, blob) = self.offload(output).await?;
+            let task_stage = TaskStage {
+                version: protocol::VERSION,
+                id: message.id,
+                workflow: message.workflow,
+                source: StageSource::Name(stage.clone()),
+                chunk: message.chunk,
+                assignee: None,
+                handoff: None,
+                accept_cached: false,
+                submitter: None,
+                priority: Default::default(),
+                labels: Default::default(),
+                deadline: None,
+                input: output,
+                blob,
+                content_type,
+                compression,
+                clocks,
+                programs: message.programs,
+                output_digests: message.output_digests,
+                logs: message.logs,
+                elapsed: message.elapsed,
+                routes,
+                branches,
+                iterations: message.iterations,
+                hints: Default::default(),
+                clock_delta: None,
+            };
+            match base {
+                Some((source, base_clocks)) => {
+                    let mut delta = task_stage.clone();
+                    delta.delta_against(source, &base_clocks);
+                    match self.publish_stage(&delta).await {
+                        Err(err) if !err.is::<Expired>() => {
+                            warn!("failed to publish clock delta of task {id:08x}: {err}");
+                            self.publish_stage(&task_stage).await
+                        }
+                        published => published,
+                    }
+                }
+                None => self.publish_stage(&task_stage).await,
+            }
+        };
+        // the task missed its deadline while the stage was executing, nothing is left to do
+        match published {
+            Err(err) if err.is::<Expired>() => {
+                info!("task {id:08x} has expired, output dropped");
+                Ok(())
+            }
+            published => published,
+        }
+    }
+
+    async fn publish_stage(&self, task_stage: &TaskStage<C::Clock, Bytes>) -> anyhow::Result<()> {
+        match self.publish_ack {
+            PublishAck::Persisted => self.transport.publish_gossip(task_stage).await.map(drop),
+            PublishAck::None => self.transport.publish_gossip_detached(task_stage).await,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use bytes::Bytes;
+use pohb::{
+    blob::{blob_key, reassemble_local, BlobRef, BlobStore, MemoryBlobStore, CHUNK_SIZE},
+    crypto::{CryptoSuite, StandardSuite},
+};
+
+fn store(chunk: &[u8]) -> (MemoryBlobStore, BlobRef) {
+    let store = MemoryBlobStore::default();
+    let digest = StandardSuite::default().digest(chunk);
+    store
+        .put(&blob_key(&digest), Bytes::copy_from_slice(chunk))
+        .unwrap();
+    let blob = BlobRef {
+        size: chunk.len() as _,
+        chunks: vec![digest],
+    };
+    (store, blob)
+}
+
+#[test]
+fn reassembles_declared_size() {
+    let (store, blob) = store(b"chunk");
+    assert_eq!(&reassemble_local(&store, &blob, 5).unwrap()[..], b"chunk");
+}
+
+// a declared size is refused before anything is fetched, so it is never allocated
+#[test]
+fn refuses_oversized_blobs() {
+    let (store, mut blob) = store(b"chunk");
+    assert!(reassemble_local(&store, &blob, 4).is_err());
+    blob.size = u64::MAX;
+    assert!(reassemble_local(&store, &blob, u64::MAX).is_err());
+    blob.size = CHUNK_SIZE as u64 + 1;
+    assert!(reassemble_local(&store, &blob, u64::MAX).is_err());
+}
+
+// a chunk repeated beyond the declared size stops the reassembly
+#[test]
+fn refuses_repeated_chunks() {
+    let (store, mut blob) = store(b"chunk");
+    blob.chunks = vec![blob.chunks[0]; 4];
+    assert!(reassemble_local(&store, &blob, u64::MAX).is_err());
+}
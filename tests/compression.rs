@@ -0,0 +1,14 @@
+#![cfg(feature = "compression")]
+
+use pohb::compression::{compress, decompress, Compression};
+
+// a payload compressing this well decompresses only up to the limit
+#[test]
+fn bounds_decompressed_size() {
+    let payload = vec![0; 1 << 20];
+    let compressed = compress(&payload, Compression::Zstd).unwrap();
+    assert!(compressed.len() < 1 << 10);
+    let decompressed = decompress(compressed.clone(), Some(Compression::Zstd), 1 << 20).unwrap();
+    assert_eq!(decompressed, payload);
+    assert!(decompress(compressed, Some(Compression::Zstd), (1 << 20) - 1).is_err());
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "network")]
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use pohb::{crypto::StandardSuite, simulation::Pipeline, OrdinaryClientContext};
+use tokio::time::timeout;
+
+// a failing stage is only logged by its worker, so the task never finishes
+const LIMIT: Duration = Duration::from_secs(60);
+
+// the example pipeline runs end to end to the expected output, and its result verifies against its
+// workflow. `false` if skipped for its modules
+async fn run(name: &str) -> bool {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("pipelines")
+        .join(name);
+    let pipeline = Pipeline::load(&dir).unwrap();
+    let result = timeout(LIMIT, pipeline.run(Arc::new(StandardSuite::default())))
+        .await
+        .expect("pipeline finishes")
+        .unwrap();
+    let Some(result) = result else {
+        return false;
+    };
+    assert_eq!(
+        String::from_utf8_lossy(&result.output),
+        String::from_utf8_lossy(&pipeline.expect)
+    );
+    result
+        .verify(&pipeline.task, &OrdinaryClientContext::new())
+        .unwrap();
+    true
+}
+
+#[tokio::test]
+async fn ocr() {
+    assert!(run("ocr").await)
+}
+
+#[tokio::test]
+async fn textstats() {
+    assert!(run("textstats").await)
+}
+
+// its map stage is a module
+#[tokio::test]
+async fn wordcount() {
+    assert_eq!(run("wordcount").await, cfg!(feature = "wasm"))
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+
+use pohb::vectors;
+
+// every golden vector of the wire format round-trips, as the `vectors` binary checks them
+#[test]
+fn golden_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("vectors");
+    let vectors = vectors::load(&dir).unwrap();
+    assert!(!vectors.is_empty(), "no vector in {}", dir.display());
+    let failed = vectors
+        .iter()
+        .filter_map(|(name, vector)| {
+            let err = vectors::check(vector).err()?;
+            Some(format!("{name} ({}): {err}", vector.description))
+        })
+        .collect::<Vec<_>>();
+    assert!(failed.is_empty(), "failed vectors:\n{}", failed.join("\n"));
+}